@@ -0,0 +1,361 @@
+use crate::device_info::temperature::Temperature;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+const EVENT_CAPACITY: usize = 64;
+
+// Threshold rule for a single thermometer.
+//
+// A high (low) alert is raised once the temperature has stayed above (below)
+// the threshold for at least `min_duration`, and cleared as soon as it comes
+// back by more than `hysteresis` degrees (in the unit of the threshold).
+#[derive(Clone, Debug)]
+pub struct AlertRule {
+    pub name: String,
+    pub thermometer: String,
+    pub high: Option<Temperature>,
+    pub low: Option<Temperature>,
+    pub hysteresis: f32,
+    pub min_duration: Duration,
+}
+
+impl AlertRule {
+    pub fn new(name: &str, thermometer: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            thermometer: thermometer.to_string(),
+            high: None,
+            low: None,
+            hysteresis: 0.0,
+            min_duration: Duration::ZERO,
+        }
+    }
+
+    pub fn above(mut self, threshold: Temperature) -> Self {
+        self.high = Some(threshold);
+        self
+    }
+
+    pub fn below(mut self, threshold: Temperature) -> Self {
+        self.low = Some(threshold);
+        self
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    pub fn with_min_duration(mut self, min_duration: Duration) -> Self {
+        self.min_duration = min_duration;
+        self
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlertKind {
+    High,
+    Low,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum AlertEvent {
+    Raised {
+        rule: String,
+        thermometer: String,
+        kind: AlertKind,
+        temperature: Temperature,
+    },
+    Cleared {
+        rule: String,
+        thermometer: String,
+        kind: AlertKind,
+        temperature: Temperature,
+    },
+}
+
+#[derive(Default)]
+struct ThresholdState {
+    pending_since: Option<Instant>,
+    active: bool,
+}
+
+struct RuleState {
+    rule: AlertRule,
+    high: ThresholdState,
+    low: ThresholdState,
+}
+
+// Evaluates alert rules against incoming readings and publishes raise/clear events.
+// Clones share the same rules and subscribers.
+#[derive(Clone)]
+pub struct AlertMonitor {
+    rules: Arc<Mutex<Vec<RuleState>>>,
+    events: broadcast::Sender<AlertEvent>,
+}
+
+impl Default for AlertMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlertMonitor {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            rules: Arc::new(Mutex::new(Vec::new())),
+            events,
+        }
+    }
+
+    pub fn add_rule(&self, rule: AlertRule) {
+        self.rules.lock().unwrap().push(RuleState {
+            rule,
+            high: ThresholdState::default(),
+            low: ThresholdState::default(),
+        });
+    }
+
+    pub fn remove_rule(&self, name: &str) {
+        self.rules
+            .lock()
+            .unwrap()
+            .retain(|state| state.rule.name != name);
+    }
+
+    // Names of the rules whose alerts are currently raised.
+    pub fn active_alerts(&self) -> Vec<(String, AlertKind)> {
+        let rules = self.rules.lock().unwrap();
+        let mut active = Vec::new();
+        for state in rules.iter() {
+            if state.high.active {
+                active.push((state.rule.name.clone(), AlertKind::High));
+            }
+            if state.low.active {
+                active.push((state.rule.name.clone(), AlertKind::Low));
+            }
+        }
+        active
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.events.subscribe()
+    }
+
+    // Feed a reading taken at `at`. Returns the events it produced, which are
+    // also sent to all subscribers.
+    pub fn process(
+        &self,
+        thermometer: &str,
+        temperature: Temperature,
+        at: Instant,
+    ) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        let mut rules = self.rules.lock().unwrap();
+        for state in rules
+            .iter_mut()
+            .filter(|s| s.rule.thermometer == thermometer)
+        {
+            let rule = &state.rule;
+            if let Some(high) = rule.high {
+                let value = temperature.to_unit(high.unit()).value();
+                let kind = AlertKind::High;
+                if let Some(change) = evaluate(
+                    &mut state.high,
+                    value > high.value(),
+                    value < high.value() - rule.hysteresis,
+                    rule.min_duration,
+                    at,
+                ) {
+                    events.push(make_event(rule, kind, temperature, change));
+                }
+            }
+            if let Some(low) = rule.low {
+                let value = temperature.to_unit(low.unit()).value();
+                let kind = AlertKind::Low;
+                if let Some(change) = evaluate(
+                    &mut state.low,
+                    value < low.value(),
+                    value > low.value() + rule.hysteresis,
+                    rule.min_duration,
+                    at,
+                ) {
+                    events.push(make_event(rule, kind, temperature, change));
+                }
+            }
+        }
+        drop(rules);
+
+        for event in &events {
+            // Nobody listening is not an error.
+            let _ = self.events.send(event.clone());
+        }
+        events
+    }
+}
+
+// Advance one threshold; returns `Some(true)` when it raises and `Some(false)` when it clears.
+fn evaluate(
+    state: &mut ThresholdState,
+    beyond: bool,
+    back_within: bool,
+    min_duration: Duration,
+    at: Instant,
+) -> Option<bool> {
+    if state.active {
+        if back_within {
+            state.active = false;
+            state.pending_since = None;
+            return Some(false);
+        }
+        return None;
+    }
+    if !beyond {
+        state.pending_since = None;
+        return None;
+    }
+    let since = *state.pending_since.get_or_insert(at);
+    if at.duration_since(since) >= min_duration {
+        state.active = true;
+        return Some(true);
+    }
+    None
+}
+
+fn make_event(
+    rule: &AlertRule,
+    kind: AlertKind,
+    temperature: Temperature,
+    raised: bool,
+) -> AlertEvent {
+    let rule_name = rule.name.clone();
+    let thermometer = rule.thermometer.clone();
+    if raised {
+        AlertEvent::Raised {
+            rule: rule_name,
+            thermometer,
+            kind,
+            temperature,
+        }
+    } else {
+        AlertEvent::Cleared {
+            rule: rule_name,
+            thermometer,
+            kind,
+            temperature,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_room_monitor() -> AlertMonitor {
+        let monitor = AlertMonitor::new();
+        monitor.add_rule(
+            AlertRule::new("ServerRoomHot", "ServerRoomThermo")
+                .above(Temperature::celsius(28.0))
+                .with_hysteresis(1.0)
+                .with_min_duration(Duration::from_secs(60)),
+        );
+        monitor
+    }
+
+    #[test]
+    fn test_high_alert_needs_min_duration() {
+        let monitor = server_room_monitor();
+        let start = Instant::now();
+        let hot = Temperature::celsius(29.0);
+        assert!(monitor.process("ServerRoomThermo", hot, start).is_empty());
+        assert!(monitor
+            .process("ServerRoomThermo", hot, start + Duration::from_secs(30))
+            .is_empty());
+        let events = monitor.process("ServerRoomThermo", hot, start + Duration::from_secs(60));
+        assert!(matches!(
+            events.as_slice(),
+            [AlertEvent::Raised {
+                kind: AlertKind::High,
+                ..
+            }]
+        ));
+        assert_eq!(
+            monitor.active_alerts(),
+            vec![("ServerRoomHot".to_string(), AlertKind::High)]
+        );
+    }
+
+    #[test]
+    fn test_short_excursion_does_not_fire() {
+        let monitor = server_room_monitor();
+        let start = Instant::now();
+        monitor.process("ServerRoomThermo", Temperature::celsius(29.0), start);
+        monitor.process(
+            "ServerRoomThermo",
+            Temperature::celsius(27.5),
+            start + Duration::from_secs(30),
+        );
+        let events = monitor.process(
+            "ServerRoomThermo",
+            Temperature::celsius(29.0),
+            start + Duration::from_secs(70),
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_hysteresis_delays_clear() {
+        let monitor = server_room_monitor();
+        let start = Instant::now();
+        monitor.process("ServerRoomThermo", Temperature::celsius(29.0), start);
+        monitor.process(
+            "ServerRoomThermo",
+            Temperature::celsius(29.0),
+            start + Duration::from_secs(60),
+        );
+        let later = start + Duration::from_secs(90);
+        assert!(monitor
+            .process("ServerRoomThermo", Temperature::celsius(27.5), later)
+            .is_empty());
+        let events = monitor.process("ServerRoomThermo", Temperature::celsius(26.9), later);
+        assert!(matches!(
+            events.as_slice(),
+            [AlertEvent::Cleared {
+                kind: AlertKind::High,
+                ..
+            }]
+        ));
+        assert!(monitor.active_alerts().is_empty());
+    }
+
+    #[test]
+    fn test_low_alert_in_other_unit() {
+        let monitor = AlertMonitor::new();
+        monitor.add_rule(
+            AlertRule::new("NurseryCold", "NurseryThermo")
+                .below(Temperature::celsius(18.0))
+                .with_hysteresis(0.5),
+        );
+        let mut receiver = monitor.subscribe();
+        let now = Instant::now();
+        monitor.process("NurseryThermo", Temperature::fahrenheit(63.0), now);
+        match receiver.try_recv().unwrap() {
+            AlertEvent::Raised {
+                rule,
+                kind,
+                temperature,
+                ..
+            } => {
+                assert_eq!(rule, "NurseryCold");
+                assert_eq!(kind, AlertKind::Low);
+                assert_eq!(temperature, Temperature::fahrenheit(63.0));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+        assert!(monitor
+            .process("OtherThermo", Temperature::celsius(5.0), now)
+            .is_empty());
+    }
+}
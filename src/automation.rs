@@ -0,0 +1,650 @@
+mod parser;
+
+use crate::device_info::devices::{Device, SocketState};
+use crate::events::{EventKind, HouseEvent};
+use crate::rules::{condition_met, Condition};
+use crate::scheduler::{RunResult, Scheduler, Trigger};
+use crate::smart_house::{ControlError, SmartHouse};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use thiserror::Error;
+
+// What starts an automation.
+#[derive(Clone, PartialEq, Debug)]
+pub enum AutomationTrigger {
+    // A cron expression or sun event, evaluated by a `Scheduler`.
+    At(Trigger),
+    // Any state change or new reading of the named device.
+    Changes(String),
+}
+
+impl fmt::Display for AutomationTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutomationTrigger::At(Trigger::Cron(cron)) => write!(f, "\"{}\"", cron),
+            AutomationTrigger::At(trigger) => write!(f, "{}", trigger),
+            AutomationTrigger::Changes(device) => write!(f, "{} changes", device),
+        }
+    }
+}
+
+// Sockets an action applies to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Target {
+    Device(String),
+    Room(String),
+    All,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum AutomationAction {
+    Switch { target: Target, state: SocketState },
+    Scene { scene: String, rollback: bool },
+    Report,
+}
+
+fn state_word(state: &SocketState) -> &'static str {
+    match state {
+        SocketState::On => "on",
+        SocketState::Off => "off",
+    }
+}
+
+impl fmt::Display for AutomationAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutomationAction::Switch { target, state } => {
+                write!(f, "turn {} ", state_word(state))?;
+                match target {
+                    Target::Device(device) => write!(f, "{}", device),
+                    Target::Room(room) => write!(f, "room \"{}\"", room),
+                    Target::All => write!(f, "all"),
+                }
+            }
+            AutomationAction::Scene { scene, rollback } => {
+                write!(f, "scene \"{}\"", scene)?;
+                if *rollback {
+                    write!(f, " with rollback")?;
+                }
+                Ok(())
+            }
+            AutomationAction::Report => write!(f, "report"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Automation {
+    pub name: String,
+    pub triggers: Vec<AutomationTrigger>,
+    // All conditions have to hold for the actions to run.
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<AutomationAction>,
+}
+
+impl Automation {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            triggers: Vec::new(),
+            conditions: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+}
+
+// Define an error type for automations.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum AutomationError {
+    #[error("Line {line}, column {column}: {message}")]
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Automation named {0} not found")]
+    NotFound(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConditionStatus {
+    Met,
+    NotMet,
+    // The device is missing or has no reading yet.
+    Unavailable(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConditionCheck {
+    pub condition: Condition,
+    pub status: ConditionStatus,
+}
+
+// What an action would do to the house right now.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PlannedChange {
+    Switch {
+        device: String,
+        from: SocketState,
+        to: SocketState,
+    },
+    Unchanged {
+        device: String,
+        state: SocketState,
+    },
+    ApplyScene(String),
+    Report,
+    Invalid(String),
+}
+
+impl fmt::Display for PlannedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedChange::Switch { device, from, to } => {
+                write!(f, "{}: {} -> {}", device, state_word(from), state_word(to))
+            }
+            PlannedChange::Unchanged { device, state } => {
+                write!(f, "{}: already {}", device, state_word(state))
+            }
+            PlannedChange::ApplyScene(scene) => write!(f, "apply scene \"{}\"", scene),
+            PlannedChange::Report => write!(f, "generate report"),
+            PlannedChange::Invalid(reason) => write!(f, "cannot run: {}", reason),
+        }
+    }
+}
+
+// Result of checking one automation against the house without changing it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DryRunEntry {
+    pub automation: String,
+    pub triggers: Vec<AutomationTrigger>,
+    pub conditions: Vec<ConditionCheck>,
+    pub would_fire: bool,
+    pub changes: Vec<PlannedChange>,
+}
+
+impl fmt::Display for DryRunEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.would_fire {
+            "would fire"
+        } else {
+            "would not fire"
+        };
+        writeln!(f, "Automation \"{}\": {}", self.automation, verdict)?;
+        for trigger in &self.triggers {
+            writeln!(f, "  when {}", trigger)?;
+        }
+        for check in &self.conditions {
+            let status = match &check.status {
+                ConditionStatus::Met => "met".to_string(),
+                ConditionStatus::NotMet => "not met".to_string(),
+                ConditionStatus::Unavailable(reason) => format!("unavailable: {}", reason),
+            };
+            writeln!(f, "  if {}: {}", check.condition, status)?;
+        }
+        for change in &self.changes {
+            writeln!(f, "  then {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ActionResult {
+    pub action: String,
+    pub result: RunResult,
+}
+
+// Outcome of running one automation.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AutomationRun {
+    pub automation: String,
+    // Whether the conditions held; actions only run if they did.
+    pub fired: bool,
+    pub results: Vec<ActionResult>,
+}
+
+// A set of automations loaded from text.
+#[derive(Default)]
+pub struct Automations {
+    automations: Vec<Automation>,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+impl Automations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Parse automations such as:
+    //
+    //     # Outdoor lights follow the sun.
+    //     automation "Garden lights" {
+    //         when sunset - 15 min
+    //         when "0 23 * * *"
+    //         when GardenThermo changes
+    //         if GardenThermo < 5C and Heater is off
+    //         then turn on GardenLamp, turn off room "Living Room"
+    //         then scene "Night" with rollback
+    //         then report
+    //     }
+    //
+    // Triggers are optional; an automation without any only runs on request.
+    // Names may be quoted when they contain spaces. Errors give the line and
+    // column of the offending token.
+    pub fn load(text: &str) -> Result<Self, AutomationError> {
+        Ok(Self {
+            automations: parser::parse(text)?,
+            last_tick: None,
+        })
+    }
+
+    pub fn load_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, LoadError> {
+        Ok(Self::load(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn automations(&self) -> &[Automation] {
+        &self.automations
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Automation> {
+        self.automations.iter().find(|a| a.name == name)
+    }
+
+    // Explain which automations would fire against the current state of the
+    // house and what they would change, without changing anything.
+    pub fn dry_run(&self, house: &SmartHouse) -> Vec<DryRunEntry> {
+        self.automations
+            .iter()
+            .map(|automation| {
+                let conditions: Vec<ConditionCheck> = automation
+                    .conditions
+                    .iter()
+                    .map(|condition| ConditionCheck {
+                        condition: condition.clone(),
+                        status: match condition_met(condition, house) {
+                            Ok(true) => ConditionStatus::Met,
+                            Ok(false) => ConditionStatus::NotMet,
+                            Err(reason) => ConditionStatus::Unavailable(reason),
+                        },
+                    })
+                    .collect();
+                let would_fire = conditions.iter().all(|c| c.status == ConditionStatus::Met);
+                let changes = if would_fire {
+                    automation
+                        .actions
+                        .iter()
+                        .flat_map(|action| plan(action, house))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                DryRunEntry {
+                    automation: automation.name.clone(),
+                    triggers: automation.triggers.clone(),
+                    conditions,
+                    would_fire,
+                    changes,
+                }
+            })
+            .collect()
+    }
+
+    // Check the conditions of the named automation and run its actions if they hold.
+    pub fn run(
+        &self,
+        name: &str,
+        house: &mut SmartHouse,
+    ) -> Result<AutomationRun, AutomationError> {
+        let automation = self
+            .get(name)
+            .ok_or_else(|| AutomationError::NotFound(name.to_string()))?;
+        Ok(execute(automation, house))
+    }
+
+    // Run the automations triggered by a change of the event's device.
+    pub fn handle_event(&self, event: &HouseEvent, house: &mut SmartHouse) -> Vec<AutomationRun> {
+        let changed = matches!(
+            event.kind,
+            EventKind::SocketStateChanged { .. } | EventKind::ReadingReceived { .. }
+        );
+        let Some(device) = event.device.as_deref().filter(|_| changed) else {
+            return Vec::new();
+        };
+        self.automations
+            .iter()
+            .filter(|automation| {
+                automation
+                    .triggers
+                    .iter()
+                    .any(|trigger| matches!(trigger, AutomationTrigger::Changes(d) if d == device))
+            })
+            .map(|automation| execute(automation, house))
+            .collect()
+    }
+
+    // Run the automations whose time triggers fired since the previous tick,
+    // using the scheduler's timezone, location and holidays. The first tick
+    // only records the time.
+    pub fn tick(
+        &mut self,
+        house: &mut SmartHouse,
+        scheduler: &Scheduler,
+        now: DateTime<Utc>,
+    ) -> Vec<AutomationRun> {
+        let Some(since) = self.last_tick.replace(now) else {
+            return Vec::new();
+        };
+        self.automations
+            .iter()
+            .filter(|automation| {
+                automation.triggers.iter().any(|trigger| match trigger {
+                    AutomationTrigger::At(trigger) => scheduler
+                        .next_occurrence(trigger, false, since)
+                        .is_some_and(|at| at <= now),
+                    AutomationTrigger::Changes(_) => false,
+                })
+            })
+            .map(|automation| execute(automation, house))
+            .collect()
+    }
+}
+
+// Define an error type for loading automation files.
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("Failed to read automations: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Automation(#[from] AutomationError),
+}
+
+// Names of the sockets a target refers to.
+fn sockets(target: &Target, house: &SmartHouse) -> Result<Vec<String>, ControlError> {
+    let socket_names = |devices: &mut dyn Iterator<Item = &Device>| -> Vec<String> {
+        devices
+            .filter_map(|device| match device {
+                Device::SmartSocket(socket) => Some(socket.name.clone()),
+                Device::SmartThermometer(_) => None,
+            })
+            .collect()
+    };
+    match target {
+        Target::Device(device) => Ok(vec![device.clone()]),
+        Target::Room(room) => house
+            .rooms()
+            .iter()
+            .find(|r| &r.name == room)
+            .map(|r| socket_names(&mut r.devices.iter()))
+            .ok_or_else(|| ControlError::RoomNotFound(room.clone())),
+        Target::All => Ok(socket_names(
+            &mut house.rooms().iter().flat_map(|room| room.devices.iter()),
+        )),
+    }
+}
+
+fn plan(action: &AutomationAction, house: &SmartHouse) -> Vec<PlannedChange> {
+    match action {
+        AutomationAction::Switch { target, state } => match sockets(target, house) {
+            Ok(devices) => devices
+                .into_iter()
+                .map(|device| match house.socket(&device) {
+                    Ok(socket) if socket.state == *state => PlannedChange::Unchanged {
+                        device,
+                        state: state.clone(),
+                    },
+                    Ok(socket) => match house.check_interlocks(&device, state) {
+                        Ok(()) => PlannedChange::Switch {
+                            from: socket.state.clone(),
+                            to: state.clone(),
+                            device,
+                        },
+                        Err(e) => PlannedChange::Invalid(e.to_string()),
+                    },
+                    Err(e) => PlannedChange::Invalid(e.to_string()),
+                })
+                .collect(),
+            Err(e) => vec![PlannedChange::Invalid(e.to_string())],
+        },
+        AutomationAction::Scene { scene, .. } => match house.scene(scene) {
+            Some(_) => vec![PlannedChange::ApplyScene(scene.clone())],
+            None => vec![PlannedChange::Invalid(format!(
+                "Scene named {} not found",
+                scene
+            ))],
+        },
+        AutomationAction::Report => vec![PlannedChange::Report],
+    }
+}
+
+fn execute(automation: &Automation, house: &mut SmartHouse) -> AutomationRun {
+    let fired = automation
+        .conditions
+        .iter()
+        .all(|condition| condition_met(condition, house) == Ok(true));
+    let mut results = Vec::new();
+    if fired {
+        for action in &automation.actions {
+            match action {
+                AutomationAction::Switch { target, state } => match sockets(target, house) {
+                    Ok(devices) => {
+                        for device in devices {
+                            results.push(ActionResult {
+                                action: format!("turn {} {}", state_word(state), device),
+                                result: RunResult::Command(
+                                    house.switch_socket(&device, state.clone()),
+                                ),
+                            });
+                        }
+                    }
+                    Err(e) => results.push(ActionResult {
+                        action: action.to_string(),
+                        result: RunResult::Command(Err(e)),
+                    }),
+                },
+                AutomationAction::Scene { scene, rollback } => results.push(ActionResult {
+                    action: action.to_string(),
+                    result: RunResult::Scene(house.apply_scene(scene, *rollback)),
+                }),
+                AutomationAction::Report => results.push(ActionResult {
+                    action: action.to_string(),
+                    result: RunResult::Report(house.create_report(house)),
+                }),
+            }
+        }
+    }
+    AutomationRun {
+        automation: automation.name.clone(),
+        fired,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::devices::ThermometerState;
+    use crate::device_info::temperature::Temperature;
+    use crate::test_support::{room, socket, thermometer};
+    use chrono::Duration;
+
+    const AUTOMATIONS: &str = r#"
+# Heating for cold evenings.
+automation "Cold evening" {
+    when "0 18 * * *"
+    when LivingThermo changes
+    if LivingThermo < 19C and Heater is off
+    then turn on Heater, turn off room "Living Room"
+}
+
+automation Leaving {
+    then turn off all
+    then report
+}
+"#;
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "Home",
+            vec![
+                room(
+                    "Living Room",
+                    vec![
+                        socket("Lamp", SocketState::On, 100.0),
+                        socket("Tv", SocketState::Off, 100.0),
+                        thermometer(
+                            "LivingThermo",
+                            ThermometerState::Temperature(Temperature::celsius(17.5)),
+                        ),
+                    ],
+                ),
+                room("Bedroom", vec![socket("Heater", SocketState::Off, 100.0)]),
+            ],
+        )
+    }
+
+    fn syntax_error(text: &str) -> (usize, usize, String) {
+        match Automations::load(text) {
+            Err(AutomationError::Syntax {
+                line,
+                column,
+                message,
+            }) => (line, column, message),
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let automations = Automations::load(AUTOMATIONS).unwrap();
+        let evening = automations.get("Cold evening").unwrap();
+        assert_eq!(evening.triggers.len(), 2);
+        assert_eq!(
+            evening.triggers[1],
+            AutomationTrigger::Changes("LivingThermo".to_string())
+        );
+        assert_eq!(evening.conditions.len(), 2);
+        assert_eq!(
+            evening.actions[1],
+            AutomationAction::Switch {
+                target: Target::Room("Living Room".to_string()),
+                state: SocketState::Off
+            }
+        );
+        assert_eq!(automations.get("Leaving").unwrap().actions.len(), 2);
+    }
+
+    #[test]
+    fn test_errors_have_line_and_column() {
+        assert_eq!(
+            syntax_error("automation A {\n  if Thermo <= 5\n  then report\n}"),
+            (2, 14, "invalid temperature `=`".to_string())
+        );
+        assert_eq!(
+            syntax_error("automation A {\n  then blink Lamp\n}"),
+            (
+                2,
+                8,
+                "unknown action `blink`, expected `turn`, `scene` or `report`".to_string()
+            )
+        );
+        assert_eq!(
+            syntax_error("automation A {\n  when \"61 * * * *\"\n  then report\n}").0,
+            2
+        );
+        assert_eq!(
+            syntax_error("automation A {\n  when sunset 15\n  then report\n}"),
+            (
+                2,
+                8,
+                "Invalid sun offset `15`, expected e.g. `sunset - 15 min` or `sunrise + 1h`"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            syntax_error("automation A {\n  then report\n"),
+            (
+                1,
+                1,
+                "automation \"A\" is missing its closing `}`".to_string()
+            )
+        );
+        assert_eq!(
+            syntax_error("automation A {\n}").2,
+            "automation \"A\" has no `then` clause"
+        );
+        assert_eq!(
+            syntax_error("rule A {").2,
+            "expected `automation`, found `rule`"
+        );
+        assert_eq!(syntax_error("automation \"A {").2, "unterminated string");
+    }
+
+    #[test]
+    fn test_dry_run_explains_without_changing_anything() {
+        let house = house();
+        let automations = Automations::load(AUTOMATIONS).unwrap();
+        let dry_run = automations.dry_run(&house);
+        assert!(dry_run[0].would_fire);
+        assert_eq!(
+            dry_run[0].to_string(),
+            "Automation \"Cold evening\": would fire\n  \
+             when \"0 18 * * *\"\n  \
+             when LivingThermo changes\n  \
+             if LivingThermo < 19.0°C: met\n  \
+             if Heater is off: met\n  \
+             then Heater: off -> on\n  \
+             then Lamp: on -> off\n  \
+             then Tv: already off\n"
+        );
+        assert_eq!(house.socket("Heater").unwrap().state, SocketState::Off);
+
+        let automations =
+            Automations::load("automation Check {\n if Missing > 20 \n then turn on Lamp\n}")
+                .unwrap();
+        let dry_run = automations.dry_run(&house);
+        assert!(!dry_run[0].would_fire);
+        assert!(matches!(
+            dry_run[0].conditions[0].status,
+            ConditionStatus::Unavailable(_)
+        ));
+    }
+
+    #[test]
+    fn test_run_and_triggers() {
+        let mut house = house();
+        let mut automations = Automations::load(AUTOMATIONS).unwrap();
+
+        let event = HouseEvent::new(
+            Some("LivingThermo"),
+            EventKind::ReadingReceived {
+                temperature: Temperature::celsius(17.0),
+            },
+        );
+        let runs = automations.handle_event(&event, &mut house);
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].fired);
+        assert_eq!(house.socket("Heater").unwrap().state, SocketState::On);
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::Off);
+
+        // The heater is now on, so the condition no longer holds.
+        let scheduler = Scheduler::default();
+        let evening = DateTime::parse_from_rfc3339("2023-12-01T17:59:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(automations.tick(&mut house, &scheduler, evening).is_empty());
+        let runs = automations.tick(&mut house, &scheduler, evening + Duration::minutes(2));
+        assert_eq!(runs.len(), 1);
+        assert!(!runs[0].fired);
+
+        let run = automations.run("Leaving", &mut house).unwrap();
+        assert!(run.fired);
+        assert_eq!(run.results.len(), 4);
+        assert!(matches!(run.results[3].result, RunResult::Report(_)));
+        assert_eq!(house.socket("Heater").unwrap().state, SocketState::Off);
+        assert_eq!(
+            automations.run("Party", &mut house),
+            Err(AutomationError::NotFound("Party".to_string()))
+        );
+    }
+}
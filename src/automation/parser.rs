@@ -0,0 +1,380 @@
+use super::{Automation, AutomationAction, AutomationError, AutomationTrigger, Target};
+use crate::device_info::devices::SocketState;
+use crate::rules::{parse_socket_state, parse_temperature, Comparison, Condition};
+use std::collections::HashSet;
+
+#[derive(Clone, PartialEq, Debug)]
+enum TokenKind {
+    Word(String),
+    Quoted(String),
+    Symbol(char),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    // 1-based, counted in characters.
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => format!("`{}`", word),
+            TokenKind::Quoted(text) => format!("\"{}\"", text),
+            TokenKind::Symbol(symbol) => format!("`{}`", symbol),
+        }
+    }
+}
+
+fn syntax(line: usize, column: usize, message: impl Into<String>) -> AutomationError {
+    AutomationError::Syntax {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+// The tokens of one line together with the raw text, so clauses such as
+// `when sunset - 15 min` can hand the rest of the line to another parser.
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Line<'a> {
+    fn tokenize(number: usize, text: &'a str) -> Result<Self, AutomationError> {
+        let mut tokens = Vec::new();
+        let mut chars = text.chars().enumerate().peekable();
+        while let Some((index, c)) = chars.next() {
+            let column = index + 1;
+            match c {
+                '#' => break,
+                c if c.is_whitespace() => {}
+                '{' | '}' | ',' | '<' | '>' => tokens.push(Token {
+                    kind: TokenKind::Symbol(c),
+                    column,
+                }),
+                '"' => {
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, c)) => value.push(c),
+                            None => return Err(syntax(number, column, "unterminated string")),
+                        }
+                    }
+                    tokens.push(Token {
+                        kind: TokenKind::Quoted(value),
+                        column,
+                    });
+                }
+                c => {
+                    let mut word = c.to_string();
+                    while let Some((_, c)) =
+                        chars.next_if(|(_, c)| !c.is_whitespace() && !"{},<>\"#".contains(*c))
+                    {
+                        word.push(c);
+                    }
+                    tokens.push(Token {
+                        kind: TokenKind::Word(word),
+                        column,
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            number,
+            text,
+            tokens,
+            position: 0,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    // Column just past the end of the line, for "expected ..." errors.
+    fn end_column(&self) -> usize {
+        self.text.trim_end().chars().count() + 1
+    }
+
+    fn error_at(&self, token: Option<&Token>, message: impl Into<String>) -> AutomationError {
+        let column = token.map_or_else(|| self.end_column(), |token| token.column);
+        syntax(self.number, column, message)
+    }
+
+    fn unexpected(&self, expected: &str) -> AutomationError {
+        let token = self.peek();
+        let found = token.map_or_else(|| "end of line".to_string(), Token::describe);
+        self.error_at(token, format!("expected {}, found {}", expected, found))
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, AutomationError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.unexpected(expected))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    // Consume the keyword `word` if it is next.
+    fn accept(&mut self, word: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token { kind: TokenKind::Word(w), .. }) if w == word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), AutomationError> {
+        if self.accept(word) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", word)))
+        }
+    }
+
+    fn accept_symbol(&mut self, symbol: char) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token { kind: TokenKind::Symbol(s), .. }) if *s == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), AutomationError> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{}`", symbol)))
+        }
+    }
+
+    // A device or room name: a bare word or a quoted string.
+    fn name(&mut self, expected: &str) -> Result<String, AutomationError> {
+        let token = self.next(expected)?;
+        match token.kind {
+            TokenKind::Word(word) => Ok(word),
+            TokenKind::Quoted(text) => Ok(text),
+            TokenKind::Symbol(_) => {
+                self.position -= 1;
+                Err(self.unexpected(expected))
+            }
+        }
+    }
+
+    fn word(&mut self, expected: &str) -> Result<(String, Token), AutomationError> {
+        let token = self.next(expected)?;
+        match &token.kind {
+            TokenKind::Word(word) => Ok((word.clone(), token)),
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected(expected))
+            }
+        }
+    }
+
+    fn state(&mut self) -> Result<SocketState, AutomationError> {
+        let (word, token) = self.word("`on` or `off`")?;
+        parse_socket_state(&word).map_err(|message| self.error_at(Some(&token), message))
+    }
+
+    fn end(&self) -> Result<(), AutomationError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => {
+                Err(self.error_at(Some(token), format!("unexpected {}", token.describe())))
+            }
+        }
+    }
+
+    // Raw text from `token` to the end of the line, without a trailing comment.
+    fn rest_from(&self, token: &Token) -> &'a str {
+        let start: usize = self
+            .text
+            .char_indices()
+            .nth(token.column - 1)
+            .map_or(self.text.len(), |(index, _)| index);
+        let rest = &self.text[start..];
+        rest.split('#').next().unwrap_or(rest).trim()
+    }
+}
+
+// Parse a whole automation file; see `Automations::load` for the syntax.
+pub(crate) fn parse(text: &str) -> Result<Vec<Automation>, AutomationError> {
+    let mut automations: Vec<Automation> = Vec::new();
+    let mut names = HashSet::new();
+    // The automation being defined and the line its header is on.
+    let mut current: Option<(Automation, usize)> = None;
+
+    for (index, raw) in text.lines().enumerate() {
+        let mut line = Line::tokenize(index + 1, raw)?;
+        if line.is_empty() {
+            continue;
+        }
+        let Some((automation, _)) = current.as_mut() else {
+            line.expect("automation")?;
+            let name_token = line.peek().cloned();
+            let name = line.name("an automation name")?;
+            if !names.insert(name.clone()) {
+                return Err(line.error_at(
+                    name_token.as_ref(),
+                    format!("automation \"{}\" is already defined", name),
+                ));
+            }
+            line.expect_symbol('{')?;
+            line.end()?;
+            current = Some((Automation::new(&name), line.number));
+            continue;
+        };
+
+        if line.accept_symbol('}') {
+            line.end()?;
+            if automation.actions.is_empty() {
+                return Err(syntax(
+                    line.number,
+                    1 + raw.len() - raw.trim_start().len(),
+                    format!("automation \"{}\" has no `then` clause", automation.name),
+                ));
+            }
+            let (automation, _) = current.take().unwrap();
+            automations.push(automation);
+        } else if line.accept("when") {
+            automation.triggers.push(trigger(&mut line)?);
+        } else if line.accept("if") {
+            loop {
+                automation.conditions.push(condition(&mut line)?);
+                if !line.accept("and") {
+                    break;
+                }
+            }
+        } else if line.accept("then") {
+            loop {
+                automation.actions.push(action(&mut line)?);
+                if !line.accept_symbol(',') {
+                    break;
+                }
+            }
+        } else {
+            return Err(line.unexpected("`when`, `if`, `then` or `}`"));
+        }
+        line.end()?;
+    }
+
+    match current {
+        Some((automation, header)) => Err(syntax(
+            header,
+            1,
+            format!(
+                "automation \"{}\" is missing its closing `}}`",
+                automation.name
+            ),
+        )),
+        None => Ok(automations),
+    }
+}
+
+fn trigger(line: &mut Line) -> Result<AutomationTrigger, AutomationError> {
+    let token = line.next("a trigger")?;
+    match &token.kind {
+        TokenKind::Quoted(text) => text
+            .parse()
+            .map(AutomationTrigger::At)
+            .map_err(|e| line.error_at(Some(&token), e.to_string())),
+        TokenKind::Word(word) if is_sun_event(word) => {
+            let text = line.rest_from(&token);
+            line.position = line.tokens.len();
+            text.parse()
+                .map(AutomationTrigger::At)
+                .map_err(|e| line.error_at(Some(&token), e.to_string()))
+        }
+        TokenKind::Word(device) => {
+            let device = device.clone();
+            line.expect("changes")?;
+            Ok(AutomationTrigger::Changes(device))
+        }
+        TokenKind::Symbol(_) => {
+            line.position -= 1;
+            Err(line.unexpected("a trigger"))
+        }
+    }
+}
+
+// `sunset`, `sunset-15m` and the like, but not a device called `sunset_lamp`.
+fn is_sun_event(word: &str) -> bool {
+    ["sunrise", "sunset"].iter().any(|event| {
+        word.strip_prefix(event)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['+', '-']))
+    })
+}
+
+fn condition(line: &mut Line) -> Result<Condition, AutomationError> {
+    let device = line.name("a device name")?;
+    if line.accept("is") {
+        return Ok(Condition::SocketIs {
+            socket: device,
+            state: line.state()?,
+        });
+    }
+    let comparison = if line.accept_symbol('<') {
+        Comparison::Below
+    } else if line.accept_symbol('>') {
+        Comparison::Above
+    } else {
+        return Err(line.unexpected("`is`, `<` or `>`"));
+    };
+    let (text, token) = line.word("a temperature")?;
+    let threshold =
+        parse_temperature(&text).map_err(|message| line.error_at(Some(&token), message))?;
+    Ok(Condition::Temperature {
+        thermometer: device,
+        comparison,
+        threshold,
+    })
+}
+
+fn action(line: &mut Line) -> Result<AutomationAction, AutomationError> {
+    let (word, token) = line.word("an action")?;
+    match word.as_str() {
+        "turn" => {
+            let state = line.state()?;
+            let target = if line.accept("room") {
+                Target::Room(line.name("a room name")?)
+            } else if line.accept("all") {
+                Target::All
+            } else {
+                Target::Device(line.name("a device name")?)
+            };
+            Ok(AutomationAction::Switch { target, state })
+        }
+        "scene" => {
+            let scene = line.name("a scene name")?;
+            let rollback = if line.accept("with") {
+                line.expect("rollback")?;
+                true
+            } else {
+                false
+            };
+            Ok(AutomationAction::Scene { scene, rollback })
+        }
+        "report" => Ok(AutomationAction::Report),
+        other => Err(line.error_at(
+            Some(&token),
+            format!(
+                "unknown action `{}`, expected `turn`, `scene` or `report`",
+                other
+            ),
+        )),
+    }
+}
@@ -0,0 +1,987 @@
+use crate::config::{ConfigError, DeviceConfig, HouseConfig, RoomConfig};
+use crate::dashboard::{self, DashboardError};
+use crate::device_info::devices::SocketState;
+use crate::http_api::{Mode, RoomSummary};
+use crate::live_feed::{ClientMessage, FeedMessage};
+use crate::modes::{HouseMode, ModeChange};
+use crate::report::{DeviceReport, HouseReport, ReportFormat};
+use crate::smart_house::ControlError;
+use crate::smart_socket::smart_socket_client::SmartSocketClient;
+use crate::storage::{Storage, StorageError};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio_tungstenite::tungstenite::Message;
+
+pub const USAGE: &str = "\
+Usage: smart_house [--config FILE] [--json] <command>
+
+Commands:
+  rooms list
+  rooms add <room>
+  rooms remove <room>
+  devices list [--room ROOM]
+  devices add <room> <device> socket [--power WATTS] [--priority N] [--address ADDRESS]
+  devices add <room> <device> thermometer [--address ADDRESS]
+  devices remove <device>
+  devices move <device> <room>
+  report [--format text|json|csv] [--history HOURS]
+  validate
+  control <device> on|off
+  dashboard --address ADDRESS
+  mode [home|away|night|vacation] --address ADDRESS
+
+Options:
+  --config FILE   house config to work on (default: house.toml)
+  --json          print results as JSON
+
+Exit codes: 0 success, 1 I/O or device failure, 2 usage error,
+3 invalid config, 4 room or device not found, 5 command rejected.";
+
+// How long to wait for the gateway to answer.
+const GATEWAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Define an error type for CLI commands. Every error maps to an exit code.
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}\n\n{USAGE}")]
+    Usage(String),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Control(#[from] ControlError),
+    #[error("Room named {0} already exists")]
+    RoomExists(String),
+    #[error("Failed to reach socket {socket} at {address}: {source}")]
+    Unreachable {
+        socket: String,
+        address: String,
+        source: std::io::Error,
+    },
+    #[error("Socket {socket} refused the command: {response}")]
+    Refused { socket: String, response: String },
+    #[error(transparent)]
+    Dashboard(#[from] DashboardError),
+    #[error("Failed to reach the gateway at {address}: {reason}")]
+    Gateway { address: String, reason: String },
+    #[error("The gateway refused the mode change: {0}")]
+    ModeRefused(String),
+    #[error("History needs a [storage] section in the config")]
+    HistoryDisabled,
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Config(ConfigError::Io(_))
+            | CliError::Unreachable { .. }
+            | CliError::Dashboard(_)
+            | CliError::Gateway { .. }
+            | CliError::Storage(_) => 1,
+            CliError::Usage(_) => 2,
+            CliError::Config(_) => 3,
+            CliError::Control(ControlError::NotFound(_) | ControlError::RoomNotFound(_)) => 4,
+            CliError::Control(_)
+            | CliError::RoomExists(_)
+            | CliError::Refused { .. }
+            | CliError::ModeRefused(_)
+            | CliError::HistoryDisabled => 5,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Help,
+    RoomsList,
+    RoomsAdd(String),
+    RoomsRemove(String),
+    DevicesList {
+        room: Option<String>,
+    },
+    DevicesAdd {
+        room: String,
+        device: DeviceConfig,
+    },
+    DevicesRemove(String),
+    DevicesMove {
+        device: String,
+        room: String,
+    },
+    // A `None` format prints JSON with `--json` and text otherwise. History
+    // covers the given number of past hours.
+    Report {
+        format: Option<ReportFormat>,
+        history: Option<u64>,
+    },
+    Validate,
+    Control {
+        device: String,
+        state: SocketState,
+    },
+    // Interactive; follows and drives a running gateway through its HTTP
+    // API at `address`.
+    Dashboard {
+        address: String,
+    },
+    // Show the mode of the house of the gateway at `address`, or change it.
+    Mode {
+        mode: Option<HouseMode>,
+        address: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Validated {
+    valid: bool,
+    rooms: usize,
+    devices: usize,
+}
+
+// A parsed command line of the `smart_house` binary.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cli {
+    pub config: PathBuf,
+    pub json: bool,
+    pub command: Command,
+}
+
+impl Cli {
+    // Parse the arguments after the program name. Options may appear
+    // anywhere on the line.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut config = PathBuf::from("house.toml");
+        let mut json = false;
+        let mut help = false;
+        let mut room = None;
+        let mut format = None;
+        let mut power = None;
+        let mut priority = None;
+        let mut address = None;
+        let mut history = None;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| {
+                args.next()
+                    .ok_or_else(|| CliError::Usage(format!("{} needs a value", option)))
+            };
+            match arg.as_str() {
+                "--config" => config = PathBuf::from(value("--config")?),
+                "--json" => json = true,
+                "-h" | "--help" => help = true,
+                "--room" => room = Some(value("--room")?),
+                "--format" => {
+                    let text = value("--format")?;
+                    format = Some(text.parse().map_err(CliError::Usage)?);
+                }
+                "--power" => {
+                    let text = value("--power")?;
+                    power = Some(text.parse::<f32>().map_err(|_| {
+                        CliError::Usage(format!("--power expects watts, got {}", text))
+                    })?);
+                }
+                "--priority" => {
+                    let text = value("--priority")?;
+                    priority = Some(text.parse::<i32>().map_err(|_| {
+                        CliError::Usage(format!("--priority expects a number, got {}", text))
+                    })?);
+                }
+                "--address" => address = Some(value("--address")?),
+                "--history" => {
+                    let text = value("--history")?;
+                    history = Some(text.parse::<u64>().map_err(|_| {
+                        CliError::Usage(format!("--history expects hours, got {}", text))
+                    })?);
+                }
+                option if option.starts_with("--") => {
+                    return Err(CliError::Usage(format!("unknown option {}", option)));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let words: Vec<&str> = positional.iter().map(String::as_str).collect();
+        let command = match words.as_slice() {
+            _ if help => Command::Help,
+            [] => return Err(CliError::Usage("missing command".to_string())),
+            ["rooms", "list"] => Command::RoomsList,
+            ["rooms", "add", name] => Command::RoomsAdd(name.to_string()),
+            ["rooms", "remove", name] => Command::RoomsRemove(name.to_string()),
+            ["devices", "list"] => Command::DevicesList { room: room.take() },
+            ["devices", "add", room, name, "socket"] => Command::DevicesAdd {
+                room: room.to_string(),
+                device: DeviceConfig::Socket {
+                    name: name.to_string(),
+                    state: SocketState::Off,
+                    power_consumption: power.take().unwrap_or(0.0),
+                    priority: priority.take().unwrap_or(0),
+                    address: address.take(),
+                },
+            },
+            ["devices", "add", room, name, "thermometer"] => Command::DevicesAdd {
+                room: room.to_string(),
+                device: DeviceConfig::Thermometer {
+                    name: name.to_string(),
+                    address: address.take(),
+                },
+            },
+            ["devices", "remove", name] => Command::DevicesRemove(name.to_string()),
+            ["devices", "move", name, room] => Command::DevicesMove {
+                device: name.to_string(),
+                room: room.to_string(),
+            },
+            ["report"] => Command::Report {
+                format: format.take(),
+                history: history.take(),
+            },
+            ["validate"] => Command::Validate,
+            ["dashboard"] => Command::Dashboard {
+                address: address.take().ok_or_else(|| {
+                    CliError::Usage("dashboard needs the --address of a gateway's HTTP API".into())
+                })?,
+            },
+            ["mode", mode @ ..] if mode.len() <= 1 => Command::Mode {
+                mode: match mode.first() {
+                    None => None,
+                    Some(&"home") => Some(HouseMode::Home),
+                    Some(&"away") => Some(HouseMode::Away),
+                    Some(&"night") => Some(HouseMode::Night),
+                    Some(&"vacation") => Some(HouseMode::Vacation),
+                    Some(other) => {
+                        return Err(CliError::Usage(format!(
+                            "expected home, away, night or vacation, got {}",
+                            other
+                        )))
+                    }
+                },
+                address: address.take().ok_or_else(|| {
+                    CliError::Usage("mode needs the --address of a gateway's HTTP API".into())
+                })?,
+            },
+            ["control", device, state] => Command::Control {
+                device: device.to_string(),
+                state: match *state {
+                    "on" => SocketState::On,
+                    "off" => SocketState::Off,
+                    other => {
+                        return Err(CliError::Usage(format!(
+                            "expected on or off, got {}",
+                            other
+                        )))
+                    }
+                },
+            },
+            _ => {
+                return Err(CliError::Usage(format!(
+                    "unknown command `{}`",
+                    positional.join(" ")
+                )))
+            }
+        };
+
+        // Options meant for another command are mistakes, not no-ops.
+        let unused = [
+            ("--room", room.is_some()),
+            ("--format", format.is_some()),
+            ("--power", power.is_some()),
+            ("--priority", priority.is_some()),
+            ("--address", address.is_some()),
+            ("--history", history.is_some()),
+        ];
+        if let Some((option, _)) = unused.iter().find(|(_, set)| *set) {
+            return Err(CliError::Usage(format!(
+                "{} does not apply to this command",
+                option
+            )));
+        }
+        Ok(Self {
+            config,
+            json,
+            command,
+        })
+    }
+
+    // Run the command and return what it prints. Commands that change the
+    // house save the config only if it is still valid afterwards.
+    pub async fn run(&self) -> Result<String, CliError> {
+        match &self.command {
+            Command::Help => return Ok(USAGE.to_string()),
+            // The gateway has its own config.
+            Command::Dashboard { address } => {
+                dashboard::run(address).await?;
+                return Ok(String::new());
+            }
+            Command::Mode { mode, address } => return self.mode(*mode, address).await,
+            _ => {}
+        }
+        let mut config = HouseConfig::load(&self.config)?;
+        match &self.command {
+            Command::Help | Command::Dashboard { .. } | Command::Mode { .. } => unreachable!(),
+            Command::RoomsList => {
+                let rooms: Vec<RoomSummary> = config.rooms.iter().map(summary).collect();
+                Ok(self.output(&rooms, || {
+                    rooms
+                        .iter()
+                        .map(|room| format!("{}: {}\n", room.name, room.devices.join(", ")))
+                        .collect()
+                }))
+            }
+            Command::RoomsAdd(name) => {
+                if config.rooms.iter().any(|room| &room.name == name) {
+                    return Err(CliError::RoomExists(name.clone()));
+                }
+                config.rooms.push(RoomConfig {
+                    name: name.clone(),
+                    devices: Vec::new(),
+                });
+                self.save(&config)?;
+                let room = summary(config.rooms.last().unwrap());
+                Ok(self.output(&room, || format!("Added room {}\n", name)))
+            }
+            Command::RoomsRemove(name) => {
+                let index = config
+                    .rooms
+                    .iter()
+                    .position(|room| &room.name == name)
+                    .ok_or_else(|| ControlError::RoomNotFound(name.clone()))?;
+                let room = summary(&config.rooms.remove(index));
+                self.save(&config)?;
+                Ok(self.output(&room, || {
+                    format!(
+                        "Removed room {} with {} devices\n",
+                        name,
+                        room.devices.len()
+                    )
+                }))
+            }
+            Command::DevicesList { room } => {
+                let report = HouseReport::new(&config.to_house());
+                if let Some(room) = room {
+                    if !report.rooms.iter().any(|r| &r.name == room) {
+                        return Err(ControlError::RoomNotFound(room.clone()).into());
+                    }
+                }
+                let devices: Vec<&DeviceReport> = report
+                    .devices()
+                    .filter(|device| room.as_ref().is_none_or(|room| &device.room == room))
+                    .collect();
+                Ok(self.output(&devices, || {
+                    devices
+                        .iter()
+                        .map(|d| format!("{} ({}): {}\n", d.name, d.room, d.status))
+                        .collect()
+                }))
+            }
+            Command::DevicesAdd { room, device } => {
+                if config
+                    .rooms
+                    .iter()
+                    .flat_map(|room| &room.devices)
+                    .any(|d| d.name() == device.name())
+                {
+                    return Err(ControlError::AlreadyExists(device.name().to_string()).into());
+                }
+                room_mut(&mut config, room)?.devices.push(device.clone());
+                self.save(&config)?;
+                self.device_output(&config, device.name(), |d| {
+                    format!("Added {} to {}: {}\n", d.name, d.room, d.status)
+                })
+            }
+            Command::DevicesRemove(name) => {
+                let removed = self.device_output(&config, name, |d| {
+                    format!("Removed {} from {}\n", d.name, d.room)
+                })?;
+                take_device(&mut config, name)?;
+                self.save(&config)?;
+                Ok(removed)
+            }
+            Command::DevicesMove { device, room } => {
+                room_mut(&mut config, room)?;
+                let moved = take_device(&mut config, device)?;
+                room_mut(&mut config, room)?.devices.push(moved);
+                self.save(&config)?;
+                self.device_output(&config, device, |d| {
+                    format!("Moved {} to {}\n", d.name, d.room)
+                })
+            }
+            Command::Report { format, history } => {
+                let format = format.unwrap_or(if self.json {
+                    ReportFormat::Json
+                } else {
+                    ReportFormat::Text
+                });
+                let mut report = HouseReport::new(&config.to_house());
+                if let Some(hours) = history {
+                    let storage = config.storage.as_ref().ok_or(CliError::HistoryDisabled)?;
+                    let now = SystemTime::now();
+                    let from = hours
+                        .checked_mul(3600)
+                        .and_then(|seconds| now.checked_sub(Duration::from_secs(seconds)))
+                        .ok_or_else(|| {
+                            CliError::Usage(format!("--history of {} hours is too long", hours))
+                        })?;
+                    report = report.with_history(&Storage::open(&storage.path)?, from, now)?;
+                }
+                let mut text = report.render(format);
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                Ok(text)
+            }
+            Command::Validate => {
+                // `load` already validated the config.
+                let validated = Validated {
+                    valid: true,
+                    rooms: config.rooms.len(),
+                    devices: config.rooms.iter().map(|room| room.devices.len()).sum(),
+                };
+                Ok(self.output(&validated, || {
+                    format!(
+                        "{} is valid: {} rooms, {} devices\n",
+                        self.config.display(),
+                        validated.rooms,
+                        validated.devices
+                    )
+                }))
+            }
+            Command::Control { device, state } => {
+                // The house model checks the device is a socket and the
+                // house's interlocks allow the command.
+                config.to_house().switch_socket(device, state.clone())?;
+                let address = config
+                    .rooms
+                    .iter()
+                    .flat_map(|room| &room.devices)
+                    .find(|d| d.name() == device)
+                    .and_then(|d| d.address().map(str::to_string));
+                if let Some(address) = address {
+                    switch_remote(device, &address, state).await?;
+                }
+                for d in config.rooms.iter_mut().flat_map(|room| &mut room.devices) {
+                    if let DeviceConfig::Socket {
+                        name,
+                        state: stored,
+                        ..
+                    } = d
+                    {
+                        if name == device {
+                            *stored = state.clone();
+                        }
+                    }
+                }
+                self.save(&config)?;
+                self.device_output(&config, device, |d| format!("{}: {}\n", d.name, d.status))
+            }
+        }
+    }
+
+    // Modes are kept by the gateway, so they are read and changed through
+    // its live feed.
+    async fn mode(&self, mode: Option<HouseMode>, address: &str) -> Result<String, CliError> {
+        let Some(mode) = mode else {
+            let mode = match ask_gateway(address, None).await? {
+                FeedMessage::Snapshot { house } => Mode { mode: house.mode },
+                _ => unreachable!(),
+            };
+            return Ok(self.output(&mode, || format!("{}\n", mode.mode)));
+        };
+        let change = match ask_gateway(address, Some(ClientMessage::SetMode { mode })).await? {
+            FeedMessage::ModeSet {
+                from,
+                to,
+                switched_off,
+            } => ModeChange {
+                from,
+                to,
+                switched_off,
+            },
+            FeedMessage::Error { message } => return Err(CliError::ModeRefused(message)),
+            _ => unreachable!(),
+        };
+        Ok(self.output(&change, || {
+            let mut text = format!("Mode: {} -> {}\n", change.from, change.to);
+            if !change.switched_off.is_empty() {
+                text.push_str(&format!(
+                    "Switched off: {}\n",
+                    change.switched_off.join(", ")
+                ));
+            }
+            text
+        }))
+    }
+
+    fn save(&self, config: &HouseConfig) -> Result<(), CliError> {
+        config.validate()?;
+        config.save(&self.config)?;
+        Ok(())
+    }
+
+    fn output<T: Serialize>(&self, value: &T, text: impl FnOnce() -> String) -> String {
+        if self.json {
+            // Only strings, numbers and plain enums; serializing can't fail.
+            let mut json = serde_json::to_string_pretty(value).unwrap_or_default();
+            json.push('\n');
+            json
+        } else {
+            text()
+        }
+    }
+
+    fn device_output(
+        &self,
+        config: &HouseConfig,
+        name: &str,
+        text: impl FnOnce(&DeviceReport) -> String,
+    ) -> Result<String, CliError> {
+        let device = DeviceReport::find(&config.to_house(), name)
+            .ok_or_else(|| ControlError::NotFound(name.to_string()))?;
+        Ok(self.output(&device, || text(&device)))
+    }
+}
+
+fn summary(room: &RoomConfig) -> RoomSummary {
+    RoomSummary {
+        name: room.name.clone(),
+        devices: room.devices.iter().map(|d| d.name().to_string()).collect(),
+    }
+}
+
+fn room_mut<'a>(config: &'a mut HouseConfig, name: &str) -> Result<&'a mut RoomConfig, CliError> {
+    config
+        .rooms
+        .iter_mut()
+        .find(|room| room.name == name)
+        .ok_or_else(|| ControlError::RoomNotFound(name.to_string()).into())
+}
+
+fn take_device(config: &mut HouseConfig, name: &str) -> Result<DeviceConfig, CliError> {
+    for room in &mut config.rooms {
+        if let Some(index) = room.devices.iter().position(|d| d.name() == name) {
+            return Ok(room.devices.remove(index));
+        }
+    }
+    Err(ControlError::NotFound(name.to_string()).into())
+}
+
+// Send the command to the socket's server, which has the final say.
+async fn switch_remote(socket: &str, address: &str, state: &SocketState) -> Result<(), CliError> {
+    let unreachable = |source| CliError::Unreachable {
+        socket: socket.to_string(),
+        address: address.to_string(),
+        source,
+    };
+    let mut client = SmartSocketClient::connect(address)
+        .await
+        .map_err(unreachable)?;
+    match client.switch_checked(state.clone()).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => Err(CliError::Refused {
+            socket: socket.to_string(),
+            response: e.to_string(),
+        }),
+        Err(e) => Err(unreachable(e)),
+    }
+}
+
+// Send `message` to the live feed of the gateway at `address` and return
+// its answer: the snapshot without a message, otherwise `ModeSet` or `Error`.
+async fn ask_gateway(
+    address: &str,
+    message: Option<ClientMessage>,
+) -> Result<FeedMessage, CliError> {
+    let unreachable = |reason: String| CliError::Gateway {
+        address: address.to_string(),
+        reason,
+    };
+    let exchange = async {
+        let (mut feed, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", address))
+            .await
+            .map_err(|e| unreachable(e.to_string()))?;
+        if let Some(message) = &message {
+            let text = serde_json::to_string(message).map_err(|e| unreachable(e.to_string()))?;
+            feed.send(Message::Text(text))
+                .await
+                .map_err(|e| unreachable(e.to_string()))?;
+        }
+        while let Some(received) = feed.next().await {
+            let Message::Text(text) = received.map_err(|e| unreachable(e.to_string()))? else {
+                continue;
+            };
+            match serde_json::from_str(&text) {
+                Ok(snapshot @ FeedMessage::Snapshot { .. }) if message.is_none() => {
+                    return Ok(snapshot)
+                }
+                Ok(answer @ (FeedMessage::ModeSet { .. } | FeedMessage::Error { .. }))
+                    if message.is_some() =>
+                {
+                    return Ok(answer)
+                }
+                _ => {}
+            }
+        }
+        Err(unreachable("the gateway closed the connection".to_string()))
+    };
+    tokio::time::timeout(GATEWAY_TIMEOUT, exchange)
+        .await
+        .unwrap_or_else(|_| {
+            Err(unreachable(format!(
+                "no answer within {:?}",
+                GATEWAY_TIMEOUT
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_api::HttpApi;
+    use crate::modes::{ModeController, ModeSettings};
+    use crate::smart_socket::smart_socket_server::SmartSocketServer;
+    use crate::test_support::{self, shared};
+    use crate::SmartSocket;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    const CONFIG: &str = r#"
+name = "Home"
+
+[[rooms]]
+name = "Kitchen"
+devices = [
+    { type = "socket", name = "Kettle", power_consumption = 2000.0 },
+    { type = "thermometer", name = "Thermo" },
+]
+
+[[rooms]]
+name = "Garage"
+devices = [{ type = "socket", name = "Charger", state = "on", power_consumption = 7000.0 }]
+
+[[interlocks]]
+type = "exclusive"
+name = "Heavy loads"
+sockets = ["Kettle", "Charger"]
+"#;
+
+    // A fresh copy of `CONFIG` for one test.
+    fn config_file(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cli_{}_{}.toml", test, std::process::id()));
+        std::fs::write(&path, CONFIG).unwrap();
+        path
+    }
+
+    async fn run(path: &std::path::Path, args: &str) -> Result<String, CliError> {
+        let mut words = vec!["--config".to_string(), path.display().to_string()];
+        words.extend(args.split_whitespace().map(str::to_string));
+        Cli::parse(words)?.run().await
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::parse(
+            "devices add Kitchen Toaster socket --power 800 --json"
+                .split_whitespace()
+                .map(str::to_string),
+        )
+        .unwrap();
+        assert!(cli.json);
+        assert_eq!(cli.config, PathBuf::from("house.toml"));
+        assert_eq!(
+            cli.command,
+            Command::DevicesAdd {
+                room: "Kitchen".to_string(),
+                device: DeviceConfig::Socket {
+                    name: "Toaster".to_string(),
+                    state: SocketState::Off,
+                    power_consumption: 800.0,
+                    priority: 0,
+                    address: None,
+                },
+            }
+        );
+        let cli = Cli::parse(
+            "dashboard --address 127.0.0.1:3000"
+                .split_whitespace()
+                .map(str::to_string),
+        )
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Dashboard {
+                address: "127.0.0.1:3000".to_string(),
+            }
+        );
+
+        for args in [
+            "",
+            "rooms",
+            "dashboard",
+            "control Kettle up",
+            "validate --power 5",
+            "report --format xml",
+            "report --history soon",
+            "validate --history 2",
+            "mode away",
+            "mode asleep --address 127.0.0.1:3000",
+            "mode home away --address 127.0.0.1:3000",
+        ] {
+            let error = Cli::parse(args.split_whitespace().map(str::to_string)).unwrap_err();
+            assert_eq!(error.exit_code(), 2, "{}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rooms_and_devices() {
+        let path = config_file("rooms");
+        run(&path, "rooms add Attic").await.unwrap();
+        run(
+            &path,
+            "devices add Attic Fan socket --power 40 --priority 2",
+        )
+        .await
+        .unwrap();
+        run(&path, "devices move Thermo Attic").await.unwrap();
+        assert_eq!(
+            run(&path, "rooms list").await.unwrap(),
+            "Kitchen: Kettle\nGarage: Charger\nAttic: Fan, Thermo\n"
+        );
+        let attic: serde_json::Value = serde_json::from_str(
+            &run(&path, "devices list --room Attic --json")
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(attic[0]["name"], "Fan");
+        assert_eq!(attic[0]["power_consumption"], 40.0);
+        assert_eq!(attic[1]["type"], "thermometer");
+
+        assert_eq!(
+            run(&path, "rooms add Attic").await.unwrap_err().exit_code(),
+            5
+        );
+        assert_eq!(
+            run(&path, "devices move Fan Cellar")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            4
+        );
+        // Charger is part of an interlock, so removing it would leave the
+        // config invalid and nothing is saved.
+        assert_eq!(
+            run(&path, "rooms remove Garage")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            3
+        );
+        run(&path, "devices remove Fan").await.unwrap();
+        run(&path, "rooms remove Attic").await.unwrap();
+
+        let config = HouseConfig::load(&path).unwrap();
+        assert_eq!(config.rooms.len(), 2);
+        assert_eq!(
+            run(&path, "validate").await.unwrap(),
+            format!("{} is valid: 2 rooms, 2 devices\n", path.display())
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_report_and_validate() {
+        let path = config_file("report");
+        let csv = run(&path, "report --format csv").await.unwrap();
+        assert!(csv.starts_with("room,device,type,state,power_consumption,temperature,unit\n"));
+        assert!(csv.contains("Garage,Charger,socket,on,7000,,\n"));
+        let json: serde_json::Value =
+            serde_json::from_str(&run(&path, "--json report").await.unwrap()).unwrap();
+        assert_eq!(json["rooms"][0]["devices"][0]["name"], "Kettle");
+
+        std::fs::write(
+            &path,
+            "name = \"Home\"\nrooms = [{ name = \"A\" }, { name = \"A\" }]",
+        )
+        .unwrap();
+        let error = run(&path, "validate").await.unwrap_err();
+        assert_eq!(error.exit_code(), 3);
+        assert_eq!(error.to_string(), "Invalid config: duplicate room name A");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run(&path, "validate").await.unwrap_err().exit_code(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_report_history() {
+        let path = config_file("history");
+        assert_eq!(
+            run(&path, "report --history 1")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            5
+        );
+
+        let db = path.with_extension("db");
+        let storage = Storage::open(&db).unwrap();
+        storage
+            .record_socket(
+                "Kettle",
+                &SocketState::On,
+                2000.0,
+                SystemTime::now() - Duration::from_secs(60),
+            )
+            .unwrap();
+        let mut config = HouseConfig::load(&path).unwrap();
+        config.storage = Some(crate::config::StorageConfig {
+            path: db.display().to_string(),
+            retention: Default::default(),
+        });
+        config.save(&path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&run(&path, "--json report --history 1").await.unwrap()).unwrap();
+        let kettle = &json["rooms"][0]["devices"][0]["history"];
+        assert_eq!(kettle["switches"], 1);
+        assert!(kettle["energy_wh"].as_f64().unwrap() > 30.0);
+        for hours in [u64::MAX, u64::MAX / 3600] {
+            let line = format!("report --history {}", hours);
+            assert_eq!(run(&path, &line).await.unwrap_err().exit_code(), 2);
+        }
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(db).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_control() {
+        let path = config_file("control");
+        assert_eq!(
+            run(&path, "control Kettle on")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            5
+        );
+        assert_eq!(
+            run(&path, "control Thermo on")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            5
+        );
+        assert_eq!(
+            run(&path, "control Toaster on")
+                .await
+                .unwrap_err()
+                .exit_code(),
+            4
+        );
+        run(&path, "control Charger off").await.unwrap();
+        assert_eq!(
+            run(&path, "control Kettle on").await.unwrap(),
+            "Kettle: socket On, 2000.0 W\n"
+        );
+
+        // A socket with an address is switched on its server too.
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Fan".to_string(),
+                state: SocketState::Off,
+                power_consumption: 0.0,
+            },
+        );
+        let handle = server.start().await.unwrap();
+        let address = handle.local_addr().unwrap();
+        run(
+            &path,
+            &format!(
+                "devices add Garage Fan socket --power 40 --address {}",
+                address
+            ),
+        )
+        .await
+        .unwrap();
+        run(&path, "control Fan on").await.unwrap();
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        handle.shutdown().await.unwrap();
+        let error = run(&path, "control Fan off").await.unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+
+        let config = HouseConfig::load(&path).unwrap();
+        let states: Vec<_> = config
+            .rooms
+            .iter()
+            .flat_map(|room| &room.devices)
+            .filter_map(|d| match d {
+                DeviceConfig::Socket { name, state, .. } => Some((name.as_str(), state.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("Kettle", SocketState::On),
+                ("Charger", SocketState::Off),
+                ("Fan", SocketState::On),
+            ]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mode() {
+        let house = shared(test_support::house());
+        house
+            .lock()
+            .await
+            .switch_socket("Kettle", SocketState::On)
+            .unwrap();
+        let modes = Arc::new(Mutex::new(ModeController::new(ModeSettings::default())));
+        let handle = HttpApi::new("127.0.0.1:0", house.clone())
+            .with_modes(modes)
+            .start()
+            .await
+            .unwrap();
+        let address = handle.local_addr().unwrap();
+        // The gateway has its own config.
+        let path = std::path::Path::new("no_such_house.toml");
+
+        assert_eq!(
+            run(path, &format!("mode --address {}", address))
+                .await
+                .unwrap(),
+            "home\n"
+        );
+        assert_eq!(
+            run(path, &format!("mode away --address {}", address))
+                .await
+                .unwrap(),
+            "Mode: home -> away\nSwitched off: Kettle\n"
+        );
+        assert_eq!(house.lock().await.mode, HouseMode::Away);
+        let refused = run(path, &format!("mode night --address {}", address))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.exit_code(), 5);
+        assert_eq!(
+            refused.to_string(),
+            "The gateway refused the mode change: Cannot switch house mode from away to night"
+        );
+        assert_eq!(
+            run(path, &format!("mode --json --address {}", address))
+                .await
+                .unwrap(),
+            "{\n  \"mode\": \"away\"\n}\n"
+        );
+        handle.shutdown().await.unwrap();
+
+        let error = run(path, &format!("mode home --address {}", address))
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 1);
+    }
+}
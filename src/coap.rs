@@ -0,0 +1,746 @@
+pub mod client;
+pub mod message;
+
+use crate::device_info::devices::ThermometerState;
+use crate::device_info::temperature::{Temperature, TemperatureUnit};
+use crate::events::{EventKind, HouseEvent};
+use crate::gateway::SharedHouse;
+use crate::readings::{Reading, ReadingFeed, ReadingStream};
+use crate::service::ServiceHandle;
+use message::{Code, Message, MessageType, CONTENT_FORMAT, JSON, LINK_FORMAT, OBSERVE, TEXT_PLAIN};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio_stream::StreamExt;
+
+// Responses to recent confirmable requests, resent when a request is
+// retransmitted instead of handling it twice.
+const RECENT_RESPONSES: usize = 64;
+
+// Registrations beyond this are answered without the Observe option, which
+// tells the client it wasn't added (RFC 7641 §4.1).
+const MAX_OBSERVERS: usize = 64;
+
+// Unacknowledged confirmable notifications are retransmitted this many times,
+// doubling the timeout every time, before the observer is dropped.
+const MAX_RETRANSMIT: u32 = 4;
+
+// Define an error type for CoAP exchanges.
+#[derive(Error, Debug)]
+pub enum CoapError {
+    #[error("CoAP transport failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed CoAP message: {0}")]
+    Format(String),
+    #[error("No response from the CoAP server")]
+    Timeout,
+    #[error("The CoAP server reset the request")]
+    Reset,
+}
+
+// A reading posted as JSON; the unit defaults to celsius.
+#[derive(Deserialize)]
+struct JsonReading {
+    temperature: f32,
+    #[serde(default)]
+    unit: TemperatureUnit,
+}
+
+// CoAP server over UDP for sensors that can't speak the thermometer
+// datagram format. Posted readings go to the same `ReadingFeed` the
+// `UdpThermometerListener`s publish to, so a gateway applies them to the
+// house, and every reading on the feed is sent to the observers of its
+// thermometer. Resources:
+//
+//   GET  /.well-known/core          thermometers in link format
+//   POST /thermometers/<name>       a reading, as text/plain celsius or as
+//                                   JSON `{"temperature": 70, "unit": "fahrenheit"}`
+//   GET  /thermometers/<name>       latest temperature as JSON in the house's
+//                                   unit; with Observe 0 the client is notified
+//                                   of every new reading, Observe 1 stops that
+//
+// `<name>` is the thermometer's name as one path segment. Notifications are
+// non-confirmable, except that each observer gets a confirmable one at least
+// once per confirm interval; observers that don't acknowledge it are dropped
+// (RFC 7641 §4.5).
+pub struct CoapServer {
+    address: String,
+    house: SharedHouse,
+    feed: ReadingFeed,
+    confirm_interval: Duration,
+    ack_timeout: Duration,
+}
+
+impl CoapServer {
+    pub fn new(address: &str, house: SharedHouse) -> Self {
+        Self {
+            address: address.to_string(),
+            house,
+            feed: ReadingFeed::default(),
+            confirm_interval: Duration::from_secs(24 * 3600),
+            ack_timeout: Duration::from_secs(2),
+        }
+    }
+
+    // How long an observer may go without a confirmable notification.
+    pub fn with_confirm_interval(mut self, interval: Duration) -> Self {
+        self.confirm_interval = interval;
+        self
+    }
+
+    // Time to wait for the acknowledgement of a confirmable notification
+    // before the first retransmission.
+    pub fn with_ack_timeout(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    // Publish readings to a shared feed instead of a private one.
+    pub fn with_feed(mut self, feed: ReadingFeed) -> Self {
+        self.feed = feed;
+        self
+    }
+
+    // Stream of every reading posted from now on.
+    pub fn readings(&self) -> ReadingStream {
+        self.feed.subscribe()
+    }
+
+    pub async fn start(&self) -> std::io::Result<ServiceHandle> {
+        let socket = UdpSocket::bind(&self.address).await?;
+        let local_addr = socket.local_addr()?;
+        let mut readings = self.feed.subscribe();
+        let mut server = Server {
+            socket,
+            house: self.house.clone(),
+            feed: self.feed.clone(),
+            observers: Vec::new(),
+            recent: VecDeque::new(),
+            next_message_id: rand::random(),
+            sequence: 0,
+            confirm_interval: self.confirm_interval,
+            ack_timeout: self.ack_timeout,
+        };
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                let mut buf = [0u8; 1152];
+                let period = (server.ack_timeout / 2).max(Duration::from_millis(1));
+                let mut retransmissions = tokio::time::interval(period);
+                loop {
+                    tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        _ = retransmissions.tick() => server.retransmit(Instant::now()).await,
+                        received = server.socket.recv_from(&mut buf) => match received {
+                            Ok((length, peer)) => server.handle(&buf[..length], peer).await,
+                            // E.g. an ICMP error for an earlier notification.
+                            Err(e) => server.report(format!("CoAP receive failed: {}", e)).await,
+                        },
+                        reading = readings.next() => match reading {
+                            Some(Ok(reading)) => server.notify(&reading).await,
+                            // Observers only need the latest reading.
+                            Some(Err(_)) => {}
+                            None => return Ok(()),
+                        },
+                    }
+                }
+            },
+        ))
+    }
+}
+
+struct Observer {
+    peer: SocketAddr,
+    token: Vec<u8>,
+    thermometer: String,
+    // Of the latest notification, which a reset refers to.
+    last_message_id: u16,
+    // When the observer last acknowledged a notification, or registered.
+    confirmed_at: Instant,
+    unacknowledged: Option<Unacknowledged>,
+}
+
+// Confirmable notification waiting for its acknowledgement.
+struct Unacknowledged {
+    message_id: u16,
+    message: Vec<u8>,
+    retransmissions: u32,
+    next_attempt: Instant,
+}
+
+struct Server {
+    socket: UdpSocket,
+    house: SharedHouse,
+    feed: ReadingFeed,
+    observers: Vec<Observer>,
+    recent: VecDeque<(SocketAddr, u16, Vec<u8>)>,
+    next_message_id: u16,
+    // Observe value of the next response, 24 bits.
+    sequence: u32,
+    confirm_interval: Duration,
+    ack_timeout: Duration,
+}
+
+impl Server {
+    fn message_id(&mut self) -> u16 {
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        self.next_message_id
+    }
+
+    fn observe_sequence(&mut self) -> u32 {
+        self.sequence = (self.sequence + 1) & 0xFF_FFFF;
+        self.sequence
+    }
+
+    // Problems with peers are reported as errors on the house's bus.
+    async fn report(&self, message: String) {
+        self.house
+            .lock()
+            .await
+            .events()
+            .publish(HouseEvent::new(None, EventKind::Error { message }));
+    }
+
+    async fn send(&self, message: &[u8], peer: SocketAddr) {
+        if let Err(e) = self.socket.send_to(message, peer).await {
+            self.report(format!("failed to send CoAP message to {}: {}", peer, e))
+                .await;
+        }
+    }
+
+    async fn handle(&mut self, datagram: &[u8], peer: SocketAddr) {
+        let request = match Message::decode(datagram) {
+            Ok(request) => request,
+            Err(e) => {
+                self.report(format!("ignoring CoAP datagram from {}: {}", peer, e))
+                    .await;
+                return;
+            }
+        };
+        match request.message_type {
+            MessageType::Reset => {
+                self.observers.retain(|observer| {
+                    observer.peer != peer || observer.last_message_id != request.message_id
+                });
+                return;
+            }
+            MessageType::Acknowledgement => {
+                let acknowledged = self.observers.iter_mut().find(|observer| {
+                    observer.peer == peer
+                        && matches!(&observer.unacknowledged, Some(u) if u.message_id == request.message_id)
+                });
+                if let Some(observer) = acknowledged {
+                    observer.unacknowledged = None;
+                    observer.confirmed_at = Instant::now();
+                }
+                return;
+            }
+            _ => {}
+        }
+        if !request.code.is_request() {
+            // An empty confirmable message is a ping, answered with a reset.
+            if request.message_type == MessageType::Confirmable && request.code == Code::EMPTY {
+                let reset = Message::new(MessageType::Reset, Code::EMPTY, request.message_id);
+                self.send(&reset.encode(), peer).await;
+            }
+            return;
+        }
+
+        let confirmable = request.message_type == MessageType::Confirmable;
+        if confirmable {
+            let cached = self
+                .recent
+                .iter()
+                .find(|(p, id, _)| *p == peer && *id == request.message_id)
+                .map(|(_, _, response)| response.clone());
+            if let Some(response) = cached {
+                self.send(&response, peer).await;
+                return;
+            }
+        }
+        let response = self.respond(&request, peer).await.encode();
+        self.send(&response, peer).await;
+        if confirmable {
+            if self.recent.len() == RECENT_RESPONSES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((peer, request.message_id, response));
+        }
+    }
+
+    async fn respond(&mut self, request: &Message, peer: SocketAddr) -> Message {
+        let path = request.path();
+        let segments: Vec<&str> = path.iter().map(String::as_str).collect();
+        let message_id = self.message_id();
+        match (segments.as_slice(), request.code) {
+            ([".well-known", "core"], Code::GET) => {
+                let house = self.house.lock().await;
+                let links: Vec<String> = house
+                    .rooms()
+                    .iter()
+                    .flat_map(|room| &room.devices)
+                    .filter(|device| house.thermometer(device.name()).is_ok())
+                    .map(|device| {
+                        format!(
+                            "</thermometers/{}>;rt=\"temperature\";obs",
+                            percent_encode(device.name())
+                        )
+                    })
+                    .collect();
+                request
+                    .reply(Code::CONTENT, message_id)
+                    .with_uint_option(CONTENT_FORMAT, LINK_FORMAT)
+                    .with_payload(links.join(",").as_bytes())
+            }
+            (["thermometers", name], Code::POST) => {
+                let (code, diagnostic) = self.post_reading(request, name).await;
+                request
+                    .reply(code, message_id)
+                    .with_payload(diagnostic.as_bytes())
+            }
+            (["thermometers", name], Code::GET) => {
+                let house = self.house.lock().await;
+                let state = match house.thermometer(name) {
+                    Ok(thermometer) => thermometer.state.clone(),
+                    Err(e) => {
+                        return request
+                            .reply(Code::NOT_FOUND, message_id)
+                            .with_payload(e.to_string().as_bytes());
+                    }
+                };
+                let unit = house.temperature_unit;
+                drop(house);
+                let temperature = match state {
+                    ThermometerState::Temperature(temperature) => Some(temperature),
+                    ThermometerState::Off => None,
+                };
+                let mut response = request
+                    .reply(Code::CONTENT, message_id)
+                    .with_uint_option(CONTENT_FORMAT, JSON)
+                    .with_payload(&representation(temperature, unit));
+                match request.uint_option(OBSERVE) {
+                    Some(0) => {
+                        // Registering again with the same token replaces the observer.
+                        self.observers
+                            .retain(|o| o.peer != peer || o.token != request.token);
+                        if self.observers.len() < MAX_OBSERVERS {
+                            self.observers.push(Observer {
+                                peer,
+                                token: request.token.clone(),
+                                thermometer: name.to_string(),
+                                last_message_id: response.message_id,
+                                confirmed_at: Instant::now(),
+                                unacknowledged: None,
+                            });
+                            let sequence = self.observe_sequence();
+                            response = response.with_uint_option(OBSERVE, sequence);
+                        }
+                    }
+                    Some(1) => self
+                        .observers
+                        .retain(|o| o.peer != peer || o.token != request.token),
+                    _ => {}
+                }
+                response
+            }
+            ([".well-known", "core"] | ["thermometers", _], _) => {
+                request.reply(Code::METHOD_NOT_ALLOWED, message_id)
+            }
+            _ => request.reply(Code::NOT_FOUND, message_id),
+        }
+    }
+
+    // Publish a posted reading. Returns the response code and a diagnostic
+    // payload for errors.
+    async fn post_reading(&self, request: &Message, name: &str) -> (Code, String) {
+        if let Err(e) = self.house.lock().await.thermometer(name) {
+            return (Code::NOT_FOUND, e.to_string());
+        }
+        let payload = String::from_utf8_lossy(&request.payload);
+        let temperature = match request.uint_option(CONTENT_FORMAT).unwrap_or(TEXT_PLAIN) {
+            TEXT_PLAIN => payload.trim().parse().map(Temperature::celsius).ok(),
+            JSON => serde_json::from_str::<JsonReading>(&payload)
+                .ok()
+                .map(|reading| Temperature::new(reading.temperature, reading.unit)),
+            format => {
+                return (
+                    Code::UNSUPPORTED_CONTENT_FORMAT,
+                    format!("content format {} is not supported", format),
+                )
+            }
+        };
+        match temperature.filter(|t| t.value().is_finite()) {
+            Some(temperature) => {
+                self.feed.publish(Reading {
+                    thermometer: name.to_string(),
+                    temperature,
+                    received_at: SystemTime::now(),
+                });
+                (Code::CHANGED, String::new())
+            }
+            None => (Code::BAD_REQUEST, format!("invalid reading `{}`", payload)),
+        }
+    }
+
+    // Send a reading to the observers of its thermometer. A notification
+    // replacing one that is still unacknowledged takes over its
+    // retransmission schedule (RFC 7641 §4.5.2).
+    async fn notify(&mut self, reading: &Reading) {
+        if !self
+            .observers
+            .iter()
+            .any(|o| o.thermometer == reading.thermometer)
+        {
+            return;
+        }
+        let unit = self.house.lock().await.temperature_unit;
+        let payload = representation(Some(reading.temperature), unit);
+        let now = Instant::now();
+        for index in 0..self.observers.len() {
+            if self.observers[index].thermometer != reading.thermometer {
+                continue;
+            }
+            let message_id = self.message_id();
+            let sequence = self.observe_sequence();
+            let ack_timeout = self.ack_timeout;
+            let observer = &mut self.observers[index];
+            observer.last_message_id = message_id;
+            let confirmable = observer.unacknowledged.is_some()
+                || now.duration_since(observer.confirmed_at) >= self.confirm_interval;
+            let message_type = if confirmable {
+                MessageType::Confirmable
+            } else {
+                MessageType::NonConfirmable
+            };
+            let notification = Message::new(message_type, Code::CONTENT, message_id)
+                .with_token(&observer.token)
+                .with_uint_option(OBSERVE, sequence)
+                .with_uint_option(CONTENT_FORMAT, JSON)
+                .with_payload(&payload)
+                .encode();
+            if confirmable {
+                let (retransmissions, next_attempt) = match &observer.unacknowledged {
+                    Some(previous) => (previous.retransmissions, previous.next_attempt),
+                    None => (0, now + ack_timeout),
+                };
+                observer.unacknowledged = Some(Unacknowledged {
+                    message_id,
+                    message: notification.clone(),
+                    retransmissions,
+                    next_attempt,
+                });
+            }
+            let peer = observer.peer;
+            self.send(&notification, peer).await;
+        }
+    }
+
+    // Resend confirmable notifications that are due, and drop the observers
+    // that never acknowledged theirs.
+    async fn retransmit(&mut self, now: Instant) {
+        let ack_timeout = self.ack_timeout;
+        self.observers.retain(|observer| {
+            !matches!(
+                &observer.unacknowledged,
+                Some(u) if u.retransmissions == MAX_RETRANSMIT && u.next_attempt <= now
+            )
+        });
+        let mut due = Vec::new();
+        for observer in &mut self.observers {
+            if let Some(unacknowledged) = &mut observer.unacknowledged {
+                if unacknowledged.next_attempt <= now {
+                    unacknowledged.retransmissions += 1;
+                    unacknowledged.next_attempt =
+                        now + ack_timeout * 2u32.pow(unacknowledged.retransmissions);
+                    due.push((unacknowledged.message.clone(), observer.peer));
+                }
+            }
+        }
+        for (message, peer) in due {
+            self.send(&message, peer).await;
+        }
+    }
+}
+
+fn representation(temperature: Option<Temperature>, unit: TemperatureUnit) -> Vec<u8> {
+    serde_json::json!({
+        "temperature": temperature.map(|t| t.to_unit(unit).value()),
+        "unit": unit,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+// Escape a path segment for a link-format URI.
+fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::devices::SocketState;
+    use crate::smart_house::SmartHouse;
+    use crate::test_support::{room, shared, socket, thermometer};
+    use client::CoapClient;
+
+    fn house() -> SharedHouse {
+        shared(SmartHouse::new(
+            "Home",
+            vec![room(
+                "Living Room",
+                vec![
+                    thermometer("Window Thermo", ThermometerState::Off),
+                    socket("Lamp", SocketState::Off, 60.0),
+                ],
+            )],
+        ))
+    }
+
+    fn json(message: &Message) -> serde_json::Value {
+        serde_json::from_slice(&message.payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post_and_observe() {
+        let server = CoapServer::new("127.0.0.1:0", house());
+        let mut readings = server.readings();
+        let handle = server.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+        let mut sensor = CoapClient::connect(addr).await.unwrap();
+        let mut observer = CoapClient::connect(addr).await.unwrap();
+
+        let registered = observer
+            .observe("thermometers/Window Thermo")
+            .await
+            .unwrap();
+        assert_eq!(registered.code, Code::CONTENT);
+        assert_eq!(registered.uint_option(CONTENT_FORMAT), Some(JSON));
+        assert_eq!(json(&registered)["temperature"], serde_json::Value::Null);
+        let first = registered.uint_option(OBSERVE).unwrap();
+
+        let changed = sensor
+            .post("thermometers/Window Thermo", TEXT_PLAIN, b" 21.5\n")
+            .await
+            .unwrap();
+        assert_eq!(changed.code, Code::CHANGED);
+        let reading = readings.next().await.unwrap().unwrap();
+        assert_eq!(reading.thermometer, "Window Thermo");
+        assert_eq!(reading.temperature, Temperature::celsius(21.5));
+        let notification = observer.notification().await.unwrap();
+        assert_eq!(notification.token, registered.token);
+        assert!(notification.uint_option(OBSERVE).unwrap() > first);
+        assert_eq!(json(&notification)["temperature"], 21.5);
+
+        // Readings are converted to the house's unit.
+        sensor
+            .post(
+                "thermometers/Window Thermo",
+                JSON,
+                br#"{"temperature": 212, "unit": "fahrenheit"}"#,
+            )
+            .await
+            .unwrap();
+        let notification = observer.notification().await.unwrap();
+        assert_eq!(json(&notification)["temperature"], 100.0);
+        assert_eq!(json(&notification)["unit"], "celsius");
+
+        // Nothing is sent once the observation is cancelled.
+        observer
+            .cancel("thermometers/Window Thermo", &registered.token)
+            .await
+            .unwrap();
+        sensor
+            .post("thermometers/Window Thermo", TEXT_PLAIN, b"19")
+            .await
+            .unwrap();
+        readings.next().await.unwrap().unwrap();
+        readings.next().await.unwrap().unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), observer.notification())
+                .await
+                .is_err()
+        );
+
+        // A reset also ends an observation.
+        observer
+            .observe("thermometers/Window Thermo")
+            .await
+            .unwrap();
+        sensor
+            .post("thermometers/Window Thermo", TEXT_PLAIN, b"20")
+            .await
+            .unwrap();
+        let notification = observer.notification().await.unwrap();
+        observer.reset(&notification).await.unwrap();
+        // The sensor's request is handled after the reset.
+        sensor
+            .post("thermometers/Window Thermo", TEXT_PLAIN, b"22")
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), observer.notification())
+                .await
+                .is_err()
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_errors_and_discovery() {
+        let server = CoapServer::new("127.0.0.1:0", house());
+        let mut readings = server.readings();
+        let handle = server.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+        let mut client = CoapClient::connect(addr).await.unwrap();
+
+        let core = client.get(".well-known/core").await.unwrap();
+        assert_eq!(core.uint_option(CONTENT_FORMAT), Some(LINK_FORMAT));
+        assert_eq!(
+            core.payload,
+            b"</thermometers/Window%20Thermo>;rt=\"temperature\";obs"
+        );
+
+        let cases: [(&str, u32, &[u8], Code); 4] = [
+            ("thermometers/Lamp", TEXT_PLAIN, b"20", Code::NOT_FOUND),
+            (
+                "thermometers/Window Thermo",
+                TEXT_PLAIN,
+                b"warm",
+                Code::BAD_REQUEST,
+            ),
+            (
+                "thermometers/Window Thermo",
+                JSON,
+                b"{\"value\": 1}",
+                Code::BAD_REQUEST,
+            ),
+            (
+                "thermometers/Window Thermo",
+                LINK_FORMAT,
+                b"20",
+                Code::UNSUPPORTED_CONTENT_FORMAT,
+            ),
+        ];
+        for (path, format, payload, code) in cases {
+            let response = client.post(path, format, payload).await.unwrap();
+            assert_eq!(response.code, code, "{}", path);
+        }
+        assert_eq!(client.get("sensors").await.unwrap().code, Code::NOT_FOUND);
+        assert_eq!(
+            client
+                .post(".well-known/core", TEXT_PLAIN, b"")
+                .await
+                .unwrap()
+                .code,
+            Code::METHOD_NOT_ALLOWED
+        );
+
+        // A retransmitted request gets the same response and is applied once.
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let request = Message::new(MessageType::Confirmable, Code::POST, 7)
+            .with_token(&[1])
+            .with_path("thermometers/Window Thermo")
+            .with_payload(b"23")
+            .encode();
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            socket.send_to(&request, addr).await.unwrap();
+            let mut buf = [0u8; 64];
+            let length = socket.recv(&mut buf).await.unwrap();
+            responses.push(Message::decode(&buf[..length]).unwrap());
+        }
+        assert_eq!(responses[0], responses[1]);
+        assert_eq!(responses[0].message_type, MessageType::Acknowledgement);
+        assert_eq!(responses[0].code, Code::CHANGED);
+        assert_eq!(
+            readings.next().await.unwrap().unwrap().temperature,
+            Temperature::celsius(23.0)
+        );
+        client
+            .post("thermometers/Window Thermo", TEXT_PLAIN, b"24")
+            .await
+            .unwrap();
+        assert_eq!(
+            readings.next().await.unwrap().unwrap().temperature,
+            Temperature::celsius(24.0)
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_observers_are_capped_and_confirmed() {
+        let server = CoapServer::new("127.0.0.1:0", house())
+            .with_confirm_interval(Duration::ZERO)
+            .with_ack_timeout(Duration::from_millis(10));
+        let handle = server.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+        let mut sensor = CoapClient::connect(addr).await.unwrap();
+        let path = "thermometers/Window Thermo";
+
+        // An observer that never acknowledges: its confirmable notification
+        // is retransmitted, then it is dropped.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        silent.connect(addr).await.unwrap();
+        let register = Message::new(MessageType::Confirmable, Code::GET, 1)
+            .with_token(&[9])
+            .with_path(path)
+            .with_uint_option(OBSERVE, 0);
+        silent.send(&register.encode()).await.unwrap();
+        let mut buf = [0u8; 256];
+        let length = silent.recv(&mut buf).await.unwrap();
+        assert!(Message::decode(&buf[..length])
+            .unwrap()
+            .option(OBSERVE)
+            .is_some());
+
+        // A well-behaved observer acknowledges and stays registered.
+        let mut observer = CoapClient::connect(addr).await.unwrap();
+        observer.observe(path).await.unwrap();
+        sensor.post(path, TEXT_PLAIN, b"20").await.unwrap();
+        let notification = observer.notification().await.unwrap();
+        assert_eq!(notification.message_type, MessageType::Confirmable);
+
+        let mut copies = Vec::new();
+        while let Ok(received) =
+            tokio::time::timeout(Duration::from_millis(500), silent.recv(&mut buf)).await
+        {
+            copies.push(Message::decode(&buf[..received.unwrap()]).unwrap());
+        }
+        assert_eq!(copies.len() as u32, 1 + MAX_RETRANSMIT);
+        assert!(copies.iter().all(|copy| copy == &copies[0]));
+        assert_eq!(copies[0].message_type, MessageType::Confirmable);
+
+        sensor.post(path, TEXT_PLAIN, b"21").await.unwrap();
+        assert_eq!(
+            json(&observer.notification().await.unwrap())["temperature"],
+            21.0
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), silent.recv(&mut buf))
+                .await
+                .is_err()
+        );
+
+        // Registrations beyond the limit are answered without Observe.
+        for _ in 1..MAX_OBSERVERS {
+            let registered = observer.observe(path).await.unwrap();
+            assert!(registered.option(OBSERVE).is_some());
+        }
+        let refused = observer.observe(path).await.unwrap();
+        assert_eq!(refused.code, Code::CONTENT);
+        assert!(refused.option(OBSERVE).is_none());
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use super::message::{Code, Message, MessageType, CONTENT_FORMAT, OBSERVE};
+use super::CoapError;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+// Wait for an acknowledgement before retransmitting, doubled every time.
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRANSMIT: u32 = 4;
+
+// CoAP client sending confirmable requests to one server. Notifications of
+// observed resources that arrive while waiting for a response are kept for
+// `notification`.
+pub struct CoapClient {
+    socket: UdpSocket,
+    next_message_id: u16,
+    next_token: u32,
+    pending: VecDeque<Message>,
+}
+
+impl CoapClient {
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self, CoapError> {
+        let server = tokio::net::lookup_host(address)
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::other("address resolved to nothing"))?;
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+        Ok(Self {
+            socket,
+            next_message_id: rand::random(),
+            next_token: rand::random(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    // POST `payload` with the given content format to `path`.
+    pub async fn post(
+        &mut self,
+        path: &str,
+        content_format: u32,
+        payload: &[u8],
+    ) -> Result<Message, CoapError> {
+        let request = self
+            .request(Code::POST, path)
+            .with_uint_option(CONTENT_FORMAT, content_format)
+            .with_payload(payload);
+        self.send(request).await
+    }
+
+    pub async fn get(&mut self, path: &str) -> Result<Message, CoapError> {
+        let request = self.request(Code::GET, path);
+        self.send(request).await
+    }
+
+    // Register as an observer of `path`. Notifications carry the token of
+    // the returned response.
+    pub async fn observe(&mut self, path: &str) -> Result<Message, CoapError> {
+        let request = self.request(Code::GET, path).with_uint_option(OBSERVE, 0);
+        self.send(request).await
+    }
+
+    // Deregister the observation made with `token`.
+    pub async fn cancel(&mut self, path: &str, token: &[u8]) -> Result<Message, CoapError> {
+        let request = self
+            .request(Code::GET, path)
+            .with_token(token)
+            .with_uint_option(OBSERVE, 1);
+        self.pending.retain(|message| message.token != token);
+        self.send(request).await
+    }
+
+    // Next notification of an observed resource. Confirmable ones are
+    // acknowledged.
+    pub async fn notification(&mut self) -> Result<Message, CoapError> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(message);
+            }
+            let message = self.receive().await?;
+            if message.option(OBSERVE).is_some() {
+                self.pending.push_back(message);
+            }
+        }
+    }
+
+    // Reject a notification, which makes the server forget the observer.
+    pub async fn reset(&mut self, notification: &Message) -> Result<(), CoapError> {
+        let reset = Message::new(MessageType::Reset, Code::EMPTY, notification.message_id);
+        self.socket.send(&reset.encode()).await?;
+        Ok(())
+    }
+
+    fn request(&mut self, code: Code, path: &str) -> Message {
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        self.next_token = self.next_token.wrapping_add(1);
+        Message::new(MessageType::Confirmable, code, self.next_message_id)
+            .with_token(&self.next_token.to_be_bytes())
+            .with_path(path)
+    }
+
+    // Send a confirmable request until it is acknowledged and return the
+    // piggybacked response.
+    async fn send(&mut self, request: Message) -> Result<Message, CoapError> {
+        let bytes = request.encode();
+        let mut timeout = ACK_TIMEOUT;
+        for _ in 0..=MAX_RETRANSMIT {
+            self.socket.send(&bytes).await?;
+            let deadline = tokio::time::Instant::now() + timeout;
+            loop {
+                let message = match tokio::time::timeout_at(deadline, self.receive()).await {
+                    Ok(message) => message?,
+                    Err(_) => break,
+                };
+                let answers_request = message.message_id == request.message_id
+                    && matches!(
+                        message.message_type,
+                        MessageType::Acknowledgement | MessageType::Reset
+                    );
+                if answers_request {
+                    if message.message_type == MessageType::Reset {
+                        return Err(CoapError::Reset);
+                    }
+                    return Ok(message);
+                }
+                if message.option(OBSERVE).is_some() {
+                    self.pending.push_back(message);
+                }
+            }
+            timeout *= 2;
+        }
+        Err(CoapError::Timeout)
+    }
+
+    async fn receive(&mut self) -> Result<Message, CoapError> {
+        let mut buf = [0u8; 1152];
+        loop {
+            let length = self.socket.recv(&mut buf).await?;
+            let Ok(message) = Message::decode(&buf[..length]) else {
+                continue;
+            };
+            if message.message_type == MessageType::Confirmable {
+                let ack = Message::new(
+                    MessageType::Acknowledgement,
+                    Code::EMPTY,
+                    message.message_id,
+                );
+                self.socket.send(&ack.encode()).await?;
+            }
+            return Ok(message);
+        }
+    }
+}
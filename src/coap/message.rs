@@ -0,0 +1,308 @@
+use super::CoapError;
+use std::fmt;
+
+// Option numbers used by the server.
+pub const OBSERVE: u16 = 6;
+pub const URI_PATH: u16 = 11;
+pub const CONTENT_FORMAT: u16 = 12;
+
+// Content formats used by the server.
+pub const TEXT_PLAIN: u32 = 0;
+pub const LINK_FORMAT: u32 = 40;
+pub const JSON: u32 = 50;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+// Request method or response code, `class.detail` packed into one byte.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Code(pub u8);
+
+impl Code {
+    pub const EMPTY: Code = Code(0);
+    pub const GET: Code = Code(1);
+    pub const POST: Code = Code(2);
+    pub const CHANGED: Code = Code::response(2, 4);
+    pub const CONTENT: Code = Code::response(2, 5);
+    pub const BAD_REQUEST: Code = Code::response(4, 0);
+    pub const NOT_FOUND: Code = Code::response(4, 4);
+    pub const METHOD_NOT_ALLOWED: Code = Code::response(4, 5);
+    pub const UNSUPPORTED_CONTENT_FORMAT: Code = Code::response(4, 15);
+
+    pub const fn response(class: u8, detail: u8) -> Code {
+        Code(class << 5 | detail)
+    }
+
+    pub fn class(&self) -> u8 {
+        self.0 >> 5
+    }
+
+    pub fn detail(&self) -> u8 {
+        self.0 & 0x1F
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.class() == 0 && *self != Code::EMPTY
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
+}
+
+// A CoAP message (RFC 7252). Options are kept sorted by number.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub code: Code,
+    pub message_id: u16,
+    pub token: Vec<u8>,
+    pub options: Vec<(u16, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(message_type: MessageType, code: Code, message_id: u16) -> Self {
+        Self {
+            message_type,
+            code,
+            message_id,
+            token: Vec::new(),
+            options: Vec::new(),
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn with_token(mut self, token: &[u8]) -> Self {
+        self.token = token.to_vec();
+        self
+    }
+
+    // Add an option after those with the same or lower number.
+    pub fn with_option(mut self, number: u16, value: Vec<u8>) -> Self {
+        let index = self.options.partition_point(|(n, _)| *n <= number);
+        self.options.insert(index, (number, value));
+        self
+    }
+
+    pub fn with_uint_option(self, number: u16, value: u32) -> Self {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        self.with_option(number, bytes[skip..].to_vec())
+    }
+
+    // One Uri-Path option per `/`-separated segment.
+    pub fn with_path(self, path: &str) -> Self {
+        path.split('/')
+            .filter(|segment| !segment.is_empty())
+            .fold(self, |message, segment| {
+                message.with_option(URI_PATH, segment.as_bytes().to_vec())
+            })
+    }
+
+    pub fn with_payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    pub fn option(&self, number: u16) -> Option<&[u8]> {
+        self.options
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        let value = self.option(number)?;
+        (value.len() <= 4).then(|| value.iter().fold(0, |n, b| n << 8 | *b as u32))
+    }
+
+    // Uri-Path segments, in order.
+    pub fn path(&self) -> Vec<String> {
+        self.options
+            .iter()
+            .filter(|(n, _)| *n == URI_PATH)
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+            .collect()
+    }
+
+    // Response to this request: piggybacked on the acknowledgement of a
+    // confirmable request, a separate non-confirmable message otherwise.
+    pub fn reply(&self, code: Code, message_id: u16) -> Message {
+        let (message_type, message_id) = match self.message_type {
+            MessageType::Confirmable => (MessageType::Acknowledgement, self.message_id),
+            _ => (MessageType::NonConfirmable, message_id),
+        };
+        Message::new(message_type, code, message_id).with_token(&self.token)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let message_type = match self.message_type {
+            MessageType::Confirmable => 0,
+            MessageType::NonConfirmable => 1,
+            MessageType::Acknowledgement => 2,
+            MessageType::Reset => 3,
+        };
+        let mut bytes = vec![
+            VERSION << 6 | message_type << 4 | self.token.len() as u8,
+            self.code.0,
+        ];
+        bytes.extend(self.message_id.to_be_bytes());
+        bytes.extend(&self.token);
+        let mut previous = 0;
+        for (number, value) in &self.options {
+            let (delta, delta_ext) = option_nibble(number - previous);
+            let (length, length_ext) = option_nibble(value.len() as u16);
+            bytes.push(delta << 4 | length);
+            bytes.extend(delta_ext);
+            bytes.extend(length_ext);
+            bytes.extend(value);
+            previous = *number;
+        }
+        if !self.payload.is_empty() {
+            bytes.push(PAYLOAD_MARKER);
+            bytes.extend(&self.payload);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, CoapError> {
+        if bytes.len() < 4 {
+            return Err(CoapError::Format("message shorter than its header".into()));
+        }
+        if bytes[0] >> 6 != VERSION {
+            return Err(CoapError::Format(format!(
+                "unknown version {}",
+                bytes[0] >> 6
+            )));
+        }
+        let message_type = match bytes[0] >> 4 & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_length = (bytes[0] & 0xF) as usize;
+        if token_length > 8 || bytes.len() < 4 + token_length {
+            return Err(CoapError::Format("bad token length".into()));
+        }
+        let mut message = Message::new(
+            message_type,
+            Code(bytes[1]),
+            u16::from_be_bytes([bytes[2], bytes[3]]),
+        )
+        .with_token(&bytes[4..4 + token_length]);
+
+        let mut rest = &bytes[4 + token_length..];
+        let mut number = 0u16;
+        while let Some((&first, tail)) = rest.split_first() {
+            if first == PAYLOAD_MARKER {
+                if tail.is_empty() {
+                    return Err(CoapError::Format("payload marker without payload".into()));
+                }
+                message.payload = tail.to_vec();
+                break;
+            }
+            rest = tail;
+            let delta = read_extended(first >> 4, &mut rest)?;
+            let length = read_extended(first & 0xF, &mut rest)? as usize;
+            if rest.len() < length {
+                return Err(CoapError::Format("option longer than the message".into()));
+            }
+            number = number
+                .checked_add(delta)
+                .ok_or_else(|| CoapError::Format("option number overflow".into()))?;
+            message.options.push((number, rest[..length].to_vec()));
+            rest = &rest[length..];
+        }
+        Ok(message)
+    }
+}
+
+// Option delta or length as its nibble and extended bytes.
+fn option_nibble(value: u16) -> (u8, Vec<u8>) {
+    match value {
+        0..=12 => (value as u8, Vec::new()),
+        13..=268 => (13, vec![(value - 13) as u8]),
+        _ => (14, (value - 269).to_be_bytes().to_vec()),
+    }
+}
+
+fn read_extended(nibble: u8, rest: &mut &[u8]) -> Result<u16, CoapError> {
+    let truncated = || CoapError::Format("truncated option".into());
+    match nibble {
+        0..=12 => Ok(nibble as u16),
+        13 => {
+            let (&byte, tail) = rest.split_first().ok_or_else(truncated)?;
+            *rest = tail;
+            Ok(byte as u16 + 13)
+        }
+        14 => {
+            if rest.len() < 2 {
+                return Err(truncated());
+            }
+            let value = u16::from_be_bytes([rest[0], rest[1]]);
+            *rest = &rest[2..];
+            value
+                .checked_add(269)
+                .ok_or_else(|| CoapError::Format("option number overflow".into()))
+        }
+        _ => Err(CoapError::Format("reserved option nibble 15".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let message = Message::new(MessageType::Confirmable, Code::GET, 0x1234)
+            .with_token(&[0xAB, 0xCD])
+            .with_path("/thermometers/Living Room Thermo")
+            .with_uint_option(OBSERVE, 0)
+            .with_option(300, vec![1; 20])
+            .with_payload(b"21.5");
+        let bytes = message.encode();
+        // Ver 1, CON, token length 2, 0.01 GET, message id.
+        assert_eq!(&bytes[..4], &[0x42, 0x01, 0x12, 0x34]);
+        // Observe (6) with an empty value comes before the Uri-Paths.
+        assert_eq!(bytes[6], 0x60);
+        let decoded = Message::decode(&bytes).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.path(), vec!["thermometers", "Living Room Thermo"]);
+        assert_eq!(decoded.uint_option(OBSERVE), Some(0));
+        assert_eq!(
+            message
+                .clone()
+                .with_uint_option(CONTENT_FORMAT, 0x1_0000)
+                .uint_option(CONTENT_FORMAT),
+            Some(0x1_0000)
+        );
+        assert_eq!(Code::UNSUPPORTED_CONTENT_FORMAT.to_string(), "4.15");
+    }
+
+    #[test]
+    fn test_malformed() {
+        for bytes in [
+            &[0x40, 0x01][..],
+            &[0x80, 0x01, 0, 1],
+            &[0x49, 0x01, 0, 1],
+            &[0x40, 0x01, 0, 1, 0xFF],
+            &[0x40, 0x01, 0, 1, 0xB5, b'a'],
+            &[0x40, 0x01, 0, 1, 0xF0],
+        ] {
+            assert!(Message::decode(bytes).is_err(), "{:?}", bytes);
+        }
+    }
+}
@@ -0,0 +1,892 @@
+use crate::alerts::{AlertMonitor, AlertRule};
+use crate::device_info::devices::{
+    Device, SmartSocket, SmartThermometer, SocketState, ThermometerState,
+};
+use crate::device_info::temperature::{Temperature, TemperatureUnit};
+use crate::interlocks::{Constraint, Interlocks};
+use crate::load_shedding::{LoadShedder, PowerBudget};
+use crate::modes::{ModeController, ModeSettings};
+use crate::rules::{Action, Condition, Rule, RuleParseError, RulesEngine};
+use crate::scene::{Scene, Selector};
+use crate::scheduler::solar::Location;
+use crate::smart_house::{Room, SmartHouse};
+use crate::storage::RetentionPolicy;
+use crate::webhooks::{WebhookEvent, WebhooksConfig};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+// Define an error type for house configuration files.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to access config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to write config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+// On-disk description of a house, stored as TOML.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HouseConfig {
+    pub name: String,
+    #[serde(default)]
+    pub temperature_unit: TemperatureUnit,
+    // Used for sunrise and sunset triggers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    // Maximum total power of the sockets that are on, in watts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_budget: Option<f32>,
+    // Where to keep the history of readings and socket changes, if anywhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageConfig>,
+    // Behaviour of the house modes, if the gateway manages them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modes: Option<ModesConfig>,
+    // HTTP endpoints notified of selected events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhooks: Option<WebhooksConfig>,
+    // Temperature thresholds watched by the gateway.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<AlertConfig>,
+    // Rules evaluated by the gateway on every reading, in the syntax of
+    // `Rule::from_str`, e.g. "heat: if Thermo < 19 for 5m then Heater on".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub rooms: Vec<RoomConfig>,
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
+    // Safety constraints between sockets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interlocks: Vec<Constraint>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StorageConfig {
+    // SQLite database file, created if missing.
+    pub path: String,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+}
+
+// See `ModeSettings` for what the fields do.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ModesConfig {
+    // File the current mode is kept in across restarts, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub essential: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub presence: Vec<String>,
+    #[serde(default = "default_night_setback")]
+    pub night_setback: f32,
+    // IANA name, e.g. "Europe/Tallinn".
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+impl ModesConfig {
+    pub fn to_settings(&self) -> Result<ModeSettings, ConfigError> {
+        let timezone = self
+            .timezone
+            .parse::<Tz>()
+            .map_err(|_| ConfigError::Invalid(format!("unknown timezone {}", self.timezone)))?;
+        Ok(ModeSettings {
+            essential: self.essential.clone(),
+            night_setback: self.night_setback,
+            presence: self.presence.clone(),
+            timezone,
+        })
+    }
+}
+
+// Threshold alert on a thermometer. Thresholds are in the house's
+// temperature unit; see `AlertRule` for how they are evaluated.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AlertConfig {
+    pub name: String,
+    pub thermometer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub above: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below: Option<f32>,
+    #[serde(default, skip_serializing_if = "is_zero_f32")]
+    pub hysteresis: f32,
+    // The threshold has to be crossed this many seconds before the alert is raised.
+    #[serde(default, skip_serializing_if = "is_zero_u64")]
+    pub seconds: u64,
+}
+
+impl AlertConfig {
+    pub fn to_rule(&self, unit: TemperatureUnit) -> AlertRule {
+        let mut rule = AlertRule::new(&self.name, &self.thermometer)
+            .with_hysteresis(self.hysteresis)
+            .with_min_duration(std::time::Duration::from_secs(self.seconds));
+        if let Some(above) = self.above {
+            rule = rule.above(Temperature::new(above, unit));
+        }
+        if let Some(below) = self.below {
+            rule = rule.below(Temperature::new(below, unit));
+        }
+        rule
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RoomConfig {
+    pub name: String,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeviceConfig {
+    Socket {
+        name: String,
+        #[serde(default = "default_socket_state")]
+        state: SocketState,
+        #[serde(default)]
+        power_consumption: f32,
+        // Load shedding switches off low priorities first.
+        #[serde(default, skip_serializing_if = "is_zero")]
+        priority: i32,
+        // Address of the `SmartSocketServer` the gateway drives this socket through.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+    },
+    Thermometer {
+        name: String,
+        // Address the gateway listens on for this thermometer's datagrams.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+    },
+}
+
+fn default_socket_state() -> SocketState {
+    SocketState::Off
+}
+
+fn default_night_setback() -> f32 {
+    ModeSettings::default().night_setback
+}
+
+fn default_timezone() -> String {
+    ModeSettings::default().timezone.name().to_string()
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+fn is_zero_f32(value: &f32) -> bool {
+    *value == 0.0
+}
+
+fn is_zero_u64(value: &u64) -> bool {
+    *value == 0
+}
+
+impl DeviceConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            DeviceConfig::Socket { name, .. } => name,
+            DeviceConfig::Thermometer { name, .. } => name,
+        }
+    }
+
+    // Network address of the device, if it is reached through the gateway.
+    pub fn address(&self) -> Option<&str> {
+        match self {
+            DeviceConfig::Socket { address, .. } | DeviceConfig::Thermometer { address, .. } => {
+                address.as_deref()
+            }
+        }
+    }
+}
+
+impl HouseConfig {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: HouseConfig = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    // Check that room and device names are unique, that scenes only refer
+    // to rooms that exist, that interlocks only refer to sockets, that alerts
+    // only refer to thermometers, that rules parse and only refer to devices
+    // of the right type, that modes only refer to sockets and that webhooks
+    // are valid, only refer to devices that exist and only wait for alerts if
+    // there are any.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut rooms = HashSet::new();
+        let mut devices = HashSet::new();
+        let mut sockets = HashSet::new();
+        let mut thermometers = HashSet::new();
+        for room in &self.rooms {
+            if !rooms.insert(room.name.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate room name {}",
+                    room.name
+                )));
+            }
+            for device in &room.devices {
+                if !devices.insert(device.name()) {
+                    return Err(ConfigError::Invalid(format!(
+                        "duplicate device name {}",
+                        device.name()
+                    )));
+                }
+                match device {
+                    DeviceConfig::Socket { name, .. } => sockets.insert(name.as_str()),
+                    DeviceConfig::Thermometer { name, .. } => thermometers.insert(name.as_str()),
+                };
+            }
+        }
+        for scene in &self.scenes {
+            for target in &scene.targets {
+                if let Selector::Room(room) = &target.selector {
+                    if !rooms.contains(room.as_str()) {
+                        return Err(ConfigError::Invalid(format!(
+                            "scene {} refers to unknown room {}",
+                            scene.name, room
+                        )));
+                    }
+                }
+            }
+        }
+        let mut always_on = HashSet::new();
+        for constraint in &self.interlocks {
+            for socket in constraint.sockets() {
+                if !sockets.contains(socket) {
+                    return Err(ConfigError::Invalid(format!(
+                        "interlock refers to unknown socket {}",
+                        socket
+                    )));
+                }
+            }
+            if let Constraint::AlwaysOn { socket } = constraint {
+                always_on.insert(socket.as_str());
+            }
+        }
+        for constraint in &self.interlocks {
+            if let Constraint::MaxOnDuration { socket, .. } = constraint {
+                if always_on.contains(socket.as_str()) {
+                    return Err(ConfigError::Invalid(format!(
+                        "socket {} can't be both always on and limited in on-duration",
+                        socket
+                    )));
+                }
+            }
+        }
+        let mut alerts = HashSet::new();
+        for alert in &self.alerts {
+            if !alerts.insert(alert.name.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate alert name {}",
+                    alert.name
+                )));
+            }
+            if !thermometers.contains(alert.thermometer.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "alert {} refers to unknown thermometer {}",
+                    alert.name, alert.thermometer
+                )));
+            }
+            if alert.above.is_none() && alert.below.is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "alert {} has neither `above` nor `below`",
+                    alert.name
+                )));
+            }
+        }
+        let mut names = HashSet::new();
+        for rule in self.parsed_rules()? {
+            if !names.insert(rule.name.clone()) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate rule name {}",
+                    rule.name
+                )));
+            }
+            let condition = match &rule.condition {
+                Condition::Temperature { thermometer, .. } => {
+                    ("thermometer", thermometer, &thermometers)
+                }
+                Condition::SocketIs { socket, .. } => ("socket", socket, &sockets),
+            };
+            let Action::SwitchSocket { socket, .. } = &rule.action;
+            for (kind, device, known) in [condition, ("socket", socket, &sockets)] {
+                if !known.contains(device.as_str()) {
+                    return Err(ConfigError::Invalid(format!(
+                        "rule {} refers to unknown {} {}",
+                        rule.name, kind, device
+                    )));
+                }
+            }
+        }
+        if let Some(modes) = &self.modes {
+            modes.to_settings()?;
+            let unknown = modes
+                .essential
+                .iter()
+                .chain(&modes.presence)
+                .find(|socket| !sockets.contains(socket.as_str()));
+            if let Some(socket) = unknown {
+                return Err(ConfigError::Invalid(format!(
+                    "modes refer to unknown socket {}",
+                    socket
+                )));
+            }
+            if !modes.night_setback.is_finite() {
+                return Err(ConfigError::Invalid(format!(
+                    "night setback must be a number of degrees, got {}",
+                    modes.night_setback
+                )));
+            }
+        }
+        if let Some(webhooks) = &self.webhooks {
+            webhooks
+                .validate()
+                .map_err(|e| ConfigError::Invalid(e.to_string()))?;
+            for hook in &webhooks.hooks {
+                if let Some(device) = hook.devices.iter().find(|d| !devices.contains(d.as_str())) {
+                    return Err(ConfigError::Invalid(format!(
+                        "webhook {} refers to unknown device {}",
+                        hook.name, device
+                    )));
+                }
+                if self.alerts.is_empty() && hook.events.contains(&WebhookEvent::AlertRaised) {
+                    return Err(ConfigError::Invalid(format!(
+                        "webhook {} waits for alert_raised but no alerts are configured",
+                        hook.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Monitor evaluating the configured alerts, if there are any. The same
+    // monitor has to be given to the gateway, which feeds it readings, and
+    // to whatever reports its alerts.
+    pub fn alert_monitor(&self) -> Option<AlertMonitor> {
+        if self.alerts.is_empty() {
+            return None;
+        }
+        let monitor = AlertMonitor::new();
+        for alert in &self.alerts {
+            monitor.add_rule(alert.to_rule(self.temperature_unit));
+        }
+        Some(monitor)
+    }
+
+    // Engine evaluating the configured rules, if there are any.
+    pub fn rules_engine(&self) -> Result<Option<RulesEngine>, ConfigError> {
+        if self.rules.is_empty() {
+            return Ok(None);
+        }
+        let mut engine = RulesEngine::new();
+        for rule in self.parsed_rules()? {
+            engine.add_rule(rule);
+        }
+        Ok(Some(engine))
+    }
+
+    fn parsed_rules(&self) -> Result<Vec<Rule>, ConfigError> {
+        self.rules
+            .iter()
+            .map(|text| {
+                text.parse::<Rule>().map_err(|e| match e {
+                    RuleParseError::Invalid { message, .. } => {
+                        ConfigError::Invalid(format!("rule `{}`: {}", text, message))
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // Controller for the configured modes, if there is a [modes] section,
+    // keeping the mode in its state file. Thermostats to set back at Night
+    // have to be added by the caller.
+    pub fn mode_controller(&self) -> Result<Option<ModeController>, ConfigError> {
+        let Some(modes) = &self.modes else {
+            return Ok(None);
+        };
+        let mut controller = ModeController::new(modes.to_settings()?);
+        if let Some(path) = &modes.state_file {
+            controller = controller.with_state_file(path);
+        }
+        Ok(Some(controller))
+    }
+
+    // Build the in-memory model. Thermometers start without a reading.
+    pub fn to_house(&self) -> SmartHouse {
+        let rooms = self
+            .rooms
+            .iter()
+            .map(|room| Room {
+                name: room.name.clone(),
+                devices: room.devices.iter().map(DeviceConfig::to_device).collect(),
+            })
+            .collect();
+        let mut house = SmartHouse::new(&self.name, rooms);
+        house.set_temperature_unit(self.temperature_unit);
+        house.location = self.location;
+        if let Some(limit) = self.power_budget {
+            let mut shedder = LoadShedder::new(PowerBudget::new(limit));
+            for device in self.rooms.iter().flat_map(|room| room.devices.iter()) {
+                if let DeviceConfig::Socket { name, priority, .. } = device {
+                    shedder.set_priority(name, *priority);
+                }
+            }
+            house.load_shedder = Some(shedder);
+        }
+        for scene in &self.scenes {
+            house.add_scene(scene.clone());
+        }
+        house.interlocks = self
+            .interlocks
+            .iter()
+            .cloned()
+            .fold(Interlocks::new(), Interlocks::with);
+        house
+    }
+
+    // Describe an existing house. Thermometer readings, device addresses,
+    // storage, modes, webhooks, alerts and rules are not part of the house
+    // model and are not stored.
+    pub fn from_house(house: &SmartHouse) -> Self {
+        Self {
+            name: house.name.clone(),
+            temperature_unit: house.temperature_unit,
+            location: house.location,
+            power_budget: house.load_shedder.as_ref().map(|s| s.budget().limit),
+            storage: None,
+            modes: None,
+            webhooks: None,
+            alerts: Vec::new(),
+            rules: Vec::new(),
+            rooms: house
+                .rooms()
+                .iter()
+                .map(|room| RoomConfig {
+                    name: room.name.clone(),
+                    devices: room
+                        .devices
+                        .iter()
+                        .map(|device| DeviceConfig::from_device(device, house))
+                        .collect(),
+                })
+                .collect(),
+            scenes: house.scenes.clone(),
+            interlocks: house.interlocks.constraints().to_vec(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    fn to_device(&self) -> Device {
+        match self {
+            DeviceConfig::Socket {
+                name,
+                state,
+                power_consumption,
+                ..
+            } => Device::SmartSocket(SmartSocket {
+                name: name.clone(),
+                state: state.clone(),
+                power_consumption: *power_consumption,
+            }),
+            DeviceConfig::Thermometer { name, .. } => Device::SmartThermometer(SmartThermometer {
+                name: name.clone(),
+                state: ThermometerState::Off,
+            }),
+        }
+    }
+
+    fn from_device(device: &Device, house: &SmartHouse) -> Self {
+        match device {
+            Device::SmartSocket(socket) => DeviceConfig::Socket {
+                name: socket.name.clone(),
+                state: socket.state.clone(),
+                power_consumption: socket.power_consumption,
+                priority: house
+                    .load_shedder
+                    .as_ref()
+                    .map_or(0, |s| s.priority(&socket.name)),
+                address: None,
+            },
+            Device::SmartThermometer(thermometer) => DeviceConfig::Thermometer {
+                name: thermometer.name.clone(),
+                address: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+name = "Home"
+temperature_unit = "fahrenheit"
+location = { latitude = 59.437, longitude = 24.7536 }
+power_budget = 3000.0
+
+[[rooms]]
+name = "Living Room"
+devices = [
+    { type = "socket", name = "Lamp", state = "on", power_consumption = 60.0 },
+    { type = "thermometer", name = "LivingThermo" },
+]
+
+[[rooms]]
+name = "Bedroom"
+devices = [{ type = "socket", name = "BedroomHeater", power_consumption = 1500.0, priority = 5 }]
+
+[[scenes]]
+name = "Night"
+targets = [
+    { room = "Living Room", state = "off" },
+    { device = "BedroomHeater", state = "on" },
+]
+
+[[scenes]]
+name = "Leaving"
+targets = [{ all = true, state = "off" }]
+
+[[interlocks]]
+type = "exclusive"
+name = "Bedside"
+sockets = ["Lamp", "BedroomHeater"]
+
+[[interlocks]]
+type = "max_on_duration"
+socket = "BedroomHeater"
+seconds = 3600
+"#;
+
+    #[test]
+    fn test_load_house_with_scenes() {
+        let config = HouseConfig::from_toml(CONFIG).unwrap();
+        let mut house = config.to_house();
+        assert_eq!(house.temperature_unit, TemperatureUnit::Fahrenheit);
+        assert_eq!(house.location, Some(Location::new(59.437, 24.7536)));
+        let shedder = house.load_shedder.as_ref().unwrap();
+        assert_eq!(shedder.budget().limit, 3000.0);
+        assert_eq!(shedder.priority("BedroomHeater"), 5);
+        assert_eq!(house.list_rooms(), vec!["Living Room", "Bedroom"]);
+        assert_eq!(
+            house.socket("BedroomHeater").unwrap().state,
+            SocketState::Off
+        );
+
+        assert!(house.apply_scene("Night", true).unwrap().succeeded());
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::Off);
+        assert_eq!(
+            house.socket("BedroomHeater").unwrap().state,
+            SocketState::On
+        );
+        assert_eq!(house.interlocks.constraints().len(), 2);
+        assert!(house.apply_scene("Leaving", false).unwrap().succeeded());
+        assert_eq!(
+            house.socket("BedroomHeater").unwrap().state,
+            SocketState::Off
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let config = HouseConfig::from_toml(CONFIG).unwrap();
+        let text = HouseConfig::from_house(&config.to_house())
+            .to_toml()
+            .unwrap();
+        assert_eq!(HouseConfig::from_toml(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_storage() {
+        let config = HouseConfig::from_toml(
+            "name = \"Home\"\n[storage]\npath = \"history.db\"\nretention = { raw_days = 2 }\n",
+        )
+        .unwrap();
+        let storage = config.storage.as_ref().unwrap();
+        assert_eq!(storage.path, "history.db");
+        assert_eq!(storage.retention.raw_days, 2);
+        assert_eq!(storage.retention.minute_days, 30);
+        assert_eq!(
+            HouseConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let text = r#"
+name = "Home"
+[modes]
+state_file = "mode.toml"
+essential = ["Fridge"]
+presence = ["Lamp"]
+timezone = "Europe/Tallinn"
+
+[[rooms]]
+name = "Kitchen"
+devices = [{ type = "socket", name = "Fridge" }, { type = "socket", name = "Lamp" }]
+"#;
+        let config = HouseConfig::from_toml(text).unwrap();
+        let settings = config.modes.as_ref().unwrap().to_settings().unwrap();
+        assert_eq!(settings.essential, vec!["Fridge"]);
+        assert_eq!(settings.presence, vec!["Lamp"]);
+        assert_eq!(settings.night_setback, 3.0);
+        assert_eq!(settings.timezone, chrono_tz::Europe::Tallinn);
+        assert!(config.mode_controller().unwrap().is_some());
+        assert_eq!(
+            HouseConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        assert!(HouseConfig::from_toml("name = \"Home\"")
+            .unwrap()
+            .mode_controller()
+            .unwrap()
+            .is_none());
+
+        for (from, to, error) in [
+            (
+                "\"Europe/Tallinn\"",
+                "\"Mars/Olympus\"",
+                "unknown timezone Mars/Olympus",
+            ),
+            (
+                "[\"Lamp\"]",
+                "[\"Heater\"]",
+                "modes refer to unknown socket Heater",
+            ),
+        ] {
+            assert_eq!(
+                HouseConfig::from_toml(&text.replace(from, to))
+                    .unwrap_err()
+                    .to_string(),
+                format!("Invalid config: {}", error)
+            );
+        }
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let text = r#"
+name = "Home"
+[[rooms]]
+name = "Hall"
+devices = [{ type = "socket", name = "Lamp" }]
+[webhooks]
+dead_letters = "dead_letters.jsonl"
+[[webhooks.hooks]]
+name = "chat"
+url = "http://127.0.0.1:9000/hook"
+events = ["socket_toggled"]
+devices = ["Lamp"]
+template = '{"text": "{{device}} is {{state}}"}'
+"#;
+        let config = HouseConfig::from_toml(text).unwrap();
+        let webhooks = config.webhooks.as_ref().unwrap();
+        assert_eq!(webhooks.hooks[0].devices, vec!["Lamp".to_string()]);
+        assert_eq!(
+            HouseConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+
+        let unknown = text.replace("devices = [\"Lamp\"]", "devices = [\"Oven\"]");
+        assert_eq!(
+            HouseConfig::from_toml(&unknown).unwrap_err().to_string(),
+            "Invalid config: webhook chat refers to unknown device Oven"
+        );
+        let template = text.replace("{{state}}", "{{power}}");
+        assert!(matches!(
+            HouseConfig::from_toml(&template),
+            Err(ConfigError::Invalid(_))
+        ));
+        let alerts = text.replace("\"socket_toggled\"", "\"alert_raised\"");
+        assert_eq!(
+            HouseConfig::from_toml(&alerts).unwrap_err().to_string(),
+            "Invalid config: webhook chat waits for alert_raised but no alerts are configured"
+        );
+    }
+
+    #[test]
+    fn test_alerts() {
+        let text = r#"
+name = "Home"
+temperature_unit = "fahrenheit"
+[[rooms]]
+name = "Hall"
+devices = [{ type = "thermometer", name = "Thermo" }, { type = "socket", name = "Lamp" }]
+[[alerts]]
+name = "Too hot"
+thermometer = "Thermo"
+above = 86.0
+seconds = 60
+"#;
+        let config = HouseConfig::from_toml(text).unwrap();
+        assert_eq!(
+            HouseConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        let rule = config.alerts[0].to_rule(config.temperature_unit);
+        assert_eq!(rule.high, Some(Temperature::celsius(30.0)));
+        assert_eq!(rule.min_duration, std::time::Duration::from_secs(60));
+        assert!(config.alert_monitor().is_some());
+        assert!(HouseConfig::from_toml("name = \"Home\"")
+            .unwrap()
+            .alert_monitor()
+            .is_none());
+
+        for (from, to, error) in [
+            (
+                "thermometer = \"Thermo\"",
+                "thermometer = \"Lamp\"",
+                "alert Too hot refers to unknown thermometer Lamp",
+            ),
+            (
+                "above = 86.0",
+                "hysteresis = 1.0",
+                "alert Too hot has neither `above` nor `below`",
+            ),
+        ] {
+            assert_eq!(
+                HouseConfig::from_toml(&text.replace(from, to))
+                    .unwrap_err()
+                    .to_string(),
+                format!("Invalid config: {}", error)
+            );
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let text = r#"
+name = "Home"
+rules = ["heat: if Thermo < 19 for 5m then Heater on priority 2"]
+[[rooms]]
+name = "Hall"
+devices = [{ type = "thermometer", name = "Thermo" }, { type = "socket", name = "Heater" }]
+"#;
+        let config = HouseConfig::from_toml(text).unwrap();
+        assert_eq!(
+            HouseConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        let engine = config.rules_engine().unwrap().unwrap();
+        let rules = engine.rules();
+        assert_eq!(rules[0].name, "heat");
+        assert_eq!(rules[0].hold, std::time::Duration::from_secs(300));
+        assert_eq!(rules[0].priority, 2);
+        assert!(HouseConfig::from_toml("name = \"Home\"")
+            .unwrap()
+            .rules_engine()
+            .unwrap()
+            .is_none());
+
+        for (from, to, error) in [
+            (
+                "Thermo < 19",
+                "Heater < 19",
+                "rule heat refers to unknown thermometer Heater",
+            ),
+            (
+                "then Heater",
+                "then Thermo",
+                "rule heat refers to unknown socket Thermo",
+            ),
+            (
+                "Thermo < 19",
+                "Thermo < warm",
+                "rule `heat: if Thermo < warm for 5m then Heater on priority 2`: \
+                 invalid temperature `warm`",
+            ),
+            (
+                "\"]",
+                "\", \"heat: if Heater is on then Heater off\"]",
+                "duplicate rule name heat",
+            ),
+        ] {
+            assert_eq!(
+                HouseConfig::from_toml(&text.replace(from, to))
+                    .unwrap_err()
+                    .to_string(),
+                format!("Invalid config: {}", error)
+            );
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let duplicate = r#"
+name = "Home"
+[[rooms]]
+name = "Kitchen"
+devices = [{ type = "socket", name = "Plug" }, { type = "thermometer", name = "Plug" }]
+"#;
+        assert!(matches!(
+            HouseConfig::from_toml(duplicate),
+            Err(ConfigError::Invalid(_))
+        ));
+        let unknown_room = r#"
+name = "Home"
+[[scenes]]
+name = "Off"
+targets = [{ room = "Garage", state = "off" }]
+"#;
+        assert!(matches!(
+            HouseConfig::from_toml(unknown_room),
+            Err(ConfigError::Invalid(_))
+        ));
+        let unknown_socket = r#"
+name = "Home"
+[[interlocks]]
+type = "exclusive"
+name = "Circuit A"
+sockets = ["Heater1", "Heater2"]
+"#;
+        assert!(matches!(
+            HouseConfig::from_toml(unknown_socket),
+            Err(ConfigError::Invalid(_))
+        ));
+        let ambiguous = r#"
+name = "Home"
+[[scenes]]
+name = "Off"
+targets = [{ device = "Plug", all = true, state = "off" }]
+"#;
+        assert!(matches!(
+            HouseConfig::from_toml(ambiguous),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            HouseConfig::from_toml("name = 5"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,664 @@
+use crate::device_info::devices::{
+    Device, SmartSocket, SmartThermometer, SocketState, ThermometerState,
+};
+use crate::device_info::temperature::Temperature;
+use crate::events::{EventKind, HouseEvent};
+use crate::from_epoch_millis;
+use crate::live_feed::{ClientMessage, FeedMessage};
+use crate::load_shedding::{total_power, ShedKind};
+use crate::report::{DeviceStatus, HouseReport};
+use crate::smart_house::{Room, SmartHouse};
+use chrono::{DateTime, Local};
+use futures_util::{SinkExt, StreamExt};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+// Readings kept per thermometer for its sparkline.
+const HISTORY: usize = 60;
+// Readings shown in a sparkline.
+const SPARKLINE_WIDTH: usize = 20;
+const LOG_LINES: usize = 100;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Define an error type for the dashboard.
+#[derive(Error, Debug)]
+pub enum DashboardError {
+    #[error("Terminal error: {0}")]
+    Terminal(#[from] std::io::Error),
+    #[error("Live feed of the gateway at {address} failed: {reason}")]
+    Feed { address: String, reason: String },
+}
+
+type Feed = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// State of the terminal dashboard that isn't part of the house model:
+// temperature history, device health, the event log and which socket is
+// selected. Rendering takes the house as it is at that moment, so the
+// dashboard is fed from a copy of the house the gateway's live feed keeps
+// in sync.
+#[derive(Default)]
+pub struct Dashboard {
+    history: HashMap<String, VecDeque<Temperature>>,
+    // Devices reported offline, with the reason.
+    offline: HashMap<String, String>,
+    log: VecDeque<String>,
+    selected: usize,
+    quit: bool,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether the user asked to leave.
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    // Start the history of thermometers that already have a reading.
+    pub fn observe(&mut self, house: &SmartHouse) {
+        for device in house.rooms().iter().flat_map(|room| &room.devices) {
+            if let Device::SmartThermometer(thermometer) = device {
+                if let ThermometerState::Temperature(temperature) = thermometer.state {
+                    let history = self.history.entry(thermometer.name.clone()).or_default();
+                    if history.is_empty() {
+                        history.push_back(temperature);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &HouseEvent) {
+        let device = event.device.clone().unwrap_or_default();
+        let what = match &event.kind {
+            // Readings are frequent and shown in the sparklines instead.
+            EventKind::ReadingReceived { temperature } => {
+                let history = self.history.entry(device).or_default();
+                history.push_back(*temperature);
+                if history.len() > HISTORY {
+                    history.pop_front();
+                }
+                return;
+            }
+            EventKind::DeviceAdded => "added".to_string(),
+            EventKind::DeviceRemoved => {
+                self.history.remove(&device);
+                self.offline.remove(&device);
+                "removed".to_string()
+            }
+            EventKind::SocketStateChanged { state } => {
+                format!("switched {}", on_off(state).to_lowercase())
+            }
+            EventKind::ModeChanged { from, to } => format!("mode {} -> {}", from, to),
+            EventKind::LoadShedding {
+                action,
+                total_before,
+                total_after,
+                ..
+            } => format!(
+                "{} by load shedding, {:.1} W -> {:.1} W",
+                match action {
+                    ShedKind::Shed => "shed",
+                    ShedKind::Restored => "restored",
+                },
+                total_before,
+                total_after
+            ),
+            EventKind::InterlockEnforced { reason } => format!("interlock: {}", reason),
+            EventKind::Offline { reason } => {
+                self.offline.insert(device, reason.clone());
+                format!("offline: {}", reason)
+            }
+            EventKind::Online => {
+                self.offline.remove(&device);
+                "back online".to_string()
+            }
+            EventKind::Error { message } => format!("error: {}", message),
+        };
+        let source = match (&event.room, &event.device) {
+            (Some(room), Some(device)) => format!("{}/{}", room, device),
+            (None, Some(device)) => device.clone(),
+            _ => "house".to_string(),
+        };
+        let at: DateTime<Local> = event.at.into();
+        self.log(format!("{} {} {}", at.format("%H:%M:%S"), source, what));
+    }
+
+    fn log(&mut self, line: String) {
+        self.log.push_back(line);
+        if self.log.len() > LOG_LINES {
+            self.log.pop_front();
+        }
+    }
+
+    // Bring the dashboard and its copy of the house up to date with a
+    // message of the gateway's live feed.
+    pub fn apply(&mut self, message: FeedMessage, house: &mut SmartHouse) {
+        match message {
+            FeedMessage::Snapshot { house: report } => {
+                *house = mirror(&report);
+                self.observe(house);
+            }
+            FeedMessage::Event {
+                at,
+                room,
+                device,
+                change,
+            } => {
+                let event = HouseEvent {
+                    at: from_epoch_millis(at),
+                    room,
+                    device,
+                    kind: change.into(),
+                };
+                if let Some(name) = event.device.as_deref() {
+                    // The gateway already checked the change.
+                    let _ = match &event.kind {
+                        EventKind::SocketStateChanged { state } => {
+                            house.switch_socket(name, state.clone())
+                        }
+                        EventKind::ReadingReceived { temperature } => house.set_thermometer_state(
+                            name,
+                            ThermometerState::Temperature(*temperature),
+                        ),
+                        EventKind::DeviceRemoved => house.remove_device(name).map(|_| ()),
+                        _ => Ok(()),
+                    };
+                }
+                if let EventKind::ModeChanged { to, .. } = event.kind {
+                    house.mode = to;
+                }
+                self.handle_event(&event);
+            }
+            FeedMessage::Lagged { missed } => self.log(format!("missed {} events", missed)),
+            // The change itself arrives as an event.
+            FeedMessage::Ack { .. } | FeedMessage::ModeSet { .. } => {}
+            FeedMessage::Error { message } => self.log(format!("command failed: {}", message)),
+        }
+    }
+
+    // Up/Down (or k/j) select a socket, Enter or Space ask to toggle it and
+    // q or Esc quit. Returns the socket and the state to switch it to.
+    pub fn handle_key(
+        &mut self,
+        key: KeyCode,
+        house: &SmartHouse,
+    ) -> Option<(String, SocketState)> {
+        let sockets = socket_names(house);
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(sockets.len().saturating_sub(1));
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                let name = sockets.get(self.selected)?;
+                let state = match house.socket(name).map(|socket| &socket.state) {
+                    Ok(SocketState::On) => SocketState::Off,
+                    _ => SocketState::On,
+                };
+                return Some((name.clone(), state));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    // Draw the whole dashboard: a header, one panel per room, the event log
+    // and a key help line.
+    pub fn draw(&self, frame: &mut Frame, house: &SmartHouse) {
+        let [header, rooms, log, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(4),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(&house.name, Style::new().add_modifier(Modifier::BOLD)),
+                Span::raw(format!(
+                    " · mode {} · {:.1} W",
+                    house.mode,
+                    total_power(house)
+                )),
+            ])),
+            header,
+        );
+        self.draw_rooms(frame, house, rooms);
+
+        let visible = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Events")),
+            log,
+        );
+        frame.render_widget(
+            Paragraph::new("↑/↓ select  Enter/Space toggle  q quit")
+                .style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    fn draw_rooms(&self, frame: &mut Frame, house: &SmartHouse, area: Rect) {
+        if house.rooms().is_empty() {
+            frame.render_widget(Paragraph::new("No rooms").block(Block::bordered()), area);
+            return;
+        }
+        let selected = socket_names(house).get(self.selected).cloned();
+        let panels = Layout::horizontal(
+            house
+                .rooms()
+                .iter()
+                .map(|_| Constraint::Ratio(1, house.rooms().len() as u32)),
+        )
+        .split(area);
+        for (room, panel) in house.rooms().iter().zip(panels.iter()) {
+            let mut power = 0.0;
+            let mut lines = Vec::new();
+            for device in &room.devices {
+                let name = device.name();
+                let health = match self.offline.get(name) {
+                    Some(_) => Span::styled("✖ ", Style::new().fg(Color::Red)),
+                    None => Span::styled("● ", Style::new().fg(Color::Green)),
+                };
+                let mut spans = match device {
+                    Device::SmartSocket(socket) => {
+                        let is_selected = selected.as_deref() == Some(name);
+                        if socket.state == SocketState::On {
+                            power += socket.power_consumption;
+                        }
+                        let color = match socket.state {
+                            SocketState::On => Color::Green,
+                            SocketState::Off => Color::DarkGray,
+                        };
+                        let name_style = if is_selected {
+                            Style::new().add_modifier(Modifier::REVERSED)
+                        } else {
+                            Style::new()
+                        };
+                        vec![
+                            Span::raw(if is_selected { "▶ " } else { "  " }),
+                            health,
+                            Span::styled(name, name_style),
+                            Span::raw(" "),
+                            Span::styled(on_off(&socket.state), Style::new().fg(color)),
+                            Span::raw(format!(" {:.1} W", socket.power_consumption)),
+                        ]
+                    }
+                    Device::SmartThermometer(thermometer) => {
+                        let reading = match thermometer.state {
+                            ThermometerState::Temperature(t) => {
+                                let t = t.to_unit(house.temperature_unit);
+                                format!(" {:.1}{}", t.value(), t.unit().symbol())
+                            }
+                            ThermometerState::Off => " no reading".to_string(),
+                        };
+                        let history = self
+                            .history
+                            .get(name)
+                            .map(|history| {
+                                history
+                                    .iter()
+                                    .map(Temperature::as_celsius)
+                                    .collect::<Vec<_>>()
+                            })
+                            .unwrap_or_default();
+                        vec![
+                            Span::raw("  "),
+                            health,
+                            Span::raw(name),
+                            Span::raw(reading),
+                            Span::raw(" "),
+                            Span::styled(sparkline(&history), Style::new().fg(Color::Cyan)),
+                        ]
+                    }
+                };
+                if self.offline.contains_key(name) {
+                    spans.push(Span::styled(" offline", Style::new().fg(Color::Red)));
+                }
+                lines.push(Line::from(spans));
+            }
+            let title = format!("{} ({:.1} W)", room.name, power);
+            frame.render_widget(
+                Paragraph::new(lines).block(Block::bordered().title(title)),
+                *panel,
+            );
+        }
+    }
+}
+
+fn on_off(state: &SocketState) -> &'static str {
+    match state {
+        SocketState::On => "ON",
+        SocketState::Off => "OFF",
+    }
+}
+
+// Sockets in the order they are shown, room by room.
+fn socket_names(house: &SmartHouse) -> Vec<String> {
+    house
+        .rooms()
+        .iter()
+        .flat_map(|room| &room.devices)
+        .filter_map(|device| match device {
+            Device::SmartSocket(socket) => Some(socket.name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// The last `SPARKLINE_WIDTH` values as bars scaled between their minimum
+// and maximum.
+fn sparkline(values: &[f32]) -> String {
+    let values = &values[values.len().saturating_sub(SPARKLINE_WIDTH)..];
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    values
+        .iter()
+        .map(|value| {
+            if max <= min {
+                return SPARKS[SPARKS.len() / 2];
+            }
+            let level = (value - min) / (max - min) * (SPARKS.len() - 1) as f32;
+            SPARKS[level.round() as usize]
+        })
+        .collect()
+}
+
+// A house with the rooms and devices of a snapshot.
+fn mirror(report: &HouseReport) -> SmartHouse {
+    let rooms = report
+        .rooms
+        .iter()
+        .map(|room| Room {
+            name: room.name.clone(),
+            devices: room
+                .devices
+                .iter()
+                .map(|device| match &device.status {
+                    DeviceStatus::Socket {
+                        state,
+                        power_consumption,
+                    } => Device::SmartSocket(SmartSocket {
+                        name: device.name.clone(),
+                        state: state.clone(),
+                        power_consumption: *power_consumption,
+                    }),
+                    DeviceStatus::Thermometer { temperature, unit } => {
+                        Device::SmartThermometer(SmartThermometer {
+                            name: device.name.clone(),
+                            state: match temperature {
+                                Some(value) => {
+                                    ThermometerState::Temperature(Temperature::new(*value, *unit))
+                                }
+                                None => ThermometerState::Off,
+                            },
+                        })
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+    let mut house = SmartHouse::new(&report.house, rooms);
+    house.mode = report.mode;
+    house.temperature_unit = report.temperature_unit;
+    house
+}
+
+// Run the dashboard in the terminal until the user quits, following the
+// live feed of the gateway whose HTTP API is at `address`. Sockets are
+// switched through the gateway, which answers rejected commands with an
+// error shown in the event log.
+pub async fn run(address: &str) -> Result<(), DashboardError> {
+    let (mut feed, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", address))
+        .await
+        .map_err(|e| feed_failed(address, e))?;
+    let mut dashboard = Dashboard::new();
+    let mut house = SmartHouse::new("", Vec::new());
+    let mut terminal = ratatui::init();
+    let result = event_loop(
+        &mut terminal,
+        &mut dashboard,
+        &mut house,
+        &mut feed,
+        address,
+    )
+    .await;
+    ratatui::restore();
+    let _ = feed.close(None).await;
+    result
+}
+
+fn feed_failed(address: &str, reason: impl std::fmt::Display) -> DashboardError {
+    DashboardError::Feed {
+        address: address.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    house: &mut SmartHouse,
+    feed: &mut Feed,
+    address: &str,
+) -> Result<(), DashboardError> {
+    let mut ticks = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        tokio::select! {
+            message = feed.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(message) => dashboard.apply(message, house),
+                    Err(e) => dashboard.log(format!("unreadable feed message: {}", e)),
+                },
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err(feed_failed(address, "the gateway closed it"))
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(feed_failed(address, e)),
+            },
+            _ = ticks.tick() => {
+                while event::poll(Duration::ZERO)? {
+                    let Event::Key(key) = event::read()? else {
+                        continue;
+                    };
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    if let Some((device, command)) = dashboard.handle_key(key.code, house) {
+                        let command = ClientMessage::Command { device, command };
+                        let text = serde_json::to_string(&command)
+                            .map_err(|e| feed_failed(address, e))?;
+                        feed.send(Message::Text(text))
+                            .await
+                            .map_err(|e| feed_failed(address, e))?;
+                    }
+                }
+                if dashboard.should_quit() {
+                    return Ok(());
+                }
+                terminal.draw(|frame| dashboard.draw(frame, house))?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::temperature::TemperatureUnit;
+    use crate::events::EventFilter;
+    use crate::live_feed::Change;
+    use crate::test_support::house;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    // Render into an in-memory buffer and return its rows.
+    fn render(dashboard: &Dashboard, house: &SmartHouse) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame, house)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    fn contains(rows: &[String], text: &str) -> bool {
+        rows.iter().any(|row| row.contains(text))
+    }
+
+    #[test]
+    fn test_render_rooms_and_readings() {
+        let mut house = house();
+        let mut dashboard = Dashboard::new();
+        let mut readings = house.events().subscribe(EventFilter::all());
+        for celsius in [20.0, 21.0, 22.0, 21.5] {
+            house
+                .set_thermometer_state(
+                    "Thermo",
+                    ThermometerState::Temperature(Temperature::celsius(celsius)),
+                )
+                .unwrap();
+            dashboard.handle_event(&readings.try_recv().unwrap().unwrap());
+        }
+        dashboard.handle_event(
+            &HouseEvent::new(
+                Some("Thermo"),
+                EventKind::Offline {
+                    reason: "no datagrams".to_string(),
+                },
+            )
+            .in_room("Living Room"),
+        );
+
+        let rows = render(&dashboard, &house);
+        assert!(rows[0].starts_with("Home · mode home · 150.0 W"));
+        assert!(contains(&rows, "Kitchen (150.0 W)"));
+        assert!(contains(&rows, "▶ ● Kettle OFF 2000.0 W"));
+        assert!(contains(&rows, "  ● Fridge ON 150.0 W"));
+        assert!(contains(&rows, "✖ Thermo 21.5°C ▁▅█▆ offline"));
+        assert!(contains(&rows, "Living Room/Thermo offline: no datagrams"));
+        assert!(contains(&rows, "↑/↓ select"));
+    }
+
+    #[test]
+    fn test_keys_control_sockets() {
+        let house = house();
+        let mut dashboard = Dashboard::new();
+
+        assert_eq!(
+            dashboard.handle_key(KeyCode::Enter, &house),
+            Some(("Kettle".to_string(), SocketState::On))
+        );
+        dashboard.handle_key(KeyCode::Down, &house);
+        dashboard.handle_key(KeyCode::Down, &house);
+        assert_eq!(
+            dashboard.handle_key(KeyCode::Char(' '), &house),
+            Some(("Fridge".to_string(), SocketState::Off))
+        );
+        // The gateway enforces the interlocks; its answer goes to the log.
+        let mut house = house;
+        dashboard.apply(
+            FeedMessage::Error {
+                message: "Rejected by interlock: Socket Fridge must always stay on".to_string(),
+            },
+            &mut house,
+        );
+
+        let rows = render(&dashboard, &house);
+        assert!(contains(
+            &rows,
+            "command failed: Rejected by interlock: Socket Fridge must always stay on"
+        ));
+        assert!(contains(&rows, "▶ ● Fridge ON"));
+        assert!(!dashboard.should_quit());
+        assert_eq!(dashboard.handle_key(KeyCode::Char('q'), &house), None);
+        assert!(dashboard.should_quit());
+    }
+
+    #[test]
+    fn test_follows_the_feed() {
+        let mut dashboard = Dashboard::new();
+        let mut house = SmartHouse::new("", Vec::new());
+        dashboard.apply(
+            FeedMessage::Snapshot {
+                house: HouseReport::new(&self::house()),
+            },
+            &mut house,
+        );
+        let event = |device: &str, room: &str, change: Change| FeedMessage::Event {
+            at: 0,
+            room: Some(room.to_string()),
+            device: Some(device.to_string()),
+            change,
+        };
+        dashboard.apply(
+            event(
+                "Kettle",
+                "Kitchen",
+                Change::SocketSwitched {
+                    state: SocketState::On,
+                },
+            ),
+            &mut house,
+        );
+        for temperature in [20.0, 22.0] {
+            dashboard.apply(
+                event(
+                    "Thermo",
+                    "Living Room",
+                    Change::TemperatureReceived {
+                        temperature,
+                        unit: TemperatureUnit::Celsius,
+                    },
+                ),
+                &mut house,
+            );
+        }
+        dashboard.apply(FeedMessage::Lagged { missed: 3 }, &mut house);
+
+        let rows = render(&dashboard, &house);
+        assert!(rows[0].starts_with("Home · mode home · 2150.0 W"));
+        assert!(contains(&rows, "▶ ● Kettle ON 2000.0 W"));
+        assert!(contains(&rows, "● Thermo 22.0°C ▁█"));
+        assert!(contains(&rows, "Kitchen/Kettle switched on"));
+        assert!(contains(&rows, "missed 3 events"));
+
+        dashboard.apply(
+            event("Kettle", "Kitchen", Change::DeviceRemoved),
+            &mut house,
+        );
+        assert!(house.socket("Kettle").is_err());
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[20.0, 20.0]), "▅▅");
+        assert_eq!(sparkline(&[0.0, 7.0, 3.5]), "▁█▅");
+        assert_eq!(sparkline(&[1.0; 30]).chars().count(), SPARKLINE_WIDTH);
+    }
+}
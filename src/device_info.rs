@@ -1,10 +1,23 @@
 use thiserror::Error;
 
 pub mod devices;
+pub mod temperature;
 use devices::{SmartSocket, SmartThermometer, SocketState};
+use temperature::TemperatureUnit;
 // Trait for providing information about the status of devices.
 pub trait DeviceInfoProvider {
     fn device_info(&self, room: &str, device_name: &str) -> Result<String, DeviceInfoError>;
+
+    // Device information with temperatures shown in the given unit.
+    // Providers that don't report temperatures can rely on the default.
+    fn device_info_in_unit(
+        &self,
+        room: &str,
+        device_name: &str,
+        _unit: TemperatureUnit,
+    ) -> Result<String, DeviceInfoError> {
+        self.device_info(room, device_name)
+    }
 }
 
 // Information provider owning the device data.
@@ -17,7 +30,7 @@ pub struct OwningDeviceInfoProvider {
 pub enum DeviceInfoError {
     #[error("Information for device named {0} not found")]
     NotFound(String),
-    #[allow(dead_code)] 
+    #[allow(dead_code)]
     #[error("Unknown error occurred")]
     Unknown,
 }
@@ -47,17 +60,26 @@ pub struct BorrowingDeviceInfoProvider<'a, 'b> {
     pub thermo: &'b SmartThermometer,
 }
 
-impl<'a, 'b> DeviceInfoProvider for BorrowingDeviceInfoProvider<'a, 'b> {
-    fn device_info(&self, room_name: &str, device_name: &str) -> Result<String, DeviceInfoError> {
+impl<'a, 'b> BorrowingDeviceInfoProvider<'a, 'b> {
+    fn describe(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        unit: Option<TemperatureUnit>,
+    ) -> Result<String, DeviceInfoError> {
         if device_name == self.socket.name {
             Ok(format!(
                 "Room: {}, Device: SmartSocket named {}, State: {:?}",
                 room_name, device_name, self.socket.state
             ))
         } else if device_name == self.thermo.name {
+            let state = match unit {
+                Some(unit) => self.thermo.state.in_unit(unit),
+                None => self.thermo.state.clone(),
+            };
             Ok(format!(
                 "Room: {}, Device: SmartThermometer named {}, State: {:?}",
-                room_name, device_name, self.thermo.state
+                room_name, device_name, state
             ))
         } else {
             Err(DeviceInfoError::NotFound(device_name.to_owned()))
@@ -65,10 +87,26 @@ impl<'a, 'b> DeviceInfoProvider for BorrowingDeviceInfoProvider<'a, 'b> {
     }
 }
 
+impl<'a, 'b> DeviceInfoProvider for BorrowingDeviceInfoProvider<'a, 'b> {
+    fn device_info(&self, room_name: &str, device_name: &str) -> Result<String, DeviceInfoError> {
+        self.describe(room_name, device_name, None)
+    }
+
+    fn device_info_in_unit(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        unit: TemperatureUnit,
+    ) -> Result<String, DeviceInfoError> {
+        self.describe(room_name, device_name, Some(unit))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use devices::{SmartSocket, SmartThermometer, SocketState, ThermometerState};
+    use temperature::Temperature;
 
     #[test]
     fn test_owning_device_info_provider_socket() {
@@ -103,7 +141,7 @@ mod tests {
         };
         let thermo = SmartThermometer {
             name: "Thermo1".to_string(),
-            state: ThermometerState::Temperature(22.0),
+            state: ThermometerState::Temperature(Temperature::celsius(22.0)),
         };
         let provider = BorrowingDeviceInfoProvider {
             socket: &socket,
@@ -125,7 +163,7 @@ mod tests {
         };
         let thermo = SmartThermometer {
             name: "Thermo1".to_string(),
-            state: ThermometerState::Temperature(22.0),
+            state: ThermometerState::Temperature(Temperature::celsius(22.0)),
         };
         let provider = BorrowingDeviceInfoProvider {
             socket: &socket,
@@ -134,7 +172,7 @@ mod tests {
         let info = provider.device_info("LivingRoom", "Thermo1").unwrap();
         assert_eq!(
             info,
-            "Room: LivingRoom, Device: SmartThermometer named Thermo1, State: Temperature(22.0)"
+            "Room: LivingRoom, Device: SmartThermometer named Thermo1, State: Temperature(22.0°C)"
         );
     }
 }
@@ -1,3 +1,6 @@
+use super::temperature::{Temperature, TemperatureUnit};
+use serde::{Deserialize, Serialize};
+
 #[derive(Clone, Debug)]
 pub struct SmartSocket {
     pub name: String,
@@ -15,6 +18,18 @@ impl Default for SmartSocket {
     }
 }
 
+impl SmartSocket {
+    // Switch the socket the way its server does; an example load is drawn
+    // while it is on.
+    pub fn switch(&mut self, state: SocketState) {
+        self.power_consumption = match state {
+            SocketState::On => 100.0,
+            SocketState::Off => 0.0,
+        };
+        self.state = state;
+    }
+}
+
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct SmartThermometer {
@@ -22,7 +37,8 @@ pub struct SmartThermometer {
     pub state: ThermometerState,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[allow(dead_code)]
 pub enum SocketState {
     On,
@@ -33,12 +49,32 @@ pub enum SocketState {
 #[allow(dead_code)]
 pub enum ThermometerState {
     Off,
-    Temperature(f32),
+    Temperature(Temperature),
+}
+
+impl ThermometerState {
+    // Same state with any reading converted to the given unit.
+    pub fn in_unit(&self, unit: TemperatureUnit) -> Self {
+        match self {
+            ThermometerState::Off => ThermometerState::Off,
+            ThermometerState::Temperature(t) => ThermometerState::Temperature(t.to_unit(unit)),
+        }
+    }
 }
 
 // Enum representing different devices.
+#[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub enum Device {
     SmartSocket(SmartSocket),
     SmartThermometer(SmartThermometer),
 }
+
+impl Device {
+    pub fn name(&self) -> &str {
+        match self {
+            Device::SmartSocket(socket) => &socket.name,
+            Device::SmartThermometer(thermometer) => &thermometer.name,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Unit a temperature value is expressed in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+            TemperatureUnit::Kelvin => "K",
+        }
+    }
+
+    // Single-byte tag used on the wire.
+    pub fn code(&self) -> u8 {
+        match self {
+            TemperatureUnit::Celsius => b'C',
+            TemperatureUnit::Fahrenheit => b'F',
+            TemperatureUnit::Kelvin => b'K',
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            b'C' => Some(TemperatureUnit::Celsius),
+            b'F' => Some(TemperatureUnit::Fahrenheit),
+            b'K' => Some(TemperatureUnit::Kelvin),
+            _ => None,
+        }
+    }
+}
+
+// A temperature value together with the unit it was measured in.
+#[derive(Clone, Copy)]
+pub struct Temperature {
+    value: f32,
+    unit: TemperatureUnit,
+}
+
+impl Temperature {
+    pub fn new(value: f32, unit: TemperatureUnit) -> Self {
+        Self { value, unit }
+    }
+
+    pub fn celsius(value: f32) -> Self {
+        Self::new(value, TemperatureUnit::Celsius)
+    }
+
+    pub fn fahrenheit(value: f32) -> Self {
+        Self::new(value, TemperatureUnit::Fahrenheit)
+    }
+
+    pub fn kelvin(value: f32) -> Self {
+        Self::new(value, TemperatureUnit::Kelvin)
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn unit(&self) -> TemperatureUnit {
+        self.unit
+    }
+
+    pub fn as_celsius(&self) -> f32 {
+        match self.unit {
+            TemperatureUnit::Celsius => self.value,
+            TemperatureUnit::Fahrenheit => (self.value - 32.0) * 5.0 / 9.0,
+            TemperatureUnit::Kelvin => self.value - 273.15,
+        }
+    }
+
+    pub fn as_fahrenheit(&self) -> f32 {
+        match self.unit {
+            TemperatureUnit::Fahrenheit => self.value,
+            _ => self.as_celsius() * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    pub fn as_kelvin(&self) -> f32 {
+        match self.unit {
+            TemperatureUnit::Kelvin => self.value,
+            _ => self.as_celsius() + 273.15,
+        }
+    }
+
+    // Convert to the given unit.
+    pub fn to_unit(&self, unit: TemperatureUnit) -> Self {
+        let value = match unit {
+            TemperatureUnit::Celsius => self.as_celsius(),
+            TemperatureUnit::Fahrenheit => self.as_fahrenheit(),
+            TemperatureUnit::Kelvin => self.as_kelvin(),
+        };
+        Self::new(value, unit)
+    }
+}
+
+// Temperatures in different units compare by their physical value.
+impl PartialEq for Temperature {
+    fn eq(&self, other: &Self) -> bool {
+        if self.unit == other.unit {
+            self.value == other.value
+        } else {
+            self.as_celsius() == other.as_celsius()
+        }
+    }
+}
+
+impl PartialOrd for Temperature {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.unit == other.unit {
+            self.value.partial_cmp(&other.value)
+        } else {
+            self.as_celsius().partial_cmp(&other.as_celsius())
+        }
+    }
+}
+
+impl fmt::Debug for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}{}", self.value, self.unit.symbol())
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(1);
+        write!(f, "{:.*}{}", precision, self.value, self.unit.symbol())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        let boiling = Temperature::celsius(100.0);
+        assert_close(boiling.as_fahrenheit(), 212.0);
+        assert_close(boiling.as_kelvin(), 373.15);
+        assert_close(Temperature::fahrenheit(32.0).as_celsius(), 0.0);
+        assert_close(Temperature::kelvin(0.0).as_fahrenheit(), -459.67);
+        let converted = Temperature::fahrenheit(71.6).to_unit(TemperatureUnit::Celsius);
+        assert_eq!(converted.unit(), TemperatureUnit::Celsius);
+        assert_close(converted.value(), 22.0);
+    }
+
+    #[test]
+    fn test_comparison_across_units() {
+        assert_eq!(Temperature::celsius(0.0), Temperature::kelvin(273.15));
+        assert!(Temperature::fahrenheit(80.0) > Temperature::celsius(25.0));
+        assert!(Temperature::kelvin(290.0) < Temperature::celsius(18.0));
+    }
+
+    #[test]
+    fn test_formatting() {
+        let temperature = Temperature::celsius(21.25);
+        assert_eq!(format!("{}", temperature), "21.2°C");
+        assert_eq!(format!("{:.2}", temperature), "21.25°C");
+        assert_eq!(format!("{:?}", Temperature::kelvin(300.0)), "300.0K");
+        assert_eq!(format!("{}", Temperature::fahrenheit(70.0)), "70.0°F");
+    }
+
+    #[test]
+    fn test_unit_codes_round_trip() {
+        for unit in [
+            TemperatureUnit::Celsius,
+            TemperatureUnit::Fahrenheit,
+            TemperatureUnit::Kelvin,
+        ] {
+            assert_eq!(TemperatureUnit::from_code(unit.code()), Some(unit));
+        }
+        assert_eq!(TemperatureUnit::from_code(b'X'), None);
+    }
+}
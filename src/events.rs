@@ -0,0 +1,309 @@
+use crate::device_info::devices::SocketState;
+use crate::device_info::temperature::Temperature;
+use crate::load_shedding::ShedKind;
+use crate::modes::HouseMode;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+const DEFAULT_CAPACITY: usize = 256;
+
+// What happened to the house.
+#[derive(Clone, PartialEq, Debug)]
+pub enum EventKind {
+    DeviceAdded,
+    DeviceRemoved,
+    SocketStateChanged {
+        state: SocketState,
+    },
+    ReadingReceived {
+        temperature: Temperature,
+    },
+    ModeChanged {
+        from: HouseMode,
+        to: HouseMode,
+    },
+    // The load shedder switched the socket off or back on. Totals are the
+    // power of the sockets that are on, before and after.
+    LoadShedding {
+        action: ShedKind,
+        priority: i32,
+        total_before: f32,
+        total_after: f32,
+    },
+    // An interlock switched the socket, e.g. off after its maximum on-duration.
+    InterlockEnforced {
+        reason: String,
+    },
+    // The device stopped answering, e.g. its socket server went away.
+    Offline {
+        reason: String,
+    },
+    // The device answers again after being offline.
+    Online,
+    Error {
+        message: String,
+    },
+}
+
+// Discriminant of `EventKind`, used for filtering.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EventType {
+    DeviceAdded,
+    DeviceRemoved,
+    SocketStateChanged,
+    ReadingReceived,
+    ModeChanged,
+    LoadShedding,
+    InterlockEnforced,
+    Offline,
+    Online,
+    Error,
+}
+
+impl EventKind {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            EventKind::DeviceAdded => EventType::DeviceAdded,
+            EventKind::DeviceRemoved => EventType::DeviceRemoved,
+            EventKind::SocketStateChanged { .. } => EventType::SocketStateChanged,
+            EventKind::ReadingReceived { .. } => EventType::ReadingReceived,
+            EventKind::ModeChanged { .. } => EventType::ModeChanged,
+            EventKind::LoadShedding { .. } => EventType::LoadShedding,
+            EventKind::InterlockEnforced { .. } => EventType::InterlockEnforced,
+            EventKind::Offline { .. } => EventType::Offline,
+            EventKind::Online => EventType::Online,
+            EventKind::Error { .. } => EventType::Error,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HouseEvent {
+    pub at: SystemTime,
+    pub room: Option<String>,
+    pub device: Option<String>,
+    pub kind: EventKind,
+}
+
+impl HouseEvent {
+    pub fn new(device: Option<&str>, kind: EventKind) -> Self {
+        Self {
+            at: SystemTime::now(),
+            room: None,
+            device: device.map(str::to_string),
+            kind,
+        }
+    }
+
+    pub fn in_room(mut self, room: &str) -> Self {
+        self.room = Some(room.to_string());
+        self
+    }
+}
+
+// Which events a subscriber wants. Empty criteria match everything.
+#[derive(Clone, Default, Debug)]
+pub struct EventFilter {
+    rooms: Vec<String>,
+    devices: Vec<String>,
+    types: Vec<EventType>,
+}
+
+impl EventFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn room(mut self, room: &str) -> Self {
+        self.rooms.push(room.to_string());
+        self
+    }
+
+    pub fn device(mut self, device: &str) -> Self {
+        self.devices.push(device.to_string());
+        self
+    }
+
+    pub fn event_type(mut self, event_type: EventType) -> Self {
+        self.types.push(event_type);
+        self
+    }
+
+    pub fn matches(&self, event: &HouseEvent) -> bool {
+        let room_ok = self.rooms.is_empty()
+            || event
+                .room
+                .as_ref()
+                .is_some_and(|room| self.rooms.contains(room));
+        let device_ok = self.devices.is_empty()
+            || event
+                .device
+                .as_ref()
+                .is_some_and(|device| self.devices.contains(device));
+        let type_ok = self.types.is_empty() || self.types.contains(&event.kind.event_type());
+        room_ok && device_ok && type_ok
+    }
+}
+
+// Define an error type for event subscriptions.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum EventBusError {
+    // The subscriber fell behind and missed this many events (before filtering).
+    #[error("Subscriber lagged behind, {0} events were skipped")]
+    Lagged(u64),
+    #[error("Event bus closed")]
+    Closed,
+}
+
+// House-wide publish/subscribe channel for `HouseEvent`s. Clones share the
+// same subscribers. The bus remembers which room each device is in, so
+// publishers that don't know about rooms still produce room-tagged events.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<HouseEvent>,
+    rooms: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    // Record which room a device is in.
+    pub fn set_room(&self, device: &str, room: &str) {
+        self.rooms
+            .write()
+            .unwrap()
+            .insert(device.to_string(), room.to_string());
+    }
+
+    pub fn forget_device(&self, device: &str) {
+        self.rooms.write().unwrap().remove(device);
+    }
+
+    pub fn publish(&self, mut event: HouseEvent) {
+        if event.room.is_none() {
+            if let Some(device) = &event.device {
+                event.room = self.rooms.read().unwrap().get(device).cloned();
+            }
+        }
+        // Nobody listening is not an error.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self, filter: EventFilter) -> EventSubscription {
+        EventSubscription {
+            receiver: self.sender.subscribe(),
+            filter,
+        }
+    }
+}
+
+pub struct EventSubscription {
+    receiver: broadcast::Receiver<HouseEvent>,
+    filter: EventFilter,
+}
+
+impl EventSubscription {
+    // Wait for the next event matching the filter.
+    pub async fn recv(&mut self) -> Result<HouseEvent, EventBusError> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.filter.matches(&event) => return Ok(event),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    return Err(EventBusError::Lagged(n))
+                }
+                Err(broadcast::error::RecvError::Closed) => return Err(EventBusError::Closed),
+            }
+        }
+    }
+
+    // Next matching event if one is already queued.
+    pub fn try_recv(&mut self) -> Option<Result<HouseEvent, EventBusError>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) if self.filter.matches(&event) => return Some(Ok(event)),
+                Ok(_) => continue,
+                Err(broadcast::error::TryRecvError::Empty) => return None,
+                Err(broadcast::error::TryRecvError::Lagged(n)) => {
+                    return Some(Err(EventBusError::Lagged(n)))
+                }
+                Err(broadcast::error::TryRecvError::Closed) => {
+                    return Some(Err(EventBusError::Closed))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_filters_by_room_device_and_type() {
+        let bus = EventBus::default();
+        bus.set_room("Lamp", "Living Room");
+        bus.set_room("Heater", "Bedroom");
+        let mut living = bus.subscribe(EventFilter::all().room("Living Room"));
+        let mut heater_errors = bus.subscribe(
+            EventFilter::all()
+                .device("Heater")
+                .event_type(EventType::Error),
+        );
+
+        bus.publish(HouseEvent::new(
+            Some("Heater"),
+            EventKind::SocketStateChanged {
+                state: SocketState::On,
+            },
+        ));
+        bus.publish(HouseEvent::new(
+            Some("Lamp"),
+            EventKind::SocketStateChanged {
+                state: SocketState::Off,
+            },
+        ));
+        bus.publish(HouseEvent::new(
+            Some("Heater"),
+            EventKind::Error {
+                message: "overheated".to_string(),
+            },
+        ));
+
+        let event = living.recv().await.unwrap();
+        assert_eq!(event.device.as_deref(), Some("Lamp"));
+        assert_eq!(event.room.as_deref(), Some("Living Room"));
+        assert!(living.try_recv().is_none());
+
+        let event = heater_errors.recv().await.unwrap();
+        assert_eq!(event.room.as_deref(), Some("Bedroom"));
+        assert_eq!(event.kind.event_type(), EventType::Error);
+        assert!(heater_errors.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber() {
+        let bus = EventBus::new(1);
+        let mut subscription = bus.subscribe(EventFilter::all());
+        for _ in 0..3 {
+            bus.publish(HouseEvent::new(None, EventKind::DeviceAdded));
+        }
+        assert_eq!(subscription.recv().await, Err(EventBusError::Lagged(2)));
+        assert!(subscription.recv().await.is_ok());
+    }
+}
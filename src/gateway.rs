@@ -0,0 +1,901 @@
+use crate::alerts::AlertMonitor;
+use crate::automation::Automations;
+use crate::coap::CoapServer;
+use crate::config::{DeviceConfig, HouseConfig};
+use crate::device_info::devices::{SocketState, ThermometerState};
+use crate::events::{
+    EventBus, EventBusError, EventFilter, EventKind, EventSubscription, EventType, HouseEvent,
+};
+use crate::metrics::Metrics;
+use crate::modes::ModeController;
+use crate::readings::{ReadingFeed, ReadingStream};
+use crate::rules::{RuleOutcome, RulesEngine};
+use crate::scheduler::Scheduler;
+use crate::service::{ServiceError, ServiceHandle, Shutdown};
+use crate::smart_house::{ControlError, SmartHouse};
+use crate::smart_socket::smart_socket_client::{confirm_switch, SmartSocketClient};
+use crate::storage::Storage;
+use crate::udp_thermometer::udp_thermometer_listener::UdpThermometerListener;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
+
+// The live house model shared by everything the gateway runs.
+pub type SharedHouse = Arc<Mutex<SmartHouse>>;
+
+// The mode controller shared by the gateway and the APIs that change modes.
+// Whoever needs both locks takes the house first.
+pub type SharedModes = Arc<Mutex<ModeController>>;
+
+// Keeps one live `SmartHouse` in sync with the network: sockets are driven
+// through their `SmartSocketServer`s and thermometer datagrams update the
+// thermometers they belong to. Changes made to the house by anyone, e.g. an
+// automation or an API, are pushed to the socket servers, and changes made
+// on a server are picked up on the next poll.
+pub struct Gateway {
+    house: SharedHouse,
+    // Socket name and the address of its server.
+    sockets: Vec<(String, String)>,
+    // Thermometer name and the address to listen on for its datagrams.
+    thermometers: Vec<(String, String)>,
+    automations: Option<Automations>,
+    poll_interval: Duration,
+    feed: ReadingFeed,
+    metrics: Metrics,
+    storage: Option<Storage>,
+    alerts: Option<AlertMonitor>,
+    rules: Option<RulesEngine>,
+    modes: Option<SharedModes>,
+    // Address of the CoAP server for sensors, if one is run.
+    coap: Option<String>,
+}
+
+impl Gateway {
+    pub fn new(house: SmartHouse) -> Self {
+        Self {
+            house: Arc::new(Mutex::new(house)),
+            sockets: Vec::new(),
+            thermometers: Vec::new(),
+            automations: None,
+            poll_interval: Duration::from_secs(5),
+            feed: ReadingFeed::default(),
+            metrics: Metrics::default(),
+            storage: None,
+            alerts: None,
+            rules: None,
+            modes: None,
+            coap: None,
+        }
+    }
+
+    // Build the house from a config and wire up every device that has an address.
+    pub fn from_config(config: &HouseConfig) -> Self {
+        let mut gateway = Self::new(config.to_house());
+        for device in config.rooms.iter().flat_map(|room| room.devices.iter()) {
+            match (device, device.address()) {
+                (DeviceConfig::Socket { name, .. }, Some(address)) => {
+                    gateway = gateway.with_socket_server(name, address);
+                }
+                (DeviceConfig::Thermometer { name, .. }, Some(address)) => {
+                    gateway = gateway.with_thermometer_listener(name, address);
+                }
+                _ => {}
+            }
+        }
+        gateway
+    }
+
+    pub fn with_socket_server(mut self, socket: &str, address: &str) -> Self {
+        self.sockets.push((socket.to_string(), address.to_string()));
+        self
+    }
+
+    pub fn with_thermometer_listener(mut self, thermometer: &str, address: &str) -> Self {
+        self.thermometers
+            .push((thermometer.to_string(), address.to_string()));
+        self
+    }
+
+    // Accept CoAP readings for the house's thermometers on this address.
+    pub fn with_coap_server(mut self, address: &str) -> Self {
+        self.coap = Some(address.to_string());
+        self
+    }
+
+    // Run these automations on house events and on their time triggers.
+    pub fn with_automations(mut self, automations: Automations) -> Self {
+        self.automations = Some(automations);
+        self
+    }
+
+    // How often socket servers are polled and the house's load shedding and
+    // interlocks are enforced.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    // Record datagram counters and socket energy in a shared registry.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // Record readings and socket changes in a history database.
+    pub fn with_storage(mut self, storage: Storage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    // Evaluate these alert rules against every thermometer reading on the
+    // feed, whether it came as a datagram or over CoAP.
+    pub fn with_alerts(mut self, monitor: AlertMonitor) -> Self {
+        self.alerts = Some(monitor);
+        self
+    }
+
+    // Evaluate these rules against the house after every thermometer
+    // reading, so a rule's hold time ends with the first reading after it.
+    pub fn with_rules(mut self, rules: RulesEngine) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    // Restore the house mode when starting and run the Vacation presence
+    // simulation. Give the same controller to the APIs changing modes.
+    pub fn with_modes(mut self, modes: SharedModes) -> Self {
+        self.modes = Some(modes);
+        self
+    }
+
+    pub fn house(&self) -> SharedHouse {
+        self.house.clone()
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    // Stream of every thermometer reading the gateway receives.
+    pub fn readings(&self) -> ReadingStream {
+        self.feed.subscribe()
+    }
+
+    // Start the listeners, socket links and house maintenance in background
+    // tasks. Fails if the saved house mode can't be restored.
+    pub async fn start(&mut self) -> std::io::Result<GatewayHandle> {
+        if let Some(modes) = &self.modes {
+            let mut house = self.house.lock().await;
+            modes
+                .lock()
+                .await
+                .restore(&mut house, Utc::now())
+                .map_err(std::io::Error::other)?;
+        }
+        let mut services = Vec::new();
+        let mut thermometers = HashMap::new();
+
+        // Subscribe before any listener can publish.
+        let readings = self.feed.subscribe();
+        for (name, address) in &self.thermometers {
+            let listener = UdpThermometerListener::new(address, name)
+                .with_feed(self.feed.clone())
+                .with_metrics(self.metrics.clone());
+            let handle = listener.start_listening().await?;
+            if let Some(addr) = handle.local_addr() {
+                thermometers.insert(name.clone(), addr);
+            }
+            services.push(handle);
+        }
+        let mut coap_addr = None;
+        if let Some(address) = &self.coap {
+            let server = CoapServer::new(address, self.house.clone()).with_feed(self.feed.clone());
+            let handle = server.start().await?;
+            coap_addr = handle.local_addr();
+            services.push(handle);
+        }
+        let house = self.house.clone();
+        let alerts = self.alerts.clone();
+        let rules = self.rules.take();
+        services.push(ServiceHandle::spawn_task(|shutdown| {
+            apply_readings(house, readings, alerts, rules, shutdown)
+        }));
+
+        let events = self.house.lock().await.events().clone();
+        for (name, address) in &self.sockets {
+            let house = self.house.clone();
+            let changes = events.subscribe(
+                EventFilter::all()
+                    .device(name)
+                    .event_type(EventType::SocketStateChanged),
+            );
+            let link = RemoteSocket::new(name, address, events.clone(), self.metrics.clone());
+            let interval = self.poll_interval;
+            services.push(ServiceHandle::spawn_task(|shutdown| {
+                drive_socket(house, link, changes, interval, shutdown)
+            }));
+        }
+
+        services.push(self.metrics.track(self.house.clone()).await);
+        if let Some(storage) = &self.storage {
+            services.push(storage.record(self.house.clone()).await);
+        }
+
+        let house = self.house.clone();
+        let all_events = events.subscribe(EventFilter::all());
+        let automations = self.automations.take();
+        let modes = self.modes.clone();
+        let interval = self.poll_interval;
+        services.push(ServiceHandle::spawn_task(|shutdown| {
+            maintain(house, automations, modes, all_events, interval, shutdown)
+        }));
+
+        Ok(GatewayHandle {
+            house: self.house.clone(),
+            thermometers,
+            coap_addr,
+            services,
+        })
+    }
+}
+
+// Handle to a running gateway.
+pub struct GatewayHandle {
+    house: SharedHouse,
+    thermometers: HashMap<String, SocketAddr>,
+    coap_addr: Option<SocketAddr>,
+    services: Vec<ServiceHandle>,
+}
+
+impl GatewayHandle {
+    pub fn house(&self) -> SharedHouse {
+        self.house.clone()
+    }
+
+    // Address the gateway listens on for a thermometer's datagrams.
+    pub fn thermometer_addr(&self, thermometer: &str) -> Option<SocketAddr> {
+        self.thermometers.get(thermometer).copied()
+    }
+
+    // Address of the CoAP server, if the gateway runs one.
+    pub fn coap_addr(&self) -> Option<SocketAddr> {
+        self.coap_addr
+    }
+
+    // Stop every task and wait for them; reports the first failure.
+    pub async fn shutdown(self) -> Result<(), ServiceError> {
+        for service in &self.services {
+            service.stop();
+        }
+        let mut result = Ok(());
+        for service in self.services {
+            let joined = service.join().await;
+            if result.is_ok() {
+                result = joined;
+            }
+        }
+        result
+    }
+}
+
+// Apply the readings of every source to the house, check them for alerts
+// and evaluate the rules against the updated house.
+async fn apply_readings(
+    house: SharedHouse,
+    mut readings: ReadingStream,
+    alerts: Option<AlertMonitor>,
+    mut rules: Option<RulesEngine>,
+    mut shutdown: Shutdown,
+) -> std::io::Result<()> {
+    loop {
+        let reading = tokio::select! {
+            _ = shutdown.requested() => return Ok(()),
+            reading = readings.next() => reading,
+        };
+        match reading {
+            Some(Ok(reading)) => {
+                if let Some(alerts) = &alerts {
+                    alerts.process(&reading.thermometer, reading.temperature, Instant::now());
+                }
+                let state = ThermometerState::Temperature(reading.temperature);
+                let mut house = house.lock().await;
+                if let Err(e) = house.set_thermometer_state(&reading.thermometer, state) {
+                    house.events().publish(HouseEvent::new(
+                        Some(&reading.thermometer),
+                        EventKind::Error {
+                            message: format!("ignoring reading: {}", e),
+                        },
+                    ));
+                    continue;
+                }
+                if let Some(rules) = &mut rules {
+                    for entry in rules.evaluate(&mut house, Instant::now()) {
+                        if let RuleOutcome::Failed(e) = &entry.outcome {
+                            house.events().publish(HouseEvent::new(
+                                None,
+                                EventKind::Error {
+                                    message: format!("rule {} failed: {}", entry.rule, e),
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+            // Missed readings are superseded by the next one.
+            Some(Err(_)) => {}
+            None => return Ok(()),
+        }
+    }
+}
+
+// Push house changes of one socket to its server and poll the server for
+// changes made there.
+async fn drive_socket(
+    house: SharedHouse,
+    mut link: RemoteSocket,
+    mut changes: EventSubscription,
+    interval: Duration,
+    mut shutdown: Shutdown,
+) -> std::io::Result<()> {
+    let mut poll = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = shutdown.requested() => return Ok(()),
+            event = changes.recv() => match event {
+                Ok(HouseEvent { kind: EventKind::SocketStateChanged { state }, .. }) => {
+                    link.push(&house, state).await;
+                }
+                Ok(_) => {}
+                Err(EventBusError::Lagged(_)) => link.poll(&house).await,
+                Err(EventBusError::Closed) => return Ok(()),
+            },
+            _ = poll.tick() => link.poll(&house).await,
+        }
+    }
+}
+
+// Run automations on house events and time triggers, simulate presence
+// while on Vacation, and keep the house within its power budget and
+// interlocks.
+async fn maintain(
+    house: SharedHouse,
+    mut automations: Option<Automations>,
+    modes: Option<SharedModes>,
+    mut events: EventSubscription,
+    interval: Duration,
+    mut shutdown: Shutdown,
+) -> std::io::Result<()> {
+    let scheduler = {
+        let house = house.lock().await;
+        match house.location {
+            Some(location) => Scheduler::default().with_location(location),
+            None => Scheduler::default(),
+        }
+    };
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = shutdown.requested() => return Ok(()),
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if let Some(automations) = &automations {
+                        automations.handle_event(&event, &mut *house.lock().await);
+                    }
+                }
+                Err(EventBusError::Lagged(n)) => {
+                    house.lock().await.events().publish(HouseEvent::new(
+                        None,
+                        EventKind::Error {
+                            message: format!("automations missed {} events", n),
+                        },
+                    ));
+                }
+                Err(EventBusError::Closed) => return Ok(()),
+            },
+            _ = ticks.tick() => {
+                let mut house = house.lock().await;
+                if let Some(automations) = &mut automations {
+                    automations.tick(&mut house, &scheduler, Utc::now());
+                }
+                if let Some(modes) = &modes {
+                    modes.lock().await.tick(&mut house, Utc::now());
+                }
+                house.enforce_interlocks(Instant::now());
+                house.shed_load(Instant::now());
+            }
+        }
+    }
+}
+
+// Connection to the server of one socket, reconnected on demand.
+struct RemoteSocket {
+    name: String,
+    address: String,
+    events: EventBus,
+    // Sessions and command latencies are recorded on this side, as the
+    // socket servers usually run in processes of their own.
+    metrics: Metrics,
+    client: Option<SmartSocketClient>,
+    // Last state the server reported or accepted; unknown until it answers.
+    remote: Option<SocketState>,
+    online: bool,
+}
+
+impl RemoteSocket {
+    fn new(name: &str, address: &str, events: EventBus, metrics: Metrics) -> Self {
+        Self {
+            name: name.to_string(),
+            address: address.to_string(),
+            events,
+            metrics,
+            client: None,
+            remote: None,
+            online: true,
+        }
+    }
+
+    async fn send(&mut self, command: &str) -> Option<String> {
+        if self.client.is_none() {
+            match SmartSocketClient::connect(&self.address).await {
+                Ok(client) => {
+                    self.metrics.tcp_session_opened(&self.name);
+                    self.client = Some(client);
+                }
+                Err(e) => {
+                    self.set_offline(format!("cannot connect to {}: {}", self.address, e));
+                    return None;
+                }
+            }
+        }
+        let client = self.client.as_mut()?;
+        let started = Instant::now();
+        match client.send_command(command).await {
+            Ok(response) => {
+                self.metrics
+                    .command_latency(&self.name, command, started.elapsed());
+                if !self.online {
+                    self.online = true;
+                    self.events
+                        .publish(HouseEvent::new(Some(&self.name), EventKind::Online));
+                }
+                Some(response)
+            }
+            Err(e) => {
+                self.client = None;
+                self.metrics.tcp_session_closed(&self.name);
+                self.set_offline(format!("connection to {} lost: {}", self.address, e));
+                None
+            }
+        }
+    }
+
+    fn set_offline(&mut self, reason: String) {
+        self.remote = None;
+        if self.online {
+            self.online = false;
+            self.events.publish(HouseEvent::new(
+                Some(&self.name),
+                EventKind::Offline { reason },
+            ));
+        }
+    }
+
+    // Diagnostics go on the bus rather than to the terminal, which a
+    // dashboard may be drawing on.
+    fn report(&self, message: String) {
+        self.events.publish(HouseEvent::new(
+            Some(&self.name),
+            EventKind::Error { message },
+        ));
+    }
+
+    // Send the house's state to the server. If the server refuses, the house
+    // goes back to the server's state.
+    async fn push(&mut self, house: &SharedHouse, state: SocketState) {
+        if self.remote.as_ref() == Some(&state) {
+            return;
+        }
+        let command = match state {
+            SocketState::On => "on",
+            SocketState::Off => "off",
+        };
+        let Some(response) = self.send(command).await else {
+            return;
+        };
+        match confirm_switch(&state, response) {
+            Ok(()) => self.remote = Some(state),
+            Err(e) => {
+                self.report(format!("refused `{}`: {}", command, e));
+                if let Some(remote) = self.remote.clone() {
+                    let _ = house.lock().await.switch_socket(&self.name, remote);
+                }
+            }
+        }
+    }
+
+    // Reconcile the house with the server's state. Changes made on the server
+    // are adopted unless they break an interlock, in which case the house's
+    // state is pushed back; a change the server missed while offline is sent.
+    // Servers guarded with `SmartSocketServer::with_gateway` refuse such
+    // changes up front, so only unguarded ones are pushed back.
+    async fn poll(&mut self, house: &SharedHouse) {
+        let Some(response) = self.send("status").await else {
+            return;
+        };
+        let Some(remote) = parse_status(&response) else {
+            self.report(format!("unexpected status: {}", response));
+            return;
+        };
+        let desired = match house.lock().await.socket(&self.name) {
+            Ok(socket) => socket.state.clone(),
+            Err(_) => return,
+        };
+        if remote == desired {
+            self.remote = Some(remote);
+        } else if self.remote.as_ref() == Some(&remote) {
+            self.push(house, desired).await;
+        } else {
+            self.remote = Some(remote.clone());
+            let result = house.lock().await.switch_socket(&self.name, remote);
+            if let Err(ControlError::Interlock(e)) = result {
+                self.report(format!("undoing change made on the server: {}", e));
+                self.push(house, desired).await;
+            }
+        }
+    }
+}
+
+impl Drop for RemoteSocket {
+    fn drop(&mut self) {
+        if self.client.take().is_some() {
+            self.metrics.tcp_session_closed(&self.name);
+        }
+    }
+}
+
+// `On, Power: 100` as answered to `status`.
+fn parse_status(response: &str) -> Option<SocketState> {
+    match response.split(',').next()?.trim() {
+        "On" => Some(SocketState::On),
+        "Off" => Some(SocketState::Off),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::{AlertEvent, AlertRule};
+    use crate::coap::client::CoapClient;
+    use crate::coap::message;
+    use crate::device_info::temperature::Temperature;
+    use crate::interlocks::Constraint;
+    use crate::modes::{HouseMode, ModeSettings};
+    use crate::smart_socket::smart_socket_server::SmartSocketServer;
+    use crate::udp_thermometer::packet;
+    use crate::SmartSocket;
+    use tokio::net::UdpSocket;
+
+    async fn eventually<F>(house: &SharedHouse, check: F)
+    where
+        F: Fn(&SmartHouse) -> bool,
+    {
+        for _ in 0..100 {
+            if check(&*house.lock().await) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("house never reached the expected state");
+    }
+
+    fn config(socket_address: &str) -> HouseConfig {
+        HouseConfig::from_toml(&format!(
+            r#"
+name = "Home"
+
+[[rooms]]
+name = "Kitchen"
+devices = [
+    {{ type = "socket", name = "Kettle", address = "{}" }},
+    {{ type = "socket", name = "Heater", state = "on" }},
+    {{ type = "thermometer", name = "KitchenThermo", address = "127.0.0.1:0" }},
+]
+
+[[interlocks]]
+type = "exclusive"
+name = "Circuit"
+sockets = ["Kettle", "Heater"]
+"#,
+            socket_address
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gateway_keeps_house_in_sync() {
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Kettle".to_string(),
+                state: SocketState::Off,
+                power_consumption: 0.0,
+            },
+        );
+        let server_handle = server.start().await.unwrap();
+        let address = server_handle.local_addr().unwrap().to_string();
+
+        let mut gateway =
+            Gateway::from_config(&config(&address)).with_poll_interval(Duration::from_millis(20));
+        let handle = gateway.start().await.unwrap();
+        let house = handle.house();
+
+        // Thermometer datagrams update the house.
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(
+                &packet::encode(Temperature::celsius(19.5)),
+                handle.thermometer_addr("KitchenThermo").unwrap(),
+            )
+            .await
+            .unwrap();
+        eventually(&house, |house| {
+            matches!(
+                house.thermometer("KitchenThermo").unwrap().state,
+                ThermometerState::Temperature(t) if t == Temperature::celsius(19.5)
+            )
+        })
+        .await;
+
+        // House changes reach the server.
+        {
+            let mut house = house.lock().await;
+            house.switch_socket("Heater", SocketState::Off).unwrap();
+            house.switch_socket("Kettle", SocketState::On).unwrap();
+        }
+        for _ in 0..100 {
+            if server.socket().lock().await.state == SocketState::On {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+
+        // Changes made on the server are picked up.
+        server.socket().lock().await.state = SocketState::Off;
+        eventually(&house, |house| {
+            house.socket("Kettle").unwrap().state == SocketState::Off
+        })
+        .await;
+
+        // The link's session and command latencies show up in the metrics.
+        let metrics = gateway.metrics();
+        let kettle = "room=\"Kitchen\",device=\"Kettle\"";
+        let text = metrics.render(&*house.lock().await, std::time::Instant::now());
+        assert!(text.contains(&format!(
+            "smart_house_tcp_sessions_active{{{}}} 1\n",
+            kettle
+        )));
+        assert!(text.contains(&format!(
+            "smart_house_command_duration_seconds_count{{{},command=\"on\"}} 1\n",
+            kettle
+        )));
+        handle.shutdown().await.unwrap();
+        let text = metrics.render(&*house.lock().await, std::time::Instant::now());
+        assert!(text.contains(&format!(
+            "smart_house_tcp_sessions_active{{{}}} 0\n",
+            kettle
+        )));
+        server_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_coap_readings() {
+        let alerts = AlertMonitor::new();
+        alerts.add_rule(AlertRule::new("Warm", "KitchenThermo").above(Temperature::celsius(20.0)));
+        let mut raised = alerts.subscribe();
+        let mut gateway = Gateway::from_config(&config("127.0.0.1:9"))
+            .with_coap_server("127.0.0.1:0")
+            .with_alerts(alerts);
+        let handle = gateway.start().await.unwrap();
+        let house = handle.house();
+        let mut client = CoapClient::connect(handle.coap_addr().unwrap())
+            .await
+            .unwrap();
+
+        // CoAP readings update the house like datagrams do.
+        let response = client
+            .post("thermometers/KitchenThermo", message::TEXT_PLAIN, b"21.5")
+            .await
+            .unwrap();
+        assert_eq!(response.code, message::Code::CHANGED);
+        eventually(&house, |house| {
+            matches!(
+                house.thermometer("KitchenThermo").unwrap().state,
+                ThermometerState::Temperature(t) if t == Temperature::celsius(21.5)
+            )
+        })
+        .await;
+        // And are checked for alerts.
+        assert!(matches!(
+            raised.recv().await.unwrap(),
+            AlertEvent::Raised { rule, .. } if rule == "Warm"
+        ));
+
+        // And CoAP observers see the datagram readings.
+        client.observe("thermometers/KitchenThermo").await.unwrap();
+        UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .send_to(
+                &packet::encode(Temperature::celsius(18.0)),
+                handle.thermometer_addr("KitchenThermo").unwrap(),
+            )
+            .await
+            .unwrap();
+        let notification = client.notification().await.unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&notification.payload).unwrap();
+        assert_eq!(payload["temperature"], 18.0);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_changes_breaking_interlocks_are_undone() {
+        let server = SmartSocketServer::new("127.0.0.1:0", SmartSocket::default());
+        let server_handle = server.start().await.unwrap();
+        let address = server_handle.local_addr().unwrap().to_string();
+        let mut gateway =
+            Gateway::from_config(&config(&address)).with_poll_interval(Duration::from_millis(20));
+        let handle = gateway.start().await.unwrap();
+        let house = handle.house();
+        assert_eq!(
+            house.lock().await.interlocks.constraints()[0],
+            Constraint::Exclusive {
+                name: "Circuit".to_string(),
+                sockets: vec!["Kettle".to_string(), "Heater".to_string()],
+            }
+        );
+
+        // The heater is on, so turning the kettle on at the server is reverted.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        server.socket().lock().await.state = SocketState::On;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if server.socket().lock().await.state == SocketState::Off {
+                break;
+            }
+        }
+        assert_eq!(server.socket().lock().await.state, SocketState::Off);
+        assert_eq!(
+            house.lock().await.socket("Kettle").unwrap().state,
+            SocketState::Off
+        );
+
+        handle.shutdown().await.unwrap();
+        server_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rules_run_on_readings() {
+        let mut config = config("127.0.0.1:9");
+        config.rules = vec![
+            "cool: if KitchenThermo > 25 then Heater off".to_string(),
+            "boil: if KitchenThermo < 10 then Kettle on".to_string(),
+        ];
+        let mut gateway = Gateway::from_config(&config)
+            .with_rules(config.rules_engine().unwrap().unwrap())
+            .with_coap_server("127.0.0.1:0");
+        let house = gateway.house();
+        let mut errors = house
+            .lock()
+            .await
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::Error));
+        let handle = gateway.start().await.unwrap();
+        let mut client = CoapClient::connect(handle.coap_addr().unwrap())
+            .await
+            .unwrap();
+
+        // The heater is on, so the kettle's rule is refused by the interlock.
+        client
+            .post("thermometers/KitchenThermo", message::TEXT_PLAIN, b"5")
+            .await
+            .unwrap();
+        let event = errors.recv().await.unwrap();
+        assert_eq!(
+            event.kind,
+            EventKind::Error {
+                message: "rule boil failed: Rejected by interlock: Socket Kettle can't be \
+                          switched on while Heater is on (exclusion group Circuit)"
+                    .to_string(),
+            }
+        );
+
+        client
+            .post("thermometers/KitchenThermo", message::TEXT_PLAIN, b"26")
+            .await
+            .unwrap();
+        eventually(&house, |house| {
+            house.socket("Heater").unwrap().state == SocketState::Off
+        })
+        .await;
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gateway_restores_mode_and_simulates_presence() {
+        let path = std::env::temp_dir().join(format!("gateway_mode_{}.toml", std::process::id()));
+        let settings = ModeSettings {
+            essential: vec!["Heater".to_string()],
+            presence: vec!["Heater".to_string()],
+            ..ModeSettings::default()
+        };
+        // Saved by an earlier run of the gateway.
+        ModeController::new(settings.clone())
+            .with_state_file(&path)
+            .set_mode(
+                &mut SmartHouse::new("Home", Vec::new()),
+                HouseMode::Vacation,
+                Utc::now(),
+            )
+            .unwrap();
+
+        let modes = Arc::new(Mutex::new(
+            ModeController::new(settings.clone())
+                .with_state_file(&path)
+                .with_seed(3),
+        ));
+        let mut gateway = Gateway::from_config(&config("127.0.0.1:9"))
+            .with_modes(modes)
+            .with_poll_interval(Duration::from_millis(20));
+        let handle = gateway.start().await.unwrap();
+        let house = handle.house();
+        assert_eq!(house.lock().await.mode, HouseMode::Vacation);
+
+        // The heater follows the presence plan of the same seed.
+        let now = Utc::now().naive_utc();
+        let mut planner = ModeController::new(settings).with_seed(3);
+        let lit = planner
+            .presence_plan(now.date())
+            .iter()
+            .any(|window| window.on <= now && now < window.off);
+        let expected = if lit {
+            SocketState::On
+        } else {
+            SocketState::Off
+        };
+        eventually(&house, |house| {
+            house.socket("Heater").unwrap().state == expected
+        })
+        .await;
+
+        handle.shutdown().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_goes_offline() {
+        // Nothing listens on the port the listener had.
+        let address = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let mut gateway =
+            Gateway::from_config(&config(&address)).with_poll_interval(Duration::from_millis(20));
+        let mut offline = gateway
+            .house()
+            .lock()
+            .await
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::Offline));
+        let handle = gateway.start().await.unwrap();
+        let event = offline.recv().await.unwrap();
+        assert_eq!(event.device.as_deref(), Some("Kettle"));
+        assert_eq!(event.room.as_deref(), Some("Kitchen"));
+        handle.shutdown().await.unwrap();
+    }
+}
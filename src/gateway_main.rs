@@ -0,0 +1,196 @@
+use smart_house::automation::Automations;
+use smart_house::config::HouseConfig;
+use smart_house::events::EventFilter;
+use smart_house::gateway::{Gateway, GatewayHandle};
+use smart_house::http_api::HttpApi;
+use smart_house::mqtt::MqttBridge;
+use smart_house::service::ServiceHandle;
+use smart_house::storage::Storage;
+use smart_house::webhooks::WebhookNotifier;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Usage: gateway [--http ADDRESS] [--mqtt BROKER] [--coap ADDRESS] [house.toml] [automations.txt]
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut http_address = None;
+    let mut mqtt_broker = None;
+    let mut coap_address = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--http" => http_address = args.next(),
+            "--mqtt" => mqtt_broker = args.next(),
+            "--coap" => coap_address = args.next(),
+            _ => positional.push(arg),
+        }
+    }
+    let mut args = positional.into_iter();
+    let config_path = args.next().unwrap_or_else(|| "house.toml".to_string());
+    let config = match HouseConfig::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", config_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut gateway = Gateway::from_config(&config);
+    let alerts = config.alert_monitor();
+    if let Some(alerts) = &alerts {
+        gateway = gateway.with_alerts(alerts.clone());
+    }
+    match config.rules_engine() {
+        Ok(Some(rules)) => gateway = gateway.with_rules(rules),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Failed to load rules: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    let modes = match config.mode_controller() {
+        Ok(modes) => modes.map(|modes| Arc::new(Mutex::new(modes))),
+        Err(e) => {
+            eprintln!("Failed to set up house modes: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(modes) = &modes {
+        gateway = gateway.with_modes(modes.clone());
+    }
+    if let Some(path) = args.next() {
+        match Automations::load_file(&path) {
+            Ok(automations) => gateway = gateway.with_automations(automations),
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(address) = &coap_address {
+        gateway = gateway.with_coap_server(address);
+    }
+    let storage = match &config.storage {
+        Some(storage) => match Storage::open(&storage.path) {
+            Ok(opened) => Some(opened.with_retention(storage.retention)),
+            Err(e) => {
+                eprintln!("Failed to open history storage {}: {}", storage.path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    if let Some(storage) = &storage {
+        gateway = gateway.with_storage(storage.clone());
+    }
+
+    let house = gateway.house();
+    let metrics = gateway.metrics();
+    let mut events = house.lock().await.events().subscribe(EventFilter::all());
+    let handle = match gateway.start().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Failed to start gateway: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("Gateway running for {}", config.name);
+    if let Some(addr) = handle.coap_addr() {
+        println!("CoAP server listening on {}", addr);
+    }
+    // Services started next to the gateway, stopped in reverse order. If one
+    // fails to start, everything already running is stopped.
+    let mut services: Vec<(&str, ServiceHandle)> = Vec::new();
+    if let Some(address) = http_address {
+        let mut api = HttpApi::new(&address, house.clone()).with_metrics(metrics);
+        if let Some(storage) = storage {
+            api = api.with_storage(storage);
+        }
+        if let Some(modes) = modes {
+            api = api.with_modes(modes);
+        }
+        match api.start().await {
+            Ok(api_handle) => {
+                println!("HTTP API listening on {}", address);
+                services.push(("HTTP API", api_handle));
+            }
+            Err(e) => {
+                eprintln!("Failed to start HTTP API on {}: {}", address, e);
+                shut_down(services, handle).await;
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(broker) = mqtt_broker {
+        match MqttBridge::new(&broker, house.clone()).start().await {
+            Ok(mqtt_handle) => {
+                println!("MQTT bridge connected to {}", broker);
+                services.push(("MQTT bridge", mqtt_handle));
+            }
+            Err(e) => {
+                eprintln!("Failed to connect to MQTT broker {}: {}", broker, e);
+                shut_down(services, handle).await;
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if let Some(webhooks) = &config.webhooks {
+        let mut notifier = WebhookNotifier::new(webhooks, house.clone());
+        if let Some(alerts) = &alerts {
+            notifier = notifier.with_alerts(alerts.clone());
+        }
+        match notifier.start().await {
+            Ok(webhooks_handle) => {
+                println!("Notifying {} webhooks", webhooks.hooks.len());
+                services.push(("Webhooks", webhooks_handle));
+            }
+            Err(e) => {
+                eprintln!("Failed to start webhooks: {}", e);
+                shut_down(services, handle).await;
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    // Log every event until Ctrl+C, then print the final state of the house.
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            event = events.recv() => match event {
+                Ok(event) => println!(
+                    "[{}] {}: {:?}",
+                    event.room.as_deref().unwrap_or("-"),
+                    event.device.as_deref().unwrap_or("-"),
+                    event.kind
+                ),
+                Err(e) => eprintln!("{}", e),
+            },
+        }
+    }
+    {
+        let house = house.lock().await;
+        println!("{}", house.create_report(&*house));
+    }
+    if !shut_down(services, handle).await {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+// Stop the services in reverse order, then the gateway. Returns whether the
+// gateway stopped cleanly.
+async fn shut_down(services: Vec<(&str, ServiceHandle)>, gateway: GatewayHandle) -> bool {
+    for (name, service) in services.into_iter().rev() {
+        if let Err(e) = service.shutdown().await {
+            eprintln!("{} stopped with error: {}", name, e);
+        }
+    }
+    if let Err(e) = gateway.shutdown().await {
+        eprintln!("Gateway stopped with error: {}", e);
+        return false;
+    }
+    true
+}
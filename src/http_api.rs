@@ -0,0 +1,781 @@
+use crate::device_info::devices::{Device, SocketState};
+use crate::device_info::temperature::Temperature;
+use crate::gateway::{SharedHouse, SharedModes};
+use crate::live_feed;
+use crate::metrics::Metrics;
+use crate::modes::{HouseMode, ModeChange, ModeError};
+use crate::report::{DeviceReport, HouseReport, ReportFormat};
+use crate::service::ServiceHandle;
+use crate::smart_house::ControlError;
+use crate::storage::{Resolution, Storage, StorageError};
+use crate::{epoch_millis, from_epoch_millis};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+use tokio::net::TcpListener;
+
+// Define an error type for HTTP API requests. Every error is answered with
+// a JSON body `{"error": "...", "status": 404}`.
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("Room named {0} not found")]
+    RoomNotFound(String),
+    #[error("No route for {0}")]
+    NoRoute(String),
+    #[error(transparent)]
+    Control(#[from] ControlError),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("History storage is not enabled")]
+    HistoryDisabled,
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("House modes are not enabled")]
+    ModesDisabled,
+    #[error(transparent)]
+    Mode(#[from] ModeError),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::RoomNotFound(_) | ApiError::NoRoute(_) => StatusCode::NOT_FOUND,
+            ApiError::Control(ControlError::NotFound(_) | ControlError::RoomNotFound(_)) => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::Control(ControlError::Interlock(_) | ControlError::AlreadyExists(_)) => {
+                StatusCode::CONFLICT
+            }
+            ApiError::Control(_) | ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::HistoryDisabled | ApiError::ModesDisabled => StatusCode::NOT_IMPLEMENTED,
+            ApiError::Mode(ModeError::InvalidTransition { .. }) => StatusCode::CONFLICT,
+            ApiError::Storage(_) | ApiError::Mode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    status: u16,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = ErrorBody {
+            error: self.to_string(),
+            status: status.as_u16(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+// Body of `POST /devices/{id}/commands`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Command {
+    pub command: SocketState,
+}
+
+// Body of `PUT /mode` and answer to `GET /mode`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Mode {
+    pub mode: HouseMode,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RoomSummary {
+    pub name: String,
+    pub devices: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    format: Option<String>,
+    // Include what each device did in this many past hours.
+    history: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    // Milliseconds since the Unix epoch; the last 24 hours by default.
+    from: Option<u64>,
+    to: Option<u64>,
+    // `raw` (default), `minute` or `hour`; only used for thermometers.
+    resolution: Option<String>,
+}
+
+#[derive(Clone)]
+struct HistoryState {
+    house: SharedHouse,
+    storage: Option<Storage>,
+}
+
+impl HistoryState {
+    fn storage(&self) -> Result<&Storage, ApiError> {
+        self.storage.as_ref().ok_or(ApiError::HistoryDisabled)
+    }
+}
+
+// Embedded HTTP server exposing the live house model:
+//
+//   GET  /rooms                      rooms and the names of their devices
+//   GET  /rooms/{room}/devices       live state of the devices of a room
+//   GET  /devices/{id}               live state of one device
+//   POST /devices/{id}/commands      `{"command": "on"}` or `{"command": "off"}`
+//   GET  /report?format=json         whole house as json (default), text or csv;
+//                                    `&history=24` adds the last 24 hours
+//   GET  /devices/{id}/history       stored history, see `HistoryQuery`
+//   GET  /mode                       `{"mode": "home"}`
+//   PUT  /mode                       `{"mode": "away"}`, answered with the
+//                                    `ModeChange`
+//   GET  /ws?room=A,B&device=C       WebSocket live feed, see `live_feed`
+//   GET  /metrics                    Prometheus metrics, see `Metrics`
+pub struct HttpApi {
+    address: String,
+    house: SharedHouse,
+    metrics: Metrics,
+    storage: Option<Storage>,
+    modes: Option<SharedModes>,
+}
+
+impl HttpApi {
+    pub fn new(address: &str, house: SharedHouse) -> Self {
+        Self {
+            address: address.to_string(),
+            house,
+            metrics: Metrics::default(),
+            storage: None,
+            modes: None,
+        }
+    }
+
+    // Serve device history and history in reports from this storage.
+    pub fn with_storage(mut self, storage: Storage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    // Change house modes through this controller, which should be the
+    // gateway's. Without one modes can only be read.
+    pub fn with_modes(mut self, modes: SharedModes) -> Self {
+        self.modes = Some(modes);
+        self
+    }
+
+    // Serve these counters on `/metrics` instead of an empty registry.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    // The routes, for serving them from another server.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/rooms", get(list_rooms))
+            .route("/rooms/:room/devices", get(room_devices))
+            .route("/devices/:id", get(device))
+            .route("/devices/:id/commands", post(command))
+            .merge(
+                Router::new()
+                    .route("/mode", get(mode).put(set_mode))
+                    .route("/ws", get(live_feed::handler))
+                    .with_state((self.house.clone(), self.modes.clone())),
+            )
+            .merge(
+                Router::new()
+                    .route("/report", get(report))
+                    .route("/devices/:id/history", get(history))
+                    .with_state(HistoryState {
+                        house: self.house.clone(),
+                        storage: self.storage.clone(),
+                    }),
+            )
+            .merge(
+                Router::new()
+                    .route("/metrics", get(metrics))
+                    .with_state((self.house.clone(), self.metrics.clone())),
+            )
+            .fallback(
+                |uri: axum::http::Uri| async move { ApiError::NoRoute(uri.path().to_string()) },
+            )
+            .with_state(self.house.clone())
+    }
+
+    // Bind the listener and serve requests in a background task.
+    pub async fn start(&self) -> std::io::Result<ServiceHandle> {
+        let listener = TcpListener::bind(&self.address).await?;
+        let local_addr = listener.local_addr()?;
+        let router = self.router();
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                axum::serve(listener, router)
+                    .with_graceful_shutdown(async move { shutdown.requested().await })
+                    .await
+            },
+        ))
+    }
+}
+
+async fn list_rooms(State(house): State<SharedHouse>) -> Json<Vec<RoomSummary>> {
+    let house = house.lock().await;
+    Json(
+        house
+            .rooms()
+            .iter()
+            .map(|room| RoomSummary {
+                name: room.name.clone(),
+                devices: room.list_devices().iter().map(|d| d.to_string()).collect(),
+            })
+            .collect(),
+    )
+}
+
+async fn room_devices(
+    State(house): State<SharedHouse>,
+    Path(room): Path<String>,
+) -> Result<Json<Vec<DeviceReport>>, ApiError> {
+    let house = house.lock().await;
+    let found = house
+        .rooms()
+        .iter()
+        .find(|r| r.name == room)
+        .ok_or(ApiError::RoomNotFound(room))?;
+    Ok(Json(
+        found
+            .devices
+            .iter()
+            .map(|device| DeviceReport::new(&found.name, device, house.temperature_unit))
+            .collect(),
+    ))
+}
+
+async fn device(
+    State(house): State<SharedHouse>,
+    Path(id): Path<String>,
+) -> Result<Json<DeviceReport>, ApiError> {
+    let house = house.lock().await;
+    DeviceReport::find(&house, &id)
+        .map(Json)
+        .ok_or(ApiError::Control(ControlError::NotFound(id)))
+}
+
+async fn command(
+    State(house): State<SharedHouse>,
+    Path(id): Path<String>,
+    body: Result<Json<Command>, JsonRejection>,
+) -> Result<Json<DeviceReport>, ApiError> {
+    let Json(command) = body?;
+    let mut house = house.lock().await;
+    house.switch_socket(&id, command.command)?;
+    DeviceReport::find(&house, &id)
+        .map(Json)
+        .ok_or(ApiError::Control(ControlError::NotFound(id)))
+}
+
+async fn mode(State((house, _)): State<(SharedHouse, Option<SharedModes>)>) -> Json<Mode> {
+    Json(Mode {
+        mode: house.lock().await.mode,
+    })
+}
+
+async fn set_mode(
+    State((house, modes)): State<(SharedHouse, Option<SharedModes>)>,
+    body: Result<Json<Mode>, JsonRejection>,
+) -> Result<Json<ModeChange>, ApiError> {
+    let Json(body) = body?;
+    change_mode(&house, modes.as_ref(), body.mode)
+        .await
+        .map(Json)
+}
+
+// Move the house to `to` through the controller; shared with the live feed.
+pub(crate) async fn change_mode(
+    house: &SharedHouse,
+    modes: Option<&SharedModes>,
+    to: HouseMode,
+) -> Result<ModeChange, ApiError> {
+    let modes = modes.ok_or(ApiError::ModesDisabled)?;
+    let mut house = house.lock().await;
+    Ok(modes.lock().await.set_mode(&mut house, to, Utc::now())?)
+}
+
+async fn metrics(State((house, metrics)): State<(SharedHouse, Metrics)>) -> Response {
+    let text = metrics.render(&*house.lock().await, Instant::now());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response()
+}
+
+async fn report(
+    State(state): State<HistoryState>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, ApiError> {
+    let format = match query.format {
+        Some(format) => format.parse().map_err(ApiError::BadRequest)?,
+        None => ReportFormat::Json,
+    };
+    let mut report = HouseReport::new(&*state.house.lock().await);
+    if let Some(hours) = query.history {
+        let now = SystemTime::now();
+        let from = hours
+            .checked_mul(3600)
+            .and_then(|seconds| now.checked_sub(Duration::from_secs(seconds)))
+            .ok_or_else(|| {
+                ApiError::BadRequest(format!("history of {} hours is too long", hours))
+            })?;
+        report = report.with_history(state.storage()?, from, now)?;
+    }
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type())],
+        report.render(format),
+    )
+        .into_response())
+}
+
+async fn history(
+    State(state): State<HistoryState>,
+    Path(id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let storage = state.storage()?;
+    let to = query.to.map_or_else(SystemTime::now, from_epoch_millis);
+    let from = query
+        .from
+        .map_or(to - Duration::from_secs(24 * 3600), from_epoch_millis);
+    let (is_socket, unit) = {
+        let house = state.house.lock().await;
+        match house.find_device(&id) {
+            Some(device) => (
+                matches!(device, Device::SmartSocket(_)),
+                house.temperature_unit,
+            ),
+            None => return Err(ApiError::Control(ControlError::NotFound(id))),
+        }
+    };
+    let mut body = if is_socket {
+        let changes: Vec<_> = storage
+            .socket_changes(&id, from, to)?
+            .into_iter()
+            .map(|change| {
+                serde_json::json!({
+                    "at": epoch_millis(change.at),
+                    "state": change.state,
+                    "power": change.power,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "type": "socket",
+            "energy_wh": storage.energy(&id, from, to)?,
+            "changes": changes,
+        })
+    } else {
+        let value = |t: Temperature| t.to_unit(unit).value();
+        match query.resolution.as_deref().unwrap_or("raw") {
+            "raw" => {
+                let readings: Vec<_> = storage
+                    .readings(&id, from, to)?
+                    .into_iter()
+                    .map(|r| serde_json::json!({ "at": epoch_millis(r.at), "temperature": value(r.temperature) }))
+                    .collect();
+                serde_json::json!({
+                    "type": "thermometer",
+                    "resolution": "raw",
+                    "unit": unit,
+                    "readings": readings,
+                })
+            }
+            other => {
+                let resolution: Resolution = other.parse().map_err(ApiError::BadRequest)?;
+                let buckets: Vec<_> = storage
+                    .rollups(&id, resolution, from, to)?
+                    .into_iter()
+                    .map(|r| {
+                        serde_json::json!({
+                            "start": epoch_millis(r.start),
+                            "count": r.count,
+                            "min": value(r.min),
+                            "max": value(r.max),
+                            "avg": value(r.avg),
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "type": "thermometer",
+                    "resolution": resolution,
+                    "unit": unit,
+                    "buckets": buckets,
+                })
+            }
+        }
+    };
+    body["device"] = id.into();
+    body["from"] = epoch_millis(from).into();
+    body["to"] = epoch_millis(to).into();
+    Ok(Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modes::{ModeController, ModeSettings};
+    use crate::test_support::{self, shared};
+    use std::net::SocketAddr;
+    use std::time::UNIX_EPOCH;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    struct Reply {
+        status: u16,
+        content_type: String,
+        body: String,
+    }
+
+    impl Reply {
+        fn json(&self) -> serde_json::Value {
+            serde_json::from_str(&self.body).unwrap()
+        }
+    }
+
+    // Minimal HTTP/1.1 client: one request per connection.
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: Option<&str>) -> Reply {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = body.unwrap_or("");
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let content_type = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-type: "))
+            .unwrap_or_default()
+            .to_string();
+        Reply {
+            status,
+            content_type,
+            body: body.to_string(),
+        }
+    }
+
+    fn house() -> SharedHouse {
+        shared(test_support::house())
+    }
+
+    #[tokio::test]
+    async fn test_rooms_and_devices() {
+        let api = HttpApi::new("127.0.0.1:0", house());
+        let handle = api.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let rooms = request(addr, "GET", "/rooms", None).await;
+        assert_eq!(rooms.status, 200);
+        assert_eq!(rooms.content_type, "application/json");
+        assert_eq!(
+            rooms.json(),
+            serde_json::json!([
+                { "name": "Kitchen", "devices": ["Kettle", "Fridge"] },
+                { "name": "Living Room", "devices": ["Thermo"] },
+            ])
+        );
+
+        let devices = request(addr, "GET", "/rooms/Living%20Room/devices", None).await;
+        assert_eq!(
+            devices.json(),
+            serde_json::json!([{
+                "name": "Thermo",
+                "room": "Living Room",
+                "type": "thermometer",
+                "temperature": null,
+                "unit": "celsius",
+            }])
+        );
+
+        let kettle = request(addr, "GET", "/devices/Kettle", None).await;
+        assert_eq!(kettle.json()["state"], "off");
+        assert_eq!(kettle.json()["power_consumption"], 2000.0);
+
+        let missing = request(addr, "GET", "/rooms/Garage/devices", None).await;
+        assert_eq!(missing.status, 404);
+        assert_eq!(
+            missing.json(),
+            serde_json::json!({ "error": "Room named Garage not found", "status": 404 })
+        );
+        let no_route = request(addr, "GET", "/garage", None).await;
+        assert_eq!(no_route.status, 404);
+        assert_eq!(no_route.json()["error"], "No route for /garage");
+
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let house = house();
+        let api = HttpApi::new("127.0.0.1:0", house.clone());
+        let handle = api.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let on = request(
+            addr,
+            "POST",
+            "/devices/Kettle/commands",
+            Some(r#"{"command": "on"}"#),
+        )
+        .await;
+        assert_eq!(on.status, 200);
+        assert_eq!(on.json()["state"], "on");
+        assert_eq!(
+            house.lock().await.socket("Kettle").unwrap().state,
+            SocketState::On
+        );
+
+        let rejected = request(
+            addr,
+            "POST",
+            "/devices/Fridge/commands",
+            Some(r#"{"command": "off"}"#),
+        )
+        .await;
+        assert_eq!(rejected.status, 409);
+        assert_eq!(
+            rejected.json()["error"],
+            "Rejected by interlock: Socket Fridge must always stay on"
+        );
+
+        let thermometer = request(
+            addr,
+            "POST",
+            "/devices/Thermo/commands",
+            Some(r#"{"command": "on"}"#),
+        )
+        .await;
+        assert_eq!(thermometer.status, 400);
+        let missing = request(
+            addr,
+            "POST",
+            "/devices/Toaster/commands",
+            Some(r#"{"command": "on"}"#),
+        )
+        .await;
+        assert_eq!(missing.status, 404);
+        let malformed = request(
+            addr,
+            "POST",
+            "/devices/Kettle/commands",
+            Some(r#"{"command": "dim"}"#),
+        )
+        .await;
+        assert_eq!(malformed.status, 400);
+        assert!(malformed.json()["error"].is_string());
+
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_modes() {
+        let house = house();
+        house
+            .lock()
+            .await
+            .switch_socket("Kettle", SocketState::On)
+            .unwrap();
+        let modes = std::sync::Arc::new(tokio::sync::Mutex::new(ModeController::new(
+            ModeSettings::default(),
+        )));
+        let api = HttpApi::new("127.0.0.1:0", house.clone()).with_modes(modes);
+        let handle = api.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let mode = request(addr, "GET", "/mode", None).await;
+        assert_eq!(mode.json(), serde_json::json!({ "mode": "home" }));
+        // The Fridge has to stay on, so only the Kettle is switched off.
+        let away = request(addr, "PUT", "/mode", Some(r#"{"mode": "away"}"#)).await;
+        assert_eq!(away.status, 200);
+        assert_eq!(
+            away.json(),
+            serde_json::json!({ "from": "home", "to": "away", "switched_off": ["Kettle"] })
+        );
+        assert_eq!(house.lock().await.mode, HouseMode::Away);
+        let night = request(addr, "PUT", "/mode", Some(r#"{"mode": "night"}"#)).await;
+        assert_eq!(night.status, 409);
+        assert_eq!(
+            night.json()["error"],
+            "Cannot switch house mode from away to night"
+        );
+        let mode = request(addr, "GET", "/mode", None).await;
+        assert_eq!(mode.json(), serde_json::json!({ "mode": "away" }));
+        handle.shutdown().await.unwrap();
+
+        // Without a controller modes can only be read.
+        let handle = HttpApi::new("127.0.0.1:0", house).start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+        let home = request(addr, "PUT", "/mode", Some(r#"{"mode": "home"}"#)).await;
+        assert_eq!(home.status, 501);
+        assert_eq!(home.json()["error"], "House modes are not enabled");
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_report_formats() {
+        let api = HttpApi::new("127.0.0.1:0", house());
+        let handle = api.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let json = request(addr, "GET", "/report", None).await;
+        assert_eq!(json.content_type, "application/json");
+        assert_eq!(json.json()["house"], "Home");
+        let csv = request(addr, "GET", "/report?format=csv", None).await;
+        assert_eq!(csv.content_type, "text/csv; charset=utf-8");
+        assert!(csv.body.contains("Kitchen,Kettle,socket,off,2000,,\n"));
+        let text = request(addr, "GET", "/report?format=text", None).await;
+        assert!(text.body.starts_with("House: Home (mode: home)\n"));
+        let unknown = request(addr, "GET", "/report?format=xml", None).await;
+        assert_eq!(unknown.status, 400);
+
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let metrics = Metrics::new();
+        metrics.udp_malformed("Thermo");
+        let api = HttpApi::new("127.0.0.1:0", house()).with_metrics(metrics);
+        let handle = api.start().await.unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let reply = request(addr, "GET", "/metrics", None).await;
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.content_type, "text/plain; version=0.0.4");
+        assert!(reply
+            .body
+            .contains("smart_house_socket_power_watts{room=\"Kitchen\",device=\"Fridge\"} 150\n"));
+        assert!(reply.body.contains(
+            "smart_house_udp_datagrams_malformed_total{room=\"Living Room\",device=\"Thermo\"} 1\n"
+        ));
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let disabled = HttpApi::new("127.0.0.1:0", house()).start().await.unwrap();
+        let addr = disabled.local_addr().unwrap();
+        let reply = request(addr, "GET", "/devices/Kettle/history", None).await;
+        assert_eq!(reply.status, 501);
+        assert_eq!(
+            request(addr, "GET", "/report?history=1", None).await.status,
+            501
+        );
+        disabled.shutdown().await.unwrap();
+
+        let storage = Storage::in_memory().unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_040);
+        storage
+            .record_socket("Kettle", &SocketState::On, 2000.0, start)
+            .unwrap();
+        storage
+            .record_socket(
+                "Kettle",
+                &SocketState::Off,
+                2000.0,
+                start + Duration::from_secs(900),
+            )
+            .unwrap();
+        for (seconds, celsius) in [(0, 20.0), (30, 24.0), (90, 19.0)] {
+            storage
+                .record_reading(
+                    "Thermo",
+                    Temperature::celsius(celsius),
+                    start + Duration::from_secs(seconds),
+                )
+                .unwrap();
+        }
+        let handle = HttpApi::new("127.0.0.1:0", house())
+            .with_storage(storage)
+            .start()
+            .await
+            .unwrap();
+        let addr = handle.local_addr().unwrap();
+        let window = format!(
+            "from={}&to={}",
+            epoch_millis(start),
+            epoch_millis(start) + 3_600_000
+        );
+
+        let kettle = request(
+            addr,
+            "GET",
+            &format!("/devices/Kettle/history?{}", window),
+            None,
+        )
+        .await
+        .json();
+        assert_eq!(kettle["type"], "socket");
+        assert_eq!(kettle["energy_wh"], 500.0);
+        assert_eq!(kettle["changes"][0]["state"], "on");
+        assert_eq!(kettle["changes"][1]["at"], epoch_millis(start) + 900_000);
+
+        let raw = request(
+            addr,
+            "GET",
+            &format!("/devices/Thermo/history?{}", window),
+            None,
+        )
+        .await
+        .json();
+        assert_eq!(raw["readings"].as_array().unwrap().len(), 3);
+        assert_eq!(raw["readings"][1]["temperature"], 24.0);
+        let minutes = request(
+            addr,
+            "GET",
+            &format!("/devices/Thermo/history?{}&resolution=minute", window),
+            None,
+        )
+        .await
+        .json();
+        assert_eq!(minutes["resolution"], "minute");
+        assert_eq!(minutes["buckets"][0]["avg"], 22.0);
+        assert_eq!(minutes["buckets"][1]["count"], 1);
+
+        let bad = request(
+            addr,
+            "GET",
+            &format!("/devices/Thermo/history?{}&resolution=day", window),
+            None,
+        )
+        .await;
+        assert_eq!(bad.status, 400);
+        assert_eq!(
+            request(addr, "GET", "/devices/Oven/history", None)
+                .await
+                .status,
+            404
+        );
+        let report = request(addr, "GET", "/report?history=1", None).await;
+        assert_eq!(report.status, 200);
+        for hours in [u64::MAX, u64::MAX / 3600] {
+            let path = format!("/report?history={}", hours);
+            assert_eq!(request(addr, "GET", &path, None).await.status, 400);
+        }
+        handle.shutdown().await.unwrap();
+    }
+}
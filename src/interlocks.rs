@@ -0,0 +1,277 @@
+use crate::device_info::devices::SocketState;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// Define an error type for commands rejected by an interlock.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum InterlockError {
+    #[error(
+        "Socket {socket} can't be switched on while {conflict} is on (exclusion group {group})"
+    )]
+    Exclusive {
+        socket: String,
+        conflict: String,
+        group: String,
+    },
+    #[error("Socket {0} must always stay on")]
+    AlwaysOn(String),
+}
+
+// A safety constraint between the sockets of a house.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Constraint {
+    // At most one socket of the group may be on at a time, e.g. two heaters
+    // sharing a circuit.
+    Exclusive { name: String, sockets: Vec<String> },
+    // The socket may never be switched off, e.g. the fridge.
+    AlwaysOn { socket: String },
+    // The socket is switched off once it has been on this many seconds.
+    MaxOnDuration { socket: String, seconds: u64 },
+}
+
+impl Constraint {
+    // Sockets the constraint refers to.
+    pub fn sockets(&self) -> Vec<&str> {
+        match self {
+            Constraint::Exclusive { sockets, .. } => sockets.iter().map(String::as_str).collect(),
+            Constraint::AlwaysOn { socket } | Constraint::MaxOnDuration { socket, .. } => {
+                vec![socket.as_str()]
+            }
+        }
+    }
+}
+
+// The constraints of a house. Every command goes through `check` before it
+// is applied, and `overdue` tells which sockets have been on for too long.
+#[derive(Clone, Debug, Default)]
+pub struct Interlocks {
+    constraints: Vec<Constraint>,
+    // When each socket with a maximum on-duration was last seen switching on.
+    on_since: HashMap<String, Instant>,
+}
+
+impl Interlocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, constraint: Constraint) -> Self {
+        self.add(constraint);
+        self
+    }
+
+    pub fn add(&mut self, constraint: Constraint) {
+        self.constraints.push(constraint);
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    // Check whether switching `socket` to `state` is allowed, given whether
+    // each other socket is currently on.
+    pub fn check(
+        &self,
+        socket: &str,
+        state: &SocketState,
+        is_on: impl Fn(&str) -> bool,
+    ) -> Result<(), InterlockError> {
+        for constraint in &self.constraints {
+            match (constraint, state) {
+                (Constraint::AlwaysOn { socket: name }, SocketState::Off) if name == socket => {
+                    return Err(InterlockError::AlwaysOn(socket.to_string()));
+                }
+                (Constraint::Exclusive { name, sockets }, SocketState::On)
+                    if sockets.iter().any(|s| s == socket) =>
+                {
+                    if let Some(conflict) = sockets.iter().find(|s| *s != socket && is_on(s)) {
+                        return Err(InterlockError::Exclusive {
+                            socket: socket.to_string(),
+                            conflict: conflict.clone(),
+                            group: name.clone(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Note a state change that went through, to time on-durations.
+    pub fn record(&mut self, socket: &str, state: &SocketState, now: Instant) {
+        match state {
+            SocketState::On => {
+                self.on_since.entry(socket.to_string()).or_insert(now);
+            }
+            SocketState::Off => {
+                self.on_since.remove(socket);
+            }
+        }
+    }
+
+    // The shortest maximum on-duration of a socket, if it has any.
+    pub fn max_on_duration(&self, socket: &str) -> Option<Duration> {
+        self.constraints
+            .iter()
+            .filter_map(|constraint| match constraint {
+                Constraint::MaxOnDuration {
+                    socket: name,
+                    seconds,
+                } if name == socket => Some(Duration::from_secs(*seconds)),
+                _ => None,
+            })
+            .min()
+    }
+
+    // Sockets that have been on longer than their maximum on-duration. A
+    // socket found on without a recorded start, e.g. one that was on when the
+    // house was loaded, is timed from now.
+    pub fn overdue(&mut self, is_on: impl Fn(&str) -> bool, now: Instant) -> Vec<String> {
+        let mut overdue = Vec::new();
+        let limited: Vec<(String, Duration)> = self
+            .constraints
+            .iter()
+            .filter_map(|constraint| match constraint {
+                Constraint::MaxOnDuration { socket, .. } => Some(socket.clone()),
+                _ => None,
+            })
+            .filter_map(|socket| self.max_on_duration(&socket).map(|limit| (socket, limit)))
+            .collect();
+        for (socket, limit) in limited {
+            if !is_on(&socket) {
+                self.on_since.remove(&socket);
+                continue;
+            }
+            let since = *self.on_since.entry(socket.clone()).or_insert(now);
+            if now.saturating_duration_since(since) >= limit && !overdue.contains(&socket) {
+                overdue.push(socket);
+            }
+        }
+        overdue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventFilter, EventKind, EventType};
+    use crate::smart_house::{ControlError, SmartHouse};
+    use crate::test_support::{room, socket};
+
+    fn house() -> SmartHouse {
+        let mut house = SmartHouse::new(
+            "Home",
+            vec![room(
+                "Kitchen",
+                vec![
+                    socket("Fridge", SocketState::On, 1000.0),
+                    socket("Heater1", SocketState::On, 1000.0),
+                    socket("Heater2", SocketState::Off, 1000.0),
+                    socket("Kettle", SocketState::Off, 1000.0),
+                ],
+            )],
+        );
+        house.interlocks = Interlocks::new()
+            .with(Constraint::Exclusive {
+                name: "Circuit A".to_string(),
+                sockets: vec!["Heater1".to_string(), "Heater2".to_string()],
+            })
+            .with(Constraint::AlwaysOn {
+                socket: "Fridge".to_string(),
+            })
+            .with(Constraint::MaxOnDuration {
+                socket: "Kettle".to_string(),
+                seconds: 300,
+            });
+        house
+    }
+
+    #[test]
+    fn test_rejects_violating_commands() {
+        let mut house = house();
+        let error = house.switch_socket("Heater2", SocketState::On).unwrap_err();
+        assert_eq!(
+            error,
+            ControlError::Interlock(InterlockError::Exclusive {
+                socket: "Heater2".to_string(),
+                conflict: "Heater1".to_string(),
+                group: "Circuit A".to_string(),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "Rejected by interlock: Socket Heater2 can't be switched on while Heater1 is on (exclusion group Circuit A)"
+        );
+        assert_eq!(house.socket("Heater2").unwrap().state, SocketState::Off);
+        assert_eq!(
+            house.switch_socket("Fridge", SocketState::Off),
+            Err(ControlError::Interlock(InterlockError::AlwaysOn(
+                "Fridge".to_string()
+            )))
+        );
+
+        // Once the first heater is off the second may run.
+        house.switch_socket("Heater1", SocketState::Off).unwrap();
+        house.switch_socket("Heater2", SocketState::On).unwrap();
+        // Switching a socket to the state it is already in is always fine.
+        house.switch_socket("Fridge", SocketState::On).unwrap();
+    }
+
+    #[test]
+    fn test_scenes_are_checked() {
+        use crate::scene::{Scene, SceneTarget, Selector};
+
+        let mut house = house();
+        house.add_scene(Scene {
+            name: "Leaving".to_string(),
+            targets: vec![SceneTarget {
+                selector: Selector::All,
+                state: SocketState::Off,
+            }],
+        });
+        let report = house.apply_scene("Leaving", true).unwrap();
+        assert!(!report.succeeded());
+        // The rollback leaves everything as it was.
+        assert_eq!(house.socket("Heater1").unwrap().state, SocketState::On);
+        assert_eq!(house.socket("Fridge").unwrap().state, SocketState::On);
+    }
+
+    #[test]
+    fn test_max_on_duration() {
+        let mut house = house();
+        let start = Instant::now();
+        house.switch_socket("Kettle", SocketState::On).unwrap();
+        let mut enforced = house
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::InterlockEnforced));
+        assert!(house.enforce_interlocks(start).is_empty());
+        let switched = house.enforce_interlocks(start + Duration::from_secs(301));
+        assert_eq!(switched, vec!["Kettle".to_string()]);
+        assert_eq!(house.socket("Kettle").unwrap().state, SocketState::Off);
+        let event = enforced.try_recv().unwrap().unwrap();
+        assert_eq!(event.device.as_deref(), Some("Kettle"));
+        assert_eq!(
+            event.kind,
+            EventKind::InterlockEnforced {
+                reason: "switched off after its maximum on-duration of 300 s".to_string()
+            }
+        );
+
+        // The timer starts again the next time the kettle is switched on.
+        house.switch_socket("Kettle", SocketState::On).unwrap();
+        let now = Instant::now();
+        assert!(house.enforce_interlocks(now).is_empty());
+        assert_eq!(
+            house.enforce_interlocks(now + Duration::from_secs(300)),
+            vec!["Kettle".to_string()]
+        );
+    }
+}
@@ -1,11 +1,57 @@
+pub mod alerts;
+pub mod automation;
+pub mod cli;
+pub mod coap;
+pub mod config;
+pub mod dashboard;
 pub mod device_info;
+pub mod events;
+pub mod gateway;
+pub mod http_api;
+pub mod interlocks;
+pub mod live_feed;
+pub mod load_shedding;
+pub mod metrics;
+pub mod modes;
+pub mod mqtt;
+pub mod readings;
+pub mod report;
+pub mod rules;
+pub mod scene;
+pub mod scheduler;
+pub mod service;
+pub mod smart_house;
+pub mod smart_socket;
+pub mod storage;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod thermostat;
+pub mod udp_thermometer;
+pub mod webhooks;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub use device_info::devices::*;
+pub use device_info::temperature::*;
 pub use device_info::*;
+pub use smart_house::*;
 
 pub mod prelude {
     pub use crate::device_info::devices::*;
+    pub use crate::device_info::temperature::*;
     pub use crate::device_info::*;
+    pub use crate::smart_house::*;
+}
+
+// Milliseconds since the Unix epoch, as timestamps are stored and sent.
+// Times before the epoch count as the epoch.
+pub fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+pub fn from_epoch_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
 }
 
 #[cfg(test)]
@@ -27,15 +73,22 @@ mod tests {
     fn test_smart_thermometer_creation() {
         let thermo = SmartThermometer {
             name: "TestThermo".to_string(),
-            state: ThermometerState::Temperature(23.0),
+            state: ThermometerState::Temperature(Temperature::celsius(23.0)),
         };
         assert_eq!(thermo.name, "TestThermo");
         match thermo.state {
-            ThermometerState::Temperature(t) => assert_eq!(t, 23.0),
+            ThermometerState::Temperature(t) => assert_eq!(t, Temperature::celsius(23.0)),
             _ => panic!("Unexpected state for the thermometer"),
         }
     }
 
+    #[test]
+    fn test_epoch_millis() {
+        let time = from_epoch_millis(1_700_000_040_123);
+        assert_eq!(epoch_millis(time), 1_700_000_040_123);
+        assert_eq!(epoch_millis(UNIX_EPOCH - Duration::from_secs(1)), 0);
+    }
+
     #[test]
     fn test_owning_device_info_provider() {
         let socket = SmartSocket {
@@ -60,7 +113,7 @@ mod tests {
         };
         let thermo = SmartThermometer {
             name: "ThermoInKitchen".to_string(),
-            state: ThermometerState::Temperature(20.0),
+            state: ThermometerState::Temperature(Temperature::celsius(20.0)),
         };
         let provider = BorrowingDeviceInfoProvider {
             socket: &socket,
@@ -74,7 +127,7 @@ mod tests {
         let thermo_info = provider.device_info("Kitchen", "ThermoInKitchen").unwrap();
         assert_eq!(
             thermo_info,
-            "Room: Kitchen, Device: SmartThermometer named ThermoInKitchen, State: Temperature(20.0)"
+            "Room: Kitchen, Device: SmartThermometer named ThermoInKitchen, State: Temperature(20.0°C)"
         );
     }
 }
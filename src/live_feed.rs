@@ -0,0 +1,529 @@
+use crate::device_info::devices::SocketState;
+use crate::device_info::temperature::{Temperature, TemperatureUnit};
+use crate::epoch_millis;
+use crate::events::{EventBusError, EventFilter, EventKind, HouseEvent};
+use crate::gateway::{SharedHouse, SharedModes};
+use crate::http_api;
+use crate::load_shedding::ShedKind;
+use crate::modes::HouseMode;
+use crate::report::HouseReport;
+use crate::smart_house::SmartHouse;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+
+// Which part of the house a client follows, from `?room=A,B&device=C`.
+// Empty lists follow everything.
+#[derive(Clone, PartialEq, Debug, Default, Deserialize)]
+pub struct Selection {
+    #[serde(default, rename = "room", deserialize_with = "comma_separated")]
+    pub rooms: Vec<String>,
+    #[serde(default, rename = "device", deserialize_with = "comma_separated")]
+    pub devices: Vec<String>,
+}
+
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    Ok(text
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+impl Selection {
+    pub fn filter(&self) -> EventFilter {
+        let filter = self
+            .rooms
+            .iter()
+            .fold(EventFilter::all(), |filter, room| filter.room(room));
+        self.devices
+            .iter()
+            .fold(filter, |filter, device| filter.device(device))
+    }
+
+    // The house report cut down to the selected rooms and devices.
+    pub fn snapshot(&self, house: &SmartHouse) -> HouseReport {
+        let mut report = HouseReport::new(house);
+        report
+            .rooms
+            .retain(|room| self.rooms.is_empty() || self.rooms.contains(&room.name));
+        for room in &mut report.rooms {
+            room.devices
+                .retain(|device| self.devices.is_empty() || self.devices.contains(&device.name));
+        }
+        report
+            .rooms
+            .retain(|room| self.devices.is_empty() || !room.devices.is_empty());
+        report
+    }
+}
+
+// A change as sent to feed clients.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    DeviceAdded,
+    DeviceRemoved,
+    SocketSwitched {
+        state: SocketState,
+    },
+    TemperatureReceived {
+        temperature: f32,
+        unit: TemperatureUnit,
+    },
+    ModeChanged {
+        from: HouseMode,
+        to: HouseMode,
+    },
+    LoadShedding {
+        action: ShedKind,
+        priority: i32,
+        total_before: f32,
+        total_after: f32,
+    },
+    InterlockEnforced {
+        reason: String,
+    },
+    Offline {
+        reason: String,
+    },
+    Online,
+    Error {
+        message: String,
+    },
+}
+
+impl From<&EventKind> for Change {
+    fn from(kind: &EventKind) -> Self {
+        match kind {
+            EventKind::DeviceAdded => Change::DeviceAdded,
+            EventKind::DeviceRemoved => Change::DeviceRemoved,
+            EventKind::SocketStateChanged { state } => Change::SocketSwitched {
+                state: state.clone(),
+            },
+            EventKind::ReadingReceived { temperature } => Change::TemperatureReceived {
+                temperature: temperature.value(),
+                unit: temperature.unit(),
+            },
+            EventKind::ModeChanged { from, to } => Change::ModeChanged {
+                from: *from,
+                to: *to,
+            },
+            EventKind::LoadShedding {
+                action,
+                priority,
+                total_before,
+                total_after,
+            } => Change::LoadShedding {
+                action: *action,
+                priority: *priority,
+                total_before: *total_before,
+                total_after: *total_after,
+            },
+            EventKind::InterlockEnforced { reason } => Change::InterlockEnforced {
+                reason: reason.clone(),
+            },
+            EventKind::Offline { reason } => Change::Offline {
+                reason: reason.clone(),
+            },
+            EventKind::Online => Change::Online,
+            EventKind::Error { message } => Change::Error {
+                message: message.clone(),
+            },
+        }
+    }
+}
+
+// The event a change was made from, for clients following the feed.
+impl From<Change> for EventKind {
+    fn from(change: Change) -> Self {
+        match change {
+            Change::DeviceAdded => EventKind::DeviceAdded,
+            Change::DeviceRemoved => EventKind::DeviceRemoved,
+            Change::SocketSwitched { state } => EventKind::SocketStateChanged { state },
+            Change::TemperatureReceived { temperature, unit } => EventKind::ReadingReceived {
+                temperature: Temperature::new(temperature, unit),
+            },
+            Change::ModeChanged { from, to } => EventKind::ModeChanged { from, to },
+            Change::LoadShedding {
+                action,
+                priority,
+                total_before,
+                total_after,
+            } => EventKind::LoadShedding {
+                action,
+                priority,
+                total_before,
+                total_after,
+            },
+            Change::InterlockEnforced { reason } => EventKind::InterlockEnforced { reason },
+            Change::Offline { reason } => EventKind::Offline { reason },
+            Change::Online => EventKind::Online,
+            Change::Error { message } => EventKind::Error { message },
+        }
+    }
+}
+
+// Messages sent by the server, as JSON text frames.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage {
+    // Always the first message: the selected part of the house as it is now.
+    Snapshot {
+        house: HouseReport,
+    },
+    Event {
+        // Milliseconds since the Unix epoch.
+        at: u64,
+        room: Option<String>,
+        device: Option<String>,
+        change: Change,
+    },
+    // The client fell behind and this many events were dropped; the next
+    // snapshot brings it up to date.
+    Lagged {
+        missed: u64,
+    },
+    // Answer to a command that was applied.
+    Ack {
+        device: String,
+        state: SocketState,
+    },
+    // Answer to a mode change that was applied.
+    ModeSet {
+        from: HouseMode,
+        to: HouseMode,
+        switched_off: Vec<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl From<&HouseEvent> for FeedMessage {
+    fn from(event: &HouseEvent) -> Self {
+        FeedMessage::Event {
+            at: epoch_millis(event.at),
+            room: event.room.clone(),
+            device: event.device.clone(),
+            change: Change::from(&event.kind),
+        }
+    }
+}
+
+// Messages accepted from clients.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    // `{"type": "command", "device": "Lamp", "command": "on"}`
+    Command {
+        device: String,
+        command: SocketState,
+    },
+    // `{"type": "set_mode", "mode": "away"}`
+    SetMode {
+        mode: HouseMode,
+    },
+}
+
+// `GET /ws`: upgrade to a live feed of the selected rooms and devices.
+pub(crate) async fn handler(
+    ws: WebSocketUpgrade,
+    State((house, modes)): State<(SharedHouse, Option<SharedModes>)>,
+    Query(selection): Query<Selection>,
+) -> Response {
+    ws.on_upgrade(move |socket| run(socket, house, modes, selection))
+}
+
+async fn send(socket: &mut WebSocket, message: &FeedMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(text) => socket.send(Message::Text(text)).await.is_ok(),
+        Err(_) => true,
+    }
+}
+
+async fn run(
+    mut socket: WebSocket,
+    house: SharedHouse,
+    modes: Option<SharedModes>,
+    selection: Selection,
+) {
+    // Subscribe under the same lock as the snapshot so no change falls between them.
+    let (snapshot, mut events) = {
+        let house = house.lock().await;
+        (
+            selection.snapshot(&house),
+            house.events().subscribe(selection.filter()),
+        )
+    };
+    if !send(&mut socket, &FeedMessage::Snapshot { house: snapshot }).await {
+        return;
+    }
+
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                // Events don't describe devices, so a snapshot introduces
+                // the new one.
+                Ok(event) if event.kind == EventKind::DeviceAdded => {
+                    if !send(&mut socket, &FeedMessage::from(&event)).await {
+                        return;
+                    }
+                    FeedMessage::Snapshot {
+                        house: selection.snapshot(&*house.lock().await),
+                    }
+                }
+                Ok(event) => FeedMessage::from(&event),
+                Err(EventBusError::Lagged(missed)) => {
+                    if !send(&mut socket, &FeedMessage::Lagged { missed }).await {
+                        return;
+                    }
+                    FeedMessage::Snapshot {
+                        house: selection.snapshot(&*house.lock().await),
+                    }
+                }
+                Err(EventBusError::Closed) => return,
+            },
+            received = socket.recv() => match received {
+                Some(Ok(Message::Text(text))) => command(&house, modes.as_ref(), &text).await,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered by axum; binary frames are ignored.
+                Some(Ok(_)) => continue,
+            },
+        };
+        if !send(&mut socket, &message).await {
+            return;
+        }
+    }
+}
+
+async fn command(house: &SharedHouse, modes: Option<&SharedModes>, text: &str) -> FeedMessage {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return FeedMessage::Error {
+                message: format!("invalid message: {}", e),
+            }
+        }
+    };
+    match message {
+        ClientMessage::Command { device, command } => {
+            match house.lock().await.switch_socket(&device, command.clone()) {
+                Ok(()) => FeedMessage::Ack {
+                    device,
+                    state: command,
+                },
+                Err(e) => FeedMessage::Error {
+                    message: e.to_string(),
+                },
+            }
+        }
+        ClientMessage::SetMode { mode } => match http_api::change_mode(house, modes, mode).await {
+            Ok(change) => FeedMessage::ModeSet {
+                from: change.from,
+                to: change.to,
+                switched_off: change.switched_off,
+            },
+            Err(e) => FeedMessage::Error {
+                message: e.to_string(),
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeedMessage;
+    use crate::device_info::devices::{Device, SmartSocket, SocketState, ThermometerState};
+    use crate::device_info::temperature::{Temperature, TemperatureUnit};
+    use crate::events::{EventKind, HouseEvent};
+    use crate::gateway::SharedHouse;
+    use crate::http_api::HttpApi;
+    use crate::report::DeviceStatus;
+    use crate::smart_house::SmartHouse;
+    use crate::test_support::{room, shared, socket, thermometer};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn house() -> SharedHouse {
+        let off = |name: &str| socket(name, SocketState::Off, 100.0);
+        shared(SmartHouse::new(
+            "Home",
+            vec![
+                room("Kitchen", vec![off("Kettle"), off("Toaster")]),
+                room(
+                    "Bedroom",
+                    vec![
+                        off("Lamp"),
+                        thermometer("BedroomThermo", ThermometerState::Off),
+                    ],
+                ),
+            ],
+        ))
+    }
+
+    async fn connect(addr: SocketAddr, query: &str) -> Client {
+        let (client, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws{}", addr, query))
+            .await
+            .unwrap();
+        client
+    }
+
+    async fn next(client: &mut Client) -> Value {
+        loop {
+            match client.next().await.unwrap().unwrap() {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_then_events() {
+        let house = house();
+        let handle = HttpApi::new("127.0.0.1:0", house.clone())
+            .start()
+            .await
+            .unwrap();
+        let mut client = connect(handle.local_addr().unwrap(), "").await;
+
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["house"]["rooms"].as_array().unwrap().len(), 2);
+
+        {
+            let mut house = house.lock().await;
+            house.switch_socket("Kettle", SocketState::On).unwrap();
+            house
+                .set_thermometer_state(
+                    "BedroomThermo",
+                    ThermometerState::Temperature(Temperature::celsius(18.5)),
+                )
+                .unwrap();
+            house.events().publish(HouseEvent::new(
+                Some("Lamp"),
+                EventKind::Offline {
+                    reason: "connection refused".to_string(),
+                },
+            ));
+        }
+        let switched = next(&mut client).await;
+        assert_eq!(switched["type"], "event");
+        assert_eq!(switched["room"], "Kitchen");
+        assert_eq!(switched["device"], "Kettle");
+        assert_eq!(
+            switched["change"],
+            json!({ "kind": "socket_switched", "state": "on" })
+        );
+        assert_eq!(
+            next(&mut client).await["change"],
+            json!({ "kind": "temperature_received", "temperature": 18.5, "unit": "celsius" })
+        );
+        assert_eq!(
+            next(&mut client).await["change"],
+            json!({ "kind": "offline", "reason": "connection refused" })
+        );
+
+        // A new device is described by the snapshot following its event.
+        house
+            .lock()
+            .await
+            .add_device(
+                "Kitchen",
+                Device::SmartSocket(SmartSocket {
+                    name: "Blender".to_string(),
+                    state: SocketState::Off,
+                    power_consumption: 300.0,
+                }),
+            )
+            .unwrap();
+        assert_eq!(next(&mut client).await["change"]["kind"], "device_added");
+        let snapshot: FeedMessage = serde_json::from_value(next(&mut client).await).unwrap();
+        let FeedMessage::Snapshot { house: report } = snapshot else {
+            panic!("expected a snapshot, got {:?}", snapshot);
+        };
+        assert_eq!(report.rooms[0].devices[2].name, "Blender");
+        assert_eq!(
+            report.rooms[1].devices[1].status,
+            DeviceStatus::Thermometer {
+                temperature: Some(18.5),
+                unit: TemperatureUnit::Celsius,
+            }
+        );
+
+        client.close(None).await.unwrap();
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_commands() {
+        let house = house();
+        let handle = HttpApi::new("127.0.0.1:0", house.clone())
+            .start()
+            .await
+            .unwrap();
+        let mut client = connect(handle.local_addr().unwrap(), "?room=Kitchen&device=Kettle").await;
+
+        let snapshot = next(&mut client).await;
+        assert_eq!(
+            snapshot["house"]["rooms"],
+            json!([{
+                "name": "Kitchen",
+                "devices": [{
+                    "name": "Kettle",
+                    "room": "Kitchen",
+                    "type": "socket",
+                    "state": "off",
+                    "power_consumption": 100.0,
+                }],
+            }])
+        );
+
+        // The toaster isn't followed, so only the kettle's change arrives.
+        let command = |device: &str, state: &str| {
+            Message::Text(
+                json!({ "type": "command", "device": device, "command": state }).to_string(),
+            )
+        };
+        client.send(command("Toaster", "on")).await.unwrap();
+        assert_eq!(
+            next(&mut client).await,
+            json!({ "type": "ack", "device": "Toaster", "state": "on" })
+        );
+        client.send(command("Kettle", "on")).await.unwrap();
+        assert_eq!(next(&mut client).await["type"], "ack");
+        let event = next(&mut client).await;
+        assert_eq!(event["device"], "Kettle");
+        assert_eq!(event["change"]["state"], "on");
+
+        client.send(command("Garage", "on")).await.unwrap();
+        assert_eq!(
+            next(&mut client).await,
+            json!({ "type": "error", "message": "Device named Garage not found" })
+        );
+        client
+            .send(Message::Text("not json".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next(&mut client).await["type"], "error");
+        assert_eq!(
+            house.lock().await.socket("Toaster").unwrap().state,
+            SocketState::On
+        );
+
+        client.close(None).await.unwrap();
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,381 @@
+use crate::device_info::devices::{Device, SocketState};
+use crate::smart_house::SmartHouse;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PowerBudget {
+    // Total power of the sockets that are on, in watts, that must not be exceeded.
+    pub limit: f32,
+    // A shed socket is only restored if the total stays this far below the
+    // limit afterwards, so sockets don't flap around the limit.
+    pub restore_margin: f32,
+    // Minimum time between shedding and the next restore.
+    pub restore_delay: Duration,
+}
+
+impl PowerBudget {
+    pub fn new(limit: f32) -> Self {
+        Self {
+            limit,
+            restore_margin: limit * 0.1,
+            restore_delay: Duration::from_secs(60),
+        }
+    }
+
+    pub fn with_restore_margin(mut self, margin: f32) -> Self {
+        self.restore_margin = margin;
+        self
+    }
+
+    pub fn with_restore_delay(mut self, delay: Duration) -> Self {
+        self.restore_delay = delay;
+        self
+    }
+}
+
+// Actions kept in a shedder's history; older ones are dropped.
+const MAX_HISTORY: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShedKind {
+    Shed,
+    Restored,
+}
+
+// One socket switched by the load shedder.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShedAction {
+    pub socket: String,
+    pub kind: ShedKind,
+    pub priority: i32,
+    // Total power of the sockets that are on, before and after the action.
+    pub total_before: f32,
+    pub total_after: f32,
+    pub at: SystemTime,
+}
+
+impl fmt::Display for ShedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self.kind {
+            ShedKind::Shed => "Shed",
+            ShedKind::Restored => "Restored",
+        };
+        write!(
+            f,
+            "{} socket {} (priority {}), total power {:.1} W -> {:.1} W",
+            verb, self.socket, self.priority, self.total_before, self.total_after
+        )
+    }
+}
+
+struct ShedSocket {
+    name: String,
+    power: f32,
+}
+
+// Keeps the total power of a house under a budget by switching off the
+// lowest-priority sockets, and switches them back on once there is room.
+// Sockets without an assigned priority have priority 0; higher is more
+// important. Among sockets of equal priority the hungriest goes first.
+pub struct LoadShedder {
+    budget: PowerBudget,
+    priorities: HashMap<String, i32>,
+    // Sockets switched off by the shedder, in the order they were shed.
+    shed: Vec<ShedSocket>,
+    last_shed: Option<Instant>,
+    history: Vec<ShedAction>,
+}
+
+// Total power of the sockets that are on.
+pub fn total_power(house: &SmartHouse) -> f32 {
+    // Folded from +0.0, as summing no sockets would give -0.0.
+    on_sockets(house)
+        .iter()
+        .fold(0.0, |total, (_, power)| total + power)
+}
+
+fn on_sockets(house: &SmartHouse) -> Vec<(String, f32)> {
+    house
+        .rooms()
+        .iter()
+        .flat_map(|room| room.devices.iter())
+        .filter_map(|device| match device {
+            Device::SmartSocket(socket) if socket.state == SocketState::On => {
+                Some((socket.name.clone(), socket.power_consumption))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+impl LoadShedder {
+    pub fn new(budget: PowerBudget) -> Self {
+        Self {
+            budget,
+            priorities: HashMap::new(),
+            shed: Vec::new(),
+            last_shed: None,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn with_priority(mut self, socket: &str, priority: i32) -> Self {
+        self.set_priority(socket, priority);
+        self
+    }
+
+    pub fn set_priority(&mut self, socket: &str, priority: i32) {
+        self.priorities.insert(socket.to_string(), priority);
+    }
+
+    pub fn priority(&self, socket: &str) -> i32 {
+        self.priorities.get(socket).copied().unwrap_or(0)
+    }
+
+    pub fn budget(&self) -> PowerBudget {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: PowerBudget) {
+        self.budget = budget;
+    }
+
+    // Sockets currently switched off because of the budget.
+    pub fn shed_sockets(&self) -> Vec<&str> {
+        self.shed.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    // The latest actions taken, oldest first.
+    pub fn history(&self) -> &[ShedAction] {
+        &self.history
+    }
+
+    // Shed sockets while the house is over budget; otherwise restore at most
+    // one shed socket, the most important one, if it fits under the budget
+    // with the restore margin. Returns the actions taken.
+    pub fn evaluate(&mut self, house: &mut SmartHouse, now: Instant) -> Vec<ShedAction> {
+        // Sockets switched back on by someone else are no longer ours to restore.
+        self.shed.retain(|shed| {
+            matches!(house.socket(&shed.name), Ok(socket) if socket.state == SocketState::Off)
+        });
+
+        let mut actions = Vec::new();
+        let mut total = total_power(house);
+        if total > self.budget.limit {
+            let mut candidates = on_sockets(house);
+            candidates.sort_by(|(a, a_power), (b, b_power)| {
+                self.priority(a)
+                    .cmp(&self.priority(b))
+                    .then(b_power.total_cmp(a_power))
+            });
+            for (name, power) in candidates {
+                if total <= self.budget.limit {
+                    break;
+                }
+                if house.switch_socket(&name, SocketState::Off).is_err() {
+                    continue;
+                }
+                actions.push(self.record(&name, ShedKind::Shed, total, total - power));
+                total -= power;
+                self.shed.push(ShedSocket { name, power });
+                self.last_shed = Some(now);
+            }
+        } else if self
+            .last_shed
+            .is_none_or(|at| now.duration_since(at) >= self.budget.restore_delay)
+        {
+            let next = self
+                .shed
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, shed)| (self.priority(&shed.name), std::cmp::Reverse(*index)))
+                .map(|(index, _)| index);
+            if let Some(index) = next {
+                let power = self.shed[index].power;
+                if total + power <= self.budget.limit - self.budget.restore_margin {
+                    let shed = self.shed.remove(index);
+                    if house.switch_socket(&shed.name, SocketState::On).is_ok() {
+                        actions.push(self.record(
+                            &shed.name,
+                            ShedKind::Restored,
+                            total,
+                            total + power,
+                        ));
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    fn record(
+        &mut self,
+        socket: &str,
+        kind: ShedKind,
+        total_before: f32,
+        total_after: f32,
+    ) -> ShedAction {
+        let action = ShedAction {
+            socket: socket.to_string(),
+            kind,
+            priority: self.priority(socket),
+            total_before,
+            total_after,
+            at: SystemTime::now(),
+        };
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(action.clone());
+        action
+    }
+
+    // Human readable summary of the budget, current load and shed sockets.
+    pub fn report(&self, house: &SmartHouse) -> String {
+        let mut report = format!(
+            "Power budget: {:.1} W, current load: {:.1} W\n",
+            self.budget.limit,
+            total_power(house)
+        );
+        for shed in &self.shed {
+            report.push_str(&format!(
+                "Shed: {} ({:.1} W, priority {})\n",
+                shed.name,
+                shed.power,
+                self.priority(&shed.name)
+            ));
+        }
+        for action in &self.history {
+            report.push_str(&format!("{}\n", action));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventFilter, EventKind, EventType};
+    use crate::test_support::{room, socket};
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "Home",
+            vec![room(
+                "Kitchen",
+                vec![
+                    socket("Fridge", SocketState::On, 150.0),
+                    socket("Kettle", SocketState::On, 2000.0),
+                    socket("Heater", SocketState::On, 1500.0),
+                    socket("Lamp", SocketState::On, 60.0),
+                ],
+            )],
+        )
+    }
+
+    fn shedder() -> LoadShedder {
+        LoadShedder::new(
+            PowerBudget::new(3000.0)
+                .with_restore_margin(200.0)
+                .with_restore_delay(Duration::from_secs(30)),
+        )
+        .with_priority("Fridge", 10)
+        .with_priority("Heater", 5)
+    }
+
+    #[test]
+    fn test_sheds_lowest_priority_first() {
+        let mut house = house();
+        let mut shedder = shedder();
+        let actions = shedder.evaluate(&mut house, Instant::now());
+        // Kettle and Lamp share priority 0; the kettle draws more and goes first.
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].socket, "Kettle");
+        assert_eq!(actions[0].kind, ShedKind::Shed);
+        assert_eq!(actions[0].total_before, 3710.0);
+        assert_eq!(actions[0].total_after, 1710.0);
+        assert_eq!(house.socket("Kettle").unwrap().state, SocketState::Off);
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::On);
+        assert_eq!(shedder.shed_sockets(), vec!["Kettle"]);
+        assert!(shedder
+            .report(&house)
+            .contains("Shed: Kettle (2000.0 W, priority 0)"));
+
+        // The same through the house's own budget, which publishes the action.
+        let mut house = self::house();
+        house.load_shedder = Some(self::shedder());
+        let mut events = house
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::LoadShedding));
+        assert_eq!(house.shed_load(Instant::now()).len(), 1);
+        assert_eq!(total_power(&house), 1710.0);
+        let event = events.try_recv().unwrap().unwrap();
+        assert_eq!(event.device.as_deref(), Some("Kettle"));
+        assert_eq!(event.room.as_deref(), Some("Kitchen"));
+        assert_eq!(
+            event.kind,
+            EventKind::LoadShedding {
+                action: ShedKind::Shed,
+                priority: 0,
+                total_before: 3710.0,
+                total_after: 1710.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut shedder = shedder();
+        for total in 0..MAX_HISTORY + 5 {
+            shedder.record("Kettle", ShedKind::Shed, total as f32, 0.0);
+        }
+        assert_eq!(shedder.history().len(), MAX_HISTORY);
+        assert_eq!(shedder.history()[0].total_before, 5.0);
+    }
+
+    #[test]
+    fn test_restores_with_hysteresis_and_delay() {
+        let mut house = house();
+        let mut shedder = shedder();
+        let start = Instant::now();
+        shedder.evaluate(&mut house, start);
+
+        // Turning the heater off leaves 210 W; the kettle would bring it to
+        // 2210 W, under 3000 - 200, but the restore delay hasn't passed yet.
+        house.switch_socket("Heater", SocketState::Off).unwrap();
+        assert!(shedder
+            .evaluate(&mut house, start + Duration::from_secs(10))
+            .is_empty());
+        let actions = shedder.evaluate(&mut house, start + Duration::from_secs(30));
+        assert_eq!(actions[0].kind, ShedKind::Restored);
+        assert_eq!(house.socket("Kettle").unwrap().state, SocketState::On);
+        assert!(shedder.shed_sockets().is_empty());
+        assert_eq!(shedder.history().len(), 2);
+    }
+
+    #[test]
+    fn test_no_restore_within_margin() {
+        let mut house = house();
+        let mut shedder = shedder();
+        let start = Instant::now();
+        shedder.evaluate(&mut house, start);
+        // 1710 W + 2000 W would be over budget, so the kettle stays off.
+        assert!(shedder
+            .evaluate(&mut house, start + Duration::from_secs(60))
+            .is_empty());
+        assert_eq!(house.socket("Kettle").unwrap().state, SocketState::Off);
+
+        // Switching it back on by hand takes it out of the shedder's hands.
+        house.switch_socket("Lamp", SocketState::Off).unwrap();
+        house.switch_socket("Kettle", SocketState::On).unwrap();
+        house.switch_socket("Heater", SocketState::Off).unwrap();
+        assert!(shedder
+            .evaluate(&mut house, start + Duration::from_secs(90))
+            .is_empty());
+        assert!(shedder.shed_sockets().is_empty());
+    }
+}
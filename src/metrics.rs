@@ -0,0 +1,414 @@
+use crate::device_info::devices::{Device, SocketState, ThermometerState};
+use crate::events::{EventBusError, EventFilter, EventType};
+use crate::gateway::SharedHouse;
+use crate::service::ServiceHandle;
+use crate::smart_house::SmartHouse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Upper bounds of the command latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 9] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+#[derive(Default)]
+struct Latency {
+    // Count per bucket of `LATENCY_BUCKETS`, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Energy {
+    watt_hours: f64,
+    // Power drawn since the last observation and when that was.
+    last: Option<(f32, Instant)>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    udp_received: BTreeMap<String, u64>,
+    udp_malformed: BTreeMap<String, u64>,
+    tcp_sessions: BTreeMap<String, u64>,
+    tcp_active: BTreeMap<String, u64>,
+    // Keyed by device and command.
+    latencies: BTreeMap<(String, String), Latency>,
+    energy: BTreeMap<String, Energy>,
+}
+
+// Counters collected by the listeners and servers, rendered together with
+// the live state of a house in the Prometheus text format. Clones share the
+// same counters, so one registry can be handed to every service.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A datagram for `thermometer` was decoded.
+    pub fn udp_received(&self, thermometer: &str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .udp_received
+            .entry(thermometer.to_string())
+            .or_default() += 1;
+    }
+
+    // A datagram for `thermometer` could not be decoded.
+    pub fn udp_malformed(&self, thermometer: &str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .udp_malformed
+            .entry(thermometer.to_string())
+            .or_default() += 1;
+    }
+
+    // A client connected to the server of `socket`.
+    pub fn tcp_session_opened(&self, socket: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state.tcp_sessions.entry(socket.to_string()).or_default() += 1;
+        *state.tcp_active.entry(socket.to_string()).or_default() += 1;
+    }
+
+    pub fn tcp_session_closed(&self, socket: &str) {
+        if let Some(active) = self.state.lock().unwrap().tcp_active.get_mut(socket) {
+            *active = active.saturating_sub(1);
+        }
+    }
+
+    // How long the server of `socket` took to answer `command`.
+    pub fn command_latency(&self, socket: &str, command: &str, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+        let latency = state
+            .latencies
+            .entry((socket.to_string(), command.to_string()))
+            .or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            latency.buckets[bucket] += 1;
+        }
+        latency.sum += seconds;
+        latency.count += 1;
+    }
+
+    // Add the energy the sockets of `house` used since the last observation,
+    // assuming each drew constant power in between. Called on every scrape
+    // and, by `track`, on every socket switch.
+    pub fn observe(&self, house: &SmartHouse, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        for socket in house.rooms().iter().flat_map(|room| &room.devices) {
+            if let Device::SmartSocket(socket) = socket {
+                let energy = state.energy.entry(socket.name.clone()).or_default();
+                if let Some((watts, since)) = energy.last {
+                    let hours = now.saturating_duration_since(since).as_secs_f64() / 3600.0;
+                    energy.watt_hours += watts as f64 * hours;
+                }
+                energy.last = Some((power(&socket.state, socket.power_consumption), now));
+            }
+        }
+    }
+
+    // Energy used by `socket` up to the last observation, in watt-hours.
+    pub fn energy(&self, socket: &str) -> f64 {
+        self.state
+            .lock()
+            .unwrap()
+            .energy
+            .get(socket)
+            .map_or(0.0, |energy| energy.watt_hours)
+    }
+
+    // Observe the house whenever a socket is switched, so energy is
+    // accounted at the power each socket actually drew.
+    pub async fn track(&self, house: SharedHouse) -> ServiceHandle {
+        let metrics = self.clone();
+        let mut switches = {
+            let house = house.lock().await;
+            metrics.observe(&house, Instant::now());
+            house
+                .events()
+                .subscribe(EventFilter::all().event_type(EventType::SocketStateChanged))
+        };
+        ServiceHandle::spawn_task(|mut shutdown| async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.requested() => return Ok(()),
+                    event = switches.recv() => match event {
+                        Ok(_) | Err(EventBusError::Lagged(_)) => {
+                            metrics.observe(&*house.lock().await, Instant::now());
+                        }
+                        Err(EventBusError::Closed) => return Ok(()),
+                    },
+                }
+            }
+        })
+    }
+
+    // Everything in the Prometheus text exposition format. Every series is
+    // labelled with its device and the room the device is in, if any.
+    pub fn render(&self, house: &SmartHouse, now: Instant) -> String {
+        self.observe(house, now);
+        let state = self.state.lock().unwrap();
+        let labels = |device: &str| {
+            format!(
+                "room=\"{}\",device=\"{}\"",
+                escape(house.room_of(device).unwrap_or("")),
+                escape(device)
+            )
+        };
+        let mut out = String::new();
+
+        let sockets: Vec<_> = house
+            .rooms()
+            .iter()
+            .flat_map(|room| &room.devices)
+            .filter_map(|device| match device {
+                Device::SmartSocket(socket) => Some(socket),
+                _ => None,
+            })
+            .collect();
+        header(
+            &mut out,
+            "smart_house_socket_on",
+            "gauge",
+            "Whether the socket is on.",
+        );
+        for socket in &sockets {
+            let on = u8::from(socket.state == SocketState::On);
+            let _ = writeln!(
+                out,
+                "smart_house_socket_on{{{}}} {}",
+                labels(&socket.name),
+                on
+            );
+        }
+        header(
+            &mut out,
+            "smart_house_socket_power_watts",
+            "gauge",
+            "Power the socket draws.",
+        );
+        for socket in &sockets {
+            let _ = writeln!(
+                out,
+                "smart_house_socket_power_watts{{{}}} {}",
+                labels(&socket.name),
+                power(&socket.state, socket.power_consumption)
+            );
+        }
+        header(
+            &mut out,
+            "smart_house_socket_energy_watt_hours_total",
+            "counter",
+            "Energy the socket used since the exporter started.",
+        );
+        for socket in &sockets {
+            let watt_hours = state
+                .energy
+                .get(&socket.name)
+                .map_or(0.0, |energy| energy.watt_hours);
+            let _ = writeln!(
+                out,
+                "smart_house_socket_energy_watt_hours_total{{{}}} {}",
+                labels(&socket.name),
+                watt_hours
+            );
+        }
+
+        header(
+            &mut out,
+            "smart_house_temperature_celsius",
+            "gauge",
+            "Last temperature the thermometer reported.",
+        );
+        for device in house.rooms().iter().flat_map(|room| &room.devices) {
+            if let Device::SmartThermometer(thermometer) = device {
+                if let ThermometerState::Temperature(temperature) = &thermometer.state {
+                    let _ = writeln!(
+                        out,
+                        "smart_house_temperature_celsius{{{}}} {}",
+                        labels(&thermometer.name),
+                        temperature.as_celsius()
+                    );
+                }
+            }
+        }
+
+        let counters = [
+            (
+                "smart_house_udp_datagrams_received_total",
+                "counter",
+                "Thermometer datagrams received and decoded.",
+                &state.udp_received,
+            ),
+            (
+                "smart_house_udp_datagrams_malformed_total",
+                "counter",
+                "Thermometer datagrams that could not be decoded.",
+                &state.udp_malformed,
+            ),
+            (
+                "smart_house_tcp_sessions_total",
+                "counter",
+                "Connections opened with a socket server.",
+                &state.tcp_sessions,
+            ),
+            (
+                "smart_house_tcp_sessions_active",
+                "gauge",
+                "Connections with a socket server currently open.",
+                &state.tcp_active,
+            ),
+        ];
+        for (name, kind, help, values) in counters {
+            header(&mut out, name, kind, help);
+            for (device, value) in values {
+                let _ = writeln!(out, "{}{{{}}} {}", name, labels(device), value);
+            }
+        }
+
+        let name = "smart_house_command_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "histogram",
+            "Time taken to answer a socket server command.",
+        );
+        for ((device, command), latency) in &state.latencies {
+            let labels = format!("{},command=\"{}\"", labels(device), escape(command));
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(latency.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name, labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, labels, latency.count
+            );
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, latency.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, latency.count);
+        }
+        out
+    }
+}
+
+// Power drawn by a socket in the given state, in watts.
+fn power(state: &SocketState, power_consumption: f32) -> f32 {
+    match state {
+        SocketState::On => power_consumption,
+        SocketState::Off => 0.0,
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// Escape a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::temperature::Temperature;
+    use crate::test_support::{room, socket, thermometer};
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "My Home",
+            vec![room(
+                "Kitchen",
+                vec![
+                    socket("Kettle", SocketState::On, 2000.0),
+                    thermometer(
+                        "Thermo",
+                        ThermometerState::Temperature(Temperature::fahrenheit(77.0)),
+                    ),
+                ],
+            )],
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        let mut house = house();
+        let metrics = Metrics::new();
+        let start = Instant::now();
+        metrics.observe(&house, start);
+        // On for half an hour, then off for an hour.
+        house.switch_socket("Kettle", SocketState::Off).unwrap();
+        metrics.observe(&house, start + Duration::from_secs(1800));
+        metrics.udp_received("Thermo");
+        metrics.udp_received("Thermo");
+        metrics.udp_malformed("Thermo");
+        metrics.tcp_session_opened("Kettle");
+        metrics.tcp_session_opened("Kettle");
+        metrics.tcp_session_closed("Kettle");
+        metrics.command_latency("Kettle", "on", Duration::from_micros(300));
+        metrics.command_latency("Kettle", "on", Duration::from_millis(20));
+
+        let text = metrics.render(&house, start + Duration::from_secs(5400));
+        let kettle = "room=\"Kitchen\",device=\"Kettle\"";
+        let thermo = "room=\"Kitchen\",device=\"Thermo\"";
+        for line in [
+            "# TYPE smart_house_socket_on gauge".to_string(),
+            format!("smart_house_socket_on{{{}}} 0", kettle),
+            format!("smart_house_socket_power_watts{{{}}} 0", kettle),
+            format!(
+                "smart_house_socket_energy_watt_hours_total{{{}}} 1000",
+                kettle
+            ),
+            format!("smart_house_temperature_celsius{{{}}} 25", thermo),
+            format!("smart_house_udp_datagrams_received_total{{{}}} 2", thermo),
+            format!("smart_house_udp_datagrams_malformed_total{{{}}} 1", thermo),
+            format!("smart_house_tcp_sessions_total{{{}}} 2", kettle),
+            format!("smart_house_tcp_sessions_active{{{}}} 1", kettle),
+            "# TYPE smart_house_command_duration_seconds histogram".to_string(),
+            format!(
+                "smart_house_command_duration_seconds_bucket{{{},command=\"on\",le=\"0.0005\"}} 1",
+                kettle
+            ),
+            format!(
+                "smart_house_command_duration_seconds_bucket{{{},command=\"on\",le=\"0.05\"}} 2",
+                kettle
+            ),
+            format!(
+                "smart_house_command_duration_seconds_count{{{},command=\"on\"}} 2",
+                kettle
+            ),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}:\n{}",
+                line,
+                text
+            );
+        }
+        assert_eq!(metrics.energy("Kettle"), 1000.0);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("Bob's \"big\" lamp\\"), "Bob's \\\"big\\\" lamp\\\\");
+    }
+}
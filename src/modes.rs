@@ -0,0 +1,576 @@
+use crate::device_info::devices::{Device, SocketState};
+use crate::device_info::temperature::Temperature;
+use crate::events::{EventKind, HouseEvent};
+use crate::smart_house::SmartHouse;
+use crate::thermostat::Thermostat;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Overall state of the house, driving which automations apply.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HouseMode {
+    #[default]
+    Home,
+    Away,
+    Night,
+    Vacation,
+}
+
+impl HouseMode {
+    // Allowed transitions: Home can go anywhere, Night ends at Home or when
+    // everybody leaves, Away can turn into a Vacation, and a Vacation always
+    // ends at Home.
+    pub fn can_transition_to(&self, to: HouseMode) -> bool {
+        use HouseMode::*;
+        matches!(
+            (self, to),
+            (Home, Away | Night | Vacation)
+                | (Away, Home | Vacation)
+                | (Night, Home | Away)
+                | (Vacation, Home)
+        )
+    }
+}
+
+impl fmt::Display for HouseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HouseMode::Home => "home",
+            HouseMode::Away => "away",
+            HouseMode::Night => "night",
+            HouseMode::Vacation => "vacation",
+        };
+        f.write_str(name)
+    }
+}
+
+// Define an error type for house mode changes.
+#[derive(Error, Debug)]
+pub enum ModeError {
+    #[error("Cannot switch house mode from {from} to {to}")]
+    InvalidTransition { from: HouseMode, to: HouseMode },
+    #[error("Failed to access mode state: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse mode state: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to write mode state: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+// How the built-in mode behaviours are configured.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ModeSettings {
+    // Sockets left alone when entering Away or Vacation, e.g. the fridge.
+    pub essential: Vec<String>,
+    // Degrees Celsius the thermostats are turned down by at Night.
+    pub night_setback: f32,
+    // Sockets switched on and off during Vacation to look like someone is home.
+    pub presence: Vec<String>,
+    // Timezone the presence schedule is planned in.
+    pub timezone: Tz,
+}
+
+impl Default for ModeSettings {
+    fn default() -> Self {
+        Self {
+            essential: Vec::new(),
+            night_setback: 3.0,
+            presence: Vec::new(),
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+// Mode state as stored on disk.
+#[derive(Serialize, Deserialize)]
+struct StoredMode {
+    mode: HouseMode,
+    changed_at: DateTime<Utc>,
+}
+
+// One period during which a presence socket is on.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PresenceWindow {
+    pub socket: String,
+    pub on: NaiveDateTime,
+    pub off: NaiveDateTime,
+}
+
+// Plan a day of plausible activity for the presence sockets: every socket
+// gets an evening period starting between 17:30 and 20:30 and lasting up to
+// three and a half hours, but ending by 23:45. About half of the sockets
+// also get a short morning period between 06:30 and 08:30.
+pub fn plan_presence(
+    sockets: &[String],
+    date: NaiveDate,
+    rng: &mut impl Rng,
+) -> Vec<PresenceWindow> {
+    let at =
+        |hour: u32, minute: u32| date.and_time(NaiveTime::from_hms_opt(hour, minute, 0).unwrap());
+    let mut windows = Vec::new();
+    for socket in sockets {
+        if rng.gen_bool(0.5) {
+            let on = at(6, 30) + Duration::minutes(rng.gen_range(0..=90));
+            let off = (on + Duration::minutes(rng.gen_range(15..=60))).min(at(8, 30));
+            windows.push(PresenceWindow {
+                socket: socket.clone(),
+                on,
+                off,
+            });
+        }
+        let on = at(17, 30) + Duration::minutes(rng.gen_range(0..=180));
+        let off = (on + Duration::minutes(rng.gen_range(45..=210))).min(at(23, 45));
+        windows.push(PresenceWindow {
+            socket: socket.clone(),
+            on,
+            off,
+        });
+    }
+    windows.sort_by_key(|window| window.on);
+    windows
+}
+
+// A mode change performed by `ModeController::set_mode`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ModeChange {
+    pub from: HouseMode,
+    pub to: HouseMode,
+    // Sockets switched off on entering Away or Vacation.
+    pub switched_off: Vec<String>,
+}
+
+pub type ModeHook = Box<dyn FnMut(&mut SmartHouse, &ModeChange) + Send>;
+
+// Moves the house between modes, running the built-in behaviour of each mode
+// and any registered hooks. Exit hooks of the old mode run before entry hooks
+// of the new one.
+pub struct ModeController {
+    settings: ModeSettings,
+    thermostats: Vec<Thermostat>,
+    // Setpoints to restore when Night ends.
+    day_setpoints: Vec<Temperature>,
+    on_enter: HashMap<HouseMode, Vec<ModeHook>>,
+    on_exit: HashMap<HouseMode, Vec<ModeHook>>,
+    state_file: Option<PathBuf>,
+    rng: StdRng,
+    presence_plan: Option<(NaiveDate, Vec<PresenceWindow>)>,
+}
+
+impl ModeController {
+    pub fn new(settings: ModeSettings) -> Self {
+        Self {
+            settings,
+            thermostats: Vec::new(),
+            day_setpoints: Vec::new(),
+            on_enter: HashMap::new(),
+            on_exit: HashMap::new(),
+            state_file: None,
+            rng: StdRng::from_entropy(),
+            presence_plan: None,
+        }
+    }
+
+    // Thermostats to turn down at Night.
+    pub fn with_thermostat(mut self, thermostat: Thermostat) -> Self {
+        self.thermostats.push(thermostat);
+        self
+    }
+
+    // Save the mode to `path` on every change; see `restore`.
+    pub fn with_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    // Make the presence simulation reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn settings(&self) -> &ModeSettings {
+        &self.settings
+    }
+
+    pub fn on_enter(&mut self, mode: HouseMode, hook: ModeHook) {
+        self.on_enter.entry(mode).or_default().push(hook);
+    }
+
+    pub fn on_exit(&mut self, mode: HouseMode, hook: ModeHook) {
+        self.on_exit.entry(mode).or_default().push(hook);
+    }
+
+    // Put the house back into the mode saved in the state file after a
+    // restart. The built-in behaviour and entry hooks of that mode run as in
+    // `set_mode`, so e.g. the Night setback is applied again; exit hooks don't,
+    // as the house never really left. Returns the restored mode, or `None` if
+    // nothing was saved yet.
+    pub fn restore(
+        &mut self,
+        house: &mut SmartHouse,
+        now: DateTime<Utc>,
+    ) -> Result<Option<HouseMode>, ModeError> {
+        let Some(path) = &self.state_file else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let stored: StoredMode = toml::from_str(&std::fs::read_to_string(path)?)?;
+        let from = house.mode;
+        let change = ModeChange {
+            from,
+            to: stored.mode,
+            switched_off: self.enter(house, stored.mode, now),
+        };
+        self.run_enter_hooks(house, &change);
+        if from != stored.mode {
+            house.events().publish(HouseEvent::new(
+                None,
+                EventKind::ModeChanged {
+                    from,
+                    to: stored.mode,
+                },
+            ));
+        }
+        Ok(Some(stored.mode))
+    }
+
+    // Move the house to `to`. The new mode is saved first, so a failure to
+    // write the state file leaves the house untouched.
+    pub fn set_mode(
+        &mut self,
+        house: &mut SmartHouse,
+        to: HouseMode,
+        now: DateTime<Utc>,
+    ) -> Result<ModeChange, ModeError> {
+        let from = house.mode;
+        if !from.can_transition_to(to) {
+            return Err(ModeError::InvalidTransition { from, to });
+        }
+        if let Some(path) = &self.state_file {
+            let stored = StoredMode {
+                mode: to,
+                changed_at: now,
+            };
+            std::fs::write(path, toml::to_string(&stored)?)?;
+        }
+        let mut change = ModeChange {
+            from,
+            to,
+            switched_off: Vec::new(),
+        };
+
+        for hook in self.on_exit.get_mut(&from).into_iter().flatten() {
+            hook(house, &change);
+        }
+        match from {
+            HouseMode::Night => {
+                for (thermostat, setpoint) in
+                    self.thermostats.iter().zip(self.day_setpoints.drain(..))
+                {
+                    thermostat.set_setpoint(setpoint);
+                }
+            }
+            HouseMode::Vacation => self.presence_plan = None,
+            HouseMode::Home | HouseMode::Away => {}
+        }
+
+        change.switched_off = self.enter(house, to, now);
+        self.run_enter_hooks(house, &change);
+        house
+            .events()
+            .publish(HouseEvent::new(None, EventKind::ModeChanged { from, to }));
+        Ok(change)
+    }
+
+    // Built-in behaviour of entering a mode. Returns the sockets switched off.
+    fn enter(&mut self, house: &mut SmartHouse, to: HouseMode, now: DateTime<Utc>) -> Vec<String> {
+        let mut switched_off = Vec::new();
+        match to {
+            HouseMode::Away => switched_off = self.switch_off_non_essential(house),
+            HouseMode::Vacation => {
+                switched_off = self.switch_off_non_essential(house);
+                let today = now.with_timezone(&self.settings.timezone).date_naive();
+                self.presence_plan(today);
+            }
+            HouseMode::Night => {
+                self.day_setpoints = self.thermostats.iter().map(Thermostat::setpoint).collect();
+                for thermostat in &self.thermostats {
+                    let setpoint = thermostat.setpoint();
+                    let lowered =
+                        Temperature::celsius(setpoint.as_celsius() - self.settings.night_setback);
+                    thermostat.set_setpoint(lowered.to_unit(setpoint.unit()));
+                }
+            }
+            HouseMode::Home => {}
+        }
+        house.mode = to;
+        switched_off
+    }
+
+    fn run_enter_hooks(&mut self, house: &mut SmartHouse, change: &ModeChange) {
+        for hook in self.on_enter.get_mut(&change.to).into_iter().flatten() {
+            hook(house, change);
+        }
+    }
+
+    fn switch_off_non_essential(&self, house: &mut SmartHouse) -> Vec<String> {
+        let sockets: Vec<String> = house
+            .rooms()
+            .iter()
+            .flat_map(|room| room.devices.iter())
+            .filter_map(|device| match device {
+                Device::SmartSocket(socket) if socket.state == SocketState::On => {
+                    Some(socket.name.clone())
+                }
+                _ => None,
+            })
+            .filter(|name| !self.settings.essential.contains(name))
+            .collect();
+        sockets
+            .into_iter()
+            .filter(|name| house.switch_socket(name, SocketState::Off).is_ok())
+            .collect()
+    }
+
+    // Presence schedule for the given local date while on Vacation.
+    pub fn presence_plan(&mut self, date: NaiveDate) -> &[PresenceWindow] {
+        let stale = !matches!(&self.presence_plan, Some((planned, _)) if *planned == date);
+        if stale {
+            let windows = plan_presence(&self.settings.presence, date, &mut self.rng);
+            self.presence_plan = Some((date, windows));
+        }
+        &self.presence_plan.as_ref().unwrap().1
+    }
+
+    // While on Vacation, switch the presence sockets according to today's plan.
+    // Returns the sockets that were switched.
+    pub fn tick(
+        &mut self,
+        house: &mut SmartHouse,
+        now: DateTime<Utc>,
+    ) -> Vec<(String, SocketState)> {
+        if house.mode != HouseMode::Vacation {
+            return Vec::new();
+        }
+        let local = now.with_timezone(&self.settings.timezone).naive_local();
+        let plan = self.presence_plan(local.date()).to_vec();
+        let mut switched = Vec::new();
+        for socket in &self.settings.presence {
+            let wanted = if plan
+                .iter()
+                .any(|w| &w.socket == socket && w.on <= local && local < w.off)
+            {
+                SocketState::On
+            } else {
+                SocketState::Off
+            };
+            let current = house.socket(socket).map(|s| s.state.clone());
+            if current.is_ok_and(|state| state != wanted)
+                && house.switch_socket(socket, wanted.clone()).is_ok()
+            {
+                switched.push((socket.clone(), wanted));
+            }
+        }
+        switched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::temperature::TemperatureUnit;
+    use crate::events::{EventFilter, EventType};
+    use crate::test_support::{room, socket};
+    use crate::thermostat::{ThermostatConfig, ThermostatMode};
+    use std::sync::{Arc, Mutex};
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "Home",
+            vec![room(
+                "Kitchen",
+                vec![
+                    socket("Fridge", SocketState::On, 100.0),
+                    socket("Kettle", SocketState::On, 100.0),
+                    socket("Lamp", SocketState::Off, 100.0),
+                ],
+            )],
+        )
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn settings() -> ModeSettings {
+        ModeSettings {
+            essential: vec!["Fridge".to_string()],
+            presence: vec!["Lamp".to_string()],
+            ..ModeSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_transitions_and_hooks() {
+        let mut house = house();
+        let mut events = house
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::ModeChanged));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut controller = ModeController::new(settings());
+        let exit_log = log.clone();
+        controller.on_exit(
+            HouseMode::Home,
+            Box::new(move |_, change| {
+                exit_log
+                    .lock()
+                    .unwrap()
+                    .push(format!("exit {}", change.from))
+            }),
+        );
+        let enter_log = log.clone();
+        controller.on_enter(
+            HouseMode::Away,
+            Box::new(move |house, change| {
+                assert_eq!(house.mode, HouseMode::Away);
+                enter_log
+                    .lock()
+                    .unwrap()
+                    .push(format!("enter {}", change.to))
+            }),
+        );
+
+        let now = utc("2023-12-01T08:00:00Z");
+        let change = controller
+            .set_mode(&mut house, HouseMode::Away, now)
+            .unwrap();
+        assert_eq!(change.switched_off, vec!["Kettle".to_string()]);
+        assert_eq!(house.socket("Fridge").unwrap().state, SocketState::On);
+        assert_eq!(*log.lock().unwrap(), vec!["exit home", "enter away"]);
+        assert_eq!(
+            events.try_recv().unwrap().unwrap().kind,
+            EventKind::ModeChanged {
+                from: HouseMode::Home,
+                to: HouseMode::Away
+            }
+        );
+
+        assert!(matches!(
+            controller.set_mode(&mut house, HouseMode::Night, now),
+            Err(ModeError::InvalidTransition {
+                from: HouseMode::Away,
+                to: HouseMode::Night
+            })
+        ));
+        assert_eq!(house.mode, HouseMode::Away);
+        assert!(events.try_recv().is_none());
+    }
+
+    #[test]
+    fn test_night_lowers_thermostat_setpoint() {
+        let mut house = house();
+        let thermostat = Thermostat::new(ThermostatConfig::heater(
+            Temperature::fahrenheit(70.0),
+            ThermostatMode::BangBang { hysteresis: 0.5 },
+        ));
+        let mut controller = ModeController::new(settings()).with_thermostat(thermostat.clone());
+        let now = utc("2023-12-01T22:00:00Z");
+
+        controller
+            .set_mode(&mut house, HouseMode::Night, now)
+            .unwrap();
+        let lowered = thermostat.setpoint();
+        assert_eq!(lowered.unit(), TemperatureUnit::Fahrenheit);
+        assert!((lowered.value() - 64.6).abs() < 1e-3);
+        controller
+            .set_mode(&mut house, HouseMode::Home, now)
+            .unwrap();
+        assert_eq!(thermostat.setpoint(), Temperature::fahrenheit(70.0));
+    }
+
+    #[test]
+    fn test_vacation_simulates_presence() {
+        let mut house = house();
+        let mut controller = ModeController::new(settings()).with_seed(7);
+        let date = NaiveDate::from_ymd_opt(2023, 12, 1).unwrap();
+        controller
+            .set_mode(&mut house, HouseMode::Vacation, utc("2023-12-01T12:00:00Z"))
+            .unwrap();
+
+        let plan = controller.presence_plan(date).to_vec();
+        assert!(!plan.is_empty());
+        for window in &plan {
+            assert!(window.on < window.off);
+            assert!(window.off.time() <= NaiveTime::from_hms_opt(23, 45, 0).unwrap());
+        }
+        let evening = plan.last().unwrap();
+        let during = evening.on + Duration::minutes(1);
+        let switched = controller.tick(&mut house, during.and_utc());
+        assert_eq!(switched, vec![("Lamp".to_string(), SocketState::On)]);
+        assert!(controller.tick(&mut house, during.and_utc()).is_empty());
+        controller.tick(&mut house, evening.off.and_utc());
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::Off);
+
+        // The same seed plans the same day.
+        let mut other = ModeController::new(settings()).with_seed(7);
+        assert_eq!(other.presence_plan(date), plan.as_slice());
+    }
+
+    #[test]
+    fn test_mode_is_persisted() {
+        let path = std::env::temp_dir().join(format!("house_mode_{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let now = utc("2023-12-01T22:00:00Z");
+        let mut house = house();
+        let mut controller = ModeController::new(settings()).with_state_file(&path);
+        assert_eq!(controller.restore(&mut house, now).unwrap(), None);
+        controller
+            .set_mode(&mut house, HouseMode::Night, now)
+            .unwrap();
+
+        // After a restart the Night setback is applied again and undone on
+        // leaving Night.
+        let thermostat = Thermostat::new(ThermostatConfig::heater(
+            Temperature::celsius(21.0),
+            ThermostatMode::BangBang { hysteresis: 0.5 },
+        ));
+        let mut restarted = self::house();
+        let mut controller = ModeController::new(settings())
+            .with_state_file(&path)
+            .with_thermostat(thermostat.clone());
+        assert_eq!(
+            controller.restore(&mut restarted, now).unwrap(),
+            Some(HouseMode::Night)
+        );
+        assert_eq!(restarted.mode, HouseMode::Night);
+        assert_eq!(thermostat.setpoint(), Temperature::celsius(18.0));
+        controller
+            .set_mode(&mut restarted, HouseMode::Home, now)
+            .unwrap();
+        assert_eq!(thermostat.setpoint(), Temperature::celsius(21.0));
+
+        // A state file that can't be written fails the change before the
+        // house is touched.
+        let mut controller = ModeController::new(settings())
+            .with_state_file(std::env::temp_dir().join("no_such_dir").join("mode.toml"));
+        assert!(matches!(
+            controller.set_mode(&mut restarted, HouseMode::Away, now),
+            Err(ModeError::Io(_))
+        ));
+        assert_eq!(restarted.mode, HouseMode::Home);
+        assert_eq!(restarted.socket("Kettle").unwrap().state, SocketState::On);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
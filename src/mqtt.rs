@@ -0,0 +1,668 @@
+pub mod broker;
+pub mod client;
+pub mod packet;
+
+use crate::device_info::devices::{Device, SocketState, ThermometerState};
+use crate::events::{EventBusError, EventFilter, EventKind, EventSubscription, HouseEvent};
+use crate::gateway::SharedHouse;
+use crate::service::ServiceHandle;
+use crate::smart_house::SmartHouse;
+use client::{MqttClient, MqttMessage};
+use packet::Will;
+use serde_json::json;
+use std::time::Duration;
+use thiserror::Error;
+
+// Delay before reconnecting to the broker, doubled after every failure.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+// Define an error type for MQTT connections.
+#[derive(Error, Debug)]
+pub enum MqttError {
+    #[error("MQTT connection failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("MQTT protocol error: {0}")]
+    Protocol(String),
+    #[error("Broker refused the connection with code {0}")]
+    Refused(u8),
+    #[error("MQTT connection closed")]
+    Closed,
+}
+
+impl From<MqttError> for std::io::Error {
+    fn from(error: MqttError) -> Self {
+        match error {
+            MqttError::Io(e) => e,
+            other => std::io::Error::other(other),
+        }
+    }
+}
+
+// Lower-case name usable as a topic level and Home Assistant object id.
+pub fn slug(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+struct Topics {
+    base: String,
+    discovery: String,
+    node: String,
+}
+
+impl Topics {
+    fn state(&self, device: &str) -> String {
+        format!("{}/{}/state", self.base, slug(device))
+    }
+
+    fn command(&self, device: &str) -> String {
+        format!("{}/{}/set", self.base, slug(device))
+    }
+
+    fn availability(&self, device: &str) -> String {
+        format!("{}/{}/availability", self.base, slug(device))
+    }
+
+    // `offline` while the bridge is disconnected, whatever the devices' own
+    // availability says.
+    fn bridge_availability(&self) -> String {
+        format!("{}/availability", self.base)
+    }
+
+    fn config(&self, component: &str, device: &str) -> String {
+        format!(
+            "{}/{}/{}/{}/config",
+            self.discovery,
+            component,
+            self.node,
+            slug(device)
+        )
+    }
+
+    // Device name a `<base>/<device>/set` topic refers to.
+    fn command_target<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic
+            .strip_prefix(&self.base)?
+            .strip_prefix('/')?
+            .strip_suffix("/set")
+    }
+}
+
+fn socket_payload(state: &SocketState) -> &'static str {
+    match state {
+        SocketState::On => "ON",
+        SocketState::Off => "OFF",
+    }
+}
+
+// Publishes every socket as a Home Assistant switch and every thermometer as
+// a temperature sensor:
+//
+//   <discovery>/switch/<node>/<device>/config   discovery config (retained)
+//   <discovery>/sensor/<node>/<device>/config   discovery config (retained)
+//   <base>/<device>/state                       `ON`/`OFF` or the temperature (retained)
+//   <base>/<device>/availability                `online`/`offline` (retained)
+//   <base>/availability                         `online`/`offline` of the bridge (retained, will)
+//   <base>/<device>/set                         `ON`/`OFF` commands for sockets
+//
+// `<node>` and `<device>` are slugs of the house and device names, and
+// `<base>` defaults to `smart_house/<node>`.
+pub struct MqttBridge {
+    broker: String,
+    house: SharedHouse,
+    client_id: Option<String>,
+    base_topic: Option<String>,
+    discovery_prefix: String,
+}
+
+impl MqttBridge {
+    pub fn new(broker: &str, house: SharedHouse) -> Self {
+        Self {
+            broker: broker.to_string(),
+            house,
+            client_id: None,
+            base_topic: None,
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+
+    pub fn with_client_id(mut self, client_id: &str) -> Self {
+        self.client_id = Some(client_id.to_string());
+        self
+    }
+
+    pub fn with_base_topic(mut self, topic: &str) -> Self {
+        self.base_topic = Some(topic.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn with_discovery_prefix(mut self, prefix: &str) -> Self {
+        self.discovery_prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    // Connect, publish discovery configs and current states, then keep the
+    // broker and the house in sync in a background task. When the broker
+    // connection is lost the task reconnects with a growing delay and
+    // publishes everything again.
+    pub async fn start(&self) -> Result<ServiceHandle, MqttError> {
+        let (topics, client_id, mut events) = {
+            let house = self.house.lock().await;
+            let node = slug(&house.name);
+            let topics = Topics {
+                base: self
+                    .base_topic
+                    .clone()
+                    .unwrap_or_else(|| format!("smart_house/{}", node)),
+                discovery: self.discovery_prefix.clone(),
+                node: node.clone(),
+            };
+            let client_id = self
+                .client_id
+                .clone()
+                .unwrap_or_else(|| format!("smart_house_{}", node));
+            (
+                topics,
+                client_id,
+                house.events().subscribe(EventFilter::all()),
+            )
+        };
+        let mut client = connect(&self.broker, &client_id, &topics, &self.house).await?;
+
+        let broker = self.broker.clone();
+        let house = self.house.clone();
+        Ok(ServiceHandle::spawn_task(|mut shutdown| async move {
+            loop {
+                let lost = tokio::select! {
+                    _ = shutdown.requested() => None,
+                    result = sync(&mut client, &topics, &house, &mut events) => result.err(),
+                };
+                let Some(error) = lost else {
+                    // The will is only sent when the connection drops.
+                    client
+                        .publish(&topics.bridge_availability(), "offline", true)
+                        .await?;
+                    client.disconnect().await?;
+                    return Ok(());
+                };
+                report(&house, format!("MQTT bridge lost the broker: {}", error)).await;
+
+                let mut delay = RECONNECT_DELAY;
+                client = loop {
+                    tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        _ = tokio::time::sleep(delay) => {}
+                    }
+                    // Everything is republished on connecting.
+                    while matches!(
+                        events.try_recv(),
+                        Some(Ok(_) | Err(EventBusError::Lagged(_)))
+                    ) {}
+                    let connected = tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        connected = connect(&broker, &client_id, &topics, &house) => connected,
+                    };
+                    match connected {
+                        Ok(client) => break client,
+                        Err(e) => {
+                            report(&house, format!("MQTT bridge can't reconnect: {}", e)).await;
+                            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                        }
+                    }
+                };
+            }
+        }))
+    }
+}
+
+// Connect with the bridge's will, then publish its availability, every
+// device and subscribe to commands.
+async fn connect(
+    broker: &str,
+    client_id: &str,
+    topics: &Topics,
+    house: &SharedHouse,
+) -> Result<MqttClient, MqttError> {
+    let will = Will {
+        topic: topics.bridge_availability(),
+        payload: b"offline".to_vec(),
+        retain: true,
+    };
+    let mut client = MqttClient::connect_with_will(broker, client_id, Some(will)).await?;
+    client
+        .publish(&topics.bridge_availability(), "online", true)
+        .await?;
+
+    let messages = {
+        let house = house.lock().await;
+        house
+            .rooms()
+            .iter()
+            .flat_map(|room| {
+                room.devices
+                    .iter()
+                    .map(|device| (room.name.as_str(), device))
+            })
+            .flat_map(|(room, device)| announce(topics, &house, room, device))
+            .collect::<Vec<_>>()
+    };
+    for (topic, payload) in messages {
+        client.publish(&topic, payload, true).await?;
+    }
+    client
+        .subscribe(&[&format!("{}/+/set", topics.base)])
+        .await?;
+    Ok(client)
+}
+
+// Forward house events and broker commands until the event bus closes or
+// the connection is lost.
+async fn sync(
+    client: &mut MqttClient,
+    topics: &Topics,
+    house: &SharedHouse,
+    events: &mut EventSubscription,
+) -> Result<(), MqttError> {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let messages = on_event(topics, house, &event).await;
+                    for (topic, payload) in messages {
+                        client.publish(&topic, payload, true).await?;
+                    }
+                }
+                Err(EventBusError::Lagged(n)) => eprintln!("MQTT bridge missed {} events", n),
+                Err(EventBusError::Closed) => return Ok(()),
+            },
+            message = client.recv() => match message {
+                Some(message) => {
+                    if let Some((topic, payload)) = on_command(topics, house, &message).await {
+                        client.publish(&topic, payload, true).await?;
+                    }
+                }
+                None => return Err(MqttError::Closed),
+            },
+        }
+    }
+}
+
+async fn report(house: &SharedHouse, message: String) {
+    house
+        .lock()
+        .await
+        .events()
+        .publish(HouseEvent::new(None, EventKind::Error { message }));
+}
+
+// Discovery config, state and availability of one device.
+fn announce(
+    topics: &Topics,
+    house: &SmartHouse,
+    room: &str,
+    device: &Device,
+) -> Vec<(String, String)> {
+    let name = device.name();
+    let device_info = json!({
+        "identifiers": [topics.node.clone()],
+        "name": house.name.clone(),
+    });
+    // An entity is available only while both the bridge and the device are.
+    let availability = json!([
+        { "topic": topics.bridge_availability() },
+        { "topic": topics.availability(name) },
+    ]);
+    let (config_topic, config) = match device {
+        Device::SmartSocket(_) => (
+            topics.config("switch", name),
+            json!({
+                "name": name,
+                "unique_id": format!("{}_{}", topics.node, slug(name)),
+                "state_topic": topics.state(name),
+                "command_topic": topics.command(name),
+                "availability": availability,
+                "availability_mode": "all",
+                "payload_on": "ON",
+                "payload_off": "OFF",
+                "suggested_area": room,
+                "device": device_info,
+            }),
+        ),
+        Device::SmartThermometer(_) => (
+            topics.config("sensor", name),
+            json!({
+                "name": name,
+                "unique_id": format!("{}_{}", topics.node, slug(name)),
+                "state_topic": topics.state(name),
+                "availability": availability,
+                "availability_mode": "all",
+                "device_class": "temperature",
+                "state_class": "measurement",
+                "unit_of_measurement": house.temperature_unit.symbol(),
+                "suggested_area": room,
+                "device": device_info,
+            }),
+        ),
+    };
+    let mut messages = vec![
+        (config_topic, config.to_string()),
+        (topics.availability(name), "online".to_string()),
+    ];
+    if let Some(state) = state_payload(house, device) {
+        messages.push((topics.state(name), state));
+    }
+    messages
+}
+
+fn state_payload(house: &SmartHouse, device: &Device) -> Option<String> {
+    match device {
+        Device::SmartSocket(socket) => Some(socket_payload(&socket.state).to_string()),
+        Device::SmartThermometer(thermometer) => match thermometer.state {
+            ThermometerState::Temperature(t) => {
+                Some(format!("{:.1}", t.to_unit(house.temperature_unit).value()))
+            }
+            ThermometerState::Off => None,
+        },
+    }
+}
+
+async fn on_event(
+    topics: &Topics,
+    house: &SharedHouse,
+    event: &HouseEvent,
+) -> Vec<(String, String)> {
+    let Some(name) = event.device.as_deref() else {
+        return Vec::new();
+    };
+    match &event.kind {
+        EventKind::SocketStateChanged { state } => {
+            vec![(topics.state(name), socket_payload(state).to_string())]
+        }
+        EventKind::ReadingReceived { temperature } => {
+            let unit = house.lock().await.temperature_unit;
+            vec![(
+                topics.state(name),
+                format!("{:.1}", temperature.to_unit(unit).value()),
+            )]
+        }
+        EventKind::Offline { .. } => vec![(topics.availability(name), "offline".to_string())],
+        EventKind::Online => vec![(topics.availability(name), "online".to_string())],
+        EventKind::DeviceAdded => {
+            let house = house.lock().await;
+            match (house.room_of(name), house.find_device(name)) {
+                (Some(room), Some(device)) => announce(topics, &house, room, device),
+                _ => Vec::new(),
+            }
+        }
+        // An empty retained message removes the entity from Home Assistant.
+        EventKind::DeviceRemoved => vec![
+            (topics.config("switch", name), String::new()),
+            (topics.config("sensor", name), String::new()),
+            (topics.state(name), String::new()),
+            (topics.availability(name), String::new()),
+        ],
+        EventKind::ModeChanged { .. }
+        | EventKind::LoadShedding { .. }
+        | EventKind::InterlockEnforced { .. }
+        | EventKind::Error { .. } => Vec::new(),
+    }
+}
+
+// Apply an `ON`/`OFF` command. A command that can't be applied answers with
+// the socket's current state, so dashboards don't show a state it never took.
+async fn on_command(
+    topics: &Topics,
+    house: &SharedHouse,
+    message: &MqttMessage,
+) -> Option<(String, String)> {
+    let target = topics.command_target(&message.topic)?;
+    let mut house = house.lock().await;
+    let socket = house
+        .rooms()
+        .iter()
+        .flat_map(|room| room.devices.iter())
+        .map(Device::name)
+        .find(|name| slug(name) == target)?
+        .to_string();
+    let state = match message.payload_str().trim().to_ascii_uppercase().as_str() {
+        "ON" => Some(SocketState::On),
+        "OFF" => Some(SocketState::Off),
+        _ => None,
+    };
+    let result = match state {
+        Some(state) => house
+            .switch_socket(&socket, state)
+            .map_err(|e| e.to_string()),
+        None => Err(format!("unknown command `{}`", message.payload_str())),
+    };
+    match result {
+        Ok(()) => None,
+        Err(e) => {
+            eprintln!("MQTT command for {} failed: {}", socket, e);
+            let current = house.socket(&socket).ok()?.state.clone();
+            Some((topics.state(&socket), socket_payload(&current).to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::broker::MqttBroker;
+    use super::*;
+    use crate::device_info::temperature::Temperature;
+    use crate::events::EventType;
+    use crate::test_support::{self, shared};
+    use std::time::Duration;
+
+    // Named so that topics use a slug of more than one word.
+    fn house() -> SharedHouse {
+        let mut house = test_support::house();
+        house.name = "My Home".to_string();
+        shared(house)
+    }
+
+    async fn retained(broker: &MqttBroker, topic: &str, expected: &str) {
+        for _ in 0..100 {
+            if broker.retained(topic).as_deref() == Some(expected.as_bytes()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!(
+            "{} is {:?}, expected {}",
+            topic,
+            broker.retained(topic).map(String::from_utf8),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discovery_and_state() {
+        let broker = MqttBroker::new("127.0.0.1:0");
+        let broker_handle = broker.start().await.unwrap();
+        let house = house();
+        let bridge = MqttBridge::new(
+            &broker_handle.local_addr().unwrap().to_string(),
+            house.clone(),
+        );
+        let handle = bridge.start().await.unwrap();
+
+        retained(&broker, "smart_house/my_home/kettle/state", "OFF").await;
+        retained(&broker, "smart_house/my_home/kettle/availability", "online").await;
+        let config: serde_json::Value = serde_json::from_slice(
+            &broker
+                .retained("homeassistant/switch/my_home/kettle/config")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config["command_topic"], "smart_house/my_home/kettle/set");
+        assert_eq!(config["unique_id"], "my_home_kettle");
+        assert_eq!(
+            config["availability"],
+            json!([
+                { "topic": "smart_house/my_home/availability" },
+                { "topic": "smart_house/my_home/kettle/availability" },
+            ])
+        );
+        retained(&broker, "smart_house/my_home/availability", "online").await;
+        assert_eq!(config["suggested_area"], "Kitchen");
+        let sensor: serde_json::Value = serde_json::from_slice(
+            &broker
+                .retained("homeassistant/sensor/my_home/thermo/config")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sensor["device_class"], "temperature");
+        assert_eq!(sensor["unit_of_measurement"], "°C");
+        // No reading yet, so no state.
+        assert!(broker
+            .retained("smart_house/my_home/thermo/state")
+            .is_none());
+
+        {
+            let mut house = house.lock().await;
+            house
+                .set_thermometer_state(
+                    "Thermo",
+                    ThermometerState::Temperature(Temperature::fahrenheit(70.7)),
+                )
+                .unwrap();
+            house.switch_socket("Kettle", SocketState::On).unwrap();
+            house.events().publish(HouseEvent::new(
+                Some("Thermo"),
+                EventKind::Offline {
+                    reason: "no datagrams".to_string(),
+                },
+            ));
+        }
+        retained(&broker, "smart_house/my_home/thermo/state", "21.5").await;
+        retained(&broker, "smart_house/my_home/kettle/state", "ON").await;
+        retained(
+            &broker,
+            "smart_house/my_home/thermo/availability",
+            "offline",
+        )
+        .await;
+
+        house.lock().await.remove_device("Thermo").unwrap();
+        for _ in 0..100 {
+            if broker
+                .retained("homeassistant/sensor/my_home/thermo/config")
+                .is_none()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(broker
+            .retained("homeassistant/sensor/my_home/thermo/config")
+            .is_none());
+
+        handle.shutdown().await.unwrap();
+        retained(&broker, "smart_house/my_home/availability", "offline").await;
+        broker_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let broker = MqttBroker::new("127.0.0.1:0");
+        let broker_handle = broker.start().await.unwrap();
+        let address = broker_handle.local_addr().unwrap().to_string();
+        let house = house();
+        let mut errors = house
+            .lock()
+            .await
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::Error));
+        let handle = MqttBridge::new(&address, house.clone())
+            .start()
+            .await
+            .unwrap();
+        retained(&broker, "smart_house/my_home/availability", "online").await;
+
+        // A restarted broker has lost every retained message.
+        broker_handle.shutdown().await.unwrap();
+        assert!(matches!(
+            errors.recv().await.unwrap().kind,
+            EventKind::Error { .. }
+        ));
+        house
+            .lock()
+            .await
+            .switch_socket("Kettle", SocketState::On)
+            .unwrap();
+        let broker = MqttBroker::new(&address);
+        let broker_handle = broker.start().await.unwrap();
+
+        retained(&broker, "smart_house/my_home/availability", "online").await;
+        retained(&broker, "smart_house/my_home/kettle/state", "ON").await;
+        assert!(broker
+            .retained("homeassistant/switch/my_home/kettle/config")
+            .is_some());
+
+        // Commands are subscribed to again.
+        let mut panel = MqttClient::connect(&address, "panel").await.unwrap();
+        panel
+            .publish("smart_house/my_home/kettle/set", "OFF", false)
+            .await
+            .unwrap();
+        retained(&broker, "smart_house/my_home/kettle/state", "OFF").await;
+
+        panel.disconnect().await.unwrap();
+        handle.shutdown().await.unwrap();
+        broker_handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let broker = MqttBroker::new("127.0.0.1:0");
+        let broker_handle = broker.start().await.unwrap();
+        let address = broker_handle.local_addr().unwrap().to_string();
+        let house = house();
+        let handle = MqttBridge::new(&address, house.clone())
+            .with_base_topic("home")
+            .start()
+            .await
+            .unwrap();
+
+        let mut panel = MqttClient::connect(&address, "panel").await.unwrap();
+        panel.publish("home/kettle/set", "on", false).await.unwrap();
+        retained(&broker, "home/kettle/state", "ON").await;
+        assert_eq!(
+            house.lock().await.socket("Kettle").unwrap().state,
+            SocketState::On
+        );
+
+        // Rejected by the interlock: the state is republished unchanged.
+        panel.subscribe(&["home/fridge/state"]).await.unwrap();
+        assert_eq!(panel.recv().await.unwrap().payload_str(), "ON");
+        panel
+            .publish("home/fridge/set", "OFF", false)
+            .await
+            .unwrap();
+        let answer = panel.recv().await.unwrap();
+        assert_eq!(answer.topic, "home/fridge/state");
+        assert_eq!(answer.payload_str(), "ON");
+        assert_eq!(
+            house.lock().await.socket("Fridge").unwrap().state,
+            SocketState::On
+        );
+
+        panel.disconnect().await.unwrap();
+        handle.shutdown().await.unwrap();
+        broker_handle.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("Living Room Lamp #2"), "living_room_lamp__2");
+    }
+}
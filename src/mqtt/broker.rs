@@ -0,0 +1,287 @@
+use super::packet::{topic_matches, Packet};
+use crate::service::ServiceHandle;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+#[derive(Default)]
+struct BrokerState {
+    retained: BTreeMap<String, Vec<u8>>,
+    clients: HashMap<u64, Subscriber>,
+    next_id: u64,
+}
+
+struct Subscriber {
+    filters: Vec<String>,
+    outgoing: mpsc::UnboundedSender<Packet>,
+}
+
+// Minimal MQTT 3.1.1 broker: QoS 0 delivery, retained messages, wills and
+// wildcard subscriptions, without authentication or sessions. Good
+// enough to run the bridge against in tests and demos.
+pub struct MqttBroker {
+    address: String,
+    state: Arc<Mutex<BrokerState>>,
+}
+
+impl MqttBroker {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            state: Arc::default(),
+        }
+    }
+
+    // Payload of the retained message on `topic`, if there is one.
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().retained.get(topic).cloned()
+    }
+
+    // Topics with a retained message, in order.
+    pub fn retained_topics(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .retained
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    // Bind the listener and start accepting clients in a background task.
+    pub async fn start(&self) -> std::io::Result<ServiceHandle> {
+        let listener = TcpListener::bind(&self.address).await?;
+        let local_addr = listener.local_addr()?;
+        let state = self.state.clone();
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                loop {
+                    let (stream, _) = tokio::select! {
+                        _ = shutdown.requested() => {
+                            // Dropping the queues ends the writers, which
+                            // closes the connections.
+                            state.lock().unwrap().clients.clear();
+                            return Ok(());
+                        }
+                        accepted = listener.accept() => accepted?,
+                    };
+                    let state = state.clone();
+                    let mut shutdown = shutdown.clone();
+                    tokio::spawn(async move {
+                        tokio::select! {
+                            _ = shutdown.requested() => {}
+                            _ = serve_client(stream, state) => {}
+                        }
+                    });
+                }
+            },
+        ))
+    }
+}
+
+async fn serve_client(stream: TcpStream, state: Arc<Mutex<BrokerState>>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Packet>();
+    tokio::spawn(async move {
+        while let Some(packet) = queue.recv().await {
+            if writer.write_all(&packet.encode()).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let will = match Packet::read(&mut reader).await {
+        Ok(Some(Packet::Connect { will, .. })) => {
+            let _ = outgoing.send(Packet::ConnAck { code: 0 });
+            will
+        }
+        _ => return,
+    };
+    let id = {
+        let mut state = state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.clients.insert(
+            id,
+            Subscriber {
+                filters: Vec::new(),
+                outgoing: outgoing.clone(),
+            },
+        );
+        id
+    };
+
+    while let Ok(Some(packet)) = Packet::read(&mut reader).await {
+        match packet {
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+                packet_id,
+            } => {
+                if let Some(packet_id) = packet_id {
+                    let _ = outgoing.send(Packet::PubAck { packet_id });
+                }
+                publish(&mut state.lock().unwrap(), topic, payload, retain);
+            }
+            Packet::Subscribe { packet_id, filters } => {
+                let mut state = state.lock().unwrap();
+                let _ = outgoing.send(Packet::SubAck {
+                    packet_id,
+                    codes: vec![0; filters.len()],
+                });
+                for (topic, payload) in &state.retained {
+                    if filters.iter().any(|f| topic_matches(f, topic)) {
+                        let _ = outgoing.send(Packet::Publish {
+                            topic: topic.clone(),
+                            payload: payload.clone(),
+                            retain: true,
+                            packet_id: None,
+                        });
+                    }
+                }
+                if let Some(subscriber) = state.clients.get_mut(&id) {
+                    subscriber.filters.extend(filters);
+                }
+            }
+            Packet::PingReq => {
+                let _ = outgoing.send(Packet::PingResp);
+            }
+            Packet::Disconnect => {
+                state.lock().unwrap().clients.remove(&id);
+                return;
+            }
+            _ => {}
+        }
+    }
+    // The connection dropped without a DISCONNECT.
+    let mut state = state.lock().unwrap();
+    state.clients.remove(&id);
+    if let Some(will) = will {
+        publish(&mut state, will.topic, will.payload, will.retain);
+    }
+}
+
+fn publish(state: &mut BrokerState, topic: String, payload: Vec<u8>, retain: bool) {
+    if retain {
+        if payload.is_empty() {
+            state.retained.remove(&topic);
+        } else {
+            state.retained.insert(topic.clone(), payload.clone());
+        }
+    }
+    for subscriber in state.clients.values() {
+        if subscriber.filters.iter().any(|f| topic_matches(f, &topic)) {
+            let _ = subscriber.outgoing.send(Packet::Publish {
+                topic: topic.clone(),
+                payload: payload.clone(),
+                retain: false,
+                packet_id: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::client::MqttClient;
+    use crate::mqtt::packet::Will;
+
+    #[tokio::test]
+    async fn test_retained_and_wildcards() {
+        let broker = MqttBroker::new("127.0.0.1:0");
+        let handle = broker.start().await.unwrap();
+        let address = handle.local_addr().unwrap();
+
+        let mut publisher = MqttClient::connect(address, "publisher").await.unwrap();
+        publisher
+            .publish("house/lamp/state", "ON", true)
+            .await
+            .unwrap();
+        publisher
+            .publish("house/heater/state", "OFF", true)
+            .await
+            .unwrap();
+        publisher
+            .publish("house/heater/state", "", true)
+            .await
+            .unwrap();
+        // Packets from one client are handled in order, so once this is
+        // acknowledged the retained messages are settled.
+        publisher.subscribe(&["unused"]).await.unwrap();
+
+        let mut subscriber = MqttClient::connect(address, "subscriber").await.unwrap();
+        subscriber.subscribe(&["house/+/state"]).await.unwrap();
+        let retained = subscriber.recv().await.unwrap();
+        assert_eq!(retained.topic, "house/lamp/state");
+        assert_eq!(retained.payload_str(), "ON");
+        assert!(retained.retain);
+        assert_eq!(broker.retained_topics(), vec!["house/lamp/state"]);
+
+        publisher
+            .publish("house/lamp/set", "OFF", false)
+            .await
+            .unwrap();
+        publisher
+            .publish("house/lamp/state", "OFF", false)
+            .await
+            .unwrap();
+        let live = subscriber.recv().await.unwrap();
+        assert_eq!(live.topic, "house/lamp/state");
+        assert_eq!(live.payload_str(), "OFF");
+        assert!(!live.retain);
+
+        publisher.disconnect().await.unwrap();
+        subscriber.disconnect().await.unwrap();
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_will() {
+        let broker = MqttBroker::new("127.0.0.1:0");
+        let handle = broker.start().await.unwrap();
+        let address = handle.local_addr().unwrap();
+        let will = || Will {
+            topic: "house/availability".to_string(),
+            payload: b"offline".to_vec(),
+            retain: true,
+        };
+
+        let mut subscriber = MqttClient::connect(address, "subscriber").await.unwrap();
+        subscriber.subscribe(&["house/availability"]).await.unwrap();
+
+        // Not published after a clean disconnect.
+        let mut bridge = MqttClient::connect_with_will(address, "bridge", Some(will()))
+            .await
+            .unwrap();
+        bridge
+            .publish("house/availability", "online", true)
+            .await
+            .unwrap();
+        assert_eq!(subscriber.recv().await.unwrap().payload_str(), "online");
+        bridge.disconnect().await.unwrap();
+
+        // Published when the connection just drops.
+        let mut bridge = MqttClient::connect_with_will(address, "bridge", Some(will()))
+            .await
+            .unwrap();
+        bridge
+            .publish("house/availability", "online", true)
+            .await
+            .unwrap();
+        assert_eq!(subscriber.recv().await.unwrap().payload_str(), "online");
+        drop(bridge);
+        assert_eq!(subscriber.recv().await.unwrap().payload_str(), "offline");
+        assert_eq!(
+            broker.retained("house/availability").as_deref(),
+            Some(&b"offline"[..])
+        );
+
+        subscriber.disconnect().await.unwrap();
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use super::packet::{Packet, Will};
+use super::MqttError;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// A message received on a subscribed topic.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    // Set for retained messages delivered when subscribing.
+    pub retain: bool,
+}
+
+impl MqttMessage {
+    pub fn payload_str(&self) -> &str {
+        std::str::from_utf8(&self.payload).unwrap_or("")
+    }
+}
+
+// MQTT 3.1.1 client publishing at QoS 0. Incoming packets are read by a
+// background task, so `recv` can be used in `select!` alongside other work.
+pub struct MqttClient {
+    writer: OwnedWriteHalf,
+    messages: mpsc::UnboundedReceiver<MqttMessage>,
+    // Acknowledgements other than for publishes, in the order received.
+    acks: mpsc::UnboundedReceiver<Packet>,
+    reader: JoinHandle<()>,
+    next_packet_id: u16,
+}
+
+impl MqttClient {
+    pub async fn connect<A: ToSocketAddrs>(address: A, client_id: &str) -> Result<Self, MqttError> {
+        Self::connect_with_will(address, client_id, None).await
+    }
+
+    // Connect with a will the broker publishes if the connection drops
+    // without `disconnect`.
+    pub async fn connect_with_will<A: ToSocketAddrs>(
+        address: A,
+        client_id: &str,
+        will: Option<Will>,
+    ) -> Result<Self, MqttError> {
+        let stream = TcpStream::connect(address).await?;
+        let (mut reader, mut writer) = stream.into_split();
+        writer
+            .write_all(
+                &Packet::Connect {
+                    client_id: client_id.to_string(),
+                    keep_alive: 0,
+                    will,
+                }
+                .encode(),
+            )
+            .await?;
+        match Packet::read(&mut reader).await? {
+            Some(Packet::ConnAck { code: 0 }) => {}
+            Some(Packet::ConnAck { code }) => return Err(MqttError::Refused(code)),
+            _ => return Err(MqttError::Protocol("expected CONNACK".into())),
+        }
+
+        let (message_sender, messages) = mpsc::unbounded_channel();
+        let (ack_sender, acks) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(async move {
+            while let Ok(Some(packet)) = Packet::read(&mut reader).await {
+                match packet {
+                    // Subscriptions are at QoS 0, so nothing needs acknowledging.
+                    Packet::Publish {
+                        topic,
+                        payload,
+                        retain,
+                        ..
+                    } => {
+                        let _ = message_sender.send(MqttMessage {
+                            topic,
+                            payload,
+                            retain,
+                        });
+                    }
+                    other => {
+                        let _ = ack_sender.send(other);
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            writer,
+            messages,
+            acks,
+            reader,
+            next_packet_id: 0,
+        })
+    }
+
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: impl AsRef<[u8]>,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        let packet = Packet::Publish {
+            topic: topic.to_string(),
+            payload: payload.as_ref().to_vec(),
+            retain,
+            packet_id: None,
+        };
+        self.writer.write_all(&packet.encode()).await?;
+        Ok(())
+    }
+
+    // Subscribe and wait for the broker to acknowledge. Retained messages
+    // matching the filters arrive through `recv` afterwards.
+    pub async fn subscribe(&mut self, filters: &[&str]) -> Result<(), MqttError> {
+        self.next_packet_id = self.next_packet_id.wrapping_add(1).max(1);
+        let packet_id = self.next_packet_id;
+        let packet = Packet::Subscribe {
+            packet_id,
+            filters: filters.iter().map(|f| f.to_string()).collect(),
+        };
+        self.writer.write_all(&packet.encode()).await?;
+        loop {
+            match self.acks.recv().await {
+                Some(Packet::SubAck { packet_id: id, .. }) if id == packet_id => return Ok(()),
+                Some(_) => {}
+                None => return Err(MqttError::Closed),
+            }
+        }
+    }
+
+    // Next message on a subscribed topic; `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<MqttMessage> {
+        self.messages.recv().await
+    }
+
+    pub async fn disconnect(mut self) -> Result<(), MqttError> {
+        self.writer.write_all(&Packet::Disconnect.encode()).await?;
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
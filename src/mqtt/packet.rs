@@ -0,0 +1,362 @@
+use super::MqttError;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Largest packet accepted, well above anything the bridge sends.
+const MAX_PACKET_SIZE: usize = 1 << 20;
+
+// Message the broker publishes at QoS 0 when a client's connection drops
+// without a DISCONNECT.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+// The MQTT 3.1.1 control packets the client and broker use. Only QoS 0 is
+// sent; QoS 1 publishes are received and acknowledged.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        keep_alive: u16,
+        will: Option<Will>,
+    },
+    ConnAck {
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+        // Only set for QoS 1, which must be acknowledged with `PubAck`.
+        packet_id: Option<u16>,
+    },
+    PubAck {
+        packet_id: u16,
+    },
+    Subscribe {
+        packet_id: u16,
+        filters: Vec<String>,
+    },
+    SubAck {
+        packet_id: u16,
+        codes: Vec<u8>,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let header = match self {
+            Packet::Connect {
+                client_id,
+                keep_alive,
+                will,
+            } => {
+                put_string(&mut body, "MQTT");
+                body.push(4); // protocol level 3.1.1
+                let flags = match will {
+                    // Clean session with a QoS 0 will.
+                    Some(will) if will.retain => 0x26,
+                    Some(_) => 0x06,
+                    None => 0x02,
+                };
+                body.push(flags);
+                body.extend_from_slice(&keep_alive.to_be_bytes());
+                put_string(&mut body, client_id);
+                if let Some(will) = will {
+                    put_string(&mut body, &will.topic);
+                    body.extend_from_slice(&(will.payload.len() as u16).to_be_bytes());
+                    body.extend_from_slice(&will.payload);
+                }
+                0x10
+            }
+            Packet::ConnAck { code } => {
+                body.extend_from_slice(&[0, *code]);
+                0x20
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+                packet_id,
+            } => {
+                put_string(&mut body, topic);
+                if let Some(id) = packet_id {
+                    body.extend_from_slice(&id.to_be_bytes());
+                }
+                body.extend_from_slice(payload);
+                let qos = if packet_id.is_some() { 0x02 } else { 0 };
+                0x30 | qos | u8::from(*retain)
+            }
+            Packet::PubAck { packet_id } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                0x40
+            }
+            Packet::Subscribe { packet_id, filters } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                for filter in filters {
+                    put_string(&mut body, filter);
+                    body.push(0); // QoS 0
+                }
+                0x82
+            }
+            Packet::SubAck { packet_id, codes } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                body.extend_from_slice(codes);
+                0x90
+            }
+            Packet::PingReq => 0xc0,
+            Packet::PingResp => 0xd0,
+            Packet::Disconnect => 0xe0,
+        };
+
+        let mut packet = vec![header];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        packet.extend_from_slice(&body);
+        packet
+    }
+
+    // Read one packet; `None` when the stream ends cleanly between packets.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Self>, MqttError> {
+        let mut header = [0u8; 1];
+        if reader.read(&mut header).await? == 0 {
+            return Ok(None);
+        }
+        let mut length = 0usize;
+        let mut multiplier = 1usize;
+        loop {
+            let byte = reader.read_u8().await?;
+            length += (byte & 0x7f) as usize * multiplier;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            multiplier *= 128;
+            if multiplier > 128 * 128 * 128 {
+                return Err(MqttError::Protocol("malformed remaining length".into()));
+            }
+        }
+        if length > MAX_PACKET_SIZE {
+            return Err(MqttError::Protocol(format!("packet of {} bytes", length)));
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await?;
+        Self::decode(header[0], &body).map(Some)
+    }
+
+    fn decode(header: u8, body: &[u8]) -> Result<Self, MqttError> {
+        let mut body = Body { data: body };
+        let packet = match header >> 4 {
+            1 => {
+                let protocol = body.string()?;
+                let level = body.u8()?;
+                if protocol != "MQTT" || level != 4 {
+                    return Err(MqttError::Protocol(format!(
+                        "unsupported protocol {} level {}",
+                        protocol, level
+                    )));
+                }
+                let flags = body.u8()?;
+                // Only a clean session is supported; the will's QoS and user
+                // name and password fields after it are ignored.
+                if flags & 0x01 != 0 {
+                    return Err(MqttError::Protocol("reserved CONNECT flag set".into()));
+                }
+                let keep_alive = body.u16()?;
+                let client_id = body.string()?;
+                let will = if flags & 0x04 != 0 {
+                    let topic = body.string()?;
+                    let length = body.u16()? as usize;
+                    Some(Will {
+                        topic,
+                        payload: body.take(length)?.to_vec(),
+                        retain: flags & 0x20 != 0,
+                    })
+                } else {
+                    None
+                };
+                Packet::Connect {
+                    client_id,
+                    keep_alive,
+                    will,
+                }
+            }
+            2 => {
+                body.u8()?;
+                Packet::ConnAck { code: body.u8()? }
+            }
+            3 => {
+                let qos = (header >> 1) & 0x03;
+                let topic = body.string()?;
+                let packet_id = if qos > 0 { Some(body.u16()?) } else { None };
+                Packet::Publish {
+                    topic,
+                    payload: std::mem::take(&mut body.data).to_vec(),
+                    retain: header & 0x01 != 0,
+                    packet_id,
+                }
+            }
+            4 => Packet::PubAck {
+                packet_id: body.u16()?,
+            },
+            8 => {
+                let packet_id = body.u16()?;
+                let mut filters = Vec::new();
+                while !body.data.is_empty() {
+                    filters.push(body.string()?);
+                    body.u8()?; // requested QoS, always granted as 0
+                }
+                Packet::Subscribe { packet_id, filters }
+            }
+            9 => Packet::SubAck {
+                packet_id: body.u16()?,
+                codes: std::mem::take(&mut body.data).to_vec(),
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            other => {
+                return Err(MqttError::Protocol(format!(
+                    "unsupported packet type {}",
+                    other
+                )))
+            }
+        };
+        Ok(packet)
+    }
+}
+
+struct Body<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Body<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MqttError> {
+        if self.data.len() < count {
+            return Err(MqttError::Protocol("packet too short".into()));
+        }
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, MqttError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MqttError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn string(&mut self) -> Result<String, MqttError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| MqttError::Protocol("string is not UTF-8".into()))
+    }
+}
+
+// Whether `topic` matches a subscription filter with `+` and `#` wildcards.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(f), Some(t)) if f == t => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let packets = vec![
+            Packet::Connect {
+                client_id: "bridge".to_string(),
+                keep_alive: 30,
+                will: None,
+            },
+            Packet::Connect {
+                client_id: "bridge".to_string(),
+                keep_alive: 0,
+                will: Some(Will {
+                    topic: "house/availability".to_string(),
+                    payload: b"offline".to_vec(),
+                    retain: true,
+                }),
+            },
+            Packet::ConnAck { code: 0 },
+            Packet::Publish {
+                topic: "house/lamp/state".to_string(),
+                // Long enough to need a two-byte remaining length.
+                payload: vec![b'x'; 300],
+                retain: true,
+                packet_id: None,
+            },
+            Packet::Publish {
+                topic: "house/lamp/set".to_string(),
+                payload: b"ON".to_vec(),
+                retain: false,
+                packet_id: Some(7),
+            },
+            Packet::PubAck { packet_id: 7 },
+            Packet::Subscribe {
+                packet_id: 1,
+                filters: vec!["house/+/set".to_string(), "homeassistant/#".to_string()],
+            },
+            Packet::SubAck {
+                packet_id: 1,
+                codes: vec![0, 0],
+            },
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect,
+        ];
+        let bytes: Vec<u8> = packets.iter().flat_map(Packet::encode).collect();
+        let mut reader = bytes.as_slice();
+        for packet in packets {
+            assert_eq!(Packet::read(&mut reader).await.unwrap(), Some(packet));
+        }
+        assert_eq!(Packet::read(&mut reader).await.unwrap(), None);
+        assert!(Packet::read(&mut [0x30u8, 0x05, 0x00].as_slice())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_topic_matches() {
+        assert!(topic_matches("house/+/set", "house/lamp/set"));
+        assert!(!topic_matches("house/+/set", "house/lamp/state"));
+        assert!(topic_matches("house/#", "house/lamp/state"));
+        assert!(topic_matches("#", "house"));
+        assert!(!topic_matches("house/lamp", "house/lamp/set"));
+        assert!(!topic_matches("house/lamp/set", "house/lamp"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::device_info::temperature::Temperature;
+use std::pin::Pin;
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+const DEFAULT_CAPACITY: usize = 256;
+
+// A single temperature reading as received from a sensor.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reading {
+    pub thermometer: String,
+    pub temperature: Temperature,
+    pub received_at: SystemTime,
+}
+
+// Define an error type for reading streams.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ReadingStreamError {
+    // The consumer fell behind by more than the feed's capacity; the oldest
+    // readings were dropped for it. The stream continues with newer readings.
+    #[error("Consumer lagged behind, {0} readings were skipped")]
+    Lagged(u64),
+}
+
+pub type ReadingStream = Pin<Box<dyn Stream<Item = Result<Reading, ReadingStreamError>> + Send>>;
+
+// Fan-out of readings to any number of stream consumers. Clones publish to
+// the same consumers, so several listeners can share one feed.
+#[derive(Clone)]
+pub struct ReadingFeed {
+    sender: broadcast::Sender<Reading>,
+}
+
+impl Default for ReadingFeed {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ReadingFeed {
+    // `capacity` is how many readings a slow consumer may fall behind before it lags.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, reading: Reading) {
+        // Nobody listening is not an error.
+        let _ = self.sender.send(reading);
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    // Stream of all readings published after this call.
+    pub fn subscribe(&self) -> ReadingStream {
+        Box::pin(BroadcastStream::new(self.sender.subscribe()).map(|item| {
+            item.map_err(|BroadcastStreamRecvError::Lagged(n)| ReadingStreamError::Lagged(n))
+        }))
+    }
+
+    // Stream of the readings of one thermometer. Lag notifications are always passed through.
+    pub fn subscribe_to(&self, thermometer: &str) -> ReadingStream {
+        let thermometer = thermometer.to_string();
+        Box::pin(self.subscribe().filter(move |item| match item {
+            Ok(reading) => reading.thermometer == thermometer,
+            Err(_) => true,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(thermometer: &str, celsius: f32) -> Reading {
+        Reading {
+            thermometer: thermometer.to_string(),
+            temperature: Temperature::celsius(celsius),
+            received_at: SystemTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filtered_stream_only_sees_its_sensor() {
+        let feed = ReadingFeed::default();
+        let mut all = feed.subscribe();
+        let mut kitchen = feed.subscribe_to("Kitchen");
+
+        feed.publish(reading("Bedroom", 19.0));
+        feed.publish(reading("Kitchen", 23.0));
+
+        assert_eq!(all.next().await.unwrap().unwrap().thermometer, "Bedroom");
+        assert_eq!(all.next().await.unwrap().unwrap().thermometer, "Kitchen");
+        let item = kitchen.next().await.unwrap().unwrap();
+        assert_eq!(item.temperature, Temperature::celsius(23.0));
+    }
+
+    #[tokio::test]
+    async fn test_lagging_consumer_is_told_how_much_it_missed() {
+        let feed = ReadingFeed::new(2);
+        let mut stream = feed.subscribe();
+        for i in 0..5 {
+            feed.publish(reading("Kitchen", 20.0 + i as f32));
+        }
+        assert_eq!(
+            stream.next().await.unwrap(),
+            Err(ReadingStreamError::Lagged(3))
+        );
+        let next = stream.next().await.unwrap().unwrap();
+        assert_eq!(next.temperature, Temperature::celsius(23.0));
+    }
+}
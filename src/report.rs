@@ -0,0 +1,494 @@
+use crate::device_info::devices::{Device, SocketState, ThermometerState};
+use crate::device_info::temperature::{Temperature, TemperatureUnit};
+use crate::epoch_millis;
+use crate::load_shedding::{total_power, ShedKind};
+use crate::modes::HouseMode;
+use crate::smart_house::SmartHouse;
+use crate::storage::{Resolution, Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+// Formats a `HouseReport` can be rendered in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "text/plain; charset=utf-8",
+            ReportFormat::Json => "application/json",
+            ReportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!(
+                "unknown report format `{}`, expected `text`, `json` or `csv`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportFormat::Text => "text",
+            ReportFormat::Json => "json",
+            ReportFormat::Csv => "csv",
+        })
+    }
+}
+
+// Snapshot of every device of a house, for reports and APIs.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HouseReport {
+    pub house: String,
+    pub mode: HouseMode,
+    pub temperature_unit: TemperatureUnit,
+    pub rooms: Vec<RoomReport>,
+    // Only for houses with a power budget.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_shedding: Option<LoadSheddingReport>,
+}
+
+// The power budget, current load and what the load shedder did lately.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LoadSheddingReport {
+    pub limit: f32,
+    pub load: f32,
+    // Sockets currently switched off because of the budget.
+    pub shed: Vec<String>,
+    // Oldest first.
+    pub actions: Vec<ShedActionReport>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ShedActionReport {
+    pub socket: String,
+    pub action: ShedKind,
+    pub priority: i32,
+    pub total_before: f32,
+    pub total_after: f32,
+    // Milliseconds since the Unix epoch.
+    pub at: u64,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RoomReport {
+    pub name: String,
+    pub devices: Vec<DeviceReport>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DeviceReport {
+    pub name: String,
+    pub room: String,
+    #[serde(flatten)]
+    pub status: DeviceStatus,
+    // Only filled in by `HouseReport::with_history`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<DeviceHistory>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeviceStatus {
+    Socket {
+        state: SocketState,
+        power_consumption: f32,
+    },
+    // `temperature` is in `unit`, and missing while there is no reading.
+    Thermometer {
+        temperature: Option<f32>,
+        unit: TemperatureUnit,
+    },
+}
+
+// What a device did over the window of a report, from stored history.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DeviceHistory {
+    Socket {
+        energy_wh: f64,
+        switches: usize,
+    },
+    // In the report's unit; missing without readings in the window.
+    Thermometer {
+        readings: u64,
+        min: Option<f32>,
+        max: Option<f32>,
+        avg: Option<f32>,
+    },
+}
+
+impl fmt::Display for DeviceHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceHistory::Socket {
+                energy_wh,
+                switches,
+            } => write!(f, "{:.1} Wh, {} switches", energy_wh, switches),
+            DeviceHistory::Thermometer {
+                readings,
+                min: Some(min),
+                max: Some(max),
+                avg: Some(avg),
+            } => write!(
+                f,
+                "{} readings, min {:.1}, avg {:.1}, max {:.1}",
+                readings, min, avg, max
+            ),
+            DeviceHistory::Thermometer { .. } => write!(f, "no readings"),
+        }
+    }
+}
+
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceStatus::Socket {
+                state,
+                power_consumption,
+            } => write!(f, "socket {:?}, {:.1} W", state, power_consumption),
+            DeviceStatus::Thermometer {
+                temperature: Some(value),
+                unit,
+            } => write!(f, "thermometer {:.1}{}", value, unit.symbol()),
+            DeviceStatus::Thermometer {
+                temperature: None, ..
+            } => write!(f, "thermometer, no reading"),
+        }
+    }
+}
+
+impl DeviceReport {
+    // Describe a device, giving temperatures in `unit`.
+    pub fn new(room: &str, device: &Device, unit: TemperatureUnit) -> Self {
+        let status = match device {
+            Device::SmartSocket(socket) => DeviceStatus::Socket {
+                state: socket.state.clone(),
+                power_consumption: socket.power_consumption,
+            },
+            Device::SmartThermometer(thermometer) => DeviceStatus::Thermometer {
+                temperature: match thermometer.state {
+                    ThermometerState::Temperature(t) => Some(t.to_unit(unit).value()),
+                    ThermometerState::Off => None,
+                },
+                unit,
+            },
+        };
+        Self {
+            name: device.name().to_string(),
+            room: room.to_string(),
+            status,
+            history: None,
+        }
+    }
+
+    // Look a device up in the house by name.
+    pub fn find(house: &SmartHouse, name: &str) -> Option<Self> {
+        let room = house.room_of(name)?;
+        let device = house.find_device(name)?;
+        Some(Self::new(room, device, house.temperature_unit))
+    }
+}
+
+impl HouseReport {
+    pub fn new(house: &SmartHouse) -> Self {
+        Self {
+            house: house.name.clone(),
+            mode: house.mode,
+            temperature_unit: house.temperature_unit,
+            rooms: house
+                .rooms()
+                .iter()
+                .map(|room| RoomReport {
+                    name: room.name.clone(),
+                    devices: room
+                        .devices
+                        .iter()
+                        .map(|device| DeviceReport::new(&room.name, device, house.temperature_unit))
+                        .collect(),
+                })
+                .collect(),
+            load_shedding: house
+                .load_shedder
+                .as_ref()
+                .map(|shedder| LoadSheddingReport {
+                    limit: shedder.budget().limit,
+                    load: total_power(house),
+                    shed: shedder
+                        .shed_sockets()
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                    actions: shedder
+                        .history()
+                        .iter()
+                        .map(|action| ShedActionReport {
+                            socket: action.socket.clone(),
+                            action: action.kind,
+                            priority: action.priority,
+                            total_before: action.total_before,
+                            total_after: action.total_after,
+                            at: epoch_millis(action.at),
+                        })
+                        .collect(),
+                }),
+        }
+    }
+
+    // Add what every device did in `[from, to)`. Thermometers are summarized
+    // from minute rollups, so raw readings past retention still count.
+    pub fn with_history(
+        mut self,
+        storage: &Storage,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Self, StorageError> {
+        let unit = self.temperature_unit;
+        for device in self
+            .rooms
+            .iter_mut()
+            .flat_map(|room| room.devices.iter_mut())
+        {
+            device.history = Some(match device.status {
+                DeviceStatus::Socket { .. } => DeviceHistory::Socket {
+                    energy_wh: storage.energy(&device.name, from, to)?,
+                    switches: storage.socket_changes(&device.name, from, to)?.len(),
+                },
+                DeviceStatus::Thermometer { .. } => {
+                    let rollups = storage.rollups(&device.name, Resolution::Minute, from, to)?;
+                    let readings: u64 = rollups.iter().map(|r| r.count).sum();
+                    let value = |t: &Temperature| t.to_unit(unit).value();
+                    let sum: f32 = rollups.iter().map(|r| value(&r.avg) * r.count as f32).sum();
+                    DeviceHistory::Thermometer {
+                        readings,
+                        min: rollups.iter().map(|r| value(&r.min)).reduce(f32::min),
+                        max: rollups.iter().map(|r| value(&r.max)).reduce(f32::max),
+                        avg: (readings > 0).then(|| sum / readings as f32),
+                    }
+                }
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceReport> {
+        self.rooms.iter().flat_map(|room| room.devices.iter())
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.to_text(),
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => self.to_csv(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("House: {} (mode: {})\n", self.house, self.mode);
+        for room in &self.rooms {
+            text.push_str(&format!("Room: {}\n", room.name));
+            for device in &room.devices {
+                text.push_str(&format!("  {}: {}", device.name, device.status));
+                if let Some(history) = &device.history {
+                    text.push_str(&format!("; history: {}", history));
+                }
+                text.push('\n');
+            }
+        }
+        if let Some(shedding) = &self.load_shedding {
+            text.push_str(&format!(
+                "Power budget: {:.1} W, current load: {:.1} W\n",
+                shedding.limit, shedding.load
+            ));
+            for action in &shedding.actions {
+                let verb = match action.action {
+                    ShedKind::Shed => "Shed",
+                    ShedKind::Restored => "Restored",
+                };
+                text.push_str(&format!(
+                    "  {} {} (priority {}), {:.1} W -> {:.1} W\n",
+                    verb, action.socket, action.priority, action.total_before, action.total_after
+                ));
+            }
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        // Only strings, numbers and plain enums; serializing can't fail.
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    // One line per device: room,device,type,state,power_consumption,temperature,unit
+    pub fn to_csv(&self) -> String {
+        let mut csv = "room,device,type,state,power_consumption,temperature,unit\n".to_string();
+        for device in self.devices() {
+            let fields = match &device.status {
+                DeviceStatus::Socket {
+                    state,
+                    power_consumption,
+                } => [
+                    "socket".to_string(),
+                    format!("{:?}", state).to_lowercase(),
+                    power_consumption.to_string(),
+                    String::new(),
+                    String::new(),
+                ],
+                DeviceStatus::Thermometer { temperature, unit } => [
+                    "thermometer".to_string(),
+                    String::new(),
+                    String::new(),
+                    temperature.map(|t| t.to_string()).unwrap_or_default(),
+                    unit.symbol().to_string(),
+                ],
+            };
+            csv.push_str(&csv_field(&device.room));
+            csv.push(',');
+            csv.push_str(&csv_field(&device.name));
+            for field in fields {
+                csv.push(',');
+                csv.push_str(&csv_field(&field));
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::temperature::Temperature;
+    use crate::test_support::{room, socket, thermometer};
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "Home",
+            vec![room(
+                "Living Room, front",
+                vec![
+                    socket("Lamp", SocketState::On, 60.0),
+                    thermometer(
+                        "Thermo",
+                        ThermometerState::Temperature(Temperature::fahrenheit(212.0)),
+                    ),
+                ],
+            )],
+        )
+    }
+
+    #[test]
+    fn test_formats() {
+        let report = HouseReport::new(&house());
+        assert_eq!(
+            report.to_text(),
+            "House: Home (mode: home)\nRoom: Living Room, front\n  Lamp: socket On, 60.0 W\n  Thermo: thermometer 100.0°C\n"
+        );
+        assert_eq!(
+            report.to_csv(),
+            "room,device,type,state,power_consumption,temperature,unit\n\
+             \"Living Room, front\",Lamp,socket,on,60,,\n\
+             \"Living Room, front\",Thermo,thermometer,,,100,°C\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["rooms"][0]["devices"][0]["type"], "socket");
+        assert_eq!(json["rooms"][0]["devices"][0]["state"], "on");
+        assert_eq!(json["rooms"][0]["devices"][1]["temperature"], 100.0);
+        assert_eq!(json["temperature_unit"], "celsius");
+        assert_eq!("CSV".parse(), Ok(ReportFormat::Csv));
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_load_shedding() {
+        use crate::load_shedding::{LoadShedder, PowerBudget};
+        use std::time::Instant;
+
+        let mut house = house();
+        assert!(!HouseReport::new(&house).to_json().contains("load_shedding"));
+        house.load_shedder = Some(LoadShedder::new(PowerBudget::new(50.0)));
+        house.shed_load(Instant::now());
+
+        let report = HouseReport::new(&house);
+        assert!(report.to_text().ends_with(
+            "Power budget: 50.0 W, current load: 0.0 W\n  Shed Lamp (priority 0), 60.0 W -> 0.0 W\n"
+        ));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["load_shedding"]["shed"][0], "Lamp");
+        assert_eq!(json["load_shedding"]["actions"][0]["action"], "shed");
+        assert_eq!(json["load_shedding"]["actions"][0]["total_before"], 60.0);
+    }
+
+    #[test]
+    fn test_history() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_040);
+        let storage = Storage::in_memory().unwrap();
+        storage
+            .record_socket("Lamp", &SocketState::On, 60.0, start)
+            .unwrap();
+        for (minute, celsius) in [(0, 18.0), (1, 22.0), (2, 23.0)] {
+            storage
+                .record_reading(
+                    "Thermo",
+                    Temperature::celsius(celsius),
+                    start + Duration::from_secs(minute * 60),
+                )
+                .unwrap();
+        }
+
+        let report = HouseReport::new(&house())
+            .with_history(&storage, start, start + Duration::from_secs(3600))
+            .unwrap();
+        let devices: Vec<_> = report.devices().collect();
+        assert_eq!(
+            devices[0].history,
+            Some(DeviceHistory::Socket {
+                energy_wh: 60.0,
+                switches: 1
+            })
+        );
+        assert_eq!(
+            devices[1].history,
+            Some(DeviceHistory::Thermometer {
+                readings: 3,
+                min: Some(18.0),
+                max: Some(23.0),
+                avg: Some(21.0)
+            })
+        );
+        assert!(report.to_text().contains(
+            "Thermo: thermometer 100.0°C; history: 3 readings, min 18.0, avg 21.0, max 23.0\n"
+        ));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["rooms"][0]["devices"][0]["history"]["energy_wh"], 60.0);
+    }
+}
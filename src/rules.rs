@@ -0,0 +1,636 @@
+use crate::device_info::devices::{SocketState, ThermometerState};
+use crate::device_info::temperature::{Temperature, TemperatureUnit};
+use crate::smart_house::{ControlError, SmartHouse};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Below,
+    Above,
+}
+
+// What has to be true for a rule to fire.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Temperature {
+        thermometer: String,
+        comparison: Comparison,
+        threshold: Temperature,
+    },
+    SocketIs {
+        socket: String,
+        state: SocketState,
+    },
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Temperature {
+                thermometer,
+                comparison,
+                threshold,
+            } => {
+                let sign = match comparison {
+                    Comparison::Below => '<',
+                    Comparison::Above => '>',
+                };
+                write!(f, "{} {} {}", thermometer, sign, threshold)
+            }
+            Condition::SocketIs { socket, state } => {
+                let state = match state {
+                    SocketState::On => "on",
+                    SocketState::Off => "off",
+                };
+                write!(f, "{} is {}", socket, state)
+            }
+        }
+    }
+}
+
+// What a rule does once it fires.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    SwitchSocket { socket: String, state: SocketState },
+}
+
+impl Action {
+    fn target(&self) -> &str {
+        match self {
+            Action::SwitchSocket { socket, .. } => socket,
+        }
+    }
+}
+
+// A rule such as "if LivingRoomThermo < 19 for 5 min then turn on HeaterSocket".
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rule {
+    pub name: String,
+    pub condition: Condition,
+    // How long the condition must hold before the action runs.
+    pub hold: Duration,
+    pub action: Action,
+    // Rules with a higher priority are evaluated first and win when several
+    // rules target the same device.
+    pub priority: i32,
+    pub enabled: bool,
+}
+
+impl Rule {
+    pub fn new(name: &str, condition: Condition, action: Action) -> Self {
+        Self {
+            name: name.to_string(),
+            condition,
+            hold: Duration::ZERO,
+            action,
+            priority: 0,
+            enabled: true,
+        }
+    }
+
+    pub fn for_at_least(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+// Result of evaluating one rule.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RuleOutcome {
+    Disabled,
+    // The condition refers to a device that is missing or has no reading.
+    Unavailable(String),
+    NotMet,
+    Pending { remaining: Duration },
+    AlreadyApplied,
+    Fired,
+    Overridden { by: String },
+    Failed(ControlError),
+}
+
+impl fmt::Display for RuleOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleOutcome::Disabled => write!(f, "disabled"),
+            RuleOutcome::Unavailable(reason) => write!(f, "unavailable: {}", reason),
+            RuleOutcome::NotMet => write!(f, "condition not met"),
+            RuleOutcome::Pending { remaining } => {
+                write!(f, "condition met, firing in {}s", remaining.as_secs())
+            }
+            RuleOutcome::AlreadyApplied => write!(f, "condition met, action already applied"),
+            RuleOutcome::Fired => write!(f, "fired"),
+            RuleOutcome::Overridden { by } => write!(f, "overridden by rule {}", by),
+            RuleOutcome::Failed(e) => write!(f, "action failed: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEntry {
+    pub rule: String,
+    pub priority: i32,
+    pub outcome: RuleOutcome,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rule: {}, Priority: {}, Outcome: {}",
+            self.rule, self.priority, self.outcome
+        )
+    }
+}
+
+// Define an error type for rule definitions.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum RuleParseError {
+    #[error("Line {line}: {message}")]
+    Invalid { line: usize, message: String },
+}
+
+struct RuleEntry {
+    rule: Rule,
+    met_since: Option<Instant>,
+}
+
+// Evaluates rules against the state of a `SmartHouse` and applies their actions.
+#[derive(Default)]
+pub struct RulesEngine {
+    rules: Vec<RuleEntry>,
+    last_trace: Vec<TraceEntry>,
+}
+
+impl RulesEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a rule, replacing any rule with the same name.
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.remove_rule(&rule.name);
+        self.rules.push(RuleEntry {
+            rule,
+            met_since: None,
+        });
+    }
+
+    pub fn remove_rule(&mut self, name: &str) {
+        self.rules.retain(|entry| entry.rule.name != name);
+    }
+
+    pub fn rules(&self) -> Vec<&Rule> {
+        self.rules.iter().map(|entry| &entry.rule).collect()
+    }
+
+    // Enable or disable a rule. Returns false if there is no such rule.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.rules.iter_mut().find(|entry| entry.rule.name == name) {
+            Some(entry) => {
+                entry.rule.enabled = enabled;
+                entry.met_since = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Load rules from text, one rule per line; see `Rule::from_str` for the syntax.
+    // Blank lines and lines starting with `#` are ignored.
+    pub fn load(&mut self, text: &str) -> Result<(), RuleParseError> {
+        let mut parsed = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = line.parse::<Rule>().map_err(|e| match e {
+                RuleParseError::Invalid { message, .. } => RuleParseError::Invalid {
+                    line: index + 1,
+                    message,
+                },
+            })?;
+            parsed.push(rule);
+        }
+        for rule in parsed {
+            self.add_rule(rule);
+        }
+        Ok(())
+    }
+
+    // Evaluate all rules at `now`, highest priority first, and apply the
+    // actions of the rules that fire. Returns the evaluation trace.
+    pub fn evaluate(&mut self, house: &mut SmartHouse, now: Instant) -> &[TraceEntry] {
+        let mut order: Vec<usize> = (0..self.rules.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.rules[i].rule.priority));
+
+        let mut claimed: HashMap<String, String> = HashMap::new();
+        let mut trace = Vec::with_capacity(order.len());
+        for i in order {
+            let entry = &mut self.rules[i];
+            let outcome = evaluate_rule(entry, house, now, &mut claimed);
+            trace.push(TraceEntry {
+                rule: entry.rule.name.clone(),
+                priority: entry.rule.priority,
+                outcome,
+            });
+        }
+        self.last_trace = trace;
+        &self.last_trace
+    }
+
+    // Trace of the most recent evaluation.
+    pub fn last_trace(&self) -> &[TraceEntry] {
+        &self.last_trace
+    }
+}
+
+fn evaluate_rule(
+    entry: &mut RuleEntry,
+    house: &mut SmartHouse,
+    now: Instant,
+    claimed: &mut HashMap<String, String>,
+) -> RuleOutcome {
+    let rule = &entry.rule;
+    if !rule.enabled {
+        entry.met_since = None;
+        return RuleOutcome::Disabled;
+    }
+    match condition_met(&rule.condition, house) {
+        Err(reason) => {
+            entry.met_since = None;
+            return RuleOutcome::Unavailable(reason);
+        }
+        Ok(false) => {
+            entry.met_since = None;
+            return RuleOutcome::NotMet;
+        }
+        Ok(true) => {}
+    }
+    let since = *entry.met_since.get_or_insert(now);
+    let elapsed = now.duration_since(since);
+    if elapsed < rule.hold {
+        return RuleOutcome::Pending {
+            remaining: rule.hold - elapsed,
+        };
+    }
+
+    let target = rule.action.target();
+    if let Some(winner) = claimed.get(target) {
+        return RuleOutcome::Overridden { by: winner.clone() };
+    }
+    claimed.insert(target.to_string(), rule.name.clone());
+
+    match &rule.action {
+        Action::SwitchSocket { socket, state } => match house.socket(socket) {
+            Ok(current) if current.state == *state => RuleOutcome::AlreadyApplied,
+            Ok(_) => match house.switch_socket(socket, state.clone()) {
+                Ok(()) => RuleOutcome::Fired,
+                Err(e) => RuleOutcome::Failed(e),
+            },
+            Err(e) => RuleOutcome::Failed(e),
+        },
+    }
+}
+
+pub(crate) fn condition_met(condition: &Condition, house: &SmartHouse) -> Result<bool, String> {
+    match condition {
+        Condition::Temperature {
+            thermometer,
+            comparison,
+            threshold,
+        } => {
+            let device = house.thermometer(thermometer).map_err(|e| e.to_string())?;
+            match &device.state {
+                ThermometerState::Off => Err(format!("no reading from {}", thermometer)),
+                ThermometerState::Temperature(t) => Ok(match comparison {
+                    Comparison::Below => t < threshold,
+                    Comparison::Above => t > threshold,
+                }),
+            }
+        }
+        Condition::SocketIs { socket, state } => {
+            let device = house.socket(socket).map_err(|e| e.to_string())?;
+            Ok(device.state == *state)
+        }
+    }
+}
+
+// Whitespace-separated tokens of a rule definition.
+struct Tokens<'a> {
+    inner: std::iter::Peekable<std::str::SplitWhitespace<'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            inner: text.split_whitespace().peekable(),
+        }
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a str, String> {
+        self.inner
+            .next()
+            .ok_or_else(|| format!("expected {}", what))
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        match self.inner.next() {
+            Some(token) if token == word => Ok(()),
+            Some(token) => Err(format!("expected `{}`, found `{}`", word, token)),
+            None => Err(format!("expected `{}`", word)),
+        }
+    }
+
+    // Consume `word` if it is the next token.
+    fn accept(&mut self, word: &str) -> bool {
+        self.inner.next_if_eq(&word).is_some()
+    }
+
+    fn is_empty(&mut self) -> bool {
+        self.inner.peek().is_none()
+    }
+}
+
+// Parses a single rule:
+//
+//     <name>: if <thermometer> <|> <temperature> [for <duration>] then <socket> on|off [priority <n>]
+//     <name>: if <socket> is on|off [for <duration>] then <socket> on|off [priority <n>]
+//
+// Temperatures default to Celsius and may carry a `C`, `F` or `K` suffix;
+// durations take an `s`, `m` or `h` suffix.
+impl FromStr for Rule {
+    type Err = RuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_rule(s).map_err(|message| RuleParseError::Invalid { line: 1, message })
+    }
+}
+
+fn parse_rule(text: &str) -> Result<Rule, String> {
+    let (name, body) = text
+        .split_once(':')
+        .ok_or_else(|| "expected `<name>: if ...`".to_string())?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid rule name {:?}", name));
+    }
+
+    let mut tokens = Tokens::new(body);
+    tokens.expect("if")?;
+    let subject = tokens.next("a device name")?.to_string();
+    let condition = match tokens.next("`<`, `>` or `is`")? {
+        "<" => Condition::Temperature {
+            thermometer: subject,
+            comparison: Comparison::Below,
+            threshold: parse_temperature(tokens.next("a temperature")?)?,
+        },
+        ">" => Condition::Temperature {
+            thermometer: subject,
+            comparison: Comparison::Above,
+            threshold: parse_temperature(tokens.next("a temperature")?)?,
+        },
+        "is" => Condition::SocketIs {
+            socket: subject,
+            state: parse_socket_state(tokens.next("`on` or `off`")?)?,
+        },
+        other => return Err(format!("expected `<`, `>` or `is`, found `{}`", other)),
+    };
+
+    let mut hold = Duration::ZERO;
+    if tokens.accept("for") {
+        hold = parse_duration(tokens.next("a duration")?)?;
+    }
+    tokens.expect("then")?;
+    let socket = tokens.next("a socket name")?.to_string();
+    let state = parse_socket_state(tokens.next("`on` or `off`")?)?;
+
+    let mut priority = 0;
+    if !tokens.is_empty() {
+        tokens.expect("priority")?;
+        let value = tokens.next("a priority")?;
+        priority = value
+            .parse()
+            .map_err(|_| format!("invalid priority `{}`", value))?;
+    }
+    if let Ok(extra) = tokens.next("") {
+        return Err(format!("unexpected `{}`", extra));
+    }
+
+    Ok(
+        Rule::new(name, condition, Action::SwitchSocket { socket, state })
+            .for_at_least(hold)
+            .with_priority(priority),
+    )
+}
+
+// Parse `19`, `19.5C`, `66F` or `291.15K`; a plain number is Celsius.
+pub(crate) fn parse_temperature(text: &str) -> Result<Temperature, String> {
+    let trimmed = text.trim_end_matches(['C', 'F', 'K']);
+    let unit = match &text[trimmed.len()..] {
+        "" | "C" => TemperatureUnit::Celsius,
+        "F" => TemperatureUnit::Fahrenheit,
+        "K" => TemperatureUnit::Kelvin,
+        _ => return Err(format!("invalid temperature `{}`", text)),
+    };
+    let value = trimmed
+        .trim_end_matches('°')
+        .parse::<f32>()
+        .map_err(|_| format!("invalid temperature `{}`", text))?;
+    Ok(Temperature::new(value, unit))
+}
+
+// Parse `30s`, `5m` or `2h`.
+pub(crate) fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{}`", text);
+    let split = text.len().saturating_sub(1);
+    let (value, suffix) = (text.get(..split).ok_or_else(invalid)?, &text[split..]);
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    let seconds = match suffix {
+        "s" => Some(value),
+        "m" => value.checked_mul(60),
+        "h" => value.checked_mul(3600),
+        _ => None,
+    };
+    seconds.map(Duration::from_secs).ok_or_else(invalid)
+}
+
+pub(crate) fn parse_socket_state(text: &str) -> Result<SocketState, String> {
+    match text {
+        "on" => Ok(SocketState::On),
+        "off" => Ok(SocketState::Off),
+        other => Err(format!("expected `on` or `off`, found `{}`", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::devices::{Device, SmartSocket, SmartThermometer};
+    use crate::smart_house::Room;
+
+    fn house(temperature: f32) -> SmartHouse {
+        let room = Room {
+            name: "LivingRoom".to_string(),
+            devices: vec![
+                Device::SmartThermometer(SmartThermometer {
+                    name: "LivingRoomThermo".to_string(),
+                    state: ThermometerState::Temperature(Temperature::celsius(temperature)),
+                }),
+                Device::SmartSocket(SmartSocket {
+                    name: "HeaterSocket".to_string(),
+                    state: SocketState::Off,
+                    power_consumption: 1500.0,
+                }),
+            ],
+        };
+        SmartHouse::new("Home", vec![room])
+    }
+
+    #[test]
+    fn test_rule_fires_after_hold_duration() {
+        let mut house = house(18.0);
+        let mut engine = RulesEngine::new();
+        engine
+            .load("heat: if LivingRoomThermo < 19 for 5m then HeaterSocket on")
+            .unwrap();
+
+        let start = Instant::now();
+        let trace = engine.evaluate(&mut house, start);
+        assert_eq!(
+            trace[0].outcome,
+            RuleOutcome::Pending {
+                remaining: Duration::from_secs(300)
+            }
+        );
+        assert_eq!(
+            house.socket("HeaterSocket").unwrap().state,
+            SocketState::Off
+        );
+
+        let trace = engine.evaluate(&mut house, start + Duration::from_secs(300));
+        assert_eq!(trace[0].outcome, RuleOutcome::Fired);
+        assert_eq!(house.socket("HeaterSocket").unwrap().state, SocketState::On);
+
+        let trace = engine.evaluate(&mut house, start + Duration::from_secs(360));
+        assert_eq!(trace[0].outcome, RuleOutcome::AlreadyApplied);
+    }
+
+    #[test]
+    fn test_higher_priority_rule_wins() {
+        let mut house = house(18.0);
+        let mut engine = RulesEngine::new();
+        engine
+            .load(
+                "# heater rules\n\
+                 heat: if LivingRoomThermo < 19 then HeaterSocket on priority 1\n\
+                 eco: if LivingRoomThermo > 10 then HeaterSocket off priority 5\n",
+            )
+            .unwrap();
+
+        let trace = engine.evaluate(&mut house, Instant::now()).to_vec();
+        assert_eq!(trace[0].rule, "eco");
+        assert_eq!(trace[0].outcome, RuleOutcome::AlreadyApplied);
+        assert_eq!(
+            trace[1].outcome,
+            RuleOutcome::Overridden {
+                by: "eco".to_string()
+            }
+        );
+        assert_eq!(
+            house.socket("HeaterSocket").unwrap().state,
+            SocketState::Off
+        );
+
+        assert!(engine.set_enabled("eco", false));
+        let trace = engine.evaluate(&mut house, Instant::now());
+        assert_eq!(trace[0].outcome, RuleOutcome::Disabled);
+        assert_eq!(trace[1].outcome, RuleOutcome::Fired);
+        assert_eq!(
+            trace[1].to_string(),
+            "Rule: heat, Priority: 1, Outcome: fired"
+        );
+    }
+
+    #[test]
+    fn test_missing_devices_are_reported() {
+        let mut house = house(18.0);
+        let mut engine = RulesEngine::new();
+        engine.add_rule(Rule::new(
+            "broken",
+            Condition::Temperature {
+                thermometer: "Attic".to_string(),
+                comparison: Comparison::Below,
+                threshold: Temperature::fahrenheit(60.0),
+            },
+            Action::SwitchSocket {
+                socket: "HeaterSocket".to_string(),
+                state: SocketState::On,
+            },
+        ));
+        engine.add_rule(
+            "cool: if HeaterSocket is off then Fan on"
+                .parse::<Rule>()
+                .unwrap(),
+        );
+        let trace = engine.evaluate(&mut house, Instant::now());
+        assert_eq!(
+            trace[0].outcome,
+            RuleOutcome::Unavailable("Device named Attic not found".to_string())
+        );
+        assert_eq!(
+            trace[1].outcome,
+            RuleOutcome::Failed(ControlError::NotFound("Fan".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule: Rule = "night: if Bedroom > 66.5F for 30s then Fan off priority -2"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.name, "night");
+        assert_eq!(rule.hold, Duration::from_secs(30));
+        assert_eq!(rule.priority, -2);
+        assert_eq!(
+            rule.condition,
+            Condition::Temperature {
+                thermometer: "Bedroom".to_string(),
+                comparison: Comparison::Above,
+                threshold: Temperature::fahrenheit(66.5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_carry_line_numbers() {
+        let mut engine = RulesEngine::new();
+        let result = engine.load("ok: if A < 19 then B on\n\nbad: if A < warm then B on\n");
+        assert_eq!(
+            result,
+            Err(RuleParseError::Invalid {
+                line: 3,
+                message: "invalid temperature `warm`".to_string()
+            })
+        );
+        assert!(engine.rules().is_empty());
+        assert!("x: if A < 19 then B dim".parse::<Rule>().is_err());
+        assert!("x: if A < 19 for 5 then B on".parse::<Rule>().is_err());
+        assert!(format!("x: if A < 19 for {}h then B on", u64::MAX)
+            .parse::<Rule>()
+            .is_err());
+        assert_eq!(
+            parse_duration(&format!("{}m", u64::MAX / 60)),
+            Ok(Duration::from_secs(u64::MAX / 60 * 60))
+        );
+    }
+}
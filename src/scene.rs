@@ -0,0 +1,334 @@
+use crate::device_info::devices::{Device, SocketState};
+use crate::smart_house::{ControlError, SmartHouse};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Which devices a scene target applies to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Selector {
+    // A single device by name.
+    Device(String),
+    // Every socket in a room.
+    Room(String),
+    // Every socket in the house.
+    All,
+}
+
+// Stored as `{ device = "...", state = "on" }`, `{ room = "...", state = "off" }`
+// or `{ all = true, state = "off" }`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "StoredTarget", into = "StoredTarget")]
+pub struct SceneTarget {
+    pub selector: Selector,
+    pub state: SocketState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTarget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    all: bool,
+    state: SocketState,
+}
+
+impl TryFrom<StoredTarget> for SceneTarget {
+    type Error = String;
+
+    fn try_from(stored: StoredTarget) -> Result<Self, Self::Error> {
+        let selector = match (stored.device, stored.room, stored.all) {
+            (Some(device), None, false) => Selector::Device(device),
+            (None, Some(room), false) => Selector::Room(room),
+            (None, None, true) => Selector::All,
+            _ => return Err("scene target needs exactly one of `device`, `room` or `all`".into()),
+        };
+        Ok(Self {
+            selector,
+            state: stored.state,
+        })
+    }
+}
+
+impl From<SceneTarget> for StoredTarget {
+    fn from(target: SceneTarget) -> Self {
+        let (device, room, all) = match target.selector {
+            Selector::Device(device) => (Some(device), None, false),
+            Selector::Room(room) => (None, Some(room), false),
+            Selector::All => (None, None, true),
+        };
+        Self {
+            device,
+            room,
+            all,
+            state: target.state,
+        }
+    }
+}
+
+// A named set of target device states, e.g. "Night" or "Leaving".
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    pub targets: Vec<SceneTarget>,
+}
+
+impl Scene {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            targets: Vec::new(),
+        }
+    }
+
+    pub fn with_target(mut self, selector: Selector, state: SocketState) -> Self {
+        self.targets.push(SceneTarget { selector, state });
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DeviceOutcome {
+    Changed,
+    Unchanged,
+    Failed(ControlError),
+    // Changed, then restored because another device of the scene failed.
+    RolledBack,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DeviceResult {
+    pub device: String,
+    pub outcome: DeviceOutcome,
+}
+
+// Per-device results of applying a scene.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SceneReport {
+    pub scene: String,
+    pub results: Vec<DeviceResult>,
+    pub rolled_back: bool,
+}
+
+impl SceneReport {
+    pub fn succeeded(&self) -> bool {
+        self.results
+            .iter()
+            .all(|r| matches!(r.outcome, DeviceOutcome::Changed | DeviceOutcome::Unchanged))
+    }
+
+    pub fn failures(&self) -> Vec<&DeviceResult> {
+        self.results
+            .iter()
+            .filter(|r| matches!(r.outcome, DeviceOutcome::Failed(_)))
+            .collect()
+    }
+}
+
+// Define an error type for scenes.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum SceneError {
+    #[error("Scene named {0} not found")]
+    NotFound(String),
+    #[error("Room named {0} not found")]
+    RoomNotFound(String),
+}
+
+// Resolve the targets of a scene into `(device, state)` pairs. Later targets
+// override earlier ones for the same device.
+fn resolve(scene: &Scene, house: &SmartHouse) -> Result<Vec<(String, SocketState)>, SceneError> {
+    let mut resolved: Vec<(String, SocketState)> = Vec::new();
+    let mut set = |device: &str, state: &SocketState| match resolved
+        .iter_mut()
+        .find(|(name, _)| name == device)
+    {
+        Some(entry) => entry.1 = state.clone(),
+        None => resolved.push((device.to_string(), state.clone())),
+    };
+    for target in &scene.targets {
+        match &target.selector {
+            Selector::Device(name) => set(name, &target.state),
+            Selector::Room(room_name) => {
+                let room = house
+                    .rooms()
+                    .iter()
+                    .find(|room| &room.name == room_name)
+                    .ok_or_else(|| SceneError::RoomNotFound(room_name.clone()))?;
+                for device in &room.devices {
+                    if let Device::SmartSocket(socket) = device {
+                        set(&socket.name, &target.state);
+                    }
+                }
+            }
+            Selector::All => {
+                for device in house.rooms().iter().flat_map(|room| room.devices.iter()) {
+                    if let Device::SmartSocket(socket) = device {
+                        set(&socket.name, &target.state);
+                    }
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+// Apply `scene` to the house. With `rollback`, any failure restores the
+// devices the scene had already changed.
+pub(crate) fn apply(
+    scene: &Scene,
+    house: &mut SmartHouse,
+    rollback: bool,
+) -> Result<SceneReport, SceneError> {
+    let targets = resolve(scene, house)?;
+    let mut results = Vec::with_capacity(targets.len());
+    let mut previous: Vec<(usize, SocketState)> = Vec::new();
+
+    for (device, state) in targets {
+        let outcome = match house.socket(&device).map(|s| s.state.clone()) {
+            Ok(current) if current == state => DeviceOutcome::Unchanged,
+            Ok(current) => match house.switch_socket(&device, state) {
+                Ok(()) => {
+                    previous.push((results.len(), current));
+                    DeviceOutcome::Changed
+                }
+                Err(e) => DeviceOutcome::Failed(e),
+            },
+            Err(e) => DeviceOutcome::Failed(e),
+        };
+        results.push(DeviceResult { device, outcome });
+    }
+
+    let failed = results
+        .iter()
+        .any(|r| matches!(r.outcome, DeviceOutcome::Failed(_)));
+    let rolled_back = rollback && failed;
+    if rolled_back {
+        for (index, state) in previous.into_iter().rev() {
+            let result = &mut results[index];
+            if house.switch_socket(&result.device, state).is_ok() {
+                result.outcome = DeviceOutcome::RolledBack;
+            }
+        }
+    }
+
+    Ok(SceneReport {
+        scene: scene.name.clone(),
+        results,
+        rolled_back,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::devices::ThermometerState;
+    use crate::test_support::{room, socket, thermometer};
+
+    fn house() -> SmartHouse {
+        let mut house = SmartHouse::new(
+            "Home",
+            vec![
+                room(
+                    "Living Room",
+                    vec![
+                        socket("Lamp", SocketState::On, 100.0),
+                        socket("Tv", SocketState::On, 100.0),
+                        thermometer("LivingThermo", ThermometerState::Off),
+                    ],
+                ),
+                room(
+                    "Bedroom",
+                    vec![socket("BedroomHeater", SocketState::Off, 100.0)],
+                ),
+            ],
+        );
+        house.add_scene(
+            Scene::new("Night")
+                .with_target(Selector::Room("Living Room".to_string()), SocketState::Off)
+                .with_target(
+                    Selector::Device("BedroomHeater".to_string()),
+                    SocketState::On,
+                ),
+        );
+        house.add_scene(Scene::new("Leaving").with_target(Selector::All, SocketState::Off));
+        house
+    }
+
+    #[test]
+    fn test_apply_night_scene() {
+        let mut house = house();
+        let report = house.apply_scene("Night", false).unwrap();
+        assert!(report.succeeded());
+        assert_eq!(report.results.len(), 3);
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::Off);
+        assert_eq!(house.socket("Tv").unwrap().state, SocketState::Off);
+        assert_eq!(
+            house.socket("BedroomHeater").unwrap().state,
+            SocketState::On
+        );
+
+        let report = house.apply_scene("Leaving", false).unwrap();
+        let outcomes: Vec<_> = report.results.iter().map(|r| &r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                &DeviceOutcome::Unchanged,
+                &DeviceOutcome::Unchanged,
+                &DeviceOutcome::Changed
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failure_is_reported_per_device() {
+        let mut house = house();
+        house.add_scene(
+            Scene::new("Broken")
+                .with_target(Selector::Device("Lamp".to_string()), SocketState::Off)
+                .with_target(Selector::Device("Fridge".to_string()), SocketState::On),
+        );
+        let report = house.apply_scene("Broken", false).unwrap();
+        assert!(!report.succeeded());
+        assert!(!report.rolled_back);
+        assert_eq!(report.failures()[0].device, "Fridge");
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::Off);
+    }
+
+    #[test]
+    fn test_rollback_restores_changed_devices() {
+        let mut house = house();
+        house.add_scene(
+            Scene::new("Broken")
+                .with_target(Selector::Device("Lamp".to_string()), SocketState::Off)
+                .with_target(
+                    Selector::Device("LivingThermo".to_string()),
+                    SocketState::On,
+                ),
+        );
+        let report = house.apply_scene("Broken", true).unwrap();
+        assert!(report.rolled_back);
+        assert_eq!(report.results[0].outcome, DeviceOutcome::RolledBack);
+        assert_eq!(
+            report.results[1].outcome,
+            DeviceOutcome::Failed(ControlError::NotASocket("LivingThermo".to_string()))
+        );
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::On);
+    }
+
+    #[test]
+    fn test_unknown_scene_or_room() {
+        let mut house = house();
+        assert_eq!(
+            house.apply_scene("Party", false),
+            Err(SceneError::NotFound("Party".to_string()))
+        );
+        house.add_scene(
+            Scene::new("Garage").with_target(Selector::Room("Garage".to_string()), SocketState::On),
+        );
+        assert_eq!(
+            house.apply_scene("Garage", false),
+            Err(SceneError::RoomNotFound("Garage".to_string()))
+        );
+    }
+}
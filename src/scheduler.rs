@@ -0,0 +1,649 @@
+pub mod cron;
+pub mod solar;
+
+use crate::device_info::devices::SocketState;
+use crate::scene::{SceneError, SceneReport};
+use crate::smart_house::{ControlError, SmartHouse};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::{CronError, CronExpr};
+use solar::{Location, SunEvent, SunTimes};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+// Days to look ahead for the next sunrise or sunset before giving up, e.g.
+// during polar night.
+const SUN_SEARCH_DAYS: i64 = 366;
+
+// When a schedule runs.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Trigger {
+    Cron(CronExpr),
+    // Sunrise or sunset at the scheduler's location, shifted by `offset`.
+    Sun { event: SunEvent, offset: Duration },
+}
+
+impl From<CronExpr> for Trigger {
+    fn from(cron: CronExpr) -> Self {
+        Trigger::Cron(cron)
+    }
+}
+
+// Define an error type for schedule triggers.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum TriggerError {
+    #[error(transparent)]
+    Cron(#[from] CronError),
+    #[error("Invalid sun offset `{0}`, expected e.g. `sunset - 15 min` or `sunrise + 1h`")]
+    InvalidOffset(String),
+}
+
+// Parses either a cron expression or `sunrise`/`sunset` with an optional
+// offset such as `sunset - 15 min` or `sunrise+1h`.
+impl FromStr for Trigger {
+    type Err = TriggerError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let (event, rest) = if let Some(rest) = text.strip_prefix("sunrise") {
+            (SunEvent::Sunrise, rest)
+        } else if let Some(rest) = text.strip_prefix("sunset") {
+            (SunEvent::Sunset, rest)
+        } else {
+            return Ok(Trigger::Cron(text.parse()?));
+        };
+        let compact: String = rest.split_whitespace().collect();
+        let offset = if compact.is_empty() {
+            Duration::zero()
+        } else {
+            let invalid = || TriggerError::InvalidOffset(rest.trim().to_string());
+            let (sign, amount) = if let Some(amount) = compact.strip_prefix('+') {
+                (1, amount)
+            } else if let Some(amount) = compact.strip_prefix('-') {
+                (-1, amount)
+            } else {
+                return Err(invalid());
+            };
+            let split = amount
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(amount.len());
+            let value: i64 = amount[..split].parse().map_err(|_| invalid())?;
+            let offset = match &amount[split..] {
+                "s" => Duration::try_seconds(value),
+                "m" | "min" => Duration::try_minutes(value),
+                "h" => Duration::try_hours(value),
+                _ => None,
+            };
+            offset.ok_or_else(invalid)? * sign
+        };
+        Ok(Trigger::Sun { event, offset })
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Cron(cron) => write!(f, "{}", cron),
+            Trigger::Sun { event, offset } => {
+                let event = match event {
+                    SunEvent::Sunrise => "sunrise",
+                    SunEvent::Sunset => "sunset",
+                };
+                let minutes = offset.num_minutes();
+                match minutes {
+                    0 => write!(f, "{}", event),
+                    m if m < 0 => write!(f, "{} - {} min", event, -m),
+                    m => write!(f, "{} + {} min", event, m),
+                }
+            }
+        }
+    }
+}
+
+// What a schedule does when it runs.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ScheduleAction {
+    ApplyScene { scene: String, rollback: bool },
+    SwitchSocket { socket: String, state: SocketState },
+    // Generate the house report.
+    Report,
+}
+
+// What to do with runs that fell into a period when the scheduler wasn't ticking.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MissedRunPolicy {
+    // Forget about them.
+    #[default]
+    Skip,
+    // Run once for all of them.
+    RunOnce,
+    // Run once for each of them, oldest first.
+    RunAll,
+}
+
+// A recurring automation such as "apply Night at 22:30 on weekdays".
+#[derive(Clone, PartialEq, Debug)]
+pub struct Schedule {
+    pub name: String,
+    pub trigger: Trigger,
+    pub action: ScheduleAction,
+    pub skip_holidays: bool,
+    pub missed: MissedRunPolicy,
+}
+
+impl Schedule {
+    pub fn new(name: &str, trigger: Trigger, action: ScheduleAction) -> Self {
+        Self {
+            name: name.to_string(),
+            trigger,
+            action,
+            skip_holidays: false,
+            missed: MissedRunPolicy::default(),
+        }
+    }
+
+    // Don't run on the scheduler's holidays.
+    pub fn skip_on_holidays(mut self) -> Self {
+        self.skip_holidays = true;
+        self
+    }
+
+    pub fn on_missed(mut self, policy: MissedRunPolicy) -> Self {
+        self.missed = policy;
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct UpcomingRun {
+    pub schedule: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum RunResult {
+    Scene(Result<SceneReport, SceneError>),
+    Command(Result<(), ControlError>),
+    Report(String),
+}
+
+// A run performed by `Scheduler::tick`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScheduledRun {
+    pub schedule: String,
+    // When the run was due.
+    pub due: DateTime<Utc>,
+    // The run was due during downtime and is being caught up on.
+    pub missed: bool,
+    pub result: RunResult,
+}
+
+// Runs `Schedule`s against a `SmartHouse`. Cron expressions are evaluated in
+// the scheduler's timezone; on days when clocks go back, a time that occurs
+// twice runs only the first time, and a time skipped by clocks going forward
+// doesn't run at all. Sun triggers need a location and never run without one.
+pub struct Scheduler {
+    timezone: Tz,
+    location: Option<Location>,
+    holidays: BTreeSet<NaiveDate>,
+    // Runs more than this late count as missed.
+    grace: Duration,
+    schedules: Vec<Schedule>,
+    last_tick: Option<DateTime<Utc>>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(Tz::UTC)
+    }
+}
+
+impl Scheduler {
+    pub fn new(timezone: Tz) -> Self {
+        Self {
+            timezone,
+            location: None,
+            holidays: BTreeSet::new(),
+            grace: Duration::minutes(1),
+            schedules: Vec::new(),
+            last_tick: None,
+        }
+    }
+
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    // Usually the house's configured location.
+    pub fn with_location(mut self, location: Location) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    // Sun times used by sun triggers on the given local date.
+    pub fn sun_times(&self, date: NaiveDate) -> Option<SunTimes> {
+        Some(self.location?.sun_times(date))
+    }
+
+    // Add a schedule, replacing any schedule with the same name.
+    pub fn add_schedule(&mut self, schedule: Schedule) {
+        self.remove_schedule(&schedule.name);
+        self.schedules.push(schedule);
+    }
+
+    pub fn remove_schedule(&mut self, name: &str) {
+        self.schedules.retain(|schedule| schedule.name != name);
+    }
+
+    pub fn schedules(&self) -> Vec<&Schedule> {
+        self.schedules.iter().collect()
+    }
+
+    // Holidays are local dates in the scheduler's timezone.
+    pub fn add_holiday(&mut self, date: NaiveDate) {
+        self.holidays.insert(date);
+    }
+
+    pub fn remove_holiday(&mut self, date: NaiveDate) {
+        self.holidays.remove(&date);
+    }
+
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        self.holidays.contains(&date)
+    }
+
+    // Time of the most recent tick. Persist it and pass it to `resume_from`
+    // after a restart so that runs missed during downtime are detected.
+    pub fn last_tick(&self) -> Option<DateTime<Utc>> {
+        self.last_tick
+    }
+
+    pub fn resume_from(&mut self, last_tick: DateTime<Utc>) {
+        self.last_tick = Some(last_tick);
+    }
+
+    // Next time `schedule` is due strictly after `after`, holidays excluded.
+    fn next_run(&self, schedule: &Schedule, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.next_occurrence(&schedule.trigger, schedule.skip_holidays, after)
+    }
+
+    // Next time `trigger` fires strictly after `after` in this scheduler's
+    // timezone and location, optionally skipping holidays.
+    pub fn next_occurrence(
+        &self,
+        trigger: &Trigger,
+        skip_holidays: bool,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let skipped = |date: NaiveDate| skip_holidays && self.is_holiday(date);
+        match trigger {
+            Trigger::Cron(cron) => {
+                let mut local = after.with_timezone(&self.timezone).naive_local();
+                loop {
+                    local = cron.next_after(local)?;
+                    if skipped(local.date()) {
+                        continue;
+                    }
+                    // `None` means the local time doesn't exist because of a DST change.
+                    if let Some(at) = self.timezone.from_local_datetime(&local).earliest() {
+                        let at = at.with_timezone(&Utc);
+                        if at > after {
+                            return Some(at);
+                        }
+                    }
+                }
+            }
+            Trigger::Sun { event, offset } => {
+                let location = self.location?;
+                // Start a day early: a negative offset can move an event onto the
+                // previous day, and the local date may lag behind UTC.
+                let first = after.with_timezone(&self.timezone).date_naive() - Duration::days(1);
+                (0..SUN_SEARCH_DAYS)
+                    .map(|day| first + Duration::days(day))
+                    .filter(|date| !skipped(*date))
+                    .filter_map(|date| location.sun_times(date).get(*event))
+                    .map(|at| at + *offset)
+                    .find(|at| *at > after)
+            }
+        }
+    }
+
+    // The next `count` runs of every schedule after `from`, in time order.
+    pub fn upcoming(&self, from: DateTime<Utc>, count: usize) -> Vec<UpcomingRun> {
+        let mut runs: Vec<UpcomingRun> = self
+            .schedules
+            .iter()
+            .flat_map(|schedule| {
+                self.runs_of(schedule, from, count)
+                    .into_iter()
+                    .map(|at| UpcomingRun {
+                        schedule: schedule.name.clone(),
+                        at,
+                    })
+            })
+            .collect();
+        runs.sort_by_key(|run| run.at);
+        runs.truncate(count);
+        runs
+    }
+
+    fn runs_of(
+        &self,
+        schedule: &Schedule,
+        from: DateTime<Utc>,
+        count: usize,
+    ) -> Vec<DateTime<Utc>> {
+        let mut runs = Vec::new();
+        let mut after = from;
+        while runs.len() < count {
+            match self.next_run(schedule, after) {
+                Some(at) => {
+                    runs.push(at);
+                    after = at;
+                }
+                None => break,
+            }
+        }
+        runs
+    }
+
+    // Run every schedule that became due since the previous tick. The first
+    // tick only records the time unless `resume_from` was called.
+    pub fn tick(&mut self, house: &mut SmartHouse, now: DateTime<Utc>) -> Vec<ScheduledRun> {
+        let Some(since) = self.last_tick.replace(now) else {
+            return Vec::new();
+        };
+        let mut due: Vec<(DateTime<Utc>, bool, usize)> = Vec::new();
+        for (index, schedule) in self.schedules.iter().enumerate() {
+            let mut missed = Vec::new();
+            let mut after = since;
+            while let Some(at) = self.next_run(schedule, after).filter(|at| *at <= now) {
+                if now - at > self.grace {
+                    missed.push(at);
+                } else {
+                    due.push((at, false, index));
+                }
+                after = at;
+            }
+            match schedule.missed {
+                MissedRunPolicy::Skip => {}
+                MissedRunPolicy::RunOnce => {
+                    if let Some(at) = missed.pop() {
+                        due.push((at, true, index));
+                    }
+                }
+                MissedRunPolicy::RunAll => {
+                    due.extend(missed.into_iter().map(|at| (at, true, index)));
+                }
+            }
+        }
+        due.sort_by_key(|(at, _, _)| *at);
+
+        due.into_iter()
+            .map(|(at, missed, index)| {
+                let schedule = &self.schedules[index];
+                ScheduledRun {
+                    schedule: schedule.name.clone(),
+                    due: at,
+                    missed,
+                    result: run(&schedule.action, house),
+                }
+            })
+            .collect()
+    }
+}
+
+fn run(action: &ScheduleAction, house: &mut SmartHouse) -> RunResult {
+    match action {
+        ScheduleAction::ApplyScene { scene, rollback } => {
+            RunResult::Scene(house.apply_scene(scene, *rollback))
+        }
+        ScheduleAction::SwitchSocket { socket, state } => {
+            RunResult::Command(house.switch_socket(socket, state.clone()))
+        }
+        ScheduleAction::Report => RunResult::Report(house.create_report(house)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{room, socket};
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn house() -> SmartHouse {
+        SmartHouse::new(
+            "Home",
+            vec![room("Hall", vec![socket("Lamp", SocketState::Off, 60.0)])],
+        )
+    }
+
+    fn lamp(state: SocketState) -> ScheduleAction {
+        ScheduleAction::SwitchSocket {
+            socket: "Lamp".to_string(),
+            state,
+        }
+    }
+
+    #[test]
+    fn test_upcoming_runs_in_timezone_with_holidays() {
+        let mut scheduler = Scheduler::new(chrono_tz::Europe::Tallinn);
+        scheduler.add_holiday(NaiveDate::from_ymd_opt(2023, 12, 25).unwrap());
+        scheduler.add_schedule(
+            Schedule::new(
+                "Wake up",
+                "30 6 * * mon-fri".parse().unwrap(),
+                lamp(SocketState::On),
+            )
+            .skip_on_holidays(),
+        );
+        scheduler.add_schedule(Schedule::new(
+            "Report",
+            "0 12 * * sun".parse().unwrap(),
+            ScheduleAction::Report,
+        ));
+
+        let runs = scheduler.upcoming(utc("2023-12-22T12:00:00Z"), 3);
+        let runs: Vec<(&str, DateTime<Utc>)> = runs
+            .iter()
+            .map(|run| (run.schedule.as_str(), run.at))
+            .collect();
+        // Tallinn is UTC+2 in winter; Monday the 25th is a holiday.
+        assert_eq!(
+            runs,
+            vec![
+                ("Report", utc("2023-12-24T10:00:00Z")),
+                ("Wake up", utc("2023-12-26T04:30:00Z")),
+                ("Wake up", utc("2023-12-27T04:30:00Z")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dst_transitions() {
+        let mut scheduler = Scheduler::new(chrono_tz::Europe::Berlin);
+        scheduler.add_schedule(Schedule::new(
+            "Night",
+            "30 2 * * *".parse().unwrap(),
+            ScheduleAction::Report,
+        ));
+        // 02:30 doesn't exist on 2024-03-31 and happens twice on 2024-10-27.
+        let spring: Vec<_> = scheduler
+            .upcoming(utc("2024-03-30T12:00:00Z"), 2)
+            .into_iter()
+            .map(|run| run.at)
+            .collect();
+        assert_eq!(
+            spring,
+            vec![utc("2024-04-01T00:30:00Z"), utc("2024-04-02T00:30:00Z")]
+        );
+        let autumn: Vec<_> = scheduler
+            .upcoming(utc("2024-10-26T12:00:00Z"), 2)
+            .into_iter()
+            .map(|run| run.at)
+            .collect();
+        assert_eq!(
+            autumn,
+            vec![utc("2024-10-27T00:30:00Z"), utc("2024-10-28T01:30:00Z")]
+        );
+    }
+
+    #[test]
+    fn test_sun_triggers() {
+        assert_eq!(
+            "sunset - 15 min".parse::<Trigger>(),
+            Ok(Trigger::Sun {
+                event: SunEvent::Sunset,
+                offset: Duration::minutes(-15)
+            })
+        );
+        assert_eq!(
+            "sunrise+1h".parse::<Trigger>().unwrap().to_string(),
+            "sunrise + 60 min"
+        );
+        assert!(matches!(
+            "sunset 15".parse::<Trigger>(),
+            Err(TriggerError::InvalidOffset(_))
+        ));
+        assert_eq!(
+            "sunrise + 90s".parse::<Trigger>(),
+            Ok(Trigger::Sun {
+                event: SunEvent::Sunrise,
+                offset: Duration::seconds(90)
+            })
+        );
+        for offset in [
+            "sunset + 2hin",
+            "sunset + 15 mins",
+            "sunset + 15",
+            "sunset + min",
+        ] {
+            assert!(
+                matches!(
+                    offset.parse::<Trigger>(),
+                    Err(TriggerError::InvalidOffset(_))
+                ),
+                "{}",
+                offset
+            );
+        }
+        // A sign that isn't a single byte is rejected rather than split.
+        assert!(matches!(
+            "sunset – 15 min".parse::<Trigger>(),
+            Err(TriggerError::InvalidOffset(_))
+        ));
+
+        let mut scheduler = Scheduler::new(chrono_tz::Europe::London)
+            .with_location(Location::new(51.5074, -0.1278));
+        scheduler.add_holiday(NaiveDate::from_ymd_opt(2023, 6, 22).unwrap());
+        scheduler.add_schedule(
+            Schedule::new(
+                "Garden lights",
+                "sunset - 15 min".parse().unwrap(),
+                ScheduleAction::Report,
+            )
+            .skip_on_holidays(),
+        );
+        let sunset = scheduler
+            .sun_times(NaiveDate::from_ymd_opt(2023, 6, 21).unwrap())
+            .unwrap()
+            .sunset
+            .unwrap();
+        let runs = scheduler.upcoming(utc("2023-06-21T12:00:00Z"), 2);
+        assert_eq!(runs[0].at, sunset - Duration::minutes(15));
+        // The 22nd is a holiday.
+        assert_eq!(
+            runs[1].at.date_naive(),
+            NaiveDate::from_ymd_opt(2023, 6, 23).unwrap()
+        );
+        // Already past today's run, so the next one is the day after the holiday.
+        let later = scheduler.upcoming(sunset, 1);
+        assert_eq!(later[0].at, runs[1].at);
+
+        assert!(Scheduler::default()
+            .upcoming(utc("2023-06-21T12:00:00Z"), 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tick_runs_due_schedules() {
+        let mut house = house();
+        let mut scheduler = Scheduler::default();
+        scheduler.add_schedule(Schedule::new(
+            "Evening",
+            "0 18 * * *".parse().unwrap(),
+            lamp(SocketState::On),
+        ));
+
+        assert!(scheduler
+            .tick(&mut house, utc("2023-12-01T17:59:00Z"))
+            .is_empty());
+        let runs = scheduler.tick(&mut house, utc("2023-12-01T18:00:20Z"));
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].due, utc("2023-12-01T18:00:00Z"));
+        assert!(!runs[0].missed);
+        assert_eq!(runs[0].result, RunResult::Command(Ok(())));
+        assert_eq!(house.socket("Lamp").unwrap().state, SocketState::On);
+        assert!(scheduler
+            .tick(&mut house, utc("2023-12-01T18:01:00Z"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_missed_run_policies() {
+        let mut house = house();
+        let mut scheduler = Scheduler::default();
+        let hourly: Trigger = "@hourly".parse().unwrap();
+        scheduler.add_schedule(Schedule::new(
+            "Skip",
+            hourly.clone(),
+            ScheduleAction::Report,
+        ));
+        scheduler.add_schedule(
+            Schedule::new("Once", hourly.clone(), ScheduleAction::Report)
+                .on_missed(MissedRunPolicy::RunOnce),
+        );
+        scheduler.add_schedule(
+            Schedule::new("All", hourly, ScheduleAction::Report).on_missed(MissedRunPolicy::RunAll),
+        );
+
+        // Down from 09:30 until 12:10, so the 10:00, 11:00 and 12:00 runs were missed.
+        scheduler.resume_from(utc("2023-12-01T09:30:00Z"));
+        let runs = scheduler.tick(&mut house, utc("2023-12-01T12:10:00Z"));
+        let summary: Vec<(&str, DateTime<Utc>)> = runs
+            .iter()
+            .map(|run| (run.schedule.as_str(), run.due))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("All", utc("2023-12-01T10:00:00Z")),
+                ("All", utc("2023-12-01T11:00:00Z")),
+                ("Once", utc("2023-12-01T12:00:00Z")),
+                ("All", utc("2023-12-01T12:00:00Z")),
+            ]
+        );
+        assert!(runs.iter().all(|run| run.missed));
+        match &runs[0].result {
+            RunResult::Report(report) => assert!(report.contains("SmartSocket named Lamp")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(scheduler.last_tick(), Some(utc("2023-12-01T12:10:00Z")));
+    }
+}
@@ -0,0 +1,321 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+// How many days ahead to look for the next match before giving up. Eight
+// years always contains a February 29th that falls on any given weekday.
+const SEARCH_DAYS: i64 = 366 * 8;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// Define an error type for cron expressions.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum CronError {
+    #[error("Expected 5 fields in cron expression, found {0}")]
+    FieldCount(usize),
+    #[error("Invalid {field} field `{value}`: {message}")]
+    InvalidField {
+        field: &'static str,
+        value: String,
+        message: String,
+    },
+}
+
+// A standard five-field cron expression: `minute hour day-of-month month day-of-week`.
+//
+// Each field accepts `*`, single values, ranges (`1-5`), steps (`*/15`,
+// `8-18/2`) and comma separated lists of those. Months and weekdays may also
+// be given by their three-letter English names, and Sunday is either 0 or 7.
+// The shortcuts `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are
+// accepted too. As in classic cron, when both day-of-month and day-of-week
+// are restricted a day matches if either of them does.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+}
+
+const MINUTE: Field = Field {
+    name: "minute",
+    min: 0,
+    max: 59,
+    names: &[],
+};
+const HOUR: Field = Field {
+    name: "hour",
+    min: 0,
+    max: 23,
+    names: &[],
+};
+const DAY: Field = Field {
+    name: "day-of-month",
+    min: 1,
+    max: 31,
+    names: &[],
+};
+const MONTH: Field = Field {
+    name: "month",
+    min: 1,
+    max: 12,
+    names: &MONTH_NAMES,
+};
+// 7 is accepted as another spelling of Sunday and folded onto 0 after parsing.
+const WEEKDAY: Field = Field {
+    name: "day-of-week",
+    min: 0,
+    max: 7,
+    names: &WEEKDAY_NAMES,
+};
+
+impl Field {
+    fn error(&self, value: &str, message: &str) -> CronError {
+        CronError::InvalidField {
+            field: self.name,
+            value: value.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn value(&self, text: &str, whole: &str) -> Result<u32, CronError> {
+        let lower = text.to_ascii_lowercase();
+        let value = match self.names.iter().position(|name| *name == lower) {
+            // Month names start at 1, weekday names at 0.
+            Some(index) => index as u32 + self.min,
+            None => text
+                .parse()
+                .map_err(|_| self.error(whole, &format!("`{}` is not a number", text)))?,
+        };
+        if value < self.min || value > self.max {
+            return Err(self.error(
+                whole,
+                &format!("{} is out of range {}-{}", value, self.min, self.max),
+            ));
+        }
+        Ok(value)
+    }
+
+    // Parse the field into a bit set of allowed values. The flag tells
+    // whether the field was a plain `*`.
+    fn parse(&self, text: &str) -> Result<(u64, bool), CronError> {
+        let mut bits = 0u64;
+        for item in text.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .map_err(|_| self.error(text, "step must be a number"))?;
+                    if step == 0 {
+                        return Err(self.error(text, "step must be greater than 0"));
+                    }
+                    (range, step)
+                }
+                None => (item, 1),
+            };
+            let (start, end) = if range == "*" {
+                (self.min, self.max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (self.value(start, text)?, self.value(end, text)?)
+            } else {
+                let start = self.value(range, text)?;
+                // `5/15` means "from 5 to the end, every 15".
+                let end = if step > 1 { self.max } else { start };
+                (start, end)
+            };
+            if start > end {
+                return Err(self.error(text, "range start is after its end"));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok((bits, text == "*"))
+    }
+}
+
+fn has(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl CronExpr {
+    // Whether the expression allows runs on `date`.
+    pub fn matches_date(&self, date: NaiveDate) -> bool {
+        if !has(self.months, date.month()) {
+            return false;
+        }
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    // Whether the expression fires at the given local time.
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.matches_date(time.date())
+            && has(self.hours, time.hour())
+            && has(self.minutes, time.minute())
+    }
+
+    // First matching minute strictly after `after`, in the same local time.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let first_day = start.date();
+        for offset in 0..SEARCH_DAYS {
+            let date = first_day + Duration::days(offset);
+            if !self.matches_date(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|hour| has(self.hours, *hour)) {
+                for minute in (0..60).filter(|minute| has(self.minutes, *minute)) {
+                    let time = date.and_time(NaiveTime::from_hms_opt(hour, minute, 0)?);
+                    if time >= start {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = CronError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let expanded = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(CronError::FieldCount(fields.len()));
+        }
+        let (minutes, _) = MINUTE.parse(fields[0])?;
+        let (hours, _) = HOUR.parse(fields[1])?;
+        let (days, all_days) = DAY.parse(fields[2])?;
+        let (months, _) = MONTH.parse(fields[3])?;
+        let (mut weekdays, all_weekdays) = WEEKDAY.parse(fields[4])?;
+        if has(weekdays, 7) {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            source: text.trim().to_string(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            days_restricted: !all_days,
+            weekdays_restricted: !all_weekdays,
+        })
+    }
+}
+
+impl fmt::Display for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl fmt::Debug for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CronExpr({:?})", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> NaiveDateTime {
+        expr.parse::<CronExpr>()
+            .unwrap()
+            .next_after(at(after))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(
+            next("*/15 * * * *", "2023-12-01 10:07"),
+            at("2023-12-01 10:15")
+        );
+        assert_eq!(
+            next("30 7 * * mon-fri", "2023-12-01 08:00"),
+            at("2023-12-04 07:30")
+        );
+        assert_eq!(
+            next("0 22 * * 7", "2023-12-01 08:00"),
+            at("2023-12-03 22:00")
+        );
+        assert_eq!(
+            next("0 0 29 feb *", "2023-03-01 00:00"),
+            at("2024-02-29 00:00")
+        );
+        assert_eq!(next("@monthly", "2023-12-15 12:00"), at("2024-01-01 00:00"));
+        assert_eq!(
+            next("5/20 8-9 * * *", "2023-12-01 08:45"),
+            at("2023-12-01 09:05")
+        );
+        // Day-of-month and day-of-week are combined with "or".
+        assert_eq!(
+            next("0 12 13 * fri", "2023-12-02 00:00"),
+            at("2023-12-08 12:00")
+        );
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert_eq!("* * *".parse::<CronExpr>(), Err(CronError::FieldCount(3)));
+        for text in [
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "* * * foo *",
+        ] {
+            assert!(
+                matches!(
+                    text.parse::<CronExpr>(),
+                    Err(CronError::InvalidField { .. })
+                ),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            "0 0 31 2 *"
+                .parse::<CronExpr>()
+                .unwrap()
+                .next_after(at("2023-01-01 00:00")),
+            None
+        );
+    }
+}
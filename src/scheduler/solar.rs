@@ -0,0 +1,156 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+// Julian day of 2000-01-01 12:00 UTC.
+const J2000: f64 = 2451545.0;
+// Sun altitude at sunrise and sunset: atmospheric refraction plus the
+// radius of the solar disc.
+const HORIZON_DEGREES: f64 = -0.833;
+const EARTH_AXIAL_TILT_DEGREES: f64 = 23.4397;
+
+// Where the house is; latitude north and longitude east are positive.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+// Sun times for one date. Sunrise and sunset are `None` during polar night
+// and polar day, when the sun doesn't cross the horizon.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SunTimes {
+    pub date: NaiveDate,
+    pub solar_noon: DateTime<Utc>,
+    pub sunrise: Option<DateTime<Utc>>,
+    pub sunset: Option<DateTime<Utc>>,
+}
+
+impl SunTimes {
+    pub fn get(&self, event: SunEvent) -> Option<DateTime<Utc>> {
+        match event {
+            SunEvent::Sunrise => self.sunrise,
+            SunEvent::Sunset => self.sunset,
+        }
+    }
+
+    pub fn day_length(&self) -> Option<Duration> {
+        Some(self.sunset? - self.sunrise?)
+    }
+}
+
+fn julian_to_utc(julian: f64) -> DateTime<Utc> {
+    let epoch = Utc.with_ymd_and_hms(2000, 1, 1, 12, 0, 0).unwrap();
+    epoch + Duration::milliseconds(((julian - J2000) * 86_400_000.0).round() as i64)
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+        }
+    }
+
+    // Sunrise, solar noon and sunset on `date` using the standard sunrise
+    // equation. The results are accurate to a minute or two, which is plenty
+    // for switching lights.
+    pub fn sun_times(&self, date: NaiveDate) -> SunTimes {
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+        let day = (date - epoch).num_days() as f64;
+
+        // Mean solar noon at this longitude.
+        let mean_noon = day - self.longitude / 360.0;
+        let anomaly = (357.5291 + 0.98560028 * mean_noon)
+            .rem_euclid(360.0)
+            .to_radians();
+        let center = 1.9148 * anomaly.sin()
+            + 0.0200 * (2.0 * anomaly).sin()
+            + 0.0003 * (3.0 * anomaly).sin();
+        let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+            .rem_euclid(360.0)
+            .to_radians();
+        let transit =
+            J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+        let declination =
+            (ecliptic_longitude.sin() * EARTH_AXIAL_TILT_DEGREES.to_radians().sin()).asin();
+        let latitude = self.latitude.to_radians();
+        let cos_hour_angle = (HORIZON_DEGREES.to_radians().sin()
+            - latitude.sin() * declination.sin())
+            / (latitude.cos() * declination.cos());
+
+        let (sunrise, sunset) = if (-1.0..=1.0).contains(&cos_hour_angle) {
+            let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+            (
+                Some(julian_to_utc(transit - half_day)),
+                Some(julian_to_utc(transit + half_day)),
+            )
+        } else {
+            (None, None)
+        };
+        SunTimes {
+            date,
+            solar_noon: julian_to_utc(transit),
+            sunrise,
+            sunset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn assert_near(actual: Option<DateTime<Utc>>, expected: &str) {
+        let expected = DateTime::parse_from_rfc3339(expected)
+            .unwrap()
+            .with_timezone(&Utc);
+        let actual = actual.expect("the sun should rise and set");
+        assert!(
+            (actual - expected).num_seconds().abs() <= 120,
+            "{} is not within 2 minutes of {}",
+            actual,
+            expected
+        );
+    }
+
+    // Reference times from the NOAA solar calculator.
+    #[test]
+    fn test_matches_reference_tables() {
+        let london = Location::new(51.5074, -0.1278);
+        let times = london.sun_times(date("2023-06-21"));
+        assert_near(times.sunrise, "2023-06-21T03:43:00Z");
+        assert_near(times.sunset, "2023-06-21T20:21:00Z");
+        assert_near(Some(times.solar_noon), "2023-06-21T12:02:00Z");
+
+        let new_york = Location::new(40.7128, -74.0060);
+        let times = new_york.sun_times(date("2023-12-21"));
+        assert_near(times.sunrise, "2023-12-21T12:16:00Z");
+        assert_near(times.sunset, "2023-12-21T21:32:00Z");
+
+        let sydney = Location::new(-33.8688, 151.2093);
+        let times = sydney.sun_times(date("2024-03-20"));
+        assert_near(times.sunrise, "2024-03-19T19:59:00Z");
+        assert_near(times.sunset, "2024-03-20T08:08:00Z");
+    }
+
+    #[test]
+    fn test_polar_day_and_night() {
+        let tromso = Location::new(69.6492, 18.9553);
+        let winter = tromso.sun_times(date("2023-12-21"));
+        assert_eq!((winter.sunrise, winter.sunset), (None, None));
+        assert_eq!(winter.day_length(), None);
+        let summer = tromso.sun_times(date("2024-06-21"));
+        assert_eq!(summer.get(SunEvent::Sunrise), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+// Define an error type for background services.
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("Service failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Service task panicked or was cancelled: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+// Receiving side of a stop request, handed to the service task.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Resolves once `stop()` was called or the handle was dropped.
+    pub async fn requested(&mut self) {
+        while !*self.receiver.borrow_and_update() {
+            if self.receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Handle to a spawned background service such as a UDP listener or simulator.
+pub struct ServiceHandle {
+    local_addr: Option<SocketAddr>,
+    stop: watch::Sender<bool>,
+    task: JoinHandle<Result<(), std::io::Error>>,
+}
+
+impl ServiceHandle {
+    // Spawn `service` on the tokio runtime. The closure receives the
+    // `Shutdown` it should watch to exit its loop.
+    pub fn spawn<F, Fut>(local_addr: SocketAddr, service: F) -> Self
+    where
+        F: FnOnce(Shutdown) -> Fut,
+        Fut: Future<Output = Result<(), std::io::Error>> + Send + 'static,
+    {
+        Self::spawn_inner(Some(local_addr), service)
+    }
+
+    // Spawn a service that doesn't own a socket, such as a control loop.
+    pub fn spawn_task<F, Fut>(service: F) -> Self
+    where
+        F: FnOnce(Shutdown) -> Fut,
+        Fut: Future<Output = Result<(), std::io::Error>> + Send + 'static,
+    {
+        Self::spawn_inner(None, service)
+    }
+
+    fn spawn_inner<F, Fut>(local_addr: Option<SocketAddr>, service: F) -> Self
+    where
+        F: FnOnce(Shutdown) -> Fut,
+        Fut: Future<Output = Result<(), std::io::Error>> + Send + 'static,
+    {
+        let (stop, receiver) = watch::channel(false);
+        let task = tokio::spawn(service(Shutdown { receiver }));
+        Self {
+            local_addr,
+            stop,
+            task,
+        }
+    }
+
+    // Address the service's socket is bound to, if it has one.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    // Ask the service to stop. Use `join` to wait until it has.
+    pub fn stop(&self) {
+        let _ = self.stop.send(true);
+    }
+
+    // True once the service task has exited, either after `stop` or because it failed.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    // Wait for the service task to exit and report how it ended.
+    pub async fn join(self) -> Result<(), ServiceError> {
+        self.task.await?.map_err(ServiceError::from)
+    }
+
+    // Stop the service and wait for it to exit.
+    pub async fn shutdown(self) -> Result<(), ServiceError> {
+        self.stop();
+        self.join().await
+    }
+}
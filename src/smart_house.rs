@@ -1,207 +1,527 @@
-pub mod device_info;
-pub mod smart_house {
-    use super::device_info::devices::Device;
-    use super::device_info::DeviceInfoProvider;
+use crate::device_info::devices::{
+    Device, SmartSocket, SmartThermometer, SocketState, ThermometerState,
+};
+use crate::device_info::temperature::TemperatureUnit;
+use crate::device_info::{DeviceInfoError, DeviceInfoProvider};
+use crate::events::{EventBus, EventKind, HouseEvent};
+use crate::interlocks::{InterlockError, Interlocks};
+use crate::load_shedding::{LoadShedder, ShedAction};
+use crate::modes::HouseMode;
+use crate::scene::{self, Scene, SceneError, SceneReport};
+use crate::scheduler::solar::Location;
+use std::time::Instant;
+use thiserror::Error;
+
+// Define an error type for commands sent to devices of the house.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ControlError {
+    #[error("Device named {0} not found")]
+    NotFound(String),
+    #[error("Device named {0} is not a socket")]
+    NotASocket(String),
+    #[error("Device named {0} is not a thermometer")]
+    NotAThermometer(String),
+    #[error("Room named {0} not found")]
+    RoomNotFound(String),
+    #[error("Device named {0} already exists")]
+    AlreadyExists(String),
+    #[error("Rejected by interlock: {0}")]
+    Interlock(#[from] InterlockError),
+}
 
-    // Main structure representing the Smart House.
-    #[allow(dead_code)]
-    pub struct SmartHouse {
-        pub name: String,
-        pub rooms: Vec<Room>,
+// Main structure representing the Smart House.
+#[allow(dead_code)]
+pub struct SmartHouse {
+    pub name: String,
+    // Changed only through the methods of the house, so that every change
+    // reaches the event bus.
+    rooms: Vec<Room>,
+    // Unit temperatures are shown in when generating reports.
+    pub temperature_unit: TemperatureUnit,
+    pub scenes: Vec<Scene>,
+    // Where the house is, for sunrise and sunset triggers.
+    pub location: Option<Location>,
+    // Changed through `ModeController::set_mode`.
+    pub mode: HouseMode,
+    // Power budget and socket priorities; see `shed_load`.
+    pub load_shedder: Option<LoadShedder>,
+    // Safety constraints every socket command is checked against.
+    pub interlocks: Interlocks,
+    // Changes made through the methods of the house are published here.
+    pub events: EventBus,
+}
+
+impl SmartHouse {
+    // Create a new Smart House.
+    pub fn new(name: &str, rooms: Vec<Room>) -> Self {
+        let events = EventBus::default();
+        for room in &rooms {
+            for device in &room.devices {
+                events.set_room(device.name(), &room.name);
+            }
+        }
+        SmartHouse {
+            name: name.to_string(),
+            rooms,
+            temperature_unit: TemperatureUnit::default(),
+            scenes: Vec::new(),
+            location: None,
+            mode: HouseMode::default(),
+            load_shedder: None,
+            interlocks: Interlocks::default(),
+            events,
+        }
     }
 
-    impl SmartHouse {
-        // Create a new Smart House.
-        pub(crate) fn new(name: &str, rooms: Vec<Room>) -> Self {
-            SmartHouse {
-                name: name.to_string(),
-                rooms,
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn set_temperature_unit(&mut self, unit: TemperatureUnit) {
+        self.temperature_unit = unit;
+    }
+
+    // Add a room with its devices. Like `add_device`, fails if one of them has
+    // the name of a device the house already has.
+    pub fn add_room(&mut self, room: Room) -> Result<(), ControlError> {
+        for (index, device) in room.devices.iter().enumerate() {
+            let repeated = room.devices[..index]
+                .iter()
+                .any(|other| other.name() == device.name());
+            if repeated || self.find_device(device.name()).is_some() {
+                return Err(ControlError::AlreadyExists(device.name().to_owned()));
             }
         }
+        for device in &room.devices {
+            self.events.set_room(device.name(), &room.name);
+            self.events
+                .publish(HouseEvent::new(Some(device.name()), EventKind::DeviceAdded));
+        }
+        self.rooms.push(room);
+        Ok(())
+    }
 
-        pub fn add_room(&mut self, room: Room) {
-            self.rooms.push(room);
+    pub fn remove_room(&mut self, room_name: &str) {
+        if let Some(room) = self.rooms.iter().find(|room| room.name == room_name) {
+            for device in &room.devices {
+                self.events.publish(HouseEvent::new(
+                    Some(device.name()),
+                    EventKind::DeviceRemoved,
+                ));
+                self.events.forget_device(device.name());
+            }
         }
+        self.rooms.retain(|room| room.name != room_name);
+    }
+
+    // Add a device to a room. Device names are unique across the house.
+    pub fn add_device(&mut self, room_name: &str, device: Device) -> Result<(), ControlError> {
+        if self.find_device(device.name()).is_some() {
+            return Err(ControlError::AlreadyExists(device.name().to_owned()));
+        }
+        let room = self
+            .rooms
+            .iter_mut()
+            .find(|room| room.name == room_name)
+            .ok_or_else(|| ControlError::RoomNotFound(room_name.to_owned()))?;
+        self.events.set_room(device.name(), room_name);
+        self.events
+            .publish(HouseEvent::new(Some(device.name()), EventKind::DeviceAdded));
+        room.devices.push(device);
+        Ok(())
+    }
 
-        pub fn remove_room(&mut self, room_name: &str) {
-            self.rooms.retain(|room| room.name != room_name);
+    // Remove a device from whichever room holds it.
+    pub fn remove_device(&mut self, device_name: &str) -> Result<Device, ControlError> {
+        for room in &mut self.rooms {
+            if let Some(index) = room.devices.iter().position(|d| d.name() == device_name) {
+                let device = room.devices.remove(index);
+                self.events
+                    .publish(HouseEvent::new(Some(device_name), EventKind::DeviceRemoved));
+                self.events.forget_device(device_name);
+                return Ok(device);
+            }
         }
+        Err(ControlError::NotFound(device_name.to_owned()))
+    }
+
+    pub fn list_rooms(&self) -> Vec<&str> {
+        self.rooms.iter().map(|room| room.name.as_str()).collect()
+    }
+
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+
+    pub fn get_room(&self, room_name: &str) -> Option<&Room> {
+        self.rooms.iter().find(|room| room.name == room_name)
+    }
+
+    // Find a device by name in any room.
+    pub fn find_device(&self, device_name: &str) -> Option<&Device> {
+        self.rooms
+            .iter()
+            .flat_map(|room| room.devices.iter())
+            .find(|device| device.name() == device_name)
+    }
 
-        pub fn list_rooms(&self) -> Vec<&str> {
-            self.rooms.iter().map(|room| room.name.as_str()).collect()
+    fn find_device_mut(&mut self, device_name: &str) -> Option<&mut Device> {
+        self.rooms
+            .iter_mut()
+            .flat_map(|room| room.devices.iter_mut())
+            .find(|device| device.name() == device_name)
+    }
+
+    // Name of the room holding the given device.
+    pub fn room_of(&self, device_name: &str) -> Option<&str> {
+        self.rooms
+            .iter()
+            .find(|room| room.devices.iter().any(|d| d.name() == device_name))
+            .map(|room| room.name.as_str())
+    }
+
+    pub fn socket(&self, name: &str) -> Result<&SmartSocket, ControlError> {
+        match self.find_device(name) {
+            Some(Device::SmartSocket(socket)) => Ok(socket),
+            Some(_) => Err(ControlError::NotASocket(name.to_owned())),
+            None => Err(ControlError::NotFound(name.to_owned())),
         }
+    }
 
-        pub fn get_room_mut(&mut self, room_name: &str) -> Option<&mut Room> {
-            self.rooms.iter_mut().find(|room| room.name == room_name)
+    pub fn thermometer(&self, name: &str) -> Result<&SmartThermometer, ControlError> {
+        match self.find_device(name) {
+            Some(Device::SmartThermometer(thermometer)) => Ok(thermometer),
+            Some(_) => Err(ControlError::NotAThermometer(name.to_owned())),
+            None => Err(ControlError::NotFound(name.to_owned())),
         }
+    }
 
-        // Get a list of rooms in the house.
-        #[allow(dead_code)]
-        pub(crate) fn get_rooms(&self) -> Vec<String> {
-            self.rooms.iter().map(|r| r.name.clone()).collect()
+    // Switch a socket of the house on or off. Commands that would break one
+    // of the house's interlocks are rejected and change nothing.
+    pub fn switch_socket(&mut self, name: &str, state: SocketState) -> Result<(), ControlError> {
+        match self.find_device(name) {
+            Some(Device::SmartSocket(socket)) if socket.state == state => return Ok(()),
+            Some(Device::SmartSocket(_)) => {}
+            Some(_) => return Err(ControlError::NotASocket(name.to_owned())),
+            None => return Err(ControlError::NotFound(name.to_owned())),
+        }
+        self.check_interlocks(name, &state)?;
+        if let Some(Device::SmartSocket(socket)) = self.find_device_mut(name) {
+            socket.state = state.clone();
         }
+        self.interlocks.record(name, &state, Instant::now());
+        self.events.publish(HouseEvent::new(
+            Some(name),
+            EventKind::SocketStateChanged { state },
+        ));
+        Ok(())
+    }
 
-        // Get a list of devices in the specified room.
-        #[allow(dead_code)]
-        pub(crate) fn devices(&self, room_name: &str) -> Option<Vec<String>> {
-            self.rooms.iter().find(|r| r.name == room_name).map(|r| {
-                r.devices
-                    .iter()
-                    .map(|d| match d {
-                        Device::SmartSocket(socket) => socket.name.clone(),
-                        Device::SmartThermometer(thermometer) => thermometer.name.clone(),
-                    })
-                    .collect()
-            })
+    // Whether the interlocks allow switching a socket to `state` right now.
+    pub fn check_interlocks(&self, name: &str, state: &SocketState) -> Result<(), InterlockError> {
+        self.interlocks
+            .check(name, state, |other| self.is_on(other))
+    }
+
+    fn is_on(&self, name: &str) -> bool {
+        self.socket(name)
+            .is_ok_and(|socket| socket.state == SocketState::On)
+    }
+
+    // Switch off the sockets that have been on longer than their maximum
+    // on-duration. Returns the sockets switched off, which are also
+    // published on the bus.
+    pub fn enforce_interlocks(&mut self, now: Instant) -> Vec<String> {
+        let mut interlocks = std::mem::take(&mut self.interlocks);
+        let overdue = interlocks.overdue(|name| self.is_on(name), now);
+        self.interlocks = interlocks;
+        let switched: Vec<String> = overdue
+            .into_iter()
+            .filter(|name| self.switch_socket(name, SocketState::Off).is_ok())
+            .collect();
+        for name in &switched {
+            let limit = self.interlocks.max_on_duration(name).unwrap_or_default();
+            self.events.publish(HouseEvent::new(
+                Some(name),
+                EventKind::InterlockEnforced {
+                    reason: format!(
+                        "switched off after its maximum on-duration of {} s",
+                        limit.as_secs()
+                    ),
+                },
+            ));
         }
+        switched
+    }
 
-        // Generate a textual report about the status of all devices in the house.
-        pub fn create_report<P: DeviceInfoProvider>(&self, provider: &P) -> String {
-            let mut report = String::new();
-            for room in &self.rooms {
-                for device in &room.devices {
-                    let device_info = match device {
-                        Device::SmartSocket(device) => {
-                            provider.device_info(&room.name, &device.name)
-                        }
-                        Device::SmartThermometer(device) => {
-                            provider.device_info(&room.name, &device.name)
-                        }
-                    };
-                    let device_name = match device {
-                        Device::SmartSocket(device) => &device.name,
-                        Device::SmartThermometer(device) => &device.name,
-                    };
-                    report.push_str(&format!(
-                        "Room: {}, Device: {}, Info: {}\n",
-                        room.name,
-                        device_name,
-                        device_info.unwrap_or_else(|e| format!("Error: {:?}", e))
+    // Record the latest state reported by a thermometer of the house.
+    pub fn set_thermometer_state(
+        &mut self,
+        name: &str,
+        state: ThermometerState,
+    ) -> Result<(), ControlError> {
+        match self.find_device_mut(name) {
+            Some(Device::SmartThermometer(thermometer)) => {
+                thermometer.state = state.clone();
+                if let ThermometerState::Temperature(temperature) = state {
+                    self.events.publish(HouseEvent::new(
+                        Some(name),
+                        EventKind::ReadingReceived { temperature },
                     ));
                 }
+                Ok(())
             }
-            report
+            Some(_) => Err(ControlError::NotAThermometer(name.to_owned())),
+            None => Err(ControlError::NotFound(name.to_owned())),
         }
     }
 
-    // Structure representing a room.
-    pub struct Room {
-        pub name: String,
-        pub devices: Vec<Device>,
+    // Keep the house within its power budget, if it has one. Every action
+    // is published on the bus.
+    pub fn shed_load(&mut self, now: Instant) -> Vec<ShedAction> {
+        let Some(mut shedder) = self.load_shedder.take() else {
+            return Vec::new();
+        };
+        let actions = shedder.evaluate(self, now);
+        self.load_shedder = Some(shedder);
+        for action in &actions {
+            self.events.publish(HouseEvent::new(
+                Some(&action.socket),
+                EventKind::LoadShedding {
+                    action: action.kind,
+                    priority: action.priority,
+                    total_before: action.total_before,
+                    total_after: action.total_after,
+                },
+            ));
+        }
+        actions
     }
 
-    impl Room {
-        // Create a new room.
-        #[allow(dead_code)]
-        pub(crate) fn new(name: &str, devices: Vec<Device>) -> Self {
-            Room {
-                name: name.to_string(),
-                devices,
-            }
-        }
+    // Add a scene, replacing any scene with the same name.
+    pub fn add_scene(&mut self, scene: Scene) {
+        self.remove_scene(&scene.name);
+        self.scenes.push(scene);
+    }
 
-        // Add a device to the room.
-        #[allow(dead_code)]
-        pub(crate) fn add_device(&mut self, device: Device) {
-            self.devices.push(device);
-        }
+    pub fn remove_scene(&mut self, name: &str) {
+        self.scenes.retain(|scene| scene.name != name);
+    }
 
-        // Remove a device from the room.
-        #[allow(dead_code)]
-        pub(crate) fn remove_device(&mut self, device_name: &str) {
-            self.devices.retain(|device| match device {
-                Device::SmartSocket(socket) => socket.name.as_str() != device_name,
-                Device::SmartThermometer(thermometer) => thermometer.name.as_str() != device_name,
-            });
-        }
+    pub fn scene(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|scene| scene.name == name)
+    }
+
+    // Apply a stored scene in one call and report the outcome for every device.
+    // With `rollback`, a failure on any device restores the ones already changed.
+    pub fn apply_scene(&mut self, name: &str, rollback: bool) -> Result<SceneReport, SceneError> {
+        let scene = self
+            .scene(name)
+            .cloned()
+            .ok_or_else(|| SceneError::NotFound(name.to_owned()))?;
+        scene::apply(&scene, self, rollback)
+    }
+
+    // Get a list of rooms in the house.
+    #[allow(dead_code)]
+    pub(crate) fn get_rooms(&self) -> Vec<String> {
+        self.rooms.iter().map(|r| r.name.clone()).collect()
+    }
 
-        // Get a list of devices in the room.
-        #[allow(dead_code)]
-        pub(crate) fn list_devices(&self) -> Vec<&str> {
-            self.devices
+    // Get a list of devices in the specified room.
+    #[allow(dead_code)]
+    pub(crate) fn devices(&self, room_name: &str) -> Option<Vec<String>> {
+        self.rooms.iter().find(|r| r.name == room_name).map(|r| {
+            r.devices
                 .iter()
-                .map(|device| match device {
-                    Device::SmartSocket(socket) => socket.name.as_str(),
-                    Device::SmartThermometer(thermometer) => thermometer.name.as_str(),
+                .map(|d| match d {
+                    Device::SmartSocket(socket) => socket.name.clone(),
+                    Device::SmartThermometer(thermometer) => thermometer.name.clone(),
                 })
                 .collect()
+        })
+    }
+
+    // Generate a textual report about the status of all devices in the house.
+    pub fn create_report<P: DeviceInfoProvider>(&self, provider: &P) -> String {
+        let mut report = String::new();
+        for room in &self.rooms {
+            for device in &room.devices {
+                let device_info = match device {
+                    Device::SmartSocket(device) => provider.device_info_in_unit(
+                        &room.name,
+                        &device.name,
+                        self.temperature_unit,
+                    ),
+                    Device::SmartThermometer(device) => provider.device_info_in_unit(
+                        &room.name,
+                        &device.name,
+                        self.temperature_unit,
+                    ),
+                };
+                let device_name = match device {
+                    Device::SmartSocket(device) => &device.name,
+                    Device::SmartThermometer(device) => &device.name,
+                };
+                report.push_str(&format!(
+                    "Room: {}, Device: {}, Info: {}\n",
+                    room.name,
+                    device_name,
+                    device_info.unwrap_or_else(|e| format!("Error: {:?}", e))
+                ));
+            }
         }
+        report
     }
 }
 
-use device_info::devices::*;
-use device_info::*;
-use smart_house::*;
-
-fn main() {
-    let living_room_socket = SmartSocket {
-        name: "LivingRoomSocket".to_string(),
-        state: SocketState::On,
-        power_consumption: 100.0f32,
-    };
-    let kitchen_socket = SmartSocket {
-        name: "KitchenSocket".to_string(),
-        state: SocketState::Off,
-        power_consumption: 200.0f32,
-    };
-    let kitchen_thermometer = SmartThermometer {
-        name: "KitchenThermometer".to_string(),
-        state: ThermometerState::Temperature(25.0f32),
-    };
+// The house can describe its own devices, so reports don't need a separate provider.
+impl DeviceInfoProvider for SmartHouse {
+    fn device_info(&self, room: &str, device_name: &str) -> Result<String, DeviceInfoError> {
+        self.device_info_in_unit(room, device_name, self.temperature_unit)
+    }
 
-    let rooms = vec![
-        Room {
-            name: "Living Room".to_string(),
-            devices: vec![Device::SmartSocket(living_room_socket.clone())],
-        },
-        Room {
-            name: "Kitchen".to_string(),
-            devices: vec![
-                Device::SmartThermometer(kitchen_thermometer.clone()),
-                Device::SmartSocket(kitchen_socket.clone()),
-            ],
-        },
-    ];
+    fn device_info_in_unit(
+        &self,
+        room: &str,
+        device_name: &str,
+        unit: TemperatureUnit,
+    ) -> Result<String, DeviceInfoError> {
+        match self.find_device(device_name) {
+            Some(Device::SmartSocket(socket)) => Ok(format!(
+                "Room: {}, Device: SmartSocket named {}, State: {:?}",
+                room, socket.name, socket.state
+            )),
+            Some(Device::SmartThermometer(thermometer)) => Ok(format!(
+                "Room: {}, Device: SmartThermometer named {}, State: {:?}",
+                room,
+                thermometer.name,
+                thermometer.state.in_unit(unit)
+            )),
+            None => Err(DeviceInfoError::NotFound(device_name.to_owned())),
+        }
+    }
+}
 
-    let house = SmartHouse::new("Home", rooms);
+// Structure representing a room.
+pub struct Room {
+    pub name: String,
+    pub devices: Vec<Device>,
+}
 
-    let socket_info_provider = OwningDeviceInfoProvider {
-        socket: living_room_socket,
-    };
-    let report_with_socket = house.create_report(&socket_info_provider);
+impl Room {
+    // Create a new room.
+    #[allow(dead_code)]
+    pub(crate) fn new(name: &str, devices: Vec<Device>) -> Self {
+        Room {
+            name: name.to_string(),
+            devices,
+        }
+    }
 
-    println!("Report with socket info:\n{}", report_with_socket);
+    // Add a device to the room.
+    #[allow(dead_code)]
+    pub(crate) fn add_device(&mut self, device: Device) {
+        self.devices.push(device);
+    }
 
-    let multi_device_info_provider = BorrowingDeviceInfoProvider {
-        socket: &kitchen_socket,
-        thermo: &kitchen_thermometer,
-    };
-    let report_with_multi_device = house.create_report(&multi_device_info_provider);
+    // Remove a device from the room.
+    #[allow(dead_code)]
+    pub(crate) fn remove_device(&mut self, device_name: &str) {
+        self.devices.retain(|device| match device {
+            Device::SmartSocket(socket) => socket.name.as_str() != device_name,
+            Device::SmartThermometer(thermometer) => thermometer.name.as_str() != device_name,
+        });
+    }
 
-    println!(
-        "Report with multi-device info:\n{}",
-        report_with_multi_device
-    );
+    // Get a list of devices in the room.
+    #[allow(dead_code)]
+    pub(crate) fn list_devices(&self) -> Vec<&str> {
+        self.devices
+            .iter()
+            .map(|device| match device {
+                Device::SmartSocket(socket) => socket.name.as_str(),
+                Device::SmartThermometer(thermometer) => thermometer.name.as_str(),
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::device_info::devices::{
+    use crate::device_info::devices::{
         Device, SmartSocket, SmartThermometer, SocketState, ThermometerState,
     };
-    use super::device_info::{BorrowingDeviceInfoProvider, OwningDeviceInfoProvider};
+    use crate::device_info::temperature::{Temperature, TemperatureUnit};
+    use crate::device_info::{BorrowingDeviceInfoProvider, OwningDeviceInfoProvider};
     use crate::smart_house::*;
 
     #[test]
     fn test_add_remove_room() {
         let mut house = SmartHouse::new("HouseName", Vec::new()); // Added "HouseName" to the SmartHouse initialization
         let room = Room::new("Living Room", Vec::new()); // Added devices Vec::new() to Room initialization
-        house.add_room(room);
+        house.add_room(room).unwrap();
         assert_eq!(house.list_rooms(), vec!["Living Room"]);
         house.remove_room("Living Room");
         assert_eq!(house.list_rooms().len(), 0);
     }
 
+    #[test]
+    fn test_add_room_rejects_duplicate_devices() {
+        let lamp = || {
+            Device::SmartSocket(SmartSocket {
+                name: "Lamp".to_string(),
+                state: SocketState::Off,
+                power_consumption: 40.0,
+            })
+        };
+        let thermo = || {
+            Device::SmartThermometer(SmartThermometer {
+                name: "Porch Thermo".to_string(),
+                state: ThermometerState::Off,
+            })
+        };
+        let mut house = SmartHouse::new("MyHouse", vec![Room::new("Living Room", vec![lamp()])]);
+        let mut events = house.events().subscribe(crate::events::EventFilter::all());
+        assert_eq!(
+            house.add_room(Room::new("Hall", vec![lamp()])),
+            Err(ControlError::AlreadyExists("Lamp".to_string()))
+        );
+        assert_eq!(
+            house.add_room(Room::new("Porch", vec![thermo(), thermo()])),
+            Err(ControlError::AlreadyExists("Porch Thermo".to_string()))
+        );
+        assert_eq!(house.list_rooms(), vec!["Living Room"]);
+        assert!(events.try_recv().is_none());
+    }
+
+    #[test]
+    fn test_rooms_publish_device_events() {
+        let mut house = SmartHouse::new("MyHouse", Vec::new());
+        let mut events = house.events().subscribe(crate::events::EventFilter::all());
+        let lamp = Device::SmartSocket(SmartSocket {
+            name: "Lamp".to_string(),
+            state: SocketState::Off,
+            power_consumption: 40.0,
+        });
+        house
+            .add_room(Room::new("Living Room", vec![lamp]))
+            .unwrap();
+        assert_eq!(house.room_of("Lamp"), Some("Living Room"));
+        house.remove_room("Living Room");
+
+        let events: Vec<HouseEvent> = std::iter::from_fn(|| events.try_recv())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, EventKind::DeviceAdded);
+        assert_eq!(events[1].kind, EventKind::DeviceRemoved);
+        for event in events {
+            assert_eq!(event.device.as_deref(), Some("Lamp"));
+            assert_eq!(event.room.as_deref(), Some("Living Room"));
+        }
+    }
+
     #[test]
     fn test_add_remove_device() {
         let mut room = Room::new("Bedroom", Vec::new()); // Added devices Vec::new() to Room initialization
@@ -220,7 +540,7 @@ mod tests {
     fn test_smart_house_creation() {
         let house = SmartHouse::new("MyHouse", Vec::new());
         assert_eq!(house.name, "MyHouse");
-        assert_eq!(house.rooms.len(), 0);
+        assert!(house.rooms().is_empty());
     }
 
     #[test]
@@ -263,7 +583,7 @@ mod tests {
         };
         let thermo = SmartThermometer {
             name: "Thermo1".to_string(),
-            state: ThermometerState::Temperature(22.0),
+            state: ThermometerState::Temperature(Temperature::celsius(22.0)),
         };
         let room = Room {
             name: "LivingRoom".to_string(),
@@ -298,6 +618,8 @@ mod tests {
         let house = SmartHouse {
             name: "MyHome".to_string(),
             rooms: vec![room],
+            temperature_unit: TemperatureUnit::Celsius,
+            ..SmartHouse::new("MyHome", Vec::new())
         };
 
         let provider = OwningDeviceInfoProvider {
@@ -310,4 +632,117 @@ mod tests {
         );
         assert!(!report.contains("Error:"));
     }
+
+    #[test]
+    fn test_report_uses_house_temperature_unit() {
+        let socket = SmartSocket {
+            name: "Socket1".to_string(),
+            state: SocketState::Off,
+            power_consumption: 0.0f32,
+        };
+        let thermo = SmartThermometer {
+            name: "Thermo1".to_string(),
+            state: ThermometerState::Temperature(Temperature::celsius(22.0)),
+        };
+        let room = Room {
+            name: "Office".to_string(),
+            devices: vec![Device::SmartThermometer(thermo.clone())],
+        };
+        let mut house = SmartHouse::new("MyHouse", vec![room]);
+        house.set_temperature_unit(TemperatureUnit::Fahrenheit);
+
+        let provider = BorrowingDeviceInfoProvider {
+            socket: &socket,
+            thermo: &thermo,
+        };
+        let report = house.create_report(&provider);
+        assert_eq!(
+            report,
+            "Room: Office, Device: Thermo1, Info: Room: Office, Device: SmartThermometer named Thermo1, State: Temperature(71.6°F)\n"
+        );
+    }
+
+    #[test]
+    fn test_switch_socket_and_update_thermometer() {
+        let room = Room {
+            name: "LivingRoom".to_string(),
+            devices: vec![
+                Device::SmartSocket(SmartSocket {
+                    name: "Heater".to_string(),
+                    state: SocketState::Off,
+                    power_consumption: 1500.0f32,
+                }),
+                Device::SmartThermometer(SmartThermometer {
+                    name: "Thermo1".to_string(),
+                    state: ThermometerState::Off,
+                }),
+            ],
+        };
+        let mut house = SmartHouse::new("MyHouse", vec![room]);
+
+        house.switch_socket("Heater", SocketState::On).unwrap();
+        assert_eq!(house.socket("Heater").unwrap().state, SocketState::On);
+        assert_eq!(house.room_of("Heater"), Some("LivingRoom"));
+        assert_eq!(
+            house.switch_socket("Thermo1", SocketState::On),
+            Err(ControlError::NotASocket("Thermo1".to_string()))
+        );
+        assert_eq!(
+            house.switch_socket("Fridge", SocketState::On),
+            Err(ControlError::NotFound("Fridge".to_string()))
+        );
+
+        house
+            .set_thermometer_state(
+                "Thermo1",
+                ThermometerState::Temperature(Temperature::celsius(18.0)),
+            )
+            .unwrap();
+        assert!(matches!(
+            house.thermometer("Thermo1").unwrap().state,
+            ThermometerState::Temperature(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_house_publishes_events() {
+        use crate::events::{EventFilter, EventType};
+
+        let mut house = SmartHouse::new("MyHouse", vec![Room::new("Kitchen", Vec::new())]);
+        let mut kitchen = house.events().subscribe(EventFilter::all().room("Kitchen"));
+        let mut switches = house
+            .events()
+            .subscribe(EventFilter::all().event_type(EventType::SocketStateChanged));
+
+        let kettle = Device::SmartSocket(SmartSocket {
+            name: "Kettle".to_string(),
+            state: SocketState::Off,
+            power_consumption: 2000.0f32,
+        });
+        house.add_device("Kitchen", kettle.clone()).unwrap();
+        assert_eq!(
+            house.add_device("Kitchen", kettle),
+            Err(ControlError::AlreadyExists("Kettle".to_string()))
+        );
+        house.switch_socket("Kettle", SocketState::On).unwrap();
+        house.switch_socket("Kettle", SocketState::On).unwrap();
+        house.remove_device("Kettle").unwrap();
+
+        let kinds: Vec<EventKind> = std::iter::from_fn(|| kitchen.try_recv())
+            .map(|event| event.unwrap().kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::DeviceAdded,
+                EventKind::SocketStateChanged {
+                    state: SocketState::On
+                },
+                EventKind::DeviceRemoved,
+            ]
+        );
+        let event = switches.recv().await.unwrap();
+        assert_eq!(event.device.as_deref(), Some("Kettle"));
+        assert!(switches.try_recv().is_none());
+    }
 }
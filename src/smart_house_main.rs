@@ -0,0 +1,30 @@
+use smart_house::cli::Cli;
+use std::process::ExitCode;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == "--json");
+    let result = match Cli::parse(args) {
+        Ok(cli) => cli.run().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(output) => {
+            print!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            let code = e.exit_code();
+            if json {
+                eprintln!(
+                    "{}",
+                    serde_json::json!({ "error": e.to_string(), "exit_code": code })
+                );
+            } else {
+                eprintln!("{}", e);
+            }
+            ExitCode::from(code as u8)
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod smart_socket_client;
+pub mod smart_socket_server;
@@ -0,0 +1,97 @@
+use crate::SocketState;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+// Client for `SmartSocketServer`.
+pub struct SmartSocketClient {
+    stream: TcpStream,
+}
+
+impl SmartSocketClient {
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Self { stream })
+    }
+
+    // Send a raw command and wait for the server's response.
+    pub async fn send_command(&mut self, command: &str) -> std::io::Result<String> {
+        self.stream.write_all(command.as_bytes()).await?;
+
+        let mut data = vec![0; 256];
+        let size = self.stream.read(&mut data).await?;
+        if size == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(String::from_utf8_lossy(&data[..size]).into_owned())
+    }
+
+    pub async fn status(&mut self) -> std::io::Result<String> {
+        self.send_command("status").await
+    }
+
+    pub async fn switch(&mut self, state: SocketState) -> std::io::Result<String> {
+        match state {
+            SocketState::On => self.send_command("on").await,
+            SocketState::Off => self.send_command("off").await,
+        }
+    }
+
+    // Switch the socket and fail unless the server confirms the switch; see
+    // `confirm_switch`.
+    pub async fn switch_checked(&mut self, state: SocketState) -> std::io::Result<()> {
+        let reply = self.switch(state.clone()).await?;
+        confirm_switch(&state, reply)
+    }
+}
+
+// Check the server's reply to `on` or `off`. Anything but `Socket turned on`
+// or `Socket turned off`, e.g. `Rejected: <reason>`, becomes an `InvalidData`
+// error carrying the reply.
+pub fn confirm_switch(state: &SocketState, reply: String) -> std::io::Result<()> {
+    let expected = match state {
+        SocketState::On => "Socket turned on",
+        SocketState::Off => "Socket turned off",
+    };
+    if reply == expected {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_socket::smart_socket_server::SmartSocketServer;
+    use crate::SmartSocket;
+
+    #[tokio::test]
+    async fn test_client_controls_server_socket() {
+        let server = SmartSocketServer::new("127.0.0.1:0", SmartSocket::default());
+        let handle = server.start().await.unwrap();
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.switch(SocketState::On).await.unwrap(),
+            "Socket turned on"
+        );
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        assert_eq!(client.status().await.unwrap(), "On, Power: 100");
+        assert_eq!(client.send_command("dim").await.unwrap(), "Unknown command");
+        assert_eq!(
+            client.switch(SocketState::Off).await.unwrap(),
+            "Socket turned off"
+        );
+        client.switch_checked(SocketState::On).await.unwrap();
+        assert_eq!(
+            confirm_switch(&SocketState::Off, "Socket turned on".to_string())
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,592 @@
+use crate::events::{EventBus, EventKind, HouseEvent};
+use crate::gateway::SharedHouse;
+use crate::interlocks::InterlockError;
+use crate::live_feed::{ClientMessage, FeedMessage};
+use crate::metrics::Metrics;
+use crate::service::{ServiceHandle, Shutdown};
+use crate::{ControlError, SmartSocket, SocketState};
+use futures_util::{SinkExt, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::from_utf8;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+// How long a command waits for the gateway to accept or refuse it.
+const GATEWAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Define an error type for switch commands a guard refuses.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum GuardError {
+    #[error(transparent)]
+    Interlock(#[from] InterlockError),
+    #[error(transparent)]
+    Control(ControlError),
+    // The gateway's house refused the command, for this reason.
+    #[error("{0}")]
+    Refused(String),
+    #[error("Gateway at {address} did not answer: {reason}")]
+    Unreachable { address: String, reason: String },
+}
+
+impl From<ControlError> for GuardError {
+    fn from(error: ControlError) -> Self {
+        match error {
+            ControlError::Interlock(e) => GuardError::Interlock(e),
+            other => GuardError::Control(other),
+        }
+    }
+}
+
+// Decides whether a switch command received over TCP may be applied, e.g.
+// by checking the house's interlocks. Called with the socket's name and the
+// requested state.
+pub type CommandGuard = Arc<
+    dyn Fn(String, SocketState) -> Pin<Box<dyn Future<Output = Result<(), GuardError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+// TCP server exposing a single smart socket through the text protocol
+// understood by `SmartSocketClient`: `status`, `on` and `off`.
+pub struct SmartSocketServer {
+    address: String,
+    socket: Arc<Mutex<SmartSocket>>,
+    events: Option<EventBus>,
+    guard: Option<CommandGuard>,
+    metrics: Option<Metrics>,
+}
+
+impl SmartSocketServer {
+    pub fn new(address: &str, socket: SmartSocket) -> Self {
+        Self {
+            address: address.to_string(),
+            socket: Arc::new(Mutex::new(socket)),
+            events: None,
+            guard: None,
+            metrics: None,
+        }
+    }
+
+    // Publish state changes and connection errors to a house event bus.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    // Reject `on` and `off` commands the guard refuses, answering
+    // `Rejected: <reason>` and leaving the socket as it is.
+    pub fn with_guard<F>(mut self, guard: F) -> Self
+    where
+        F: Fn(&str, &SocketState) -> Result<(), InterlockError> + Send + Sync + 'static,
+    {
+        self.guard = Some(Arc::new(move |name, state| {
+            let result = guard(&name, &state).map_err(GuardError::from);
+            Box::pin(async move { result })
+        }));
+        self
+    }
+
+    // Make a house in this process the authority over the socket: a command
+    // is only applied once the same switch went through the house, which
+    // checks its interlocks and publishes the change. Commands for a socket
+    // the house doesn't have are rejected.
+    pub fn with_house(mut self, house: SharedHouse) -> Self {
+        self.guard = Some(Arc::new(move |name, state| {
+            let house = house.clone();
+            Box::pin(async move {
+                house
+                    .lock()
+                    .await
+                    .switch_socket(&name, state)
+                    .map_err(GuardError::from)
+            })
+        }));
+        self
+    }
+
+    // Make the house of the gateway whose HTTP API is at `address` the
+    // authority over the socket, as `with_house` does for a house in this
+    // process. Commands are sent over the gateway's live feed; while the
+    // gateway can't be reached they are rejected.
+    pub fn with_gateway(mut self, address: &str) -> Self {
+        let address = address.to_string();
+        self.guard = Some(Arc::new(move |name, state| {
+            Box::pin(ask_gateway(address.clone(), name, state))
+        }));
+        self
+    }
+
+    // Count client sessions and time every command.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Shared handle to the socket the server controls.
+    pub fn socket(&self) -> Arc<Mutex<SmartSocket>> {
+        self.socket.clone()
+    }
+
+    // Bind the listener and start accepting clients in a background task.
+    pub async fn start(&self) -> std::io::Result<ServiceHandle> {
+        let listener = TcpListener::bind(&self.address).await?;
+        let local_addr = listener.local_addr()?;
+        let socket = self.socket.clone();
+        let events = self.events.clone();
+        let guard = self.guard.clone();
+        let metrics = self.metrics.clone();
+
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                loop {
+                    let (stream, _) = tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        accepted = listener.accept() => accepted?,
+                    };
+                    let socket = Arc::clone(&socket);
+                    let shutdown = shutdown.clone();
+                    let events = events.clone();
+                    let guard = guard.clone();
+                    let metrics = metrics.clone();
+
+                    tokio::spawn(async move {
+                        let name = socket.lock().await.name.clone();
+                        if let Some(metrics) = &metrics {
+                            metrics.tcp_session_opened(&name);
+                        }
+                        handle_client(stream, socket, events, guard, metrics.as_ref(), shutdown)
+                            .await;
+                        if let Some(metrics) = &metrics {
+                            metrics.tcp_session_closed(&name);
+                        }
+                    });
+                }
+            },
+        ))
+    }
+}
+
+// Switch the socket in the gateway's house and wait for it to accept or
+// refuse the command.
+async fn ask_gateway(
+    address: String,
+    device: String,
+    state: SocketState,
+) -> Result<(), GuardError> {
+    let unreachable = |reason: String| GuardError::Unreachable {
+        address: address.clone(),
+        reason,
+    };
+    let exchange = async {
+        let (mut feed, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", address))
+            .await
+            .map_err(|e| unreachable(e.to_string()))?;
+        let command = ClientMessage::Command {
+            device,
+            command: state,
+        };
+        let text = serde_json::to_string(&command).map_err(|e| unreachable(e.to_string()))?;
+        feed.send(Message::Text(text))
+            .await
+            .map_err(|e| unreachable(e.to_string()))?;
+        // Only the answer matters; the snapshot and events are skipped.
+        while let Some(message) = feed.next().await {
+            let Message::Text(text) = message.map_err(|e| unreachable(e.to_string()))? else {
+                continue;
+            };
+            match serde_json::from_str(&text) {
+                Ok(FeedMessage::Ack { .. }) => return Ok(()),
+                Ok(FeedMessage::Error { message }) => return Err(GuardError::Refused(message)),
+                _ => {}
+            }
+        }
+        Err(unreachable("the gateway closed the connection".to_string()))
+    };
+    tokio::time::timeout(GATEWAY_TIMEOUT, exchange)
+        .await
+        .unwrap_or_else(|_| {
+            Err(unreachable(format!(
+                "no answer within {:?}",
+                GATEWAY_TIMEOUT
+            )))
+        })
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    socket: Arc<Mutex<SmartSocket>>,
+    events: Option<EventBus>,
+    guard: Option<CommandGuard>,
+    metrics: Option<&Metrics>,
+    mut shutdown: Shutdown,
+) {
+    let mut data = vec![0_u8; 50]; // Use a Vec<u8> to allow for resizing if necessary
+    loop {
+        let read = tokio::select! {
+            _ = shutdown.requested() => return,
+            read = stream.read(&mut data) => read,
+        };
+        let size = match read {
+            Ok(0) => return, // Connection closed
+            Ok(size) => size,
+            Err(e) => {
+                // Printed only without a bus to report on, which may be
+                // shown in a terminal UI.
+                match &events {
+                    Some(events) => {
+                        let name = socket.lock().await.name.clone();
+                        events.publish(HouseEvent::new(
+                            Some(&name),
+                            EventKind::Error {
+                                message: format!("connection error: {}", e),
+                            },
+                        ));
+                    }
+                    None => eprintln!("An error occurred with the connection: {}", e),
+                }
+                return;
+            }
+        };
+
+        let cmd = match from_utf8(&data[..size]) {
+            Ok(cmd) => cmd.trim(),
+            Err(_) => "",
+        };
+        let started = Instant::now();
+        let response = execute(cmd, &socket, events.as_ref(), guard.as_ref()).await;
+        if let Some(metrics) = metrics {
+            let command = match cmd {
+                "status" | "on" | "off" => cmd,
+                _ => "unknown",
+            };
+            let name = socket.lock().await.name.clone();
+            metrics.command_latency(&name, command, started.elapsed());
+        }
+        if stream.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Apply a single command to the socket and return the response text.
+async fn execute(
+    cmd: &str,
+    socket: &Mutex<SmartSocket>,
+    events: Option<&EventBus>,
+    guard: Option<&CommandGuard>,
+) -> String {
+    let mut socket = socket.lock().await; // Acquire lock before accessing socket
+    let previous = socket.state.clone();
+
+    let requested = match cmd {
+        "on" => Some(SocketState::On),
+        "off" => Some(SocketState::Off),
+        _ => None,
+    };
+    if let (Some(guard), Some(state)) = (guard, requested) {
+        if state != previous {
+            if let Err(e) = guard(socket.name.clone(), state).await {
+                return format!("Rejected: {}", e);
+            }
+        }
+    }
+
+    let response = match cmd {
+        "status" => format!("{:?}, Power: {}", socket.state, socket.power_consumption),
+        "on" => {
+            socket.switch(SocketState::On);
+            "Socket turned on".to_string()
+        }
+        "off" => {
+            socket.switch(SocketState::Off);
+            "Socket turned off".to_string()
+        }
+        _ => "Unknown command".to_string(),
+    };
+    if let Some(events) = events {
+        if socket.state != previous {
+            events.publish(HouseEvent::new(
+                Some(&socket.name),
+                EventKind::SocketStateChanged {
+                    state: socket.state.clone(),
+                },
+            ));
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventFilter, EventType};
+    use crate::smart_socket::smart_socket_client::SmartSocketClient;
+
+    #[tokio::test]
+    async fn test_server_publishes_state_changes() {
+        let events = EventBus::default();
+        let mut changes =
+            events.subscribe(EventFilter::all().event_type(EventType::SocketStateChanged));
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Kettle".to_string(),
+                state: SocketState::Off,
+                power_consumption: 0.0,
+            },
+        )
+        .with_events(events);
+        let handle = server.start().await.unwrap();
+
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.switch(SocketState::On).await.unwrap();
+        client.switch(SocketState::On).await.unwrap();
+        client.switch(SocketState::Off).await.unwrap();
+
+        let states: Vec<EventKind> = vec![
+            changes.recv().await.unwrap().kind,
+            changes.recv().await.unwrap().kind,
+        ];
+        assert_eq!(
+            states,
+            vec![
+                EventKind::SocketStateChanged {
+                    state: SocketState::On
+                },
+                EventKind::SocketStateChanged {
+                    state: SocketState::Off
+                },
+            ]
+        );
+        assert!(changes.try_recv().is_none());
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_guard_rejects_commands() {
+        use crate::interlocks::{Constraint, Interlocks};
+
+        let interlocks = Interlocks::new().with(Constraint::AlwaysOn {
+            socket: "Fridge".to_string(),
+        });
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Fridge".to_string(),
+                state: SocketState::On,
+                power_consumption: 150.0,
+            },
+        )
+        .with_guard(move |name, state| interlocks.check(name, state, |_| false));
+        let handle = server.start().await.unwrap();
+
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            client.switch(SocketState::Off).await.unwrap(),
+            "Rejected: Socket Fridge must always stay on"
+        );
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        assert_eq!(
+            client.switch(SocketState::On).await.unwrap(),
+            "Socket turned on"
+        );
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_house_guard() {
+        use crate::interlocks::Constraint;
+        use crate::smart_house::{Room, SmartHouse};
+        use crate::Device;
+
+        let kettle = SmartSocket {
+            name: "Kettle".to_string(),
+            state: SocketState::Off,
+            power_consumption: 0.0,
+        };
+        let heater = SmartSocket {
+            name: "Heater".to_string(),
+            state: SocketState::On,
+            power_consumption: 2000.0,
+        };
+        let mut house = SmartHouse::new(
+            "Home",
+            vec![Room {
+                name: "Kitchen".to_string(),
+                devices: vec![
+                    Device::SmartSocket(kettle.clone()),
+                    Device::SmartSocket(heater),
+                ],
+            }],
+        );
+        house.interlocks = house.interlocks.clone().with(Constraint::Exclusive {
+            name: "Circuit A".to_string(),
+            sockets: vec!["Kettle".to_string(), "Heater".to_string()],
+        });
+        let house: SharedHouse = Arc::new(Mutex::new(house));
+        let server = SmartSocketServer::new("127.0.0.1:0", kettle).with_house(house.clone());
+        let handle = server.start().await.unwrap();
+
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert!(client
+            .switch(SocketState::On)
+            .await
+            .unwrap()
+            .starts_with("Rejected: "));
+        assert_eq!(server.socket().lock().await.state, SocketState::Off);
+
+        house
+            .lock()
+            .await
+            .switch_socket("Heater", SocketState::Off)
+            .unwrap();
+        client.switch_checked(SocketState::On).await.unwrap();
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        assert_eq!(
+            house.lock().await.socket("Kettle").unwrap().state,
+            SocketState::On
+        );
+        handle.shutdown().await.unwrap();
+
+        // Sockets the house doesn't have can't be switched at all.
+        let toaster =
+            SmartSocketServer::new("127.0.0.1:0", SmartSocket::default()).with_house(house.clone());
+        let handle = toaster.start().await.unwrap();
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            client.switch(SocketState::On).await.unwrap(),
+            "Rejected: Device named TestSocket not found"
+        );
+        assert_eq!(toaster.socket().lock().await.state, SocketState::Off);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gateway_guard() {
+        use crate::http_api::HttpApi;
+        use crate::interlocks::Constraint;
+        use crate::smart_house::SmartHouse;
+        use crate::test_support::{room, shared, socket};
+
+        let mut house = SmartHouse::new(
+            "Home",
+            vec![room(
+                "Kitchen",
+                vec![
+                    socket("Kettle", SocketState::Off, 2000.0),
+                    socket("Heater", SocketState::On, 1500.0),
+                ],
+            )],
+        );
+        house.interlocks = house.interlocks.clone().with(Constraint::Exclusive {
+            name: "Circuit A".to_string(),
+            sockets: vec!["Kettle".to_string(), "Heater".to_string()],
+        });
+        let house = shared(house);
+        let api = HttpApi::new("127.0.0.1:0", house.clone())
+            .start()
+            .await
+            .unwrap();
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Kettle".to_string(),
+                state: SocketState::Off,
+                power_consumption: 0.0,
+            },
+        )
+        .with_gateway(&api.local_addr().unwrap().to_string());
+        let handle = server.start().await.unwrap();
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        // The gateway's house refuses, so the server does too.
+        assert_eq!(
+            client.switch(SocketState::On).await.unwrap(),
+            "Rejected: Rejected by interlock: Socket Kettle can't be switched on while Heater is on (exclusion group Circuit A)"
+        );
+        assert_eq!(server.socket().lock().await.state, SocketState::Off);
+
+        // Once the house allows it, the switch happens in both.
+        house
+            .lock()
+            .await
+            .switch_socket("Heater", SocketState::Off)
+            .unwrap();
+        client.switch_checked(SocketState::On).await.unwrap();
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        assert_eq!(
+            house.lock().await.socket("Kettle").unwrap().state,
+            SocketState::On
+        );
+
+        // Without the gateway nothing is switched.
+        api.shutdown().await.unwrap();
+        assert!(client
+            .switch(SocketState::Off)
+            .await
+            .unwrap()
+            .starts_with("Rejected: Gateway at "));
+        assert_eq!(server.socket().lock().await.state, SocketState::On);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_records_metrics() {
+        let metrics = Metrics::new();
+        let server = SmartSocketServer::new(
+            "127.0.0.1:0",
+            SmartSocket {
+                name: "Kettle".to_string(),
+                state: SocketState::Off,
+                power_consumption: 0.0,
+            },
+        )
+        .with_metrics(metrics.clone());
+        let handle = server.start().await.unwrap();
+
+        let mut client = SmartSocketClient::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.switch(SocketState::On).await.unwrap();
+        client.status().await.unwrap();
+        drop(client);
+
+        let house = crate::smart_house::SmartHouse::new("Home", Vec::new());
+        let expected = [
+            "smart_house_tcp_sessions_total{room=\"\",device=\"Kettle\"} 1",
+            "smart_house_tcp_sessions_active{room=\"\",device=\"Kettle\"} 0",
+            "smart_house_command_duration_seconds_count{room=\"\",device=\"Kettle\",command=\"on\"} 1",
+            "smart_house_command_duration_seconds_count{room=\"\",device=\"Kettle\",command=\"status\"} 1",
+        ];
+        // The session is closed once the server notices the client is gone.
+        for _ in 0..100 {
+            let text = metrics.render(&house, std::time::Instant::now());
+            if expected.iter().all(|line| text.lines().any(|l| l == *line)) {
+                handle.shutdown().await.unwrap();
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!(
+            "missing metrics:\n{}",
+            metrics.render(&house, std::time::Instant::now())
+        );
+    }
+}
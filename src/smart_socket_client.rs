@@ -1,12 +1,11 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use smart_house::smart_socket::smart_socket_client::SmartSocketClient;
 
 #[tokio::main]
 async fn main() {
     let mut buffer = String::new();
 
-    match TcpStream::connect("127.0.0.1:8080").await {
-        Ok(mut stream) => {
+    match SmartSocketClient::connect("127.0.0.1:8080").await {
+        Ok(mut client) => {
             println!("Successfully connected to server");
 
             loop {
@@ -20,22 +19,13 @@ async fn main() {
                     continue; // Skip empty inputs
                 }
 
-                // Send to server asynchronously
-                stream
-                    .write_all(trimmed.as_bytes())
-                    .await
-                    .expect("Failed to write to stream");
-
-                let mut data = vec![0; 50]; // Use Vec<u8> to be able to extend it later
-                let mut buffer = tokio::io::ReadBuf::new(&mut data);
-                stream
-                    .read_buf(&mut buffer)
-                    .await
-                    .expect("Failed to read from stream");
-
-                let response = buffer.filled(); // Get the filled part of the buffer
-                if let Ok(text) = std::str::from_utf8(response) {
-                    println!("Response: {}", text);
+                // Send to server asynchronously and print its answer
+                match client.send_command(trimmed).await {
+                    Ok(response) => println!("Response: {}", response),
+                    Err(e) => {
+                        println!("Connection lost: {}", e);
+                        return;
+                    }
                 }
             }
         }
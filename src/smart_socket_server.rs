@@ -1,61 +1,75 @@
-mod device_info;
-use device_info::devices::{SmartSocket, SocketState};
-use std::str::from_utf8;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use smart_house::config::HouseConfig;
+use smart_house::events::{EventBus, EventFilter, EventKind, EventType};
+use smart_house::smart_socket::smart_socket_server::SmartSocketServer;
+use smart_house::SmartSocket;
+use std::process::ExitCode;
 
-async fn handle_client(mut stream: TcpStream, socket: Arc<Mutex<SmartSocket>>) {
-    let mut data = vec![0_u8; 50]; // Use a Vec<u8> to allow for resizing if necessary
-    loop {
-        let size = match stream.read(&mut data).await {
-            Ok(0) => return, // Connection closed
-            Ok(size) => size,
-            Err(_) => {
-                eprintln!("An error occurred with the connection.");
-                return;
-            }
-        };
-
-        let cmd = from_utf8(&data[..size]).unwrap().trim();
-        let mut socket = socket.lock().await; // Acquire lock before accessing socket
-
-        match cmd {
-            "status" => {
-                let status = format!("{:?}, Power: {}", socket.state, socket.power_consumption);
-                stream.write_all(status.as_bytes()).await.unwrap();
-            }
-            "on" => {
-                socket.state = SocketState::On;
-                socket.power_consumption = 100.0; // just an example value
-                stream.write_all(b"Socket turned on").await.unwrap();
-            }
-            "off" => {
-                socket.state = SocketState::Off;
-                socket.power_consumption = 0.0;
-                stream.write_all(b"Socket turned off").await.unwrap();
-            }
-            _ => {
-                stream.write_all(b"Unknown command").await.unwrap();
+// Usage: smart_socket_server [house.toml SOCKET GATEWAY]
+//
+// With a config the server serves that socket on its configured address.
+// Every `on` and `off` is first switched in the house of the gateway whose
+// HTTP API is at GATEWAY, so commands breaking the house's interlocks are
+// refused, as are all commands while the gateway is unreachable.
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let server = match args.as_slice() {
+        [] => SmartSocketServer::new("127.0.0.1:8080", SmartSocket::default()),
+        [path, name, gateway] => {
+            let config = match HouseConfig::load(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Failed to load {}: {}", path, e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let address = config
+                .rooms
+                .iter()
+                .flat_map(|room| &room.devices)
+                .find(|device| device.name() == name)
+                .and_then(|device| device.address())
+                .unwrap_or("127.0.0.1:8080")
+                .to_string();
+            let socket = match config.to_house().socket(name) {
+                Ok(socket) => socket.clone(),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            SmartSocketServer::new(&address, socket).with_gateway(gateway)
+        }
+        _ => {
+            eprintln!("Usage: smart_socket_server [house.toml SOCKET GATEWAY]");
+            return ExitCode::FAILURE;
+        }
+    };
+    // Connection errors are reported on a bus and printed here.
+    let events = EventBus::default();
+    let mut errors = events.subscribe(EventFilter::all().event_type(EventType::Error));
+    tokio::spawn(async move {
+        while let Ok(event) = errors.recv().await {
+            if let EventKind::Error { message } = event.kind {
+                eprintln!("{}", message);
             }
         }
+    });
+    let server = server.with_events(events);
+    let handle = match server.start().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Failed to start server: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(addr) = handle.local_addr() {
+        println!("Server listening on {}", addr);
     }
-}
-
-#[tokio::main]
-async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-    println!("Server listening on port 8080");
-
-    let socket = Arc::new(Mutex::new(SmartSocket::default()));
-
-    loop {
-        let (stream, _) = listener.accept().await.unwrap();
-        let socket = Arc::clone(&socket);
 
-        tokio::spawn(async move {
-            handle_client(stream, socket).await;
-        });
+    if let Err(e) = handle.join().await {
+        eprintln!("Server stopped: {}", e);
+        return ExitCode::FAILURE;
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,736 @@
+use crate::device_info::devices::{Device, SocketState};
+use crate::device_info::temperature::Temperature;
+use crate::events::{EventBusError, EventFilter, EventKind, EventType};
+use crate::gateway::SharedHouse;
+use crate::service::ServiceHandle;
+use crate::{epoch_millis, from_epoch_millis};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+// Schema changes in order; `PRAGMA user_version` records how many of them
+// a database has applied. Never edit a released migration, add a new one.
+const MIGRATIONS: [&str; 2] = [
+    // 1: raw readings, in celsius, and socket state changes.
+    "CREATE TABLE readings (
+        device TEXT NOT NULL,
+        at_ms INTEGER NOT NULL,
+        celsius REAL NOT NULL
+    );
+    CREATE INDEX readings_device_at ON readings (device, at_ms);
+    CREATE TABLE socket_changes (
+        device TEXT NOT NULL,
+        at_ms INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        power REAL NOT NULL
+    );
+    CREATE INDEX socket_changes_device_at ON socket_changes (device, at_ms);",
+    // 2: per-minute and per-hour rollups of readings, filled from the
+    // readings already stored.
+    "CREATE TABLE reading_rollups (
+        device TEXT NOT NULL,
+        resolution TEXT NOT NULL,
+        bucket_ms INTEGER NOT NULL,
+        count INTEGER NOT NULL,
+        min REAL NOT NULL,
+        max REAL NOT NULL,
+        sum REAL NOT NULL,
+        PRIMARY KEY (device, resolution, bucket_ms)
+    );
+    INSERT INTO reading_rollups
+        SELECT device, 'minute', at_ms - at_ms % 60000, COUNT(*), MIN(celsius), MAX(celsius), SUM(celsius)
+        FROM readings GROUP BY device, at_ms - at_ms % 60000;
+    INSERT INTO reading_rollups
+        SELECT device, 'hour', at_ms - at_ms % 3600000, COUNT(*), MIN(celsius), MAX(celsius), SUM(celsius)
+        FROM readings GROUP BY device, at_ms - at_ms % 3600000;",
+];
+
+// Define an error type for history storage.
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Storage failed: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Database schema version {0} is newer than this program supports")]
+    UnknownVersion(usize),
+}
+
+// Width of the buckets readings are downsampled into.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    fn millis(&self) -> i64 {
+        match self {
+            Resolution::Minute => 60_000,
+            Resolution::Hour => 3_600_000,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Minute => "minute",
+            Resolution::Hour => "hour",
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minute" => Ok(Resolution::Minute),
+            "hour" => Ok(Resolution::Hour),
+            _ => Err(format!(
+                "unknown resolution `{}`, expected `minute` or `hour`",
+                s
+            )),
+        }
+    }
+}
+
+// How long each kind of data is kept. Rollups outlive the raw data they
+// summarize; hourly rollups are kept forever unless limited.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    #[serde(default = "default_raw_days")]
+    pub raw_days: u32,
+    #[serde(default = "default_minute_days")]
+    pub minute_days: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hour_days: Option<u32>,
+}
+
+fn default_raw_days() -> u32 {
+    7
+}
+
+fn default_minute_days() -> u32 {
+    30
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            raw_days: default_raw_days(),
+            minute_days: default_minute_days(),
+            hour_days: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct StoredReading {
+    pub at: SystemTime,
+    pub temperature: Temperature,
+}
+
+// Readings of one bucket, which starts at `start`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Rollup {
+    pub start: SystemTime,
+    pub count: u64,
+    pub min: Temperature,
+    pub max: Temperature,
+    pub avg: Temperature,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SocketChange {
+    pub at: SystemTime,
+    pub state: SocketState,
+    // Power the socket draws while on, in watts.
+    pub power: f32,
+}
+
+// Timestamps are stored as milliseconds in signed SQLite integers.
+fn to_ms(time: SystemTime) -> i64 {
+    epoch_millis(time) as i64
+}
+
+fn from_ms(ms: i64) -> SystemTime {
+    from_epoch_millis(ms.max(0) as u64)
+}
+
+fn days_before(now: SystemTime, days: u32) -> i64 {
+    to_ms(now) - days as i64 * 86_400_000
+}
+
+fn state_name(state: &SocketState) -> &'static str {
+    match state {
+        SocketState::On => "on",
+        SocketState::Off => "off",
+    }
+}
+
+// Time-series history of thermometer readings and socket changes in an
+// embedded SQLite database. Clones share the same connection.
+#[derive(Clone)]
+pub struct Storage {
+    // Statements are short, so they run on the calling task under a plain
+    // mutex rather than on a blocking thread.
+    conn: Arc<Mutex<Connection>>,
+    retention: RetentionPolicy,
+}
+
+impl Storage {
+    // Open or create the database at `path` and bring its schema up to date.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, StorageError> {
+        migrate(&mut conn, MIGRATIONS.len())?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            retention: RetentionPolicy::default(),
+        })
+    }
+
+    // Retention applied by the recorder.
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    // Store a reading and add it to its minute and hour rollups.
+    pub fn record_reading(
+        &self,
+        device: &str,
+        temperature: Temperature,
+        at: SystemTime,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let at = to_ms(at);
+        let celsius = temperature.as_celsius() as f64;
+        tx.execute(
+            "INSERT INTO readings (device, at_ms, celsius) VALUES (?1, ?2, ?3)",
+            params![device, at, celsius],
+        )?;
+        for resolution in [Resolution::Minute, Resolution::Hour] {
+            tx.execute(
+                "INSERT INTO reading_rollups VALUES (?1, ?2, ?3, 1, ?4, ?4, ?4)
+                 ON CONFLICT (device, resolution, bucket_ms) DO UPDATE SET
+                    count = count + 1,
+                    min = MIN(min, excluded.min),
+                    max = MAX(max, excluded.max),
+                    sum = sum + excluded.sum",
+                params![
+                    device,
+                    resolution.as_str(),
+                    at - at % resolution.millis(),
+                    celsius
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn record_socket(
+        &self,
+        device: &str,
+        state: &SocketState,
+        power: f32,
+        at: SystemTime,
+    ) -> Result<(), StorageError> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO socket_changes (device, at_ms, state, power) VALUES (?1, ?2, ?3, ?4)",
+            params![device, to_ms(at), state_name(state), power as f64],
+        )?;
+        Ok(())
+    }
+
+    // Raw readings of a thermometer in `[from, to)`, oldest first.
+    pub fn readings(
+        &self,
+        device: &str,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<StoredReading>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT at_ms, celsius FROM readings
+             WHERE device = ?1 AND at_ms >= ?2 AND at_ms < ?3 ORDER BY at_ms",
+        )?;
+        let rows = statement.query_map(params![device, to_ms(from), to_ms(to)], |row| {
+            Ok(StoredReading {
+                at: from_ms(row.get(0)?),
+                temperature: Temperature::celsius(row.get::<_, f64>(1)? as f32),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // Rollups of a thermometer whose buckets start in `[from, to)`, oldest first.
+    pub fn rollups(
+        &self,
+        device: &str,
+        resolution: Resolution,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<Rollup>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT bucket_ms, count, min, max, sum FROM reading_rollups
+             WHERE device = ?1 AND resolution = ?2 AND bucket_ms >= ?3 AND bucket_ms < ?4
+             ORDER BY bucket_ms",
+        )?;
+        let rows = statement.query_map(
+            params![device, resolution.as_str(), to_ms(from), to_ms(to)],
+            |row| {
+                let count: i64 = row.get(1)?;
+                let sum: f64 = row.get(4)?;
+                Ok(Rollup {
+                    start: from_ms(row.get(0)?),
+                    count: count as u64,
+                    min: Temperature::celsius(row.get::<_, f64>(2)? as f32),
+                    max: Temperature::celsius(row.get::<_, f64>(3)? as f32),
+                    avg: Temperature::celsius((sum / count as f64) as f32),
+                })
+            },
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // State changes of a socket in `[from, to)`, oldest first.
+    pub fn socket_changes(
+        &self,
+        device: &str,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<Vec<SocketChange>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT at_ms, state, power FROM socket_changes
+             WHERE device = ?1 AND at_ms >= ?2 AND at_ms < ?3 ORDER BY at_ms, rowid",
+        )?;
+        let rows = statement.query_map(params![device, to_ms(from), to_ms(to)], socket_change)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // Energy a socket used in `[from, to)`, in watt-hours, assuming it kept
+    // each recorded state and power until the next change.
+    pub fn energy(
+        &self,
+        device: &str,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> Result<f64, StorageError> {
+        let before = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT at_ms, state, power FROM socket_changes
+                 WHERE device = ?1 AND at_ms < ?2 ORDER BY at_ms DESC, rowid DESC LIMIT 1",
+                params![device, to_ms(from)],
+                socket_change,
+            )
+            .optional()?;
+        let mut current = before.map(|change| SocketChange { at: from, ..change });
+        let mut watt_hours = 0.0;
+        let mut changes = self.socket_changes(device, from, to)?;
+        changes.push(SocketChange {
+            at: to,
+            state: SocketState::Off,
+            power: 0.0,
+        });
+        for change in changes {
+            if let Some(previous) = &current {
+                if previous.state == SocketState::On {
+                    let hours = change
+                        .at
+                        .duration_since(previous.at)
+                        .unwrap_or_default()
+                        .as_secs_f64()
+                        / 3600.0;
+                    watt_hours += previous.power as f64 * hours;
+                }
+            }
+            current = Some(change);
+        }
+        Ok(watt_hours)
+    }
+
+    // Delete data older than the retention policy allows and return how
+    // many rows were removed. The last change of each socket before the
+    // cut-off is kept, so its state at any later time is still known.
+    pub fn apply_retention(&self, now: SystemTime) -> Result<usize, StorageError> {
+        let policy = self.retention;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let raw = days_before(now, policy.raw_days);
+        let mut removed = tx.execute("DELETE FROM readings WHERE at_ms < ?1", params![raw])?;
+        removed += tx.execute(
+            "DELETE FROM socket_changes WHERE at_ms < ?1 AND rowid NOT IN (
+                SELECT MAX(rowid) FROM socket_changes WHERE at_ms < ?1 GROUP BY device
+            )",
+            params![raw],
+        )?;
+        removed += tx.execute(
+            "DELETE FROM reading_rollups WHERE resolution = 'minute' AND bucket_ms < ?1",
+            params![days_before(now, policy.minute_days)],
+        )?;
+        if let Some(days) = policy.hour_days {
+            removed += tx.execute(
+                "DELETE FROM reading_rollups WHERE resolution = 'hour' AND bucket_ms < ?1",
+                params![days_before(now, days)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    // Write every reading and socket change of the house from now on,
+    // starting with the current state of each socket, and apply the
+    // retention policy every hour.
+    pub async fn record(&self, house: SharedHouse) -> ServiceHandle {
+        let storage = self.clone();
+        let mut events = {
+            let house = house.lock().await;
+            let now = SystemTime::now();
+            for device in house.rooms().iter().flat_map(|room| &room.devices) {
+                if let Device::SmartSocket(socket) = device {
+                    if let Err(e) = storage.record_socket(
+                        &socket.name,
+                        &socket.state,
+                        socket.power_consumption,
+                        now,
+                    ) {
+                        eprintln!("Failed to store state of {}: {}", socket.name, e);
+                    }
+                }
+            }
+            house.events().subscribe(
+                EventFilter::all()
+                    .event_type(EventType::ReadingReceived)
+                    .event_type(EventType::SocketStateChanged),
+            )
+        };
+        ServiceHandle::spawn_task(|mut shutdown| async move {
+            let mut retention = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                tokio::select! {
+                    _ = shutdown.requested() => return Ok(()),
+                    _ = retention.tick() => {
+                        if let Err(e) = storage.apply_retention(SystemTime::now()) {
+                            eprintln!("Failed to apply retention: {}", e);
+                        }
+                    }
+                    event = events.recv() => {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(EventBusError::Lagged(n)) => {
+                                eprintln!("History storage missed {} events", n);
+                                continue;
+                            }
+                            Err(EventBusError::Closed) => return Ok(()),
+                        };
+                        let Some(device) = &event.device else { continue };
+                        let stored = match &event.kind {
+                            EventKind::ReadingReceived { temperature } => {
+                                storage.record_reading(device, *temperature, event.at)
+                            }
+                            EventKind::SocketStateChanged { state } => {
+                                let power = house
+                                    .lock()
+                                    .await
+                                    .socket(device)
+                                    .map_or(0.0, |socket| socket.power_consumption);
+                                storage.record_socket(device, state, power, event.at)
+                            }
+                            _ => Ok(()),
+                        };
+                        if let Err(e) = stored {
+                            eprintln!("Failed to store event of {}: {}", device, e);
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn socket_change(row: &rusqlite::Row) -> rusqlite::Result<SocketChange> {
+    let state: String = row.get(1)?;
+    Ok(SocketChange {
+        at: from_ms(row.get(0)?),
+        state: if state == "on" {
+            SocketState::On
+        } else {
+            SocketState::Off
+        },
+        power: row.get::<_, f64>(2)? as f32,
+    })
+}
+
+// Apply the first `target` migrations that haven't been applied yet, each
+// in its own transaction.
+fn migrate(conn: &mut Connection, target: usize) -> Result<(), StorageError> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::UnknownVersion(version));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().take(target).skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_readings_and_rollups() {
+        let storage = Storage::in_memory().unwrap();
+        for (seconds, celsius) in [(0, 20.0), (30, 22.0), (59, 21.0), (60, 25.0), (3600, 18.0)] {
+            storage
+                .record_reading("Thermo", Temperature::celsius(celsius), at(seconds))
+                .unwrap();
+        }
+        storage
+            .record_reading("Other", Temperature::fahrenheit(212.0), at(10))
+            .unwrap();
+
+        let readings = storage.readings("Thermo", at(30), at(3600)).unwrap();
+        assert_eq!(
+            readings
+                .iter()
+                .map(|r| r.temperature.as_celsius())
+                .collect::<Vec<_>>(),
+            vec![22.0, 21.0, 25.0]
+        );
+        assert_eq!(readings[0].at, at(30));
+
+        // 1_700_000_000 is 20 seconds past a minute and 800 seconds past an hour.
+        let minutes = storage
+            .rollups(
+                "Thermo",
+                Resolution::Minute,
+                at(0) - Duration::from_secs(60),
+                at(7200),
+            )
+            .unwrap();
+        assert_eq!(
+            minutes
+                .iter()
+                .map(|r| (
+                    r.count,
+                    r.min.as_celsius(),
+                    r.max.as_celsius(),
+                    r.avg.as_celsius()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, 20.0, 22.0, 21.0),
+                (2, 21.0, 25.0, 23.0),
+                (1, 18.0, 18.0, 18.0)
+            ]
+        );
+        let hours = storage
+            .rollups(
+                "Thermo",
+                Resolution::Hour,
+                at(0) - Duration::from_secs(3600),
+                at(7200),
+            )
+            .unwrap();
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[0].count, 4);
+        assert_eq!(hours[0].start, at(0) - Duration::from_secs(800));
+        assert_eq!(hours[1].max, Temperature::celsius(18.0));
+    }
+
+    #[test]
+    fn test_socket_energy() {
+        let storage = Storage::in_memory().unwrap();
+        storage
+            .record_socket("Heater", &SocketState::On, 1000.0, at(0))
+            .unwrap();
+        storage
+            .record_socket("Heater", &SocketState::Off, 1000.0, at(1800))
+            .unwrap();
+        storage
+            .record_socket("Heater", &SocketState::On, 2000.0, at(3600))
+            .unwrap();
+
+        // On at 1 kW for half an hour, then at 2 kW for the last 15 minutes.
+        let energy = storage.energy("Heater", at(0), at(4500)).unwrap();
+        assert!((energy - 1000.0).abs() < 1e-6, "{}", energy);
+        // Starting mid-way uses the state recorded before the window.
+        let energy = storage.energy("Heater", at(900), at(1800)).unwrap();
+        assert!((energy - 250.0).abs() < 1e-6, "{}", energy);
+        assert_eq!(storage.energy("Kettle", at(0), at(4500)).unwrap(), 0.0);
+        assert_eq!(
+            storage
+                .socket_changes("Heater", at(0), at(3600))
+                .unwrap()
+                .iter()
+                .map(|c| c.state.clone())
+                .collect::<Vec<_>>(),
+            vec![SocketState::On, SocketState::Off]
+        );
+    }
+
+    #[test]
+    fn test_retention() {
+        let day = 86_400;
+        let storage = Storage::in_memory()
+            .unwrap()
+            .with_retention(RetentionPolicy {
+                raw_days: 1,
+                minute_days: 2,
+                hour_days: Some(3),
+            });
+        for days in [0, 1, 2, 3, 4] {
+            storage
+                .record_reading("Thermo", Temperature::celsius(20.0), at(days * day))
+                .unwrap();
+            storage
+                .record_socket("Heater", &SocketState::On, 100.0, at(days * day))
+                .unwrap();
+        }
+
+        storage.apply_retention(at(4 * day + 60)).unwrap();
+        let all = (at(0), at(5 * day));
+        assert_eq!(storage.readings("Thermo", all.0, all.1).unwrap().len(), 1);
+        // The change from day 3 is kept as the state before the cut-off.
+        assert_eq!(
+            storage
+                .socket_changes("Heater", all.0, all.1)
+                .unwrap()
+                .len(),
+            2
+        );
+        let minutes = storage
+            .rollups("Thermo", Resolution::Minute, all.0, all.1)
+            .unwrap();
+        assert_eq!(minutes.len(), 2);
+        let hours = storage
+            .rollups("Thermo", Resolution::Hour, all.0, all.1)
+            .unwrap();
+        assert_eq!(hours.len(), 3);
+    }
+
+    #[test]
+    fn test_migrations() {
+        let path = std::env::temp_dir().join(format!("storage_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            // A database from before rollups existed.
+            let mut conn = Connection::open(&path).unwrap();
+            migrate(&mut conn, 1).unwrap();
+            conn.execute(
+                "INSERT INTO readings VALUES ('Thermo', ?1, 19.0), ('Thermo', ?2, 21.0)",
+                params![to_ms(at(0)), to_ms(at(10))],
+            )
+            .unwrap();
+        }
+        let storage = Storage::open(&path).unwrap();
+        let rollups = storage
+            .rollups(
+                "Thermo",
+                Resolution::Minute,
+                at(0) - Duration::from_secs(60),
+                at(60),
+            )
+            .unwrap();
+        assert_eq!(rollups.len(), 1);
+        assert_eq!(rollups[0].count, 2);
+        assert_eq!(rollups[0].avg, Temperature::celsius(20.0));
+        drop(storage);
+
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", 99)
+            .unwrap();
+        assert!(matches!(
+            Storage::open(&path),
+            Err(StorageError::UnknownVersion(99))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        use crate::device_info::devices::{SmartSocket, SmartThermometer, ThermometerState};
+        use crate::smart_house::{Room, SmartHouse};
+
+        let house: SharedHouse = Arc::new(tokio::sync::Mutex::new(SmartHouse::new(
+            "Home",
+            vec![Room {
+                name: "Hall".to_string(),
+                devices: vec![
+                    Device::SmartSocket(SmartSocket {
+                        name: "Lamp".to_string(),
+                        state: SocketState::Off,
+                        power_consumption: 60.0,
+                    }),
+                    Device::SmartThermometer(SmartThermometer {
+                        name: "Thermo".to_string(),
+                        state: ThermometerState::Off,
+                    }),
+                ],
+            }],
+        )));
+        let storage = Storage::in_memory().unwrap();
+        let start = SystemTime::now() - Duration::from_secs(1);
+        let handle = storage.record(house.clone()).await;
+        {
+            let mut house = house.lock().await;
+            house.switch_socket("Lamp", SocketState::On).unwrap();
+            house
+                .set_thermometer_state(
+                    "Thermo",
+                    ThermometerState::Temperature(Temperature::celsius(21.5)),
+                )
+                .unwrap();
+        }
+
+        let end = SystemTime::now() + Duration::from_secs(1);
+        for _ in 0..100 {
+            if !storage.readings("Thermo", start, end).unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let readings = storage.readings("Thermo", start, end).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0].temperature, Temperature::celsius(21.5));
+        let changes = storage.socket_changes("Lamp", start, end).unwrap();
+        assert_eq!(
+            changes.iter().map(|c| &c.state).collect::<Vec<_>>(),
+            vec![&SocketState::Off, &SocketState::On]
+        );
+        assert_eq!(changes[1].power, 60.0);
+        handle.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use crate::device_info::devices::{
+    Device, SmartSocket, SmartThermometer, SocketState, ThermometerState,
+};
+use crate::gateway::SharedHouse;
+use crate::interlocks::{Constraint, Interlocks};
+use crate::smart_house::{Room, SmartHouse};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Fixtures shared by the tests of the crate's modules.
+
+pub(crate) fn socket(name: &str, state: SocketState, power_consumption: f32) -> Device {
+    Device::SmartSocket(SmartSocket {
+        name: name.to_string(),
+        state,
+        power_consumption,
+    })
+}
+
+pub(crate) fn thermometer(name: &str, state: ThermometerState) -> Device {
+    Device::SmartThermometer(SmartThermometer {
+        name: name.to_string(),
+        state,
+    })
+}
+
+pub(crate) fn room(name: &str, devices: Vec<Device>) -> Room {
+    Room {
+        name: name.to_string(),
+        devices,
+    }
+}
+
+pub(crate) fn shared(house: SmartHouse) -> SharedHouse {
+    Arc::new(Mutex::new(house))
+}
+
+// A kitchen with a kettle that is off and a fridge that must always stay
+// on, and a living room thermometer without a reading.
+pub(crate) fn house() -> SmartHouse {
+    let mut house = SmartHouse::new(
+        "Home",
+        vec![
+            room(
+                "Kitchen",
+                vec![
+                    socket("Kettle", SocketState::Off, 2000.0),
+                    socket("Fridge", SocketState::On, 150.0),
+                ],
+            ),
+            room(
+                "Living Room",
+                vec![thermometer("Thermo", ThermometerState::Off)],
+            ),
+        ],
+    );
+    house.interlocks = Interlocks::new().with(Constraint::AlwaysOn {
+        socket: "Fridge".to_string(),
+    });
+    house
+}
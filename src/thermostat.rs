@@ -0,0 +1,475 @@
+use crate::device_info::devices::{SmartThermometer, SocketState, ThermometerState};
+use crate::device_info::temperature::Temperature;
+use crate::gateway::SharedHouse;
+use crate::service::ServiceHandle;
+use crate::smart_socket::smart_socket_client::SmartSocketClient;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Where the thermostat reads the current temperature from.
+pub trait TemperatureSource: Send + 'static {
+    // Latest reading, or `None` while the sensor has nothing to report.
+    fn current_temperature(&mut self) -> impl Future<Output = Option<Temperature>> + Send;
+}
+
+// The heater or cooler the thermostat switches.
+pub trait SwitchOutput: Send + 'static {
+    fn switch(&mut self, state: SocketState) -> impl Future<Output = std::io::Result<()>> + Send;
+}
+
+// In-memory thermometer, e.g. the one updated by `UdpThermometerListener`.
+impl TemperatureSource for Arc<Mutex<SmartThermometer>> {
+    async fn current_temperature(&mut self) -> Option<Temperature> {
+        match self.lock().await.state {
+            ThermometerState::Temperature(t) => Some(t),
+            ThermometerState::Off => None,
+        }
+    }
+}
+
+// A socket of a house in this process, e.g. the gateway's. Switches go
+// through the house, so its interlocks apply; refusals are errors.
+pub struct HouseSocket {
+    house: SharedHouse,
+    name: String,
+}
+
+impl HouseSocket {
+    pub fn new(house: SharedHouse, name: &str) -> Self {
+        Self {
+            house,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl SwitchOutput for HouseSocket {
+    async fn switch(&mut self, state: SocketState) -> std::io::Result<()> {
+        self.house
+            .lock()
+            .await
+            .switch_socket(&self.name, state)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::PermissionDenied, e))
+    }
+}
+
+// Remote socket driven over TCP. Refusals by the server are errors.
+impl SwitchOutput for SmartSocketClient {
+    async fn switch(&mut self, state: SocketState) -> std::io::Result<()> {
+        self.switch_checked(state).await
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ThermostatMode {
+    // Switch on below `setpoint - hysteresis`, off above `setpoint + hysteresis`.
+    BangBang {
+        hysteresis: f32,
+    },
+    // PID output in 0..=1 is used as the duty cycle of a time-proportioning
+    // window: the output is on for `duty * window` at the start of each window.
+    Pid {
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        window: Duration,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputKind {
+    Heater,
+    Cooler,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ThermostatConfig {
+    pub setpoint: Temperature,
+    pub mode: ThermostatMode,
+    pub output: OutputKind,
+    // The output is never switched again sooner than this after its last switch.
+    pub min_cycle: Duration,
+    // How often the control loop runs.
+    pub period: Duration,
+}
+
+impl ThermostatConfig {
+    pub fn heater(setpoint: Temperature, mode: ThermostatMode) -> Self {
+        Self {
+            setpoint,
+            mode,
+            output: OutputKind::Heater,
+            min_cycle: Duration::ZERO,
+            period: Duration::from_secs(10),
+        }
+    }
+
+    pub fn cooler(setpoint: Temperature, mode: ThermostatMode) -> Self {
+        Self {
+            output: OutputKind::Cooler,
+            ..Self::heater(setpoint, mode)
+        }
+    }
+
+    pub fn with_min_cycle(mut self, min_cycle: Duration) -> Self {
+        self.min_cycle = min_cycle;
+        self
+    }
+
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+}
+
+// The control algorithm, independent of any I/O.
+pub struct ThermostatController {
+    config: ThermostatConfig,
+    output_on: bool,
+    last_switch: Option<Instant>,
+    integral: f32,
+    last_error: Option<f32>,
+    last_update: Option<Instant>,
+    window_start: Option<Instant>,
+}
+
+impl ThermostatController {
+    pub fn new(config: ThermostatConfig) -> Self {
+        Self {
+            config,
+            output_on: false,
+            last_switch: None,
+            integral: 0.0,
+            last_error: None,
+            last_update: None,
+            window_start: None,
+        }
+    }
+
+    pub fn config(&self) -> &ThermostatConfig {
+        &self.config
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: Temperature) {
+        self.config.setpoint = setpoint;
+    }
+
+    pub fn output_on(&self) -> bool {
+        self.output_on
+    }
+
+    // Feed a measurement taken at `now`; returns whether the output should be on.
+    pub fn update(&mut self, measured: Temperature, now: Instant) -> bool {
+        let setpoint = self.config.setpoint;
+        let measured = measured.to_unit(setpoint.unit()).value();
+        // Positive error means the output should work harder.
+        let error = match self.config.output {
+            OutputKind::Heater => setpoint.value() - measured,
+            OutputKind::Cooler => measured - setpoint.value(),
+        };
+
+        let desired = match self.config.mode {
+            ThermostatMode::BangBang { hysteresis } => {
+                if error >= hysteresis {
+                    true
+                } else if error <= -hysteresis {
+                    false
+                } else {
+                    self.output_on
+                }
+            }
+            ThermostatMode::Pid { kp, ki, kd, window } => {
+                let duty = self.pid_duty(error, kp, ki, kd, now);
+                let window_start = *self.window_start.get_or_insert(now);
+                let mut elapsed = now.duration_since(window_start);
+                if elapsed >= window {
+                    self.window_start = Some(now);
+                    elapsed = Duration::ZERO;
+                }
+                elapsed.as_secs_f32() < duty * window.as_secs_f32()
+            }
+        };
+
+        if desired != self.output_on {
+            let allowed = match self.last_switch {
+                Some(last) => now.duration_since(last) >= self.config.min_cycle,
+                None => true,
+            };
+            if allowed {
+                self.output_on = desired;
+                self.last_switch = Some(now);
+            }
+        }
+        self.output_on
+    }
+
+    fn pid_duty(&mut self, error: f32, kp: f32, ki: f32, kd: f32, now: Instant) -> f32 {
+        let dt = self
+            .last_update
+            .map(|last| now.duration_since(last).as_secs_f32())
+            .unwrap_or(0.0);
+        self.last_update = Some(now);
+
+        let derivative = match self.last_error {
+            Some(last) if dt > 0.0 => (error - last) / dt,
+            _ => 0.0,
+        };
+        self.last_error = Some(error);
+
+        self.integral += error * dt;
+        // Anti-windup: the integral term alone never exceeds the output range.
+        if ki > 0.0 {
+            self.integral = self.integral.clamp(0.0, 1.0 / ki);
+        }
+
+        (kp * error + ki * self.integral + kd * derivative).clamp(0.0, 1.0)
+    }
+}
+
+// Thermostat running as a background task. Clones share the same controller,
+// so the setpoint can be changed while it runs.
+#[derive(Clone)]
+pub struct Thermostat {
+    controller: Arc<std::sync::Mutex<ThermostatController>>,
+}
+
+impl Thermostat {
+    pub fn new(config: ThermostatConfig) -> Self {
+        Self {
+            controller: Arc::new(std::sync::Mutex::new(ThermostatController::new(config))),
+        }
+    }
+
+    pub fn setpoint(&self) -> Temperature {
+        self.controller.lock().unwrap().config().setpoint
+    }
+
+    pub fn set_setpoint(&self, setpoint: Temperature) {
+        self.controller.lock().unwrap().set_setpoint(setpoint);
+    }
+
+    pub fn output_on(&self) -> bool {
+        self.controller.lock().unwrap().output_on()
+    }
+
+    // Start the control loop. The output is switched whenever the controller
+    // changes its mind; the task ends with an error if switching fails.
+    pub fn start<S, O>(&self, mut source: S, mut output: O) -> ServiceHandle
+    where
+        S: TemperatureSource,
+        O: SwitchOutput,
+    {
+        let controller = self.controller.clone();
+        let period = controller.lock().unwrap().config().period;
+
+        ServiceHandle::spawn_task(|mut shutdown| async move {
+            let mut applied: Option<bool> = None;
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = shutdown.requested() => return Ok(()),
+                    _ = ticker.tick() => {}
+                }
+                let Some(measured) = source.current_temperature().await else {
+                    continue;
+                };
+                let on = controller.lock().unwrap().update(measured, Instant::now());
+                if applied != Some(on) {
+                    let state = if on {
+                        SocketState::On
+                    } else {
+                        SocketState::Off
+                    };
+                    output.switch(state).await?;
+                    applied = Some(on);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_info::devices::SmartSocket;
+    use crate::interlocks::Constraint;
+    use crate::service::ServiceError;
+    use crate::smart_house::SmartHouse;
+    use crate::smart_socket::smart_socket_server::SmartSocketServer;
+    use crate::test_support::{room, shared, socket};
+
+    fn bang_bang() -> ThermostatController {
+        ThermostatController::new(ThermostatConfig::heater(
+            Temperature::celsius(20.0),
+            ThermostatMode::BangBang { hysteresis: 0.5 },
+        ))
+    }
+
+    #[test]
+    fn test_bang_bang_hysteresis() {
+        let mut controller = bang_bang();
+        let now = Instant::now();
+        assert!(controller.update(Temperature::celsius(19.4), now));
+        assert!(controller.update(Temperature::celsius(20.3), now));
+        assert!(!controller.update(Temperature::celsius(20.5), now));
+        assert!(!controller.update(Temperature::celsius(19.7), now));
+        assert!(controller.update(Temperature::fahrenheit(66.0), now));
+    }
+
+    #[test]
+    fn test_cooler_runs_when_too_warm() {
+        let mut controller = ThermostatController::new(ThermostatConfig::cooler(
+            Temperature::celsius(24.0),
+            ThermostatMode::BangBang { hysteresis: 1.0 },
+        ));
+        let now = Instant::now();
+        assert!(controller.update(Temperature::celsius(25.0), now));
+        assert!(!controller.update(Temperature::celsius(23.0), now));
+    }
+
+    #[test]
+    fn test_min_cycle_delays_switching() {
+        let mut controller = ThermostatController::new(
+            ThermostatConfig::heater(
+                Temperature::celsius(20.0),
+                ThermostatMode::BangBang { hysteresis: 0.5 },
+            )
+            .with_min_cycle(Duration::from_secs(120)),
+        );
+        let start = Instant::now();
+        assert!(controller.update(Temperature::celsius(18.0), start));
+        let soon = start + Duration::from_secs(60);
+        assert!(controller.update(Temperature::celsius(22.0), soon));
+        let later = start + Duration::from_secs(120);
+        assert!(!controller.update(Temperature::celsius(22.0), later));
+    }
+
+    #[test]
+    fn test_pid_duty_cycle() {
+        let mut controller = ThermostatController::new(ThermostatConfig::heater(
+            Temperature::celsius(20.0),
+            ThermostatMode::Pid {
+                kp: 0.5,
+                ki: 0.0,
+                kd: 0.0,
+                window: Duration::from_secs(100),
+            },
+        ));
+        // One degree below the setpoint gives a 50% duty cycle.
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(controller.update(Temperature::celsius(19.0), at(0)));
+        assert!(controller.update(Temperature::celsius(19.0), at(40)));
+        assert!(!controller.update(Temperature::celsius(19.0), at(60)));
+        assert!(controller.update(Temperature::celsius(19.0), at(100)));
+        // Above the setpoint the output stays off.
+        assert!(!controller.update(Temperature::celsius(21.0), at(110)));
+    }
+
+    #[tokio::test]
+    async fn test_thermostat_drives_house_socket() {
+        let thermometer = Arc::new(Mutex::new(SmartThermometer {
+            name: "LivingRoomThermo".to_string(),
+            state: ThermometerState::Temperature(Temperature::celsius(18.0)),
+        }));
+        let mut house = SmartHouse::new(
+            "Home",
+            vec![room(
+                "Living Room",
+                vec![
+                    socket("Heater", SocketState::Off, 1500.0),
+                    socket("Kettle", SocketState::Off, 2000.0),
+                ],
+            )],
+        );
+        house.interlocks = house.interlocks.clone().with(Constraint::Exclusive {
+            name: "Circuit".to_string(),
+            sockets: vec!["Heater".to_string(), "Kettle".to_string()],
+        });
+        let house = shared(house);
+        let thermostat = Thermostat::new(
+            ThermostatConfig::heater(
+                Temperature::celsius(20.0),
+                ThermostatMode::BangBang { hysteresis: 0.5 },
+            )
+            .with_period(Duration::from_millis(10)),
+        );
+        let handle = thermostat.start(
+            thermometer.clone(),
+            HouseSocket::new(house.clone(), "Heater"),
+        );
+
+        let heater_is = |state: SocketState| {
+            let house = house.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while house.lock().await.socket("Heater").unwrap().state != state {
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                })
+                .await
+                .expect("heater never reached the expected state");
+            }
+        };
+        heater_is(SocketState::On).await;
+        thermometer.lock().await.state = ThermometerState::Temperature(Temperature::celsius(21.0));
+        heater_is(SocketState::Off).await;
+
+        // Switching the heater back on breaks the circuit's interlock, which
+        // stops the thermostat with an error.
+        house
+            .lock()
+            .await
+            .switch_socket("Kettle", SocketState::On)
+            .unwrap();
+        thermostat.set_setpoint(Temperature::celsius(22.0));
+        let result = tokio::time::timeout(Duration::from_secs(5), handle.join())
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, Err(ServiceError::Io(e)) if e.kind() == std::io::ErrorKind::PermissionDenied)
+        );
+        assert_eq!(
+            house.lock().await.socket("Heater").unwrap().state,
+            SocketState::Off
+        );
+    }
+
+    #[tokio::test]
+    async fn test_thermostat_drives_socket_over_tcp() {
+        let server = SmartSocketServer::new("127.0.0.1:0", SmartSocket::default());
+        let server_handle = server.start().await.unwrap();
+        let client = SmartSocketClient::connect(server_handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        let thermometer = Arc::new(Mutex::new(SmartThermometer {
+            name: "NurseryThermo".to_string(),
+            state: ThermometerState::Temperature(Temperature::celsius(17.0)),
+        }));
+
+        let thermostat = Thermostat::new(
+            ThermostatConfig::heater(
+                Temperature::celsius(18.0),
+                ThermostatMode::BangBang { hysteresis: 0.5 },
+            )
+            .with_period(Duration::from_millis(10)),
+        );
+        let handle = thermostat.start(thermometer, client);
+        wait_for_state(&server.socket(), SocketState::On).await;
+
+        handle.shutdown().await.unwrap();
+        server_handle.shutdown().await.unwrap();
+    }
+
+    async fn wait_for_state(socket: &Arc<Mutex<SmartSocket>>, state: SocketState) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while socket.lock().await.state != state {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("socket never reached the expected state");
+    }
+}
@@ -1,2 +1,4 @@
+pub mod packet;
+pub mod temperature_profile;
 pub mod udp_thermometer_listener;
 pub mod udp_thermometer_simulator;
@@ -0,0 +1,66 @@
+use crate::{Temperature, TemperatureUnit};
+use thiserror::Error;
+
+// Datagram layout: one unit tag byte (`C`, `F` or `K`) followed by the value as a big-endian f32.
+// Bare 4-byte datagrams from older senders are accepted as Celsius.
+pub const PACKET_SIZE: usize = 5;
+const LEGACY_PACKET_SIZE: usize = 4;
+
+// Define an error type for malformed datagrams.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum PacketError {
+    #[error("Unexpected datagram length {0}")]
+    InvalidLength(usize),
+    #[error("Unknown temperature unit tag {0:#04x}")]
+    UnknownUnit(u8),
+}
+
+pub fn encode(temperature: Temperature) -> [u8; PACKET_SIZE] {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[0] = temperature.unit().code();
+    packet[1..].copy_from_slice(&temperature.value().to_be_bytes());
+    packet
+}
+
+pub fn decode(datagram: &[u8]) -> Result<Temperature, PacketError> {
+    match datagram.len() {
+        PACKET_SIZE => {
+            let unit = TemperatureUnit::from_code(datagram[0])
+                .ok_or(PacketError::UnknownUnit(datagram[0]))?;
+            let value = f32::from_be_bytes(datagram[1..].try_into().unwrap());
+            Ok(Temperature::new(value, unit))
+        }
+        LEGACY_PACKET_SIZE => Ok(Temperature::celsius(f32::from_be_bytes(
+            datagram.try_into().unwrap(),
+        ))),
+        len => Err(PacketError::InvalidLength(len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_keeps_unit() {
+        let temperature = Temperature::fahrenheit(68.5);
+        let decoded = decode(&encode(temperature)).unwrap();
+        assert_eq!(decoded.unit(), TemperatureUnit::Fahrenheit);
+        assert_eq!(decoded.value(), 68.5);
+    }
+
+    #[test]
+    fn test_legacy_packet_is_celsius() {
+        let decoded = decode(&21.0f32.to_be_bytes()).unwrap();
+        assert_eq!(decoded.unit(), TemperatureUnit::Celsius);
+        assert_eq!(decoded.value(), 21.0);
+    }
+
+    #[test]
+    fn test_malformed_packets() {
+        assert_eq!(decode(&[1, 2, 3]), Err(PacketError::InvalidLength(3)));
+        let mut packet = encode(Temperature::celsius(20.0));
+        packet[0] = b'X';
+        assert_eq!(decode(&packet), Err(PacketError::UnknownUnit(b'X')));
+    }
+}
@@ -0,0 +1,489 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+use std::io::BufRead;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+// Shape of the temperature signal produced by the simulator.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemperatureProfile {
+    // Independent uniform values in `min..max` (the original simulator behaviour).
+    Uniform {
+        min: f32,
+        max: f32,
+    },
+    // Each sample moves at most `max_step` away from the previous one, clamped to `min..=max`.
+    RandomWalk {
+        start: f32,
+        max_step: f32,
+        min: f32,
+        max: f32,
+    },
+    // Sine wave around `mean` with one full cycle per `period` of simulated time, plus optional noise.
+    DailyCycle {
+        mean: f32,
+        amplitude: f32,
+        period: Duration,
+        noise: f32,
+    },
+    // Cycles through `levels`, holding each level for `hold` samples.
+    Steps {
+        levels: Vec<f32>,
+        hold: u32,
+    },
+    // Replays recorded samples, starting over when the trace is exhausted.
+    Replay {
+        samples: Vec<f32>,
+    },
+    // Adds a spike of +/- `magnitude` to the base signal with the given probability.
+    Spikes {
+        base: Box<TemperatureProfile>,
+        probability: f64,
+        magnitude: f32,
+    },
+    // Drops samples of the base signal with the given probability; nothing is sent for them.
+    Dropouts {
+        base: Box<TemperatureProfile>,
+        probability: f64,
+    },
+}
+
+// Define an error type for loading recorded traces and checking profiles.
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Failed to read trace: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid temperature {value:?} on line {line}")]
+    Parse { line: usize, value: String },
+    #[error("Trace contains no samples")]
+    Empty,
+    #[error("Invalid profile: {0}")]
+    Invalid(String),
+}
+
+impl Default for TemperatureProfile {
+    fn default() -> Self {
+        TemperatureProfile::Uniform {
+            min: 15.0,
+            max: 30.0,
+        }
+    }
+}
+
+impl TemperatureProfile {
+    // Wrap this profile so that it randomly produces spikes.
+    pub fn with_spikes(self, probability: f64, magnitude: f32) -> Self {
+        TemperatureProfile::Spikes {
+            base: Box::new(self),
+            probability,
+            magnitude,
+        }
+    }
+
+    // Wrap this profile so that it randomly drops samples.
+    pub fn with_dropouts(self, probability: f64) -> Self {
+        TemperatureProfile::Dropouts {
+            base: Box::new(self),
+            probability,
+        }
+    }
+
+    // Check the parameters a generator would otherwise panic on or turn into
+    // NaN readings: empty or inverted ranges, non-finite values, empty level
+    // and sample lists and probabilities outside `0..=1`.
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let invalid = |message: String| Err(ProfileError::Invalid(message));
+        let probability = |probability: f64| {
+            if (0.0..=1.0).contains(&probability) {
+                Ok(())
+            } else {
+                invalid(format!("probability {} is not within 0..=1", probability))
+            }
+        };
+        match self {
+            TemperatureProfile::Uniform { min, max } => {
+                if !(min.is_finite() && max.is_finite() && min < max) {
+                    return invalid(format!("uniform range {}..{} is empty", min, max));
+                }
+            }
+            TemperatureProfile::RandomWalk {
+                start,
+                max_step,
+                min,
+                max,
+            } => {
+                if !(min.is_finite() && max.is_finite() && min <= max) {
+                    return invalid(format!("random walk range {}..={} is empty", min, max));
+                }
+                if !(max_step.is_finite() && *max_step >= 0.0 && start.is_finite()) {
+                    return invalid(format!(
+                        "random walk needs a finite start and step, got {} and {}",
+                        start, max_step
+                    ));
+                }
+            }
+            TemperatureProfile::DailyCycle {
+                mean,
+                amplitude,
+                period,
+                noise,
+            } => {
+                if !(mean.is_finite() && amplitude.is_finite()) {
+                    return invalid(format!(
+                        "daily cycle needs a finite mean and amplitude, got {} and {}",
+                        mean, amplitude
+                    ));
+                }
+                if period.is_zero() {
+                    return invalid("daily cycle period is zero".to_string());
+                }
+                if !(noise.is_finite() && *noise >= 0.0) {
+                    return invalid(format!("daily cycle noise {} is negative", noise));
+                }
+            }
+            TemperatureProfile::Steps { levels, .. } => {
+                if levels.is_empty() || !levels.iter().all(|level| level.is_finite()) {
+                    return invalid(format!("steps need finite levels, got {:?}", levels));
+                }
+            }
+            TemperatureProfile::Replay { samples } => {
+                if samples.is_empty() || !samples.iter().all(|sample| sample.is_finite()) {
+                    return invalid("replay needs finite samples".to_string());
+                }
+            }
+            TemperatureProfile::Spikes {
+                base,
+                probability: p,
+                magnitude,
+            } => {
+                probability(*p)?;
+                if !magnitude.is_finite() {
+                    return invalid(format!("spike magnitude {} is not finite", magnitude));
+                }
+                base.validate()?;
+            }
+            TemperatureProfile::Dropouts {
+                base,
+                probability: p,
+            } => {
+                probability(*p)?;
+                base.validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Build a replay profile from a CSV trace.
+    //
+    // Each line holds either a single temperature or `timestamp,temperature`;
+    // the temperature is always taken from the last column. A header line
+    // that does not parse as a number is skipped, as are empty lines.
+    pub fn from_csv<R: BufRead>(reader: R) -> Result<Self, ProfileError> {
+        let mut samples = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            let value = trimmed.rsplit(',').next().unwrap_or_default().trim();
+            match value.parse::<f32>() {
+                Ok(temperature) => samples.push(temperature),
+                Err(_) if index == 0 => continue,
+                Err(_) => {
+                    return Err(ProfileError::Parse {
+                        line: index + 1,
+                        value: value.to_string(),
+                    })
+                }
+            }
+        }
+        if samples.is_empty() {
+            return Err(ProfileError::Empty);
+        }
+        Ok(TemperatureProfile::Replay { samples })
+    }
+
+    // Build a replay profile from a CSV file on disk.
+    pub fn from_csv_file<P: AsRef<Path>>(path: P) -> Result<Self, ProfileError> {
+        let file = std::fs::File::open(path)?;
+        Self::from_csv(std::io::BufReader::new(file))
+    }
+}
+
+// Produces samples for a profile. Seeded generators always yield the same sequence.
+pub struct TemperatureGenerator {
+    profile: TemperatureProfile,
+    sampler: Sampler,
+}
+
+// Everything but the profile, so that sampling can borrow both at once.
+struct Sampler {
+    interval: Duration,
+    rng: StdRng,
+    tick: u64,
+    walk: Option<f32>,
+}
+
+impl TemperatureGenerator {
+    // Fails if the profile doesn't pass `TemperatureProfile::validate`.
+    pub fn new(
+        profile: TemperatureProfile,
+        interval: Duration,
+        seed: Option<u64>,
+    ) -> Result<Self, ProfileError> {
+        profile.validate()?;
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Ok(Self {
+            profile,
+            sampler: Sampler {
+                interval,
+                rng,
+                tick: 0,
+                walk: None,
+            },
+        })
+    }
+
+    // Produce the next sample, or `None` when the profile drops it.
+    pub fn next_sample(&mut self) -> Option<f32> {
+        let sample = self.sampler.sample(&self.profile);
+        self.sampler.tick += 1;
+        sample
+    }
+}
+
+impl Sampler {
+    fn sample(&mut self, profile: &TemperatureProfile) -> Option<f32> {
+        match profile {
+            TemperatureProfile::Uniform { min, max } => Some(self.rng.gen_range(*min..*max)),
+            TemperatureProfile::RandomWalk {
+                start,
+                max_step,
+                min,
+                max,
+            } => {
+                let next = match self.walk {
+                    None => *start,
+                    Some(current) => current + self.rng.gen_range(-*max_step..=*max_step),
+                };
+                let next = next.clamp(*min, *max);
+                self.walk = Some(next);
+                Some(next)
+            }
+            TemperatureProfile::DailyCycle {
+                mean,
+                amplitude,
+                period,
+                noise,
+            } => {
+                let elapsed = self.interval.as_secs_f32() * self.tick as f32;
+                let phase = 2.0 * PI * elapsed / period.as_secs_f32();
+                let noise = if *noise > 0.0 {
+                    self.rng.gen_range(-*noise..=*noise)
+                } else {
+                    0.0
+                };
+                Some(mean + amplitude * phase.sin() + noise)
+            }
+            TemperatureProfile::Steps { levels, hold } => {
+                let index = (self.tick / u64::from((*hold).max(1))) as usize % levels.len();
+                Some(levels[index])
+            }
+            TemperatureProfile::Replay { samples } => {
+                Some(samples[self.tick as usize % samples.len()])
+            }
+            TemperatureProfile::Spikes {
+                base,
+                probability,
+                magnitude,
+            } => {
+                let value = self.sample(base)?;
+                if self.rng.gen_bool(*probability) {
+                    let sign = if self.rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+                    Some(value + sign * magnitude)
+                } else {
+                    Some(value)
+                }
+            }
+            TemperatureProfile::Dropouts { base, probability } => {
+                let value = self.sample(base)?;
+                if self.rng.gen_bool(*probability) {
+                    None
+                } else {
+                    Some(value)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(profile: TemperatureProfile, seed: u64, count: usize) -> Vec<Option<f32>> {
+        let mut generator =
+            TemperatureGenerator::new(profile, Duration::from_secs(60), Some(seed)).unwrap();
+        (0..count).map(|_| generator.next_sample()).collect()
+    }
+
+    #[test]
+    fn test_seeded_profiles_are_reproducible() {
+        let profile = TemperatureProfile::RandomWalk {
+            start: 20.0,
+            max_step: 0.5,
+            min: 15.0,
+            max: 30.0,
+        }
+        .with_spikes(0.1, 5.0)
+        .with_dropouts(0.1);
+        assert_eq!(take(profile.clone(), 42, 50), take(profile, 42, 50));
+    }
+
+    #[test]
+    fn test_random_walk_is_bounded() {
+        let profile = TemperatureProfile::RandomWalk {
+            start: 20.0,
+            max_step: 0.5,
+            min: 19.0,
+            max: 21.0,
+        };
+        let samples: Vec<f32> = take(profile, 7, 200).into_iter().flatten().collect();
+        assert_eq!(samples[0], 20.0);
+        for pair in samples.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= 0.5 + f32::EPSILON);
+        }
+        assert!(samples.iter().all(|t| (19.0..=21.0).contains(t)));
+    }
+
+    #[test]
+    fn test_daily_cycle_peaks_after_a_quarter_period() {
+        let profile = TemperatureProfile::DailyCycle {
+            mean: 20.0,
+            amplitude: 5.0,
+            period: Duration::from_secs(240),
+            noise: 0.0,
+        };
+        let samples = take(profile, 1, 4);
+        assert_eq!(samples[0], Some(20.0));
+        assert!((samples[1].unwrap() - 25.0).abs() < 1e-4);
+        assert!((samples[3].unwrap() - 15.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_steps_hold_each_level() {
+        let profile = TemperatureProfile::Steps {
+            levels: vec![18.0, 24.0],
+            hold: 2,
+        };
+        let samples: Vec<f32> = take(profile, 1, 5).into_iter().flatten().collect();
+        assert_eq!(samples, vec![18.0, 18.0, 24.0, 24.0, 18.0]);
+    }
+
+    #[test]
+    fn test_replay_from_csv() {
+        let csv = "timestamp,temperature\n0,21.5\n60,22.0\n\n120,22.5\n";
+        let profile = TemperatureProfile::from_csv(csv.as_bytes()).unwrap();
+        let samples: Vec<f32> = take(profile, 1, 4).into_iter().flatten().collect();
+        assert_eq!(samples, vec![21.5, 22.0, 22.5, 21.5]);
+    }
+
+    #[test]
+    fn test_replay_rejects_bad_lines() {
+        let csv = "21.5\nwarm\n";
+        let result = TemperatureProfile::from_csv(csv.as_bytes());
+        assert!(matches!(result, Err(ProfileError::Parse { line: 2, .. })));
+        let result = TemperatureProfile::from_csv("temperature\n".as_bytes());
+        assert!(matches!(result, Err(ProfileError::Empty)));
+    }
+
+    #[test]
+    fn test_dropouts_skip_samples() {
+        let profile = TemperatureProfile::Steps {
+            levels: vec![20.0],
+            hold: 1,
+        }
+        .with_dropouts(1.0);
+        assert!(take(profile, 3, 10).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_invalid_profiles_are_rejected() {
+        let walk = TemperatureProfile::RandomWalk {
+            start: 20.0,
+            max_step: 0.5,
+            min: 15.0,
+            max: 30.0,
+        };
+        for profile in [
+            TemperatureProfile::Uniform {
+                min: 20.0,
+                max: 20.0,
+            },
+            TemperatureProfile::RandomWalk {
+                start: 20.0,
+                max_step: -0.5,
+                min: 15.0,
+                max: 30.0,
+            },
+            TemperatureProfile::RandomWalk {
+                start: 20.0,
+                max_step: 0.5,
+                min: 30.0,
+                max: 15.0,
+            },
+            TemperatureProfile::DailyCycle {
+                mean: 20.0,
+                amplitude: 5.0,
+                period: Duration::ZERO,
+                noise: 0.0,
+            },
+            TemperatureProfile::DailyCycle {
+                mean: f32::NAN,
+                amplitude: 5.0,
+                period: Duration::from_secs(60),
+                noise: 0.0,
+            },
+            TemperatureProfile::DailyCycle {
+                mean: 20.0,
+                amplitude: f32::INFINITY,
+                period: Duration::from_secs(60),
+                noise: 0.0,
+            },
+            TemperatureProfile::Steps {
+                levels: Vec::new(),
+                hold: 1,
+            },
+            TemperatureProfile::Replay {
+                samples: Vec::new(),
+            },
+            TemperatureProfile::Replay {
+                samples: vec![20.0, f32::NAN],
+            },
+            walk.clone().with_spikes(0.5, f32::INFINITY),
+            walk.clone().with_spikes(1.5, 5.0),
+            walk.clone().with_dropouts(-0.1),
+            TemperatureProfile::default()
+                .with_spikes(f64::NAN, 1.0)
+                .with_dropouts(0.5),
+        ] {
+            let result = TemperatureGenerator::new(profile.clone(), Duration::from_secs(1), None);
+            assert!(
+                matches!(result, Err(ProfileError::Invalid(_))),
+                "{:?}",
+                profile
+            );
+        }
+        assert!(walk
+            .with_spikes(1.0, 5.0)
+            .with_dropouts(0.0)
+            .validate()
+            .is_ok());
+    }
+}
@@ -1,12 +1,22 @@
+use super::packet;
+use crate::alerts::AlertMonitor;
+use crate::events::{EventBus, EventKind, HouseEvent};
+use crate::metrics::Metrics;
+use crate::readings::{Reading, ReadingFeed, ReadingStream};
+use crate::service::ServiceHandle;
 use crate::{SmartThermometer, ThermometerState};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::net::UdpSocket;
 use tokio::sync::Mutex;
 
 pub struct UdpThermometerListener {
     address: String,
     thermometer: Arc<Mutex<SmartThermometer>>,
+    alerts: Option<AlertMonitor>,
+    feed: ReadingFeed,
+    events: Option<EventBus>,
+    metrics: Option<Metrics>,
 }
 
 impl UdpThermometerListener {
@@ -17,31 +27,225 @@ impl UdpThermometerListener {
                 name: name.to_string(),
                 state: ThermometerState::Off,
             })),
+            alerts: None,
+            feed: ReadingFeed::default(),
+            events: None,
+            metrics: None,
         }
     }
 
-    pub async fn start_listening(&self) {
-        let socket = UdpSocket::bind(&self.address)
-            .await
-            .expect("Couldn't bind to address");
+    // Evaluate every received reading against the monitor's alert rules.
+    pub fn with_alerts(mut self, monitor: AlertMonitor) -> Self {
+        self.alerts = Some(monitor);
+        self
+    }
+
+    // Publish readings to a shared feed instead of a private one.
+    pub fn with_feed(mut self, feed: ReadingFeed) -> Self {
+        self.feed = feed;
+        self
+    }
+
+    // Publish readings and malformed datagrams to a house event bus.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    // Count received and malformed datagrams.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Stream of every reading this listener applies from now on.
+    pub fn readings(&self) -> ReadingStream {
+        self.feed.subscribe()
+    }
+
+    // Stream of the readings of one thermometer on this listener's feed.
+    pub fn readings_for(&self, thermometer: &str) -> ReadingStream {
+        self.feed.subscribe_to(thermometer)
+    }
+
+    // Bind the socket and start applying received readings in a background task.
+    pub async fn start_listening(&self) -> std::io::Result<ServiceHandle> {
+        let socket = UdpSocket::bind(&self.address).await?;
+        let local_addr = socket.local_addr()?;
         let thermometer_handle = self.thermometer.clone();
+        let alerts = self.alerts.clone();
+        let feed = self.feed.clone();
+        let events = self.events.clone();
+        let metrics = self.metrics.clone();
 
-        tokio::spawn(async move {
-            let mut buf = [0u8; 8];
-            loop {
-                if let Ok((amt, _src)) = socket.recv_from(&mut buf).await {
-                    let temperature: f32 = f32::from_be_bytes(buf[0..amt].try_into().unwrap());
-                    thermometer_handle.lock().await.state =
-                        ThermometerState::Temperature(temperature);
-                    println!("Received temperature: {}", temperature);
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                let mut buf = [0u8; 16];
+                loop {
+                    let received = tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        received = socket.recv_from(&mut buf) => received,
+                    };
+                    if let Ok((amt, src)) = received {
+                        match packet::decode(&buf[..amt]) {
+                            Ok(temperature) => {
+                                let mut thermometer = thermometer_handle.lock().await;
+                                thermometer.state = ThermometerState::Temperature(temperature);
+                                if let Some(metrics) = &metrics {
+                                    metrics.udp_received(&thermometer.name);
+                                }
+                                if let Some(alerts) = &alerts {
+                                    alerts.process(&thermometer.name, temperature, Instant::now());
+                                }
+                                feed.publish(Reading {
+                                    thermometer: thermometer.name.clone(),
+                                    temperature,
+                                    received_at: SystemTime::now(),
+                                });
+                                if let Some(events) = &events {
+                                    events.publish(HouseEvent::new(
+                                        Some(&thermometer.name),
+                                        EventKind::ReadingReceived { temperature },
+                                    ));
+                                }
+                            }
+                            Err(e) => {
+                                if let Some(metrics) = &metrics {
+                                    metrics.udp_malformed(&thermometer_handle.lock().await.name);
+                                }
+                                // Printed only without a bus to report on, which
+                                // may be shown in a terminal UI.
+                                match &events {
+                                    Some(events) => {
+                                        let thermometer = thermometer_handle.lock().await;
+                                        events.publish(HouseEvent::new(
+                                            Some(&thermometer.name),
+                                            EventKind::Error {
+                                                message: format!("datagram from {}: {}", src, e),
+                                            },
+                                        ));
+                                    }
+                                    None => eprintln!("Ignoring datagram from {}: {}", src, e),
+                                }
+                            }
+                        }
+                    }
+                    tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        _ = tokio::time::sleep(Duration::from_secs(2)) => {}
+                    }
                 }
-                tokio::time::sleep(Duration::from_secs(2)).await;
-            }
-        });
+            },
+        ))
+    }
+
+    // Shared handle to the thermometer this listener updates.
+    pub fn thermometer(&self) -> Arc<Mutex<SmartThermometer>> {
+        self.thermometer.clone()
     }
 
-    #[allow(dead_code)]
     pub async fn get_temperature(&self) -> ThermometerState {
         self.thermometer.lock().await.state.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udp_thermometer::packet;
+    use crate::Temperature;
+
+    #[tokio::test]
+    async fn test_listener_applies_reading_and_stops() {
+        let listener = UdpThermometerListener::new("127.0.0.1:0", "TestThermo");
+        let handle = listener.start_listening().await.unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(
+                &packet::encode(Temperature::celsius(21.5)),
+                handle.local_addr().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let state = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let ThermometerState::Temperature(t) = listener.get_temperature().await {
+                    return t;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(state, Temperature::celsius(21.5));
+
+        handle.stop();
+        tokio::time::timeout(Duration::from_secs(1), handle.join())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_streams_readings() {
+        use tokio_stream::StreamExt;
+
+        let listener = UdpThermometerListener::new("127.0.0.1:0", "Nursery");
+        let mut readings = listener.readings_for("Nursery");
+        let handle = listener.start_listening().await.unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(
+                &packet::encode(Temperature::celsius(19.5)),
+                handle.local_addr().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let reading = tokio::time::timeout(Duration::from_secs(5), readings.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(reading.thermometer, "Nursery");
+        assert_eq!(reading.temperature, Temperature::celsius(19.5));
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_publishes_errors_to_event_bus() {
+        use crate::events::{EventFilter, EventType};
+
+        let events = EventBus::default();
+        events.set_room("Nursery", "Kids Room");
+        let mut errors = events.subscribe(EventFilter::all().event_type(EventType::Error));
+        let listener = UdpThermometerListener::new("127.0.0.1:0", "Nursery").with_events(events);
+        let handle = listener.start_listening().await.unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .send_to(b"ab", handle.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), errors.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.device.as_deref(), Some("Nursery"));
+        assert_eq!(event.room.as_deref(), Some("Kids Room"));
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_failure_is_reported() {
+        let taken = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = taken.local_addr().unwrap().to_string();
+        let listener = UdpThermometerListener::new(&address, "TestThermo");
+        assert!(listener.start_listening().await.is_err());
+    }
+}
@@ -1,34 +1,145 @@
-use rand::Rng;
-use std::time::Duration;
+use super::packet;
+use super::temperature_profile::{TemperatureGenerator, TemperatureProfile};
+use crate::readings::{Reading, ReadingFeed, ReadingStream};
+use crate::service::ServiceHandle;
+use crate::{Temperature, TemperatureUnit};
+use std::time::{Duration, SystemTime};
 use tokio::net::UdpSocket;
 
 pub struct UdpThermometerSimulator {
     destination: String,
+    profile: TemperatureProfile,
+    interval: Duration,
+    seed: Option<u64>,
+    unit: TemperatureUnit,
+    sent: ReadingFeed,
 }
 
 impl UdpThermometerSimulator {
     pub fn new(destination: &str) -> Self {
         Self {
             destination: destination.to_string(),
+            profile: TemperatureProfile::default(),
+            interval: Duration::from_secs(4),
+            seed: None,
+            unit: TemperatureUnit::Celsius,
+            sent: ReadingFeed::default(),
         }
     }
 
-    pub async fn start_sending(&self) {
-        let socket = UdpSocket::bind("0.0.0.0:0")
-            .await
-            .expect("Couldn't bind to address");
+    // Use the given temperature profile instead of uniform random values.
+    pub fn with_profile(mut self, profile: TemperatureProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    // Seed the generator so that runs are reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Change the delay between two datagrams.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // Unit the profile values are expressed in and sent as.
+    pub fn with_unit(mut self, unit: TemperatureUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    // Stream of every reading sent, e.g. for logging. Readings carry the
+    // destination as their thermometer.
+    pub fn sent(&self) -> ReadingStream {
+        self.sent.subscribe()
+    }
+
+    // Bind a sending socket and start emitting readings in a background task.
+    // An invalid profile is reported as `InvalidInput`; the task ends with an
+    // error if a datagram can't be sent.
+    pub async fn start_sending(&self) -> std::io::Result<ServiceHandle> {
+        let mut generator =
+            TemperatureGenerator::new(self.profile.clone(), self.interval, self.seed)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let local_addr = socket.local_addr()?;
         let destination = self.destination.clone();
+        let interval = self.interval;
+        let unit = self.unit;
+        let sent = self.sent.clone();
+
+        Ok(ServiceHandle::spawn(
+            local_addr,
+            |mut shutdown| async move {
+                loop {
+                    if let Some(value) = generator.next_sample() {
+                        let temperature = Temperature::new(value, unit);
+                        socket
+                            .send_to(&packet::encode(temperature), &destination)
+                            .await?;
+                        sent.publish(Reading {
+                            thermometer: destination.clone(),
+                            temperature,
+                            received_at: SystemTime::now(),
+                        });
+                    }
+                    tokio::select! {
+                        _ = shutdown.requested() => return Ok(()),
+                        _ = tokio::time::sleep(interval) => {}
+                    }
+                }
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_simulator_sends_profile_until_stopped() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let destination = receiver.local_addr().unwrap().to_string();
+        let simulator = UdpThermometerSimulator::new(&destination)
+            .with_profile(TemperatureProfile::Steps {
+                levels: vec![70.0],
+                hold: 1,
+            })
+            .with_unit(TemperatureUnit::Fahrenheit)
+            .with_interval(Duration::from_millis(10));
+        let mut sent = simulator.sent();
+        let handle = simulator.start_sending().await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (amt, _) = tokio::time::timeout(Duration::from_secs(5), receiver.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            packet::decode(&buf[..amt]).unwrap(),
+            Temperature::fahrenheit(70.0)
+        );
+        let reading = sent.next().await.unwrap().unwrap();
+        assert_eq!(reading.thermometer, destination);
+        assert_eq!(reading.temperature, Temperature::fahrenheit(70.0));
+
+        assert!(!handle.is_finished());
+        handle.shutdown().await.unwrap();
+    }
 
-        tokio::spawn(async move {
-            loop {
-                let temperature: f32 = rand::thread_rng().gen_range(15.0..30.0);
-                socket
-                    .send_to(&temperature.to_be_bytes(), &destination)
-                    .await
-                    .expect("Failed to send data");
-                println!("Sent temperature: {}", temperature);
-                tokio::time::sleep(Duration::from_secs(4)).await;
-            }
-        });
+    #[tokio::test]
+    async fn test_invalid_profile_is_rejected() {
+        let simulator =
+            UdpThermometerSimulator::new("127.0.0.1:9").with_profile(TemperatureProfile::Uniform {
+                min: 30.0,
+                max: 15.0,
+            });
+        let result = simulator.start_sending().await;
+        assert!(matches!(result, Err(e) if e.kind() == std::io::ErrorKind::InvalidInput));
     }
 }
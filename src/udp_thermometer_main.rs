@@ -1,18 +1,67 @@
-mod udp_thermometer;
-use udp_thermometer::udp_thermometer_listener::UdpThermometerListener;
-use udp_thermometer::udp_thermometer_simulator::UdpThermometerSimulator;
-
-mod device_info;
-use device_info::devices::{SmartThermometer, ThermometerState};
+use smart_house::udp_thermometer::temperature_profile::TemperatureProfile;
+use smart_house::udp_thermometer::udp_thermometer_listener::UdpThermometerListener;
+use smart_house::udp_thermometer::udp_thermometer_simulator::UdpThermometerSimulator;
+use tokio_stream::StreamExt;
 
 #[tokio::main]
 async fn main() {
     let listener = UdpThermometerListener::new("127.0.0.1:7878", "Living Room Thermometer");
-    listener.start_listening().await;
+    let mut readings = listener.readings();
+    tokio::spawn(async move {
+        while let Some(reading) = readings.next().await {
+            if let Ok(reading) = reading {
+                println!("Received temperature: {}", reading.temperature);
+            }
+        }
+    });
+    let listener_handle = match listener.start_listening().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            println!("Failed to start listener: {}", e);
+            return;
+        }
+    };
 
-    let simulator = UdpThermometerSimulator::new("127.0.0.1:7878");
-    simulator.start_sending().await;
+    let profile = match std::env::args().nth(1) {
+        Some(path) => match TemperatureProfile::from_csv_file(&path) {
+            Ok(profile) => profile,
+            Err(e) => {
+                println!("Failed to load trace {}: {}", path, e);
+                return;
+            }
+        },
+        None => TemperatureProfile::RandomWalk {
+            start: 21.0,
+            max_step: 0.3,
+            min: 15.0,
+            max: 30.0,
+        },
+    };
+    let simulator = UdpThermometerSimulator::new("127.0.0.1:7878").with_profile(profile);
+    let mut sent = simulator.sent();
+    tokio::spawn(async move {
+        while let Some(reading) = sent.next().await {
+            if let Ok(reading) = reading {
+                println!("Sent temperature: {}", reading.temperature);
+            }
+        }
+    });
+    let simulator_handle = match simulator.start_sending().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            println!("Failed to start simulator: {}", e);
+            return;
+        }
+    };
 
-    // To keep the main thread alive and let spawned tasks run
+    // Run until Ctrl+C, then stop both tasks and wait for them to finish
     tokio::signal::ctrl_c().await.unwrap();
+    for (name, handle) in [
+        ("Simulator", simulator_handle),
+        ("Listener", listener_handle),
+    ] {
+        if let Err(e) = handle.shutdown().await {
+            println!("{} stopped with error: {}", name, e);
+        }
+    }
 }
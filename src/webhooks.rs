@@ -0,0 +1,805 @@
+pub mod delivery;
+pub mod template;
+
+use crate::alerts::{AlertEvent, AlertKind, AlertMonitor};
+use crate::epoch_millis;
+use crate::events::{EventBusError, EventFilter, EventKind, EventType, HouseEvent};
+use crate::gateway::SharedHouse;
+use crate::service::{ServiceHandle, Shutdown};
+use delivery::{post, sign, DeadLetter, DeadLetterLog, DeliveryError, Endpoint};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use template::Template;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+
+// Define an error type for webhook configuration.
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("Invalid webhook URL {0}, expected http://host[:port]/path")]
+    Url(String),
+    #[error("Invalid payload template: {0}")]
+    Template(String),
+    #[error("Invalid webhook: {0}")]
+    Invalid(String),
+}
+
+// House events a webhook can be notified of.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    AlertRaised,
+    DeviceOffline,
+    SocketToggled,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::AlertRaised => "alert_raised",
+            WebhookEvent::DeviceOffline => "device_offline",
+            WebhookEvent::SocketToggled => "socket_toggled",
+        }
+    }
+}
+
+// When to retry a failed delivery. Waits double after every attempt, up to
+// `max_backoff_ms`.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    // How long to wait for the receiver to answer an attempt.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_max_backoff_ms() -> u64 {
+    60_000
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            backoff_ms: default_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+            timeout_ms: default_timeout_ms(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Wait after the given failed attempt, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(32);
+        Duration::from_millis(
+            self.backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+// An HTTP endpoint notified of selected events.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    pub name: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    // Only events of these devices; every device when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    // JSON payload with placeholders, see `template::Template`. Without one
+    // the payload is an object of all the event's variables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    // Key for the `X-SmartHouse-Signature` header, see `delivery::sign`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+impl Webhook {
+    pub fn new(name: &str, url: &str, events: &[WebhookEvent]) -> Self {
+        Self {
+            name: name.to_string(),
+            url: url.to_string(),
+            events: events.to_vec(),
+            devices: Vec::new(),
+            template: None,
+            secret: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.devices.push(device.to_string());
+        self
+    }
+
+    pub fn with_template(mut self, template: &str) -> Self {
+        self.template = Some(template.to_string());
+        self
+    }
+
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        self.events.contains(&notification.event)
+            && (self.devices.is_empty()
+                || notification
+                    .device
+                    .as_ref()
+                    .is_some_and(|device| self.devices.contains(device)))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct WebhooksConfig {
+    // JSON lines file for deliveries that were given up on. Without one
+    // they are only reported on stderr.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letters: Option<String>,
+    #[serde(default)]
+    pub hooks: Vec<Webhook>,
+}
+
+impl WebhooksConfig {
+    // Check that names are unique, URLs are supported and templates parse.
+    pub fn validate(&self) -> Result<(), WebhookError> {
+        let mut names = HashSet::new();
+        for hook in &self.hooks {
+            if !names.insert(hook.name.as_str()) {
+                return Err(WebhookError::Invalid(format!(
+                    "duplicate webhook name {}",
+                    hook.name
+                )));
+            }
+            if hook.events.is_empty() {
+                return Err(WebhookError::Invalid(format!(
+                    "webhook {} has no events",
+                    hook.name
+                )));
+            }
+            if hook.retry.max_attempts == 0 {
+                return Err(WebhookError::Invalid(format!(
+                    "webhook {} needs at least one attempt",
+                    hook.name
+                )));
+            }
+            Endpoint::parse(&hook.url)?;
+            if let Some(template) = &hook.template {
+                Template::parse(template)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Something that happened, with the variables its payload is rendered from.
+struct Notification {
+    event: WebhookEvent,
+    device: Option<String>,
+    variables: Map<String, Value>,
+}
+
+impl Notification {
+    fn new(
+        event: WebhookEvent,
+        house: &str,
+        room: Option<&str>,
+        device: Option<&str>,
+        at: SystemTime,
+    ) -> Self {
+        let mut variables = Map::new();
+        variables.insert("event".into(), event.as_str().into());
+        variables.insert("house".into(), house.into());
+        if let Some(room) = room {
+            variables.insert("room".into(), room.into());
+        }
+        if let Some(device) = device {
+            variables.insert("device".into(), device.into());
+        }
+        variables.insert("at".into(), epoch_millis(at).into());
+        Self {
+            event,
+            device: device.map(str::to_string),
+            variables,
+        }
+    }
+
+    fn with(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.variables.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+// A rendered payload on its way to one webhook. The id stays the same
+// across retries so receivers can drop duplicates.
+struct Delivery {
+    id: u64,
+    event: WebhookEvent,
+    payload: Value,
+}
+
+// Posts JSON notifications of house events and raised alerts to the
+// configured webhooks. Every webhook has its own queue, so a slow or failing
+// receiver only delays its own deliveries. Requests carry the headers
+//
+//   X-SmartHouse-Event       e.g. `socket_toggled`
+//   X-SmartHouse-Delivery    id of the delivery, the same for every attempt
+//   X-SmartHouse-Signature   `sha256=<hex HMAC of the body>`, with a secret
+//
+// Deliveries that fail for good, or are still pending when the notifier
+// stops, go to the dead-letter log.
+pub struct WebhookNotifier {
+    config: WebhooksConfig,
+    house: SharedHouse,
+    alerts: Option<AlertMonitor>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhooksConfig, house: SharedHouse) -> Self {
+        Self {
+            config: config.clone(),
+            house,
+            alerts: None,
+        }
+    }
+
+    // Notify `alert_raised` webhooks of the alerts this monitor raises.
+    pub fn with_alerts(mut self, monitor: AlertMonitor) -> Self {
+        self.alerts = Some(monitor);
+        self
+    }
+
+    // Fails if a webhook waits for `alert_raised` but no monitor was given.
+    pub async fn start(&self) -> Result<ServiceHandle, WebhookError> {
+        self.config.validate()?;
+        if self.alerts.is_none() {
+            let waiting = self
+                .config
+                .hooks
+                .iter()
+                .find(|hook| hook.events.contains(&WebhookEvent::AlertRaised));
+            if let Some(hook) = waiting {
+                return Err(WebhookError::Invalid(format!(
+                    "webhook {} waits for alert_raised but there is no alert monitor",
+                    hook.name
+                )));
+            }
+        }
+        let dead_letters = match &self.config.dead_letters {
+            Some(path) => DeadLetterLog::new(path),
+            None => DeadLetterLog::default(),
+        };
+        let mut hooks = Vec::new();
+        for hook in &self.config.hooks {
+            let worker = HookWorker {
+                endpoint: Endpoint::parse(&hook.url)?,
+                hook: hook.clone(),
+            };
+            let template = hook.template.as_deref().map(Template::parse).transpose()?;
+            hooks.push((worker, template));
+        }
+
+        let house = self.house.clone();
+        let mut events = house.lock().await.events().subscribe(
+            EventFilter::all()
+                .event_type(EventType::SocketStateChanged)
+                .event_type(EventType::Offline),
+        );
+        let mut alerts = self.alerts.as_ref().map(AlertMonitor::subscribe);
+        Ok(ServiceHandle::spawn_task(|mut shutdown| async move {
+            let mut queues = Vec::new();
+            let mut workers = Vec::new();
+            for (worker, template) in hooks {
+                let (queue, deliveries) = mpsc::unbounded_channel();
+                queues.push((queue, worker.hook.clone(), template));
+                workers.push(tokio::spawn(worker.run(
+                    deliveries,
+                    dead_letters.clone(),
+                    shutdown.clone(),
+                )));
+            }
+
+            let mut next_id = 1;
+            loop {
+                let notification = tokio::select! {
+                    _ = shutdown.requested() => break,
+                    event = events.recv() => match event {
+                        Ok(event) => from_event(&house, &event).await,
+                        Err(EventBusError::Lagged(n)) => {
+                            eprintln!("Webhooks missed {} events", n);
+                            None
+                        }
+                        Err(EventBusError::Closed) => break,
+                    },
+                    alert = next_alert(&mut alerts) => match alert {
+                        Ok(alert) => from_alert(&house, &alert).await,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            eprintln!("Webhooks missed {} alerts", n);
+                            None
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            alerts = None;
+                            None
+                        }
+                    },
+                };
+                let Some(notification) = notification else {
+                    continue;
+                };
+                for (queue, hook, template) in &queues {
+                    if !hook.wants(&notification) {
+                        continue;
+                    }
+                    let mut variables = notification.variables.clone();
+                    variables.insert("delivery".into(), next_id.into());
+                    let payload = match template {
+                        Some(template) => template.render(&variables),
+                        None => Value::Object(variables),
+                    };
+                    let _ = queue.send(Delivery {
+                        id: next_id,
+                        event: notification.event,
+                        payload,
+                    });
+                    next_id += 1;
+                }
+            }
+
+            // Workers see the same shutdown and dead-letter what is left.
+            drop(queues);
+            for worker in workers {
+                worker.await?;
+            }
+            Ok(())
+        }))
+    }
+}
+
+// Resolves with the next alert, or never without a monitor.
+async fn next_alert(
+    alerts: &mut Option<broadcast::Receiver<AlertEvent>>,
+) -> Result<AlertEvent, broadcast::error::RecvError> {
+    match alerts {
+        Some(alerts) => alerts.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn from_event(house: &SharedHouse, event: &HouseEvent) -> Option<Notification> {
+    let (webhook_event, detail) = match &event.kind {
+        EventKind::SocketStateChanged { state } => (
+            WebhookEvent::SocketToggled,
+            ("state", serde_json::to_value(state).ok()?),
+        ),
+        EventKind::Offline { reason } => (
+            WebhookEvent::DeviceOffline,
+            ("reason", reason.as_str().into()),
+        ),
+        _ => return None,
+    };
+    let name = house.lock().await.name.clone();
+    Some(
+        Notification::new(
+            webhook_event,
+            &name,
+            event.room.as_deref(),
+            event.device.as_deref(),
+            event.at,
+        )
+        .with(detail.0, detail.1),
+    )
+}
+
+async fn from_alert(house: &SharedHouse, alert: &AlertEvent) -> Option<Notification> {
+    let AlertEvent::Raised {
+        rule,
+        thermometer,
+        kind,
+        temperature,
+    } = alert
+    else {
+        return None;
+    };
+    let house = house.lock().await;
+    let unit = house.temperature_unit;
+    let notification = Notification::new(
+        WebhookEvent::AlertRaised,
+        &house.name,
+        house.room_of(thermometer),
+        Some(thermometer),
+        SystemTime::now(),
+    )
+    .with("rule", rule.as_str())
+    .with(
+        "kind",
+        match kind {
+            AlertKind::High => "high",
+            AlertKind::Low => "low",
+        },
+    )
+    .with("temperature", temperature.to_unit(unit).value())
+    .with("unit", serde_json::to_value(unit).ok()?);
+    Some(notification)
+}
+
+struct HookWorker {
+    hook: Webhook,
+    endpoint: Endpoint,
+}
+
+impl HookWorker {
+    async fn run(
+        self,
+        mut deliveries: mpsc::UnboundedReceiver<Delivery>,
+        dead_letters: DeadLetterLog,
+        mut shutdown: Shutdown,
+    ) {
+        loop {
+            let delivery = tokio::select! {
+                _ = shutdown.requested() => break,
+                delivery = deliveries.recv() => match delivery {
+                    Some(delivery) => delivery,
+                    None => break,
+                },
+            };
+            if let Err((attempts, error)) = self.deliver(&delivery, &mut shutdown).await {
+                self.give_up(&dead_letters, delivery, attempts, error);
+            }
+        }
+        while let Ok(delivery) = deliveries.try_recv() {
+            self.give_up(
+                &dead_letters,
+                delivery,
+                0,
+                "Stopped before the first attempt".to_string(),
+            );
+        }
+    }
+
+    // Post until the receiver accepts, the error is permanent or the
+    // attempts run out. Returns the number of attempts made on failure.
+    async fn deliver(
+        &self,
+        delivery: &Delivery,
+        shutdown: &mut Shutdown,
+    ) -> Result<(), (u32, String)> {
+        let body = delivery.payload.to_string().into_bytes();
+        let mut headers = vec![
+            ("X-SmartHouse-Event", delivery.event.as_str().to_string()),
+            ("X-SmartHouse-Delivery", delivery.id.to_string()),
+        ];
+        if let Some(secret) = &self.hook.secret {
+            headers.push(("X-SmartHouse-Signature", sign(secret, &body)));
+        }
+        let retry = self.hook.retry;
+        let timeout = Duration::from_millis(retry.timeout_ms);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let result: Result<u16, DeliveryError> = tokio::select! {
+                _ = shutdown.requested() => {
+                    return Err((attempt, "Stopped during an attempt".to_string()));
+                }
+                result = post(&self.endpoint, &headers, &body, timeout) => result,
+            };
+            match result {
+                Ok(_) => return Ok(()),
+                Err(e) if !e.is_retryable() || attempt >= retry.max_attempts => {
+                    return Err((attempt, e.to_string()));
+                }
+                Err(_) => {}
+            }
+            tokio::select! {
+                _ = shutdown.requested() => {
+                    return Err((attempt, "Stopped while waiting to retry".to_string()));
+                }
+                _ = tokio::time::sleep(retry.backoff(attempt)) => {}
+            }
+        }
+    }
+
+    fn give_up(
+        &self,
+        dead_letters: &DeadLetterLog,
+        delivery: Delivery,
+        attempts: u32,
+        error: String,
+    ) {
+        let letter = DeadLetter {
+            webhook: self.hook.name.clone(),
+            event: delivery.event.as_str().to_string(),
+            delivery: delivery.id,
+            at: epoch_millis(SystemTime::now()),
+            attempts,
+            error,
+            payload: delivery.payload,
+        };
+        if let Err(e) = dead_letters.append(&letter) {
+            eprintln!(
+                "Failed to write dead letter of webhook {}: {}",
+                self.hook.name, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertRule;
+    use crate::device_info::devices::{SocketState, ThermometerState};
+    use crate::device_info::temperature::Temperature;
+    use crate::smart_house::SmartHouse;
+    use crate::test_support::{room, shared, socket, thermometer};
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    // Stand-in for a webhook receiver: records every request and answers
+    // with the queued statuses, then 200.
+    #[derive(Clone, Default)]
+    struct Receiver {
+        requests: Arc<Mutex<Vec<(HeaderMap, Value)>>>,
+        statuses: Arc<Mutex<VecDeque<u16>>>,
+    }
+
+    impl Receiver {
+        async fn start(statuses: &[u16]) -> (Self, SocketAddr) {
+            let receiver = Receiver::default();
+            receiver.statuses.lock().unwrap().extend(statuses);
+            let app = Router::new()
+                .route("/hook", post(receive))
+                .with_state(receiver.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (receiver, addr)
+        }
+
+        async fn wait_for(&self, count: usize) -> Vec<(HeaderMap, Value)> {
+            for _ in 0..200 {
+                if self.requests.lock().unwrap().len() >= count {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        let payload = serde_json::from_slice(&body).unwrap();
+        receiver.requests.lock().unwrap().push((headers, payload));
+        let status = receiver.statuses.lock().unwrap().pop_front().unwrap_or(200);
+        StatusCode::from_u16(status).unwrap()
+    }
+
+    fn house() -> SharedHouse {
+        shared(SmartHouse::new(
+            "Home",
+            vec![room(
+                "Hall",
+                vec![
+                    socket("Lamp", SocketState::Off, 60.0),
+                    socket("Heater", SocketState::Off, 2000.0),
+                    thermometer("Thermo", ThermometerState::Off),
+                ],
+            )],
+        ))
+    }
+
+    fn fast_retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff_ms: 10,
+            max_backoff_ms: 40,
+            timeout_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config: WebhooksConfig = toml::from_str(
+            r#"
+dead_letters = "dead.jsonl"
+[[hooks]]
+name = "ops"
+url = "http://127.0.0.1:9000/hook"
+events = ["device_offline", "alert_raised"]
+retry = { max_attempts = 3 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.hooks[0].retry.max_attempts, 3);
+        assert_eq!(config.hooks[0].retry.backoff_ms, 1000);
+        assert!(config.validate().is_ok());
+
+        let retry = RetryPolicy::default();
+        assert_eq!(retry.backoff(1), Duration::from_secs(1));
+        assert_eq!(retry.backoff(3), Duration::from_secs(4));
+        assert_eq!(retry.backoff(40), Duration::from_secs(60));
+
+        let mut duplicate = config.clone();
+        duplicate.hooks.push(config.hooks[0].clone());
+        assert!(duplicate.validate().is_err());
+        let mut secure = config.clone();
+        secure.hooks[0].url = "https://example.com/hook".to_string();
+        assert!(matches!(secure.validate(), Err(WebhookError::Url(_))));
+    }
+
+    #[tokio::test]
+    async fn test_notifications() {
+        let (receiver, addr) = Receiver::start(&[]).await;
+        let url = format!("http://{}/hook", addr);
+        let config = WebhooksConfig {
+            dead_letters: None,
+            hooks: vec![
+                Webhook::new(
+                    "chat",
+                    &url,
+                    &[WebhookEvent::SocketToggled, WebhookEvent::AlertRaised],
+                )
+                .with_device("Lamp")
+                .with_device("Thermo")
+                .with_template(
+                    r#"{"text": "{{device}} in {{room}}: {{state}}{{rule}}", "value": "{{temperature}}"}"#,
+                )
+                .with_secret("s3cret"),
+            ],
+        };
+        let house = house();
+        // Nothing would ever raise the alerts the hook waits for.
+        assert!(matches!(
+            WebhookNotifier::new(&config, house.clone()).start().await,
+            Err(WebhookError::Invalid(_))
+        ));
+        let alerts = AlertMonitor::new();
+        alerts.add_rule(AlertRule::new("Too hot", "Thermo").above(Temperature::celsius(30.0)));
+        let handle = WebhookNotifier::new(&config, house.clone())
+            .with_alerts(alerts.clone())
+            .start()
+            .await
+            .unwrap();
+
+        {
+            let mut house = house.lock().await;
+            // Heater isn't one of the hook's devices.
+            house.switch_socket("Heater", SocketState::On).unwrap();
+            house.switch_socket("Lamp", SocketState::On).unwrap();
+        }
+        assert_eq!(receiver.wait_for(1).await.len(), 1);
+        alerts.process("Thermo", Temperature::celsius(31.5), Instant::now());
+        let requests = receiver.wait_for(2).await;
+        assert_eq!(requests.len(), 2);
+
+        let (headers, payload) = &requests[0];
+        assert_eq!(
+            payload,
+            &serde_json::json!({ "text": "Lamp in Hall: on", "value": null })
+        );
+        assert_eq!(headers["x-smarthouse-event"], "socket_toggled");
+        assert_eq!(headers["x-smarthouse-delivery"], "1");
+        assert_eq!(
+            headers["x-smarthouse-signature"],
+            sign("s3cret", payload.to_string().as_bytes()).as_str()
+        );
+        let (headers, payload) = &requests[1];
+        assert_eq!(headers["x-smarthouse-event"], "alert_raised");
+        assert_eq!(payload["text"], "Thermo in Hall: Too hot");
+        assert_eq!(payload["value"], 31.5);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_dead_letters() {
+        let (flaky, flaky_addr) = Receiver::start(&[503, 500]).await;
+        let (rejecting, rejecting_addr) = Receiver::start(&[400]).await;
+        // Nothing listens on a port that was just released.
+        let closed = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let path = std::env::temp_dir().join(format!("webhook_dead_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let events = [WebhookEvent::SocketToggled];
+        let config = WebhooksConfig {
+            dead_letters: Some(path.display().to_string()),
+            hooks: vec![
+                Webhook::new("flaky", &format!("http://{}/hook", flaky_addr), &events)
+                    .with_retry(fast_retry(3)),
+                Webhook::new(
+                    "rejecting",
+                    &format!("http://{}/hook", rejecting_addr),
+                    &events,
+                )
+                .with_retry(fast_retry(3)),
+                Webhook::new("down", &format!("http://{}/hook", closed), &events)
+                    .with_retry(fast_retry(2)),
+            ],
+        };
+        let house = house();
+        let handle = WebhookNotifier::new(&config, house.clone())
+            .start()
+            .await
+            .unwrap();
+        house
+            .lock()
+            .await
+            .switch_socket("Lamp", SocketState::On)
+            .unwrap();
+
+        // The same delivery is retried until the receiver accepts it.
+        let attempts = flaky.wait_for(3).await;
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts
+            .iter()
+            .all(|(headers, _)| headers["x-smarthouse-delivery"] == "1"));
+        assert_eq!(attempts[2].1["state"], "on");
+        assert_eq!(attempts[2].1["device"], "Lamp");
+
+        let log = DeadLetterLog::new(&path);
+        let mut letters = Vec::new();
+        for _ in 0..200 {
+            letters = log.read().unwrap();
+            if letters.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        letters.sort_by(|a, b| a.webhook.cmp(&b.webhook));
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].webhook, "down");
+        assert_eq!(letters[0].attempts, 2);
+        // A client error isn't retried.
+        assert_eq!(letters[1].webhook, "rejecting");
+        assert_eq!(letters[1].attempts, 1);
+        assert_eq!(letters[1].error, "Receiver answered with status 400");
+        assert_eq!(letters[1].payload["event"], "socket_toggled");
+        assert_eq!(rejecting.wait_for(1).await.len(), 1);
+
+        handle.shutdown().await.unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use super::WebhookError;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+// Define an error type for single delivery attempts.
+#[derive(Error, Debug)]
+pub enum DeliveryError {
+    #[error("Failed to reach receiver: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Receiver did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("Receiver answered with status {0}")]
+    Status(u16),
+    #[error("Malformed response: {0}")]
+    Protocol(String),
+}
+
+impl DeliveryError {
+    // Client errors other than timeouts and rate limits won't go away by
+    // sending the same request again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DeliveryError::Status(status) => {
+                !(400..500).contains(status) || *status == 408 || *status == 429
+            }
+            _ => true,
+        }
+    }
+}
+
+// Where a webhook is posted to, parsed from an `http://host[:port]/path` URL.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Endpoint {
+    pub fn parse(url: &str) -> Result<Self, WebhookError> {
+        let invalid = || WebhookError::Url(url.to_string());
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+// Value of the signature header: `sha256=` and the hex HMAC-SHA256 of the
+// body keyed with the webhook's secret.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+// POST `body` as JSON with the extra `headers` and wait for the status line.
+// Anything but a 2xx status is an error.
+pub async fn post(
+    endpoint: &Endpoint,
+    headers: &[(&str, String)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<u16, DeliveryError> {
+    let attempt = async {
+        let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            endpoint.path,
+            endpoint.host,
+            endpoint.port,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        stream.write_all(body).await?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line).await?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| DeliveryError::Protocol(format!("bad status line {:?}", status_line)))?;
+        if (200..300).contains(&status) {
+            Ok(status)
+        } else {
+            Err(DeliveryError::Status(status))
+        }
+    };
+    tokio::time::timeout(timeout, attempt)
+        .await
+        .map_err(|_| DeliveryError::Timeout(timeout))?
+}
+
+// A delivery that was given up on.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub webhook: String,
+    pub event: String,
+    pub delivery: u64,
+    // Milliseconds since the Unix epoch.
+    pub at: u64,
+    pub attempts: u32,
+    pub error: String,
+    pub payload: serde_json::Value,
+}
+
+// Append-only log of dead letters, one JSON object per line. Without a
+// file they are only reported on stderr.
+#[derive(Clone, Default, Debug)]
+pub struct DeadLetterLog {
+    path: Option<PathBuf>,
+}
+
+impl DeadLetterLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: Some(path.as_ref().to_path_buf()),
+        }
+    }
+
+    pub fn append(&self, letter: &DeadLetter) -> std::io::Result<()> {
+        eprintln!(
+            "Webhook {} gave up on delivery {} after {} attempts: {}",
+            letter.webhook, letter.delivery, letter.attempts, letter.error
+        );
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(letter)?;
+        line.push('\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    }
+
+    // Every letter in the log, oldest first.
+    pub fn read(&self) -> std::io::Result<Vec<DeadLetter>> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        text.lines()
+            .map(|line| serde_json::from_str(line).map_err(std::io::Error::from))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            Endpoint::parse("http://hooks.local:8080/notify?key=1").unwrap(),
+            Endpoint {
+                host: "hooks.local".to_string(),
+                port: 8080,
+                path: "/notify?key=1".to_string(),
+            }
+        );
+        assert_eq!(Endpoint::parse("http://hooks.local").unwrap().path, "/");
+        assert_eq!(Endpoint::parse("http://hooks.local/").unwrap().port, 80);
+        for url in [
+            "https://hooks.local/",
+            "http://:80/",
+            "http://hooks.local:x/",
+        ] {
+            assert!(Endpoint::parse(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_dead_letter_log() {
+        let path = std::env::temp_dir().join(format!("dead_letters_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = DeadLetterLog::new(&path);
+        assert!(log.read().unwrap().is_empty());
+        let letter = DeadLetter {
+            webhook: "ops".to_string(),
+            event: "device_offline".to_string(),
+            delivery: 3,
+            at: 1_700_000_000_000,
+            attempts: 5,
+            error: "Receiver answered with status 500".to_string(),
+            payload: serde_json::json!({ "device": "Lamp" }),
+        };
+        log.append(&letter).unwrap();
+        log.append(&letter).unwrap();
+        assert_eq!(log.read().unwrap(), vec![letter.clone(), letter]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
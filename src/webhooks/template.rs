@@ -0,0 +1,157 @@
+use super::WebhookError;
+use serde_json::{Map, Value};
+
+// Variables a payload template can refer to. Those an event doesn't have,
+// e.g. `reason` for a socket toggle, render as null.
+pub const VARIABLES: [&str; 12] = [
+    "event",
+    "delivery",
+    "house",
+    "room",
+    "device",
+    "at",
+    "state",
+    "reason",
+    "rule",
+    "kind",
+    "temperature",
+    "unit",
+];
+
+// A JSON payload with `{{name}}` placeholders in its string values. A string
+// that is exactly one placeholder is replaced by the variable itself, so
+// numbers stay numbers; placeholders inside longer strings are replaced by
+// the variable as text.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Template {
+    value: Value,
+}
+
+impl Template {
+    // Parse the template, rejecting invalid JSON and unknown placeholders.
+    pub fn parse(text: &str) -> Result<Self, WebhookError> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|e| WebhookError::Template(e.to_string()))?;
+        check(&value)?;
+        Ok(Self { value })
+    }
+
+    pub fn render(&self, variables: &Map<String, Value>) -> Value {
+        render(&self.value, variables)
+    }
+}
+
+// Name of the placeholder a string consists of, if it is exactly one.
+fn whole_placeholder(text: &str) -> Option<&str> {
+    let name = text.strip_prefix("{{")?.strip_suffix("}}")?;
+    (!name.contains("{{") && !name.contains("}}")).then(|| name.trim())
+}
+
+// Split a string into its literal text and placeholder names, in order.
+fn parts(mut text: &str) -> Result<Vec<(&str, Option<&str>)>, WebhookError> {
+    let mut parts = Vec::new();
+    while let Some(start) = text.find("{{") {
+        let end = text[start..]
+            .find("}}")
+            .ok_or_else(|| WebhookError::Template(format!("unclosed placeholder in `{}`", text)))?;
+        parts.push((&text[..start], Some(text[start + 2..start + end].trim())));
+        text = &text[start + end + 2..];
+    }
+    parts.push((text, None));
+    Ok(parts)
+}
+
+fn check(value: &Value) -> Result<(), WebhookError> {
+    match value {
+        Value::String(text) => {
+            for (_, name) in parts(text)? {
+                if let Some(name) = name.filter(|name| !VARIABLES.contains(name)) {
+                    return Err(WebhookError::Template(format!(
+                        "unknown placeholder `{{{{{}}}}}`",
+                        name
+                    )));
+                }
+            }
+            Ok(())
+        }
+        Value::Array(items) => items.iter().try_for_each(check),
+        Value::Object(fields) => fields.values().try_for_each(check),
+        _ => Ok(()),
+    }
+}
+
+fn render(value: &Value, variables: &Map<String, Value>) -> Value {
+    match value {
+        Value::String(text) => {
+            if let Some(name) = whole_placeholder(text) {
+                return variables.get(name).cloned().unwrap_or(Value::Null);
+            }
+            // `check` accepted the template, so it splits cleanly.
+            let mut rendered = String::new();
+            for (literal, name) in parts(text).unwrap_or_default() {
+                rendered.push_str(literal);
+                match name.and_then(|name| variables.get(name)) {
+                    Some(Value::String(value)) => rendered.push_str(value),
+                    Some(Value::Null) | None => {}
+                    Some(value) => rendered.push_str(&value.to_string()),
+                }
+            }
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| render(v, variables)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, v)| (key.clone(), render(v, variables)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse(
+            r#"{
+                "text": "{{device}} in {{room}} is {{ state }} ({{reason}})",
+                "temperature": "{{temperature}}",
+                "tags": ["smart_house", "{{event}}"],
+                "missing": "{{rule}}",
+                "fixed": 1
+            }"#,
+        )
+        .unwrap();
+        let variables = json!({
+            "event": "socket_toggled",
+            "device": "Lamp",
+            "room": "Hall",
+            "state": "on",
+            "temperature": 21.5,
+        });
+        assert_eq!(
+            template.render(variables.as_object().unwrap()),
+            json!({
+                "text": "Lamp in Hall is on ()",
+                "temperature": 21.5,
+                "tags": ["smart_house", "socket_toggled"],
+                "missing": null,
+                "fixed": 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            Template::parse("{\"text\": \"{{humidity}}\"}"),
+            Err(WebhookError::Template(message)) if message == "unknown placeholder `{{humidity}}`"
+        ));
+        assert!(Template::parse("{\"text\": \"{{device\"}").is_err());
+        assert!(Template::parse("{text}").is_err());
+    }
+}